@@ -0,0 +1,120 @@
+//! Benchmark prefetch against a database of many small genomes, where most
+//! sketches are too small to ever reach the overlap threshold.
+//!
+//! Run with:
+//! ```
+//! cargo bench --no-default-features --features lib --bench prefetch
+//! ```
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use sourmash::collection::Collection;
+use sourmash::encodings::HashFunctions;
+use sourmash::signature::Signature;
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+use sourmash::sketch::Sketch;
+
+use sourmash_plugin_branchwater::gather::load_sketches_above_threshold;
+
+const SCALED: u64 = 1000;
+const KSIZE: u32 = 31;
+const QUERY_HASHES: usize = 50_000;
+const N_GENOMES: usize = 20_000;
+const GENOME_HASHES: usize = 20;
+const THRESHOLD_HASHES: u64 = 50;
+const ROUNDS: u32 = 5;
+
+/// A small, deterministic xorshift generator, so runs are comparable.
+struct Rng(u64);
+
+impl Rng {
+    fn next_hash(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % max_hash_for_scaled(SCALED)
+    }
+}
+
+fn new_mh() -> KmerMinHash {
+    KmerMinHash::new(SCALED, KSIZE, HashFunctions::Murmur64Dna, 42, false, 0)
+}
+
+fn build_sketches() -> (KmerMinHash, Vec<Signature>) {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+    let query_hashes: Vec<u64> = (0..QUERY_HASHES).map(|_| rng.next_hash()).collect();
+    let mut query = new_mh();
+    query.add_many(&query_hashes).unwrap();
+
+    // half of the genomes share hashes with the query.
+    let sigs = (0..N_GENOMES)
+        .map(|i| {
+            let mut mh = new_mh();
+            for j in 0..GENOME_HASHES {
+                if i % 2 == 0 {
+                    mh.add_hash(query_hashes[(i * GENOME_HASHES + j) % QUERY_HASHES]);
+                } else {
+                    mh.add_hash(rng.next_hash());
+                }
+            }
+            let mut sig = Signature::default();
+            sig.set_name(&format!("genome{}", i));
+            sig.push(Sketch::MinHash(mh));
+            sig
+        })
+        .collect();
+
+    (query, sigs)
+}
+
+/// Prefetch without the size bound: intersect every sketch with the query.
+fn intersect_all(collection: Collection, query: &KmerMinHash) -> usize {
+    collection
+        .par_iter()
+        .filter(|(_idx, record)| {
+            let sig = collection.sig_from_record(record).unwrap();
+            let mh = sig
+                .minhash()
+                .unwrap()
+                .downsample_scaled(query.scaled())
+                .unwrap();
+            mh.count_common(query, false).unwrap() >= THRESHOLD_HASHES
+        })
+        .count()
+}
+
+fn time<F: FnMut(Collection) -> usize>(sigs: &[Signature], mut f: F) -> (Duration, usize) {
+    let mut total = Duration::ZERO;
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        let collection = Collection::from_sigs(sigs.to_vec()).unwrap();
+        let start = Instant::now();
+        found = f(collection);
+        total += start.elapsed();
+    }
+    (total / ROUNDS, found)
+}
+
+fn main() {
+    let (query, sigs) = build_sketches();
+    println!(
+        "prefetch: query of {} hashes against {} genomes of {} hashes, threshold {} hashes",
+        QUERY_HASHES, N_GENOMES, GENOME_HASHES, THRESHOLD_HASHES
+    );
+
+    let (elapsed, found) = time(&sigs, |collection| intersect_all(collection, &query));
+    println!(
+        "  {:<32} {:?} ({} matches)",
+        "intersect all:", elapsed, found
+    );
+
+    let (elapsed, found) = time(&sigs, |collection| {
+        let (matches, _skipped, _failed) =
+            load_sketches_above_threshold(collection, &query, THRESHOLD_HASHES, None).unwrap();
+        matches.len()
+    });
+    println!(
+        "  {:<32} {:?} ({} matches)",
+        "load_sketches_above_threshold:", elapsed, found
+    );
+}
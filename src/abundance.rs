@@ -0,0 +1,137 @@
+/// abundance: the shared all-pairs driver for comparisons between
+/// abundance-tracking sketches (betadiversity, weighted_containment).
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use crate::errors::BranchwaterError;
+use crate::progress::Progress;
+use crate::schema::ResultSchema;
+use crate::utils::{
+    csvwriter_thread_with_extras, load_collection, load_sketches, OutputOptions, Policies,
+    ReportType, SmallSignature, WriterExtras,
+};
+use sourmash::selection::Selection;
+
+/// A pair of abundance-tracking sketches, with their (sorted) hashes and
+/// abundances, and the sum of each sketch's abundances.
+pub struct AbundancePair<'a> {
+    pub query: &'a SmallSignature,
+    pub against: &'a SmallSignature,
+    pub query_abunds: &'a [(u64, u64)],
+    pub against_abunds: &'a [(u64, u64)],
+    pub query_total: u64,
+    pub against_total: u64,
+}
+
+/// Load the sketches in 'siglist', which must all track abundance, and
+/// call 'compare' on every pair of them in parallel, writing each result
+/// it returns as given in 'options'. 'command' names the caller in error
+/// messages.
+///
+/// Note: this function loads all _signatures_ into memory.
+#[allow(clippy::too_many_arguments)]
+pub fn compare_abundance_pairs<T, F>(
+    command: &str,
+    siglist: String,
+    selection: &Selection,
+    policies: &Policies,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    options: &OutputOptions,
+    compare: F,
+) -> Result<()>
+where
+    T: Serialize + ResultSchema + Send + 'static,
+    F: Fn(&AbundancePair) -> Option<T> + Sync,
+{
+    let collection = load_collection(&siglist, selection, ReportType::General, policies)?;
+    if collection.len() <= 1 {
+        bail!(
+            "{} requires two or more sketches. Check input: '{:?}'",
+            command,
+            &siglist
+        )
+    }
+    let sketches = load_sketches(collection, selection, ReportType::General)?;
+
+    let flat: Vec<&SmallSignature> = sketches
+        .iter()
+        .filter(|ss| !ss.minhash.track_abundance())
+        .collect();
+    if !flat.is_empty() {
+        for ss in flat.iter().take(5) {
+            eprintln!("  no abundances: '{}'", ss.name);
+        }
+        bail!(
+            "{} requires abundance-tracking sketches, but {} sketches have no abundances.",
+            command,
+            flat.len()
+        );
+    }
+
+    let abunds: Vec<Vec<(u64, u64)>> = sketches
+        .iter()
+        .map(|ss| ss.minhash.to_vec_abunds())
+        .collect();
+    let totals: Vec<u64> = abunds
+        .iter()
+        .map(|a| a.iter().map(|(_, abund)| abund).sum())
+        .collect();
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) = std::sync::mpsc::sync_channel::<T>(rayon::current_num_threads());
+
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let extras = WriterExtras {
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
+
+    let processed_cmp = AtomicUsize::new(0);
+    let n_comparisons = sketches.len() * (sketches.len() - 1) / 2;
+    let progress = Progress::new(n_comparisons, "comparisons");
+
+    sketches.par_iter().enumerate().for_each(|(i, query)| {
+        for j in (i + 1)..sketches.len() {
+            let pair = AbundancePair {
+                query,
+                against: &sketches[j],
+                query_abunds: &abunds[i],
+                against_abunds: &abunds[j],
+                query_total: totals[i],
+                against_total: totals[j],
+            };
+            if let Some(result) = compare(&pair) {
+                send.send(result).unwrap();
+            }
+
+            let n = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
+            progress.inc();
+            if !progress.is_shown() && n % 100000 == 0 && n > 0 {
+                eprintln!("Processed {} comparisons", n);
+            }
+        }
+    });
+
+    // do some cleanup and error handling -
+    drop(send); // close the channel
+
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+
+    // done!
+    progress.finish();
+    let n: usize = processed_cmp.load(atomic::Ordering::SeqCst);
+    eprintln!("DONE. Processed {} comparisons", n);
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+/// betadiversity: abundance-weighted pairwise dissimilarity of metagenome sketches.
+use anyhow::Result;
+
+use crate::abundance::compare_abundance_pairs;
+use crate::utils::{BetaDiversityResult, OutputOptions, Policies};
+use sourmash::selection::Selection;
+
+/// Per-pair sums over the union of hashes in two abundance sketches.
+struct AbundanceSums {
+    intersect_hashes: usize,
+    sum_min: u64,
+    sum_max: u64,
+}
+
+/// Walk the (sorted) hashes of two abundance sketches together, summing
+/// the smaller and larger abundance of each hash.
+fn abundance_sums(query: &[(u64, u64)], against: &[(u64, u64)]) -> AbundanceSums {
+    let mut sums = AbundanceSums {
+        intersect_hashes: 0,
+        sum_min: 0,
+        sum_max: 0,
+    };
+    let mut query_iter = query.iter().peekable();
+    let mut against_iter = against.iter().peekable();
+
+    loop {
+        match (query_iter.peek(), against_iter.peek()) {
+            (Some((q_hash, q_abund)), Some((a_hash, a_abund))) if q_hash == a_hash => {
+                sums.intersect_hashes += 1;
+                sums.sum_min += std::cmp::min(*q_abund, *a_abund);
+                sums.sum_max += std::cmp::max(*q_abund, *a_abund);
+                query_iter.next();
+                against_iter.next();
+            }
+            (Some((q_hash, q_abund)), Some((a_hash, _))) if q_hash < a_hash => {
+                sums.sum_max += q_abund;
+                query_iter.next();
+            }
+            (_, Some((_, a_abund))) => {
+                sums.sum_max += a_abund;
+                against_iter.next();
+            }
+            (Some((_, q_abund)), None) => {
+                sums.sum_max += q_abund;
+                query_iter.next();
+            }
+            (None, None) => break,
+        }
+    }
+    sums
+}
+
+/// Compute Bray-Curtis dissimilarity and weighted Jaccard similarity
+/// between every pair of abundance-tracking sketches in 'siglist'.
+/// Every pair is written, including pairs with no shared hashes.
+///
+/// Note: this function loads all _signatures_ into memory.
+pub fn betadiversity(
+    siglist: String,
+    selection: &Selection,
+    policies: &Policies,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    options: &OutputOptions,
+) -> Result<()> {
+    compare_abundance_pairs(
+        "betadiversity",
+        siglist,
+        selection,
+        policies,
+        output,
+        columns,
+        query_buckets,
+        options,
+        |pair| {
+            let sums = abundance_sums(pair.query_abunds, pair.against_abunds);
+            let total = pair.query_total + pair.against_total;
+
+            let bray_curtis = if total > 0 {
+                1.0 - (2 * sums.sum_min) as f64 / total as f64
+            } else {
+                0.0
+            };
+            let weighted_jaccard = if sums.sum_max > 0 {
+                sums.sum_min as f64 / sums.sum_max as f64
+            } else {
+                1.0
+            };
+
+            Some(BetaDiversityResult {
+                query_name: pair.query.name.clone(),
+                query_md5: pair.query.md5sum.clone(),
+                match_name: pair.against.name.clone(),
+                match_md5: pair.against.md5sum.clone(),
+                bray_curtis,
+                weighted_jaccard,
+                intersect_hashes: sums.intersect_hashes,
+                query_sum_abunds: pair.query_total,
+                match_sum_abunds: pair.against_total,
+            })
+        },
+    )
+}
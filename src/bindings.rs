@@ -0,0 +1,2258 @@
+/// Python bindings for sourmash_plugin_branchwater, via pyo3.
+use pyo3::prelude::*;
+
+use crate::biom::BiomTable;
+use crate::checkpoint::Checkpointing;
+use crate::dryrun::Residency;
+use crate::errors::{error_code, ErrorCode};
+use crate::indexset::{output_for_ksize, resolve_index_set};
+use crate::report::RunReport;
+use crate::utils::build_num_selection;
+use crate::utils::build_selection;
+use crate::utils::check_output_paths;
+use crate::utils::find_revindex_database;
+use crate::utils::parse_column_list;
+use crate::utils::parse_moltype_search;
+use crate::utils::AgainstMetadata;
+use crate::utils::BranchwaterGatherResult;
+use crate::utils::GatherOptions;
+use crate::utils::MixedScaled;
+use crate::utils::NegativeControls;
+use crate::utils::OutputFormat;
+use crate::utils::OutputOptions;
+use crate::utils::Policies;
+use crate::utils::QueryAnonymizer;
+use crate::utils::QueryGroupSummary;
+use crate::utils::RankBy;
+use crate::utils::Schedule;
+use crate::utils::SpikeIn;
+use crate::utils::WriterExtras;
+
+use crate::{
+    betadiversity, check, cluster, dryrun, fastgather, fastmanysearch, fastmultigather, index,
+    manysearch, manysketch, mastiff_manygather, mastiff_manysearch, multisearch, pairwise,
+    progress, rarefy, schema, setops, simulate, timeseries, utils, weighted_containment,
+};
+use camino::Utf8PathBuf as PathBuf;
+use sourmash::selection::Selection;
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+pyo3::create_exception!(
+    sourmash_plugin_branchwater,
+    BranchwaterError,
+    pyo3::exceptions::PyRuntimeError,
+    "A branchwater failure, with a stable error code in 'code' and its name in 'name'."
+);
+
+/// Report a command failure, and return its exit code.
+fn command_failed(e: &(dyn std::error::Error + 'static)) -> u8 {
+    eprintln!("Error: {e}");
+    error_code(e).exit_code()
+}
+
+/// Convert an error into a Python `BranchwaterError` carrying its code.
+fn to_py_err(py: Python, e: anyhow::Error) -> PyErr {
+    let code = error_code(&*e);
+    let err = BranchwaterError::new_err(e.to_string());
+    let value = err.value_bound(py);
+    value.setattr("code", code.exit_code()).ok();
+    value.setattr("name", code.name()).ok();
+    err
+}
+
+/// Output path(s) for results: a single path, or a list of paths that
+/// each get a copy of the results.
+#[derive(FromPyObject)]
+enum OutputPaths {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn parse_output_paths(output: Option<OutputPaths>) -> anyhow::Result<Vec<String>> {
+    let paths = match output {
+        None => vec![],
+        Some(OutputPaths::One(path)) => vec![path],
+        Some(OutputPaths::Many(paths)) => paths,
+    };
+    check_output_paths(&paths)?;
+    Ok(paths)
+}
+
+/// The selection and path of each collection to dry-run a search against:
+/// each index in 'index_runs', if 'path' is an index set, or 'path'.
+fn search_targets(
+    index_runs: &Option<Vec<(u32, PathBuf)>>,
+    path: &str,
+    selection: &Selection,
+    scaled: usize,
+    moltype: &str,
+) -> Vec<(Selection, String)> {
+    match index_runs {
+        Some(runs) => runs
+            .iter()
+            .map(|(ksize, index)| {
+                (
+                    build_selection(*ksize as u8, scaled, moltype),
+                    index.to_string(),
+                )
+            })
+            .collect(),
+        None => vec![(selection.clone(), path.to_string())],
+    }
+}
+
+/// Parse `--max-matchlist-mem`; spilled matches can only be reloaded in
+/// order when gather ranks matches by overlap.
+fn parse_max_matchlist_mem(size: Option<String>, rank_by: RankBy) -> anyhow::Result<Option<usize>> {
+    let max_bytes = size.as_deref().map(dryrun::parse_memory_size).transpose()?;
+    if max_bytes.is_some() && rank_by != RankBy::Overlap {
+        bail!(
+            "--max-matchlist-mem requires --rank-by overlap, not --rank-by {}.",
+            rank_by
+        );
+    }
+    Ok(max_bytes)
+}
+
+/// Parse `--checkpoint-every` and `--resume`.
+fn parse_checkpointing(
+    every: Option<usize>,
+    resume: bool,
+) -> anyhow::Result<Option<Checkpointing>> {
+    match every {
+        Some(0) => bail!("--checkpoint-every must be at least 1."),
+        Some(every) => Ok(Some(Checkpointing { every, resume })),
+        None if resume => bail!("--resume requires --checkpoint-every."),
+        None => Ok(None),
+    }
+}
+
+/// Load the `--query-groups` CSV, if any, for summarizing results per group,
+/// and the `--against-metadata` CSV, if any, for appending to each result,
+/// with results written in 'format'.
+fn parse_writer_extras(
+    groups_csv: Option<String>,
+    groups_output: Option<String>,
+    against_metadata: Option<String>,
+    against_metadata_columns: Option<String>,
+    format: OutputFormat,
+    command: &'static str,
+    options: &OutputOptions,
+) -> anyhow::Result<WriterExtras> {
+    let query_groups = match (groups_csv, groups_output) {
+        (None, None) => None,
+        (Some(groups_csv), Some(output)) => Some(QueryGroupSummary::from_csv(
+            &groups_csv,
+            output,
+            options.clone(),
+        )?),
+        (Some(_), None) => bail!("--query-groups requires --query-groups-output"),
+        (None, Some(_)) => bail!("--query-groups-output requires --query-groups"),
+    };
+    let against_metadata = match (against_metadata, against_metadata_columns) {
+        (None, None) => None,
+        (Some(path), columns) => Some(AgainstMetadata::from_csv(
+            &path,
+            parse_column_list(columns),
+        )?),
+        (None, Some(_)) => bail!("--against-metadata-columns requires --against-metadata"),
+    };
+    Ok(WriterExtras {
+        query_groups,
+        against_metadata,
+        biom: None,
+        format,
+        command,
+        report: None,
+        options: options.clone(),
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    querylist_path,
+    siglist_path,
+    threshold,
+    ksize,
+    scaled,
+    moltype,
+    min_query_hashes,
+    output_path,
+    columns,
+    skipped_output,
+    query_buckets=0,
+    query_groups=None,
+    query_groups_output=None,
+    against_metadata=None,
+    against_metadata_columns=None,
+    moltype_searches=Vec::new(),
+    screen=false,
+    negative_controls=None,
+    subtract_negative_controls=false,
+    output_downsampled=None,
+    all_ksizes=false,
+    dry_run=false,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    collapse_identical=false,
+    output_format=String::from("csv"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_manysearch(
+    py: Python,
+    querylist_path: String,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    min_query_hashes: usize,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    skipped_output: Option<String>,
+    query_buckets: usize,
+    query_groups: Option<String>,
+    query_groups_output: Option<String>,
+    against_metadata: Option<String>,
+    against_metadata_columns: Option<String>,
+    moltype_searches: Vec<String>,
+    screen: bool,
+    negative_controls: Option<String>,
+    subtract_negative_controls: bool,
+    output_downsampled: Option<String>,
+    all_ksizes: bool,
+    dry_run: bool,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    collapse_identical: bool,
+    output_format: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let againstfile_path: PathBuf = siglist_path.clone().into();
+    let selection = build_selection(ksize, scaled, &moltype);
+    eprintln!("selection scaled: {:?}", selection.scaled());
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_format: OutputFormat = match output_format.parse() {
+        Ok(output_format) => output_format,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let moltype_searches = match moltype_searches
+        .iter()
+        .map(|spec| parse_moltype_search(spec, ksize, scaled))
+        .collect::<anyhow::Result<Vec<_>>>()
+    {
+        Ok(searches) => searches,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    if let Err(e) = output_format.check_outputs(&output_path, query_buckets) {
+        return Ok(command_failed(&*e));
+    }
+
+    let extras = match parse_writer_extras(
+        query_groups.clone(),
+        query_groups_output.clone(),
+        against_metadata.clone(),
+        against_metadata_columns.clone(),
+        output_format,
+        "manysearch",
+        &options,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    // a directory of ksize-specific indexes is searched one index at a time
+    let index_runs = match resolve_index_set(&againstfile_path, &selection, all_ksizes) {
+        Ok(runs) => runs,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    let negative_controls = match negative_controls {
+        Some(path) => match NegativeControls::from_file(&path, subtract_negative_controls) {
+            Ok(controls) => Some(controls),
+            Err(e) => return Ok(command_failed(&*e)),
+        },
+        None if subtract_negative_controls => {
+            bail!("--subtract-negative-controls requires --negative-controls");
+        }
+        None => None,
+    };
+
+    if dry_run {
+        let targets = search_targets(&index_runs, &siglist_path, &selection, scaled, &moltype);
+        return py.allow_threads(|| {
+            for (selection, path) in targets.iter() {
+                if let Err(e) = dryrun::search(
+                    "manysearch",
+                    (&querylist_path, Residency::InMemory),
+                    (path, Residency::OneAtATime),
+                    selection,
+                    &policies,
+                    None,
+                ) {
+                    return Ok(command_failed(&*e));
+                }
+            }
+            Ok(0)
+        });
+    }
+
+    // if siglist_path is revindex, run mastiff_manysearch; otherwise run manysearch
+    if index_runs.is_some() || find_revindex_database(&againstfile_path).is_some() {
+        if negative_controls.is_some() {
+            bail!("--negative-controls is not supported when searching a rocksdb.");
+        }
+        if output_downsampled.is_some() {
+            bail!("--output-downsampled is not supported when searching a rocksdb.");
+        }
+        if !moltype_searches.is_empty() {
+            bail!("--moltype-search is not supported when searching a rocksdb.");
+        }
+        if screen {
+            bail!("--screen is not supported when searching a rocksdb.");
+        }
+        if collapse_identical {
+            bail!("--collapse-identical is not supported when searching a rocksdb.");
+        }
+        let runs = index_runs.unwrap_or_else(|| vec![(ksize as u32, againstfile_path)]);
+        let mut extras = Some(extras);
+        py.allow_threads(|| {
+            for (run_ksize, index) in runs {
+                // with --all-ksizes, each ksize gets its own tagged outputs
+                let tag = |path: &String| {
+                    if all_ksizes {
+                        output_for_ksize(path, run_ksize)
+                    } else {
+                        path.clone()
+                    }
+                };
+                let run_extras = match extras.take() {
+                    Some(extras) if !all_ksizes => Ok(extras),
+                    _ => parse_writer_extras(
+                        query_groups.clone(),
+                        query_groups_output.as_ref().map(tag),
+                        against_metadata.clone(),
+                        against_metadata_columns.clone(),
+                        output_format,
+                        "manysearch",
+                        &options,
+                    ),
+                };
+                if let Err(e) = run_extras.and_then(|run_extras| {
+                    mastiff_manysearch::mastiff_manysearch(
+                        querylist_path.clone(),
+                        index,
+                        &build_selection(run_ksize as u8, scaled, &moltype),
+                        threshold,
+                        output_path.iter().map(tag).collect(),
+                        &policies,
+                        columns.clone(),
+                        query_buckets,
+                        min_query_hashes,
+                        skipped_output.as_ref().map(tag),
+                        run_extras,
+                    )
+                }) {
+                    return Ok(command_failed(&*e));
+                }
+            }
+            Ok(0)
+        })
+    } else {
+        if screen {
+            if !moltype_searches.is_empty() {
+                bail!("--screen cannot be combined with --moltype-search.");
+            }
+            if extras.query_groups.is_some() || extras.against_metadata.is_some() {
+                bail!("--screen cannot be combined with --query-groups or --against-metadata.");
+            }
+            if negative_controls.is_some() {
+                bail!("--screen cannot be combined with --negative-controls.");
+            }
+            if collapse_identical {
+                bail!("--screen cannot be combined with --collapse-identical.");
+            }
+            return py.allow_threads(|| {
+                match manysearch::screen(
+                    querylist_path,
+                    siglist_path,
+                    &selection,
+                    threshold,
+                    output_path,
+                    &policies,
+                    columns,
+                    query_buckets,
+                    min_query_hashes,
+                    skipped_output,
+                    output_downsampled,
+                    output_format,
+                    &options,
+                ) {
+                    Ok(_) => Ok(0),
+                    Err(e) => Ok(command_failed(&*e)),
+                }
+            });
+        }
+        py.allow_threads(|| {
+            match manysearch::manysearch(
+                querylist_path,
+                siglist_path,
+                &selection,
+                threshold,
+                output_path,
+                &policies,
+                columns,
+                query_buckets,
+                min_query_hashes,
+                skipped_output,
+                extras,
+                moltype_searches,
+                output_downsampled,
+                negative_controls,
+                collapse_identical,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        })
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    query_filename,
+    siglist_path,
+    threshold_bp,
+    ksize,
+    scaled,
+    moltype,
+    min_query_hashes,
+    output_path_prefetch,
+    output_path_gather,
+    output_path_remaining,
+    columns,
+    skipped_output,
+    rank_by=String::from("overlap"),
+    spike_in=None,
+    spike_in_amount=1.0,
+    output_downsampled=None,
+    ignore_abundance=false,
+    dry_run=false,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    max_matchlist_mem=None,
+    prefetch_threshold_bp=None,
+    checkpoint_every=None,
+    resume=false,
+    output_format=String::from("csv"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_fastgather(
+    py: Python,
+    query_filename: String,
+    siglist_path: String,
+    threshold_bp: usize,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    min_query_hashes: usize,
+    output_path_prefetch: Option<String>,
+    output_path_gather: Option<String>,
+    output_path_remaining: Option<String>,
+    columns: Option<String>,
+    skipped_output: Option<String>,
+    rank_by: String,
+    spike_in: Option<String>,
+    spike_in_amount: f64,
+    output_downsampled: Option<String>,
+    ignore_abundance: bool,
+    dry_run: bool,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    max_matchlist_mem: Option<String>,
+    prefetch_threshold_bp: Option<usize>,
+    checkpoint_every: Option<usize>,
+    resume: bool,
+    output_format: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_format: OutputFormat = match output_format.parse() {
+        Ok(output_format) => output_format,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let rank_by: RankBy = match rank_by.parse() {
+        Ok(rank_by) => rank_by,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let spike_in = match SpikeIn::from_options(spike_in, spike_in_amount) {
+        Ok(spike_in) => spike_in,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let max_matchlist_mem = match parse_max_matchlist_mem(max_matchlist_mem, rank_by) {
+        Ok(max_matchlist_mem) => max_matchlist_mem,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let checkpoint = match parse_checkpointing(checkpoint_every, resume) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let gather_outputs: Vec<String> = output_path_gather.iter().cloned().collect();
+    if let Err(e) = output_format.check_outputs(&gather_outputs, 0) {
+        return Ok(command_failed(&*e));
+    }
+    if let Some(prefetch_threshold_bp) = prefetch_threshold_bp {
+        if prefetch_threshold_bp > threshold_bp {
+            bail!(
+                "--prefetch-threshold-bp ({}) must not be above --threshold-bp ({}).",
+                prefetch_threshold_bp,
+                threshold_bp
+            );
+        }
+    }
+    if ignore_abundance {
+        if spike_in.is_some() {
+            bail!(
+                "--spike-in requires query abundances, and cannot be used with --ignore-abundance."
+            );
+        }
+        if rank_by == RankBy::FUniqueWeighted {
+            bail!("--rank-by f_unique_weighted cannot be used with --ignore-abundance.");
+        }
+    }
+
+    if dry_run {
+        return py.allow_threads(|| {
+            match dryrun::search(
+                "fastgather",
+                (&query_filename, Residency::InMemory),
+                (&siglist_path, Residency::OneAtATime),
+                &selection,
+                &policies,
+                None,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        });
+    }
+
+    let gather = GatherOptions {
+        rank_by,
+        spike_in,
+        ignore_abundance,
+    };
+    py.allow_threads(|| {
+        match fastgather::fastgather(
+            query_filename,
+            siglist_path,
+            threshold_bp,
+            prefetch_threshold_bp,
+            scaled,
+            &selection,
+            output_path_prefetch,
+            output_path_gather,
+            output_path_remaining,
+            &policies,
+            columns,
+            min_query_hashes,
+            skipped_output,
+            &gather,
+            output_downsampled,
+            max_matchlist_mem,
+            checkpoint,
+            None,
+            output_format,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+/// Gather results pulled one at a time, as JSON, from a gather running in
+/// a background thread; see `iter_gather` in `results.py`.
+#[pyclass]
+struct GatherIterator {
+    recv: Mutex<Option<Receiver<BranchwaterGatherResult>>>,
+    thrd: Mutex<Option<JoinHandle<anyhow::Result<()>>>>,
+}
+
+#[pymethods]
+impl GatherIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Wait for the next result; once gather has finished, raise its
+    /// error, if any, or stop.
+    fn __next__(&self, py: Python) -> PyResult<Option<String>> {
+        let next = py.allow_threads(|| {
+            let recv = self.recv.lock().unwrap();
+            recv.as_ref().and_then(|recv| recv.recv().ok())
+        });
+        if let Some(result) = next {
+            return serde_json::to_string(&result)
+                .map(Some)
+                .map_err(|e| to_py_err(py, e.into()));
+        }
+
+        *self.recv.lock().unwrap() = None;
+        let thrd = self.thrd.lock().unwrap().take();
+        if let Some(thrd) = thrd {
+            py.allow_threads(|| {
+                thrd.join()
+                    .unwrap_or_else(|e| Err(anyhow::anyhow!("Thread panicked: {:?}", e)))
+            })
+            .map_err(|e| to_py_err(py, e))?;
+        }
+        Ok(None)
+    }
+}
+
+/// How a command writes its outputs, given to each `do_*` call; see
+/// `OutputOptions`.
+#[pyclass(name = "OutputOptions")]
+#[derive(Clone, Default)]
+struct PyOutputOptions {
+    options: OutputOptions,
+}
+
+#[pymethods]
+impl PyOutputOptions {
+    #[new]
+    #[pyo3(signature = (float_precision=None, anonymize_salt=None, checksums=false))]
+    fn new(
+        float_precision: Option<usize>,
+        anonymize_salt: Option<String>,
+        checksums: bool,
+    ) -> Self {
+        PyOutputOptions {
+            options: OutputOptions {
+                float_precision,
+                anonymizer: anonymize_salt.map(QueryAnonymizer::new),
+                checksums,
+            },
+        }
+    }
+
+    /// The (original, anonymized) query names and filenames written so
+    /// far by calls given these options.
+    fn anonymized_queries(&self) -> Vec<(String, String)> {
+        self.options
+            .anonymizer
+            .as_ref()
+            .map(|anonymizer| anonymizer.names())
+            .unwrap_or_default()
+    }
+}
+
+/// Start gathering the query in 'query_filename' against 'siglist_path',
+/// returning an iterator over the results as they are found.
+#[pyfunction]
+#[pyo3(signature = (
+    query_filename,
+    siglist_path,
+    threshold_bp,
+    ksize,
+    scaled,
+    moltype,
+    rank_by=String::from("overlap"),
+    ignore_abundance=false,
+    hash_seed=42
+))]
+#[allow(clippy::too_many_arguments)]
+fn gather_iter(
+    py: Python,
+    query_filename: String,
+    siglist_path: String,
+    threshold_bp: usize,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    rank_by: String,
+    ignore_abundance: bool,
+    hash_seed: u64,
+) -> PyResult<GatherIterator> {
+    let rank_by: RankBy = rank_by.parse().map_err(|e| to_py_err(py, e))?;
+    if ignore_abundance && rank_by == RankBy::FUniqueWeighted {
+        return Err(to_py_err(
+            py,
+            anyhow::anyhow!("rank_by f_unique_weighted cannot be used with ignore_abundance."),
+        ));
+    }
+    let selection = build_selection(ksize, scaled, &moltype);
+    let policies = Policies {
+        hash_seed,
+        ..Policies::default()
+    };
+
+    let (send, recv) = std::sync::mpsc::sync_channel(rayon::current_num_threads());
+    let thrd = std::thread::spawn(move || {
+        fastgather::fastgather(
+            query_filename,
+            siglist_path,
+            threshold_bp,
+            None,
+            scaled,
+            &selection,
+            None,
+            None,
+            None,
+            &policies,
+            None,
+            0,
+            None,
+            &GatherOptions {
+                rank_by,
+                ignore_abundance,
+                ..GatherOptions::default()
+            },
+            None,
+            None,
+            None,
+            Some(send),
+            OutputFormat::Csv,
+            &OutputOptions::default(),
+        )
+    });
+
+    Ok(GatherIterator {
+        recv: Mutex::new(Some(recv)),
+        thrd: Mutex::new(Some(thrd)),
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    query_filenames,
+    siglist_path,
+    threshold_bp,
+    ksize,
+    scaled,
+    moltype,
+    save_remaining,
+    save_coverage,
+    min_query_hashes,
+    channel_capacity,
+    output_path,
+    columns,
+    skipped_output,
+    query_buckets=0,
+    skip_done=Vec::new(),
+    query_groups=None,
+    query_groups_output=None,
+    against_metadata=None,
+    against_metadata_columns=None,
+    output_dir=None,
+    job_prefix=None,
+    rank_by=String::from("overlap"),
+    spike_in=None,
+    spike_in_amount=1.0,
+    output_downsampled=None,
+    ignore_abundance=false,
+    report_no_matches=false,
+    all_ksizes=false,
+    dry_run=false,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    cache_dir=None,
+    lineages=None,
+    output_biom=None,
+    max_matchlist_mem=None,
+    checkpoint_every=None,
+    resume=false,
+    output_format=String::from("csv"),
+    report=None,
+    schedule=String::from("size"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_fastmultigather(
+    py: Python,
+    query_filenames: String,
+    siglist_path: String,
+    threshold_bp: usize,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    save_remaining: bool,
+    save_coverage: bool,
+    min_query_hashes: usize,
+    channel_capacity: usize,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    skipped_output: Option<String>,
+    query_buckets: usize,
+    skip_done: Vec<String>,
+    query_groups: Option<String>,
+    query_groups_output: Option<String>,
+    against_metadata: Option<String>,
+    against_metadata_columns: Option<String>,
+    output_dir: Option<String>,
+    job_prefix: Option<String>,
+    rank_by: String,
+    spike_in: Option<String>,
+    spike_in_amount: f64,
+    output_downsampled: Option<String>,
+    ignore_abundance: bool,
+    report_no_matches: bool,
+    all_ksizes: bool,
+    dry_run: bool,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    cache_dir: Option<String>,
+    lineages: Option<String>,
+    output_biom: Option<String>,
+    max_matchlist_mem: Option<String>,
+    checkpoint_every: Option<usize>,
+    resume: bool,
+    output_format: String,
+    report: Option<String>,
+    schedule: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let againstfile_path: camino::Utf8PathBuf = siglist_path.clone().into();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_format: OutputFormat = match output_format.parse() {
+        Ok(output_format) => output_format,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let rank_by: RankBy = match rank_by.parse() {
+        Ok(rank_by) => rank_by,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let schedule: Schedule = match schedule.parse() {
+        Ok(schedule) => schedule,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let spike_in = match SpikeIn::from_options(spike_in, spike_in_amount) {
+        Ok(spike_in) => spike_in,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let max_matchlist_mem = match parse_max_matchlist_mem(max_matchlist_mem, rank_by) {
+        Ok(max_matchlist_mem) => max_matchlist_mem,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    // without --checkpoint-every, --resume skips the queries listed in the
+    // output index of a previous run, and starts the others over.
+    let checkpoint =
+        match parse_checkpointing(checkpoint_every, resume && checkpoint_every.is_some()) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => return Ok(command_failed(&*e)),
+        };
+    if ignore_abundance {
+        if spike_in.is_some() {
+            bail!(
+                "--spike-in requires query abundances, and cannot be used with --ignore-abundance."
+            );
+        }
+        if rank_by == RankBy::FUniqueWeighted {
+            bail!("--rank-by f_unique_weighted cannot be used with --ignore-abundance.");
+        }
+    }
+
+    if let Some(path) = skip_done.iter().find(|path| output_path.contains(path)) {
+        eprintln!("Error: '{path}' is given as both --skip-done and an output; results would be overwritten.");
+        return Ok(1);
+    }
+    if report.is_some() && (resume || !skip_done.is_empty()) {
+        bail!("--report only summarizes the queries gathered in this run, and cannot be used with --resume or --skip-done.");
+    }
+    // the run parameters listed in the report
+    let report_params = |run_ksize: u32| -> Vec<(String, String)> {
+        vec![
+            ("query".into(), query_filenames.clone()),
+            ("against".into(), siglist_path.clone()),
+            ("ksize".into(), run_ksize.to_string()),
+            ("scaled".into(), scaled.to_string()),
+            ("moltype".into(), moltype.clone()),
+            ("threshold_bp".into(), threshold_bp.to_string()),
+            ("rank_by".into(), rank_by.to_string()),
+            ("ignore_abundance".into(), ignore_abundance.to_string()),
+        ]
+    };
+
+    let extras = match parse_writer_extras(
+        query_groups.clone(),
+        query_groups_output.clone(),
+        against_metadata.clone(),
+        against_metadata_columns.clone(),
+        output_format,
+        "fastmultigather",
+        &options,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    // a directory of ksize-specific indexes is searched one index at a time
+    let index_runs = match resolve_index_set(&againstfile_path, &selection, all_ksizes) {
+        Ok(runs) => runs,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    if dry_run {
+        let targets = search_targets(&index_runs, &siglist_path, &selection, scaled, &moltype);
+        return py.allow_threads(|| {
+            for (selection, path) in targets.iter() {
+                if let Err(e) = dryrun::search(
+                    "fastmultigather",
+                    (&query_filenames, Residency::OneAtATime),
+                    (path, Residency::InMemory),
+                    selection,
+                    &policies,
+                    None,
+                ) {
+                    return Ok(command_failed(&*e));
+                }
+            }
+            Ok(0)
+        });
+    }
+
+    // if a siglist path is a revindex, run mastiff_manygather. If not, run multigather
+    if index_runs.is_some() || find_revindex_database(&againstfile_path).is_some() {
+        if save_remaining {
+            bail!("saving remaining query sketches is not supported when searching a rocksdb.");
+        }
+        if save_coverage {
+            bail!("saving query hash coverage is not supported when searching a rocksdb.");
+        }
+        if output_dir.is_some() || job_prefix.is_some() {
+            bail!("--output-dir is not supported when searching a rocksdb; use -o instead.");
+        }
+        if cache_dir.is_some() {
+            bail!("--cache-dir is not supported when searching a rocksdb, which is already indexed on disk.");
+        }
+        if lineages.is_some() {
+            bail!("--lineages is not supported when searching a rocksdb.");
+        }
+        if max_matchlist_mem.is_some() {
+            bail!("--max-matchlist-mem is not supported when searching a rocksdb.");
+        }
+        if checkpoint_every.is_some() {
+            bail!("--checkpoint-every is not supported when searching a rocksdb.");
+        }
+        if resume {
+            bail!("--resume is not supported when searching a rocksdb; use --skip-done with the previous output instead.");
+        }
+        if let Err(e) = output_format.check_outputs(&output_path, query_buckets) {
+            return Ok(command_failed(&*e));
+        }
+        if rank_by != RankBy::Overlap {
+            bail!(
+                "--rank-by {} is not supported when searching a rocksdb.",
+                rank_by
+            );
+        }
+        if output_downsampled.is_some() {
+            bail!("--output-downsampled is not supported when searching a rocksdb.");
+        }
+        if spike_in.is_some() {
+            bail!("--spike-in is not supported when searching a rocksdb.");
+        }
+        let runs = index_runs.unwrap_or_else(|| vec![(ksize as u32, againstfile_path)]);
+        let mut extras = Some(extras);
+        py.allow_threads(|| {
+            for (run_ksize, index) in runs {
+                // with --all-ksizes, each ksize gets its own tagged outputs
+                let tag = |path: &String| {
+                    if all_ksizes {
+                        output_for_ksize(path, run_ksize)
+                    } else {
+                        path.clone()
+                    }
+                };
+                let run_extras = match extras.take() {
+                    Some(extras) if !all_ksizes => Ok(extras),
+                    _ => parse_writer_extras(
+                        query_groups.clone(),
+                        query_groups_output.as_ref().map(tag),
+                        against_metadata.clone(),
+                        against_metadata_columns.clone(),
+                        output_format,
+                        "fastmultigather",
+                        &options,
+                    ),
+                };
+                let run_extras = run_extras.and_then(|mut run_extras| {
+                    run_extras.biom = output_biom
+                        .as_ref()
+                        .map(|path| BiomTable::new(tag(path), options.clone()))
+                        .transpose()?;
+                    run_extras.report = report.as_ref().map(|path| {
+                        RunReport::new(tag(path), report_params(run_ksize), options.clone())
+                    });
+                    Ok(run_extras)
+                });
+                if let Err(e) = run_extras.and_then(|run_extras| {
+                    mastiff_manygather::mastiff_manygather(
+                        query_filenames.clone(),
+                        index,
+                        &build_selection(run_ksize as u8, scaled, &moltype),
+                        threshold_bp,
+                        output_path.iter().map(tag).collect(),
+                        &policies,
+                        columns.clone(),
+                        query_buckets,
+                        min_query_hashes,
+                        skipped_output.as_ref().map(tag),
+                        channel_capacity,
+                        skip_done.iter().map(tag).collect(),
+                        run_extras,
+                        ignore_abundance,
+                        report_no_matches,
+                        schedule,
+                    )
+                }) {
+                    return Ok(command_failed(&*e));
+                }
+            }
+            Ok(0)
+        })
+    } else {
+        if !output_path.is_empty() {
+            bail!("output path specified, but not running fastmultigather against a rocksdb. See issue #239");
+        }
+        if extras.query_groups.is_some() {
+            bail!("summarizing query groups is only supported when searching a rocksdb.");
+        }
+        if extras.against_metadata.is_some() {
+            bail!("joining against metadata is only supported when searching a rocksdb.");
+        }
+        if report_no_matches {
+            bail!("--report-no-matches is only supported when searching a rocksdb.");
+        }
+        if output_biom.is_some() {
+            bail!("--output-biom is only supported when searching a rocksdb.");
+        }
+        if checkpoint.is_some_and(|c| c.resume) && output_dir.is_some() {
+            bail!("--resume with --checkpoint-every cannot be used with --output-dir, which never overwrites outputs.");
+        }
+        let report =
+            report.map(|path| RunReport::new(path, report_params(ksize as u32), options.clone()));
+        let gather = GatherOptions {
+            rank_by,
+            spike_in,
+            ignore_abundance,
+        };
+        let outputs = fastmultigather::MultiGatherOutputs {
+            save_remaining,
+            save_coverage,
+            columns,
+            output_dir,
+            job_prefix,
+            lineages,
+            skipped_output,
+            downsampled_output: output_downsampled,
+            format: output_format,
+            report,
+            checkpoint,
+        };
+        py.allow_threads(|| {
+            match fastmultigather::fastmultigather(
+                query_filenames,
+                siglist_path,
+                threshold_bp,
+                scaled,
+                &selection,
+                &policies,
+                min_query_hashes,
+                skip_done,
+                resume,
+                cache_dir,
+                max_matchlist_mem,
+                schedule,
+                &gather,
+                outputs,
+                &options,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        })
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    samples_path,
+    index_path,
+    threshold,
+    ksize,
+    scaled,
+    output_path,
+    columns,
+    query_buckets=0,
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_fastmanysearch(
+    py: Python,
+    samples_path: String,
+    index_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    query_buckets: usize,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, "dna");
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    py.allow_threads(|| {
+        match fastmanysearch::fastmanysearch(
+            samples_path,
+            index_path.into(),
+            &selection,
+            threshold,
+            output_path,
+            columns,
+            query_buckets,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+fn set_global_thread_pool(num_threads: usize) -> PyResult<usize> {
+    if std::panic::catch_unwind(|| {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()
+    })
+    .is_ok()
+    {
+        Ok(rayon::current_num_threads())
+    } else {
+        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "Could not set the number of threads. Global thread pool might already be initialized.",
+        ))
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    siglist,
+    ksize,
+    scaled,
+    moltype,
+    output,
+    colors,
+    dry_run=false,
+    allow_failed_paths=false,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize")
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_index(
+    py: Python,
+    siglist: String,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    output: String,
+    colors: bool,
+    dry_run: bool,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+) -> anyhow::Result<u8> {
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    if dry_run {
+        return py.allow_threads(|| match dryrun::index(&siglist, &selection, &policies) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        });
+    }
+
+    py.allow_threads(
+        || match index::index(siglist, &selection, output, colors, &policies) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        },
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    sig_iter,
+    ksize,
+    scaled,
+    moltype,
+    output,
+    sketches_output,
+    colors,
+    allow_failed_paths=false,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize")
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_index_from_iter(
+    sig_iter: &Bound<'_, PyAny>,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    output: String,
+    sketches_output: String,
+    colors: bool,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+) -> anyhow::Result<u8> {
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let sigs = sig_iter.iter()?.map(|item| Ok(item?.extract::<String>()?));
+    match index::index_from_iter(sigs, &selection, output, sketches_output, colors, &policies) {
+        Ok(_) => Ok(0),
+        Err(e) => Ok(command_failed(&*e)),
+    }
+}
+
+#[pyfunction]
+fn set_progress(enabled: bool) {
+    progress::set_progress(enabled);
+}
+
+/// The fields of each result struct, as (name, [(field, Python type)]).
+#[pyfunction]
+fn result_schemas() -> Vec<(String, Vec<(String, String)>)> {
+    schema::result_schemas()
+}
+
+#[pyfunction]
+#[pyo3(signature = (index_path=None))]
+fn clear_index_cache(index_path: Option<String>) {
+    utils::clear_revindex_cache(index_path);
+}
+
+#[pyfunction]
+fn reload_index(py: Python, index_path: String) -> PyResult<()> {
+    let index: PathBuf = index_path.into();
+    py.allow_threads(|| utils::reload_revindex(&index))
+        .map_err(|e| to_py_err(py, e))
+}
+
+#[pyfunction]
+fn do_index_migrate(py: Python, index: String) -> anyhow::Result<u8> {
+    let idx: PathBuf = index.into();
+    py.allow_threads(|| match index::index_migrate(idx) {
+        Ok(_) => Ok(0),
+        Err(e) => Ok(command_failed(&*e)),
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (index, quick, deep=false, output=None))]
+fn do_check(
+    py: Python,
+    index: String,
+    quick: bool,
+    deep: bool,
+    output: Option<String>,
+) -> anyhow::Result<u8> {
+    let idx: PathBuf = index.into();
+    py.allow_threads(|| match check::check(idx, quick, deep, output) {
+        Ok(_) => Ok(0),
+        Err(e) => Ok(command_failed(&*e)),
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    querylist_path,
+    siglist_path,
+    threshold,
+    ksize,
+    scaled,
+    moltype,
+    estimate_ani,
+    num,
+    min_query_hashes,
+    output_path,
+    columns,
+    skipped_output,
+    query_buckets=0,
+    min_ani=None,
+    output_downsampled=None,
+    dry_run=false,
+    max_mem=None,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    compact_mem=false,
+    output_format=String::from("csv"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_multisearch(
+    py: Python,
+    querylist_path: String,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    estimate_ani: bool,
+    num: u32,
+    min_query_hashes: usize,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    skipped_output: Option<String>,
+    query_buckets: usize,
+    min_ani: Option<f64>,
+    output_downsampled: Option<String>,
+    dry_run: bool,
+    max_mem: Option<String>,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    compact_mem: bool,
+    output_format: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_format: OutputFormat = match output_format.parse() {
+        Ok(output_format) => output_format,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    if let Err(e) = output_format.check_outputs(&output_path, query_buckets) {
+        return Ok(command_failed(&*e));
+    }
+    let max_mem = match max_mem
+        .as_deref()
+        .map(dryrun::parse_memory_size)
+        .transpose()
+    {
+        Ok(max_mem) => max_mem,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+
+    if dry_run {
+        let selection = if num > 0 {
+            build_num_selection(ksize, num, &moltype)
+        } else {
+            build_selection(ksize, scaled, &moltype)
+        };
+        let against_residency = if compact_mem && num == 0 {
+            Residency::Compact
+        } else {
+            Residency::InMemory
+        };
+        return py.allow_threads(|| {
+            match dryrun::search(
+                "multisearch",
+                (&querylist_path, Residency::InMemory),
+                (&siglist_path, against_residency),
+                &selection,
+                &policies,
+                max_mem,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        });
+    }
+
+    // num sketches only support jaccard, so run the jaccard-only search
+    if num > 0 {
+        if estimate_ani {
+            eprintln!("WARNING: cannot estimate ANI from num sketches; ignoring --ani.");
+        }
+        if min_ani.is_some() {
+            eprintln!("WARNING: cannot estimate ANI from num sketches; ignoring --min-ani.");
+        }
+        if output_downsampled.is_some() {
+            eprintln!(
+                "WARNING: num sketches are never downsampled; ignoring --output-downsampled."
+            );
+        }
+        if compact_mem {
+            eprintln!("WARNING: --compact-mem is not supported for num sketches; ignoring.");
+        }
+        let selection = build_num_selection(ksize, num, &moltype);
+        py.allow_threads(|| {
+            match multisearch::multisearch_num(
+                querylist_path,
+                siglist_path,
+                threshold,
+                &selection,
+                &policies,
+                output_path,
+                columns,
+                query_buckets,
+                min_query_hashes,
+                skipped_output,
+                max_mem,
+                output_format,
+                &options,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        })
+    } else {
+        let selection = build_selection(ksize, scaled, &moltype);
+        py.allow_threads(|| {
+            match multisearch::multisearch(
+                querylist_path,
+                siglist_path,
+                threshold,
+                &selection,
+                &policies,
+                estimate_ani,
+                min_ani,
+                output_path,
+                columns,
+                query_buckets,
+                min_query_hashes,
+                skipped_output,
+                output_downsampled,
+                max_mem,
+                compact_mem,
+                output_format,
+                &options,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        })
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    siglist_path,
+    threshold,
+    ksize,
+    scaled,
+    moltype,
+    estimate_ani,
+    write_all,
+    output_path,
+    columns,
+    query_buckets=0,
+    against_path=None,
+    output_downsampled=None,
+    dry_run=false,
+    max_mem=None,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    lsh_bands=0,
+    lsh_rows=0,
+    output_format=String::from("csv"),
+    min_ani=None,
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_pairwise(
+    py: Python,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    estimate_ani: bool,
+    write_all: bool,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    query_buckets: usize,
+    against_path: Option<String>,
+    output_downsampled: Option<String>,
+    dry_run: bool,
+    max_mem: Option<String>,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    lsh_bands: usize,
+    lsh_rows: usize,
+    output_format: String,
+    min_ani: Option<f64>,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let output_format: OutputFormat = match output_format.parse() {
+        Ok(output_format) => output_format,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    if let Err(e) = output_format.check_outputs(&output_path, query_buckets) {
+        return Ok(command_failed(&*e));
+    }
+    let max_mem = match max_mem
+        .as_deref()
+        .map(dryrun::parse_memory_size)
+        .transpose()
+    {
+        Ok(max_mem) => max_mem,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let lsh = match pairwise::LshBands::from_options(lsh_bands, lsh_rows) {
+        Ok(lsh) => lsh,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    if dry_run {
+        return py.allow_threads(|| {
+            match dryrun::pairwise(
+                &siglist_path,
+                against_path.as_ref(),
+                &selection,
+                &policies,
+                max_mem,
+            ) {
+                Ok(_) => Ok(0),
+                Err(e) => Ok(command_failed(&*e)),
+            }
+        });
+    }
+
+    py.allow_threads(|| {
+        match pairwise::pairwise(
+            siglist_path,
+            against_path,
+            threshold,
+            &selection,
+            &policies,
+            estimate_ani,
+            min_ani,
+            write_all,
+            output_path,
+            columns,
+            query_buckets,
+            output_downsampled,
+            max_mem,
+            lsh,
+            output_format,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    filelist,
+    param_str,
+    output,
+    singleton,
+    force,
+    sanitize_names,
+    max_name_length,
+    channel_capacity,
+    modified_names_output,
+    dry_run=false
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_manysketch(
+    py: Python,
+    filelist: String,
+    param_str: String,
+    output: String,
+    singleton: bool,
+    force: bool,
+    sanitize_names: bool,
+    max_name_length: usize,
+    channel_capacity: usize,
+    modified_names_output: Option<String>,
+    dry_run: bool,
+) -> anyhow::Result<u8> {
+    if dry_run {
+        return py.allow_threads(|| match dryrun::manysketch(filelist, param_str, force) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        });
+    }
+
+    let name_sanitizer = if sanitize_names {
+        Some(utils::NameSanitizer::new(max_name_length))
+    } else {
+        None
+    };
+    py.allow_threads(|| {
+        match manysketch::manysketch(
+            filelist,
+            param_str,
+            output,
+            singleton,
+            force,
+            channel_capacity,
+            name_sanitizer,
+            modified_names_output,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+fn do_sketch_records(
+    py: Python,
+    records: Vec<(String, Vec<u8>, String)>,
+    param_str: String,
+) -> PyResult<Vec<String>> {
+    py.allow_threads(|| -> anyhow::Result<Vec<String>> {
+        manysketch::sketch_records(records, param_str)?
+            .iter()
+            .map(|sig| Ok(serde_json::to_string(&vec![sig])?))
+            .collect()
+    })
+    .map_err(|e| to_py_err(py, e))
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    pairwise_csv,
+    output_clusters,
+    similarity_column,
+    similarity_threshold,
+    cluster_sizes,
+    metric=String::from("similarity"),
+    output_graphml=None,
+    output_edges=None,
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_cluster(
+    py: Python,
+    pairwise_csv: String,
+    output_clusters: String,
+    similarity_column: String,
+    similarity_threshold: f64,
+    cluster_sizes: Option<String>,
+    metric: String,
+    output_graphml: Option<String>,
+    output_edges: Option<String>,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let metric: cluster::Metric = match metric.parse() {
+        Ok(metric) => metric,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    py.allow_threads(|| {
+        match cluster::cluster(
+            pairwise_csv,
+            output_clusters,
+            similarity_column,
+            metric,
+            similarity_threshold,
+            cluster_sizes,
+            output_graphml,
+            output_edges,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    siglist_path,
+    ksize,
+    scaled,
+    moltype,
+    output_path,
+    columns,
+    query_buckets=0,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_betadiversity(
+    py: Python,
+    siglist_path: String,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    query_buckets: usize,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    py.allow_threads(|| {
+        match betadiversity::betadiversity(
+            siglist_path,
+            &selection,
+            &policies,
+            output_path,
+            columns,
+            query_buckets,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    siglist_path,
+    threshold,
+    ksize,
+    scaled,
+    moltype,
+    output_path,
+    columns,
+    query_buckets=0,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_weighted_containment(
+    py: Python,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    query_buckets: usize,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    py.allow_threads(|| {
+        match weighted_containment::weighted_containment(
+            siglist_path,
+            threshold,
+            &selection,
+            &policies,
+            output_path,
+            columns,
+            query_buckets,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    querylist_path,
+    siglist_path,
+    threshold,
+    ksize,
+    scaled,
+    moltype,
+    fractions,
+    replicates,
+    seed,
+    output_path,
+    columns,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize"),
+    output_options=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_rarefy(
+    py: Python,
+    querylist_path: String,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    fractions: Vec<f64>,
+    replicates: usize,
+    seed: u64,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    py.allow_threads(|| {
+        match rarefy::rarefy(
+            querylist_path,
+            siglist_path,
+            &selection,
+            threshold,
+            fractions,
+            replicates,
+            seed,
+            output_path,
+            &policies,
+            columns,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    timepoints_path,
+    output_path,
+    columns=None,
+    fill_missing=true,
+    output_options=None
+))]
+fn do_timeseries(
+    py: Python,
+    timepoints_path: String,
+    output_path: Option<OutputPaths>,
+    columns: Option<String>,
+    fill_missing: bool,
+    output_options: Option<PyOutputOptions>,
+) -> anyhow::Result<u8> {
+    let options = output_options.map(|o| o.options).unwrap_or_default();
+    let columns = parse_column_list(columns);
+    let output_path = match parse_output_paths(output_path) {
+        Ok(paths) => paths,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    py.allow_threads(|| {
+        match timeseries::timeseries(
+            timepoints_path,
+            output_path,
+            columns,
+            fill_missing,
+            &options,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    spec_path,
+    ksize,
+    scaled,
+    moltype,
+    seed,
+    output,
+    truth_output=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_simulate(
+    py: Python,
+    spec_path: String,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    seed: u64,
+    output: String,
+    truth_output: Option<String>,
+) -> anyhow::Result<u8> {
+    py.allow_threads(|| {
+        match simulate::simulate(
+            spec_path,
+            ksize,
+            scaled,
+            moltype,
+            seed,
+            output,
+            truth_output,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    collection_a,
+    collection_b,
+    operation,
+    ksize,
+    scaled,
+    moltype,
+    merge,
+    name,
+    output,
+    allow_failed_paths=true,
+    allow_empty_collection=false,
+    dedupe_md5=false,
+    strict_scaled=false,
+    validate_names=false,
+    hash_seed=42,
+    mixed_scaled=String::from("harmonize")
+))]
+#[allow(clippy::too_many_arguments)]
+fn do_sig_setops(
+    py: Python,
+    collection_a: String,
+    collection_b: String,
+    operation: String,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    merge: bool,
+    name: Option<String>,
+    output: String,
+    allow_failed_paths: bool,
+    allow_empty_collection: bool,
+    dedupe_md5: bool,
+    strict_scaled: bool,
+    validate_names: bool,
+    hash_seed: u64,
+    mixed_scaled: String,
+) -> anyhow::Result<u8> {
+    let selection = build_selection(ksize, scaled, &moltype);
+    let mixed_scaled: MixedScaled = match mixed_scaled.parse() {
+        Ok(mixed_scaled) => mixed_scaled,
+        Err(e) => return Ok(command_failed(&*e)),
+    };
+    let policies = Policies {
+        allow_failed_paths,
+        allow_empty_collection,
+        dedupe_md5,
+        strict_scaled,
+        validate_names,
+        hash_seed,
+        mixed_scaled,
+    };
+    py.allow_threads(|| {
+        match setops::setops(
+            collection_a,
+            collection_b,
+            operation,
+            &selection,
+            &policies,
+            merge,
+            name,
+            output,
+        ) {
+            Ok(_) => Ok(0),
+            Err(e) => Ok(command_failed(&*e)),
+        }
+    })
+}
+
+#[pymodule]
+fn sourmash_plugin_branchwater(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("BranchwaterError", py.get_type_bound::<BranchwaterError>())?;
+    let error_codes = pyo3::types::PyDict::new_bound(py);
+    for code in ErrorCode::ALL {
+        error_codes.set_item(code.name(), code.exit_code())?;
+    }
+    m.add("ERROR_CODES", error_codes)?;
+    m.add_function(wrap_pyfunction!(do_manysearch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastgather, m)?)?;
+    m.add_function(wrap_pyfunction!(gather_iter, m)?)?;
+    m.add_class::<GatherIterator>()?;
+    m.add_class::<PyOutputOptions>()?;
+    m.add_function(wrap_pyfunction!(do_fastmultigather, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastmanysearch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_index, m)?)?;
+    m.add_function(wrap_pyfunction!(do_index_from_iter, m)?)?;
+    m.add_function(wrap_pyfunction!(do_check, m)?)?;
+    m.add_function(wrap_pyfunction!(do_index_migrate, m)?)?;
+    m.add_function(wrap_pyfunction!(do_manysketch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_sketch_records, m)?)?;
+    m.add_function(wrap_pyfunction!(set_global_thread_pool, m)?)?;
+    m.add_function(wrap_pyfunction!(set_progress, m)?)?;
+    m.add_function(wrap_pyfunction!(result_schemas, m)?)?;
+    m.add_function(wrap_pyfunction!(clear_index_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(reload_index, m)?)?;
+    m.add_function(wrap_pyfunction!(do_multisearch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_pairwise, m)?)?;
+    m.add_function(wrap_pyfunction!(do_cluster, m)?)?;
+    m.add_function(wrap_pyfunction!(do_sig_setops, m)?)?;
+    m.add_function(wrap_pyfunction!(do_betadiversity, m)?)?;
+    m.add_function(wrap_pyfunction!(do_weighted_containment, m)?)?;
+    m.add_function(wrap_pyfunction!(do_rarefy, m)?)?;
+    m.add_function(wrap_pyfunction!(do_timeseries, m)?)?;
+    m.add_function(wrap_pyfunction!(do_simulate, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+/// biom: a matches x queries table of gather results, written in BIOM
+/// format for QIIME or phyloseq (`fastmultigather --output-biom`).
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::schema::{field_value, serialize_fields};
+use crate::utils::OutputOptions;
+
+/// The result column used as the value of each table entry.
+const VALUE_COLUMN: &str = "f_unique_weighted";
+
+/// Row or column ids of a BIOM table, in the order first seen.
+#[derive(Default)]
+struct Ids {
+    ids: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Ids {
+    fn get_or_insert(&mut self, id: &str) -> usize {
+        if let Some(&idx) = self.index.get(id) {
+            return idx;
+        }
+        self.ids.push(id.to_string());
+        self.index.insert(id.to_string(), self.ids.len() - 1);
+        self.ids.len() - 1
+    }
+
+    fn to_json(&self) -> Vec<serde_json::Value> {
+        self.ids
+            .iter()
+            .map(|id| serde_json::json!({ "id": id, "metadata": null }))
+            .collect()
+    }
+}
+
+/// A sparse table of the weighted fraction of each query (sample) found
+/// by each match (feature), built as results stream through the writer
+/// thread.
+pub struct BiomTable {
+    output: String,
+    options: OutputOptions,
+    matches: Ids,
+    queries: Ids,
+    data: BTreeMap<(usize, usize), f64>,
+}
+
+impl BiomTable {
+    /// Start a table to be written to 'output', which must be a JSON BIOM
+    /// file; HDF5 BIOM is not supported. Values are those of the results
+    /// as written with 'options'.
+    pub fn new(output: String, options: OutputOptions) -> Result<Self> {
+        if output.ends_with(".h5") || output.ends_with(".hdf5") {
+            bail!(
+                "HDF5 BIOM output is not supported; use a JSON BIOM file, e.g. 'table.biom', instead of '{}'",
+                output
+            );
+        }
+        Ok(BiomTable {
+            output,
+            options,
+            matches: Ids::default(),
+            queries: Ids::default(),
+            data: BTreeMap::new(),
+        })
+    }
+
+    pub fn add<T: Serialize>(&mut self, item: &T) -> Result<()> {
+        let mut fields = serialize_fields(item, self.options.float_precision)?;
+        self.options.anonymize_fields(&mut fields);
+        let column = |name: &str| field_value(&fields, name);
+
+        let query_name = column("query_name")
+            .ok_or_else(|| anyhow!("results have no query_name column for the BIOM table"))?;
+        let query_idx = self.queries.get_or_insert(query_name);
+        let match_name = column("match_name").unwrap_or_default();
+        // placeholder rows for queries with no matches only add the sample
+        if match_name.is_empty() {
+            return Ok(());
+        }
+        let value: f64 = column(VALUE_COLUMN)
+            .ok_or_else(|| anyhow!("results have no {} column for the BIOM table", VALUE_COLUMN))?
+            .parse()?;
+
+        let match_idx = self.matches.get_or_insert(match_name);
+        *self.data.entry((match_idx, query_idx)).or_insert(0.0) += value;
+        Ok(())
+    }
+
+    pub fn write(self) -> Result<()> {
+        let data: Vec<(usize, usize, f64)> = self
+            .data
+            .into_iter()
+            .filter(|(_, value)| *value != 0.0)
+            .map(|((row, col), value)| (row, col, value))
+            .collect();
+        let table = serde_json::json!({
+            "id": null,
+            "format": "Biological Observation Matrix 1.0.0",
+            "format_url": "http://biom-format.org",
+            "type": "OTU table",
+            "generated_by": format!("sourmash_plugin_branchwater {}", env!("CARGO_PKG_VERSION")),
+            "date": utc_timestamp(),
+            "rows": self.matches.to_json(),
+            "columns": self.queries.to_json(),
+            "matrix_type": "sparse",
+            "matrix_element_type": "float",
+            "shape": [self.matches.ids.len(), self.queries.ids.len()],
+            "data": data,
+        });
+
+        let file = File::create(&self.output)
+            .with_context(|| format!("Failed to create BIOM file '{}'", self.output))?;
+        let mut w = BufWriter::new(file);
+        serde_json::to_writer(&mut w, &table)?;
+        w.flush()?;
+        eprintln!(
+            "Wrote a BIOM table of {} matches x {} queries to '{}'",
+            self.matches.ids.len(),
+            self.queries.ids.len(),
+            self.output
+        );
+        Ok(())
+    }
+}
+
+/// The current time as an ISO 8601 UTC timestamp, e.g.
+/// '2024-06-01T12:00:00Z'.
+pub(crate) fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // civil date from days since 1970-01-01, after Howard Hinnant
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
@@ -1,18 +1,135 @@
-use crate::utils::is_revindex_database;
+use crate::utils::{open_revindex, resolve_revindex_database};
 
+use csv::Writer;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::Mutex;
+
+use sourmash::collection::Collection;
 use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::manifest::Record;
+
+/// A dataset that failed the deep check.
+#[derive(Serialize)]
+struct DatasetProblem {
+    internal_location: String,
+    name: String,
+    manifest_md5: String,
+    loaded_md5: String,
+    problem: String,
+}
+
+/// Load the collection (manifest + storage) that a RevIndex refers to.
+pub fn collection_from_revindex(index: &camino::Utf8PathBuf) -> anyhow::Result<Collection> {
+    let db = RevIndex::open(index, true, None)?;
+    let collection = db.collection();
+    Ok(Collection::new(
+        collection.manifest().clone(),
+        collection.storage().clone(),
+    ))
+}
 
-pub fn check(index: camino::Utf8PathBuf, quick: bool) -> Result<(), Box<dyn std::error::Error>> {
-    if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
+/// Load one dataset from storage and compare its md5 to the manifest.
+fn check_dataset(collection: &Collection, record: &Record) -> Option<DatasetProblem> {
+    let problem = |loaded_md5: String, problem: String| DatasetProblem {
+        internal_location: record.internal_location().to_string(),
+        name: record.name().clone(),
+        manifest_md5: record.md5().clone(),
+        loaded_md5,
+        problem,
+    };
+
+    // loading asserts on malformed storage, so treat panics as load failures.
+    let loaded = std::panic::catch_unwind(|| collection.sig_from_record(record));
+    match loaded {
+        Ok(Ok(sig)) => {
+            let md5 = sig.md5sum();
+            if &md5 != record.md5() {
+                Some(problem(md5, "md5_mismatch".into()))
+            } else {
+                None
+            }
+        }
+        Ok(Err(e)) => Some(problem("".into(), format!("load_failed: {}", e))),
+        Err(_) => Some(problem(
+            "".into(),
+            "load_failed: panic while loading".into(),
+        )),
+    }
+}
+
+/// Load every dataset in the index from storage and verify its md5,
+/// writing any problems to `output` as CSV if given.
+fn deep_check(index: &camino::Utf8PathBuf, output: Option<String>) -> anyhow::Result<()> {
+    let collection = collection_from_revindex(index)?;
+    eprintln!(
+        "Deep check: verifying {} datasets against storage",
+        collection.len()
+    );
+
+    let problems = Mutex::new(vec![]);
+    collection.par_iter().for_each(|(_idx, record)| {
+        if let Some(problem) = check_dataset(&collection, record) {
+            eprintln!(
+                "WARNING: dataset '{}' ({}): {}",
+                problem.name, problem.internal_location, problem.problem
+            );
+            problems.lock().unwrap().push(problem);
+        }
+    });
+    let mut problems = problems.into_inner().unwrap();
+    problems.sort_by(|a, b| a.internal_location.cmp(&b.internal_location));
+
+    if let Some(output_path) = output {
+        let mut writer = Writer::from_path(&output_path)?;
+        if problems.is_empty() {
+            writer.write_record([
+                "internal_location",
+                "name",
+                "manifest_md5",
+                "loaded_md5",
+                "problem",
+            ])?;
+        }
+        for problem in problems.iter() {
+            writer.serialize(problem)?;
+        }
+        writer.flush()?;
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "{} of {} datasets failed the deep check.",
+            problems.len(),
+            collection.len()
+        );
+    }
+    eprintln!("Deep check: all {} datasets ok", collection.len());
+    Ok(())
+}
+
+pub fn check(
+    index: camino::Utf8PathBuf,
+    quick: bool,
+    deep: bool,
+    output: Option<String>,
+) -> anyhow::Result<()> {
+    let index = resolve_revindex_database(&index)?;
+    if output.is_some() && !deep {
+        bail!("an output CSV is only written by the deep check (--deep)");
     }
 
     println!("Opening DB");
-    let db = RevIndex::open(index, true, None)?;
+    let db = open_revindex(&index)?;
 
     println!("Starting check");
     db.check(quick);
 
+    if deep {
+        println!("Starting deep check");
+        deep_check(&index, output)?;
+    }
+
     println!("Finished check");
     Ok(())
 }
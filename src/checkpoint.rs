@@ -0,0 +1,138 @@
+/// checkpoint: record the completed ranks of a gather (`--checkpoint-every`),
+/// so that a run that dies part way through a large query can be resumed
+/// from the last checkpoint (`--resume`) rather than started over.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use crate::utils::RankBy;
+
+/// How often to checkpoint gather, and whether to resume from an existing
+/// checkpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpointing {
+    /// flush the gather output and write a checkpoint every this many
+    /// results.
+    pub every: usize,
+    /// resume from the checkpoint of a previous run, if there is one.
+    pub resume: bool,
+}
+
+/// A match found at one gather rank. The rank's hashes are recalculated
+/// from the match sketch when resuming.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckpointMatch {
+    pub match_name: String,
+    pub match_md5: String,
+    pub match_location: String,
+    pub f_unique_weighted: f64,
+    pub sum_weighted_found: usize,
+}
+
+/// The completed ranks of gather for one query, saved next to the gather
+/// output as '<output>.checkpoint.json'.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GatherCheckpoint {
+    pub query_md5: String,
+    pub scaled: u64,
+    pub threshold_hashes: u64,
+    pub rank_by: String,
+    /// the length of the gather output when the checkpoint was written;
+    /// anything after this is discarded when resuming.
+    pub output_bytes: u64,
+    /// gather, and all of its outputs, finished for this query.
+    pub complete: bool,
+    pub matches: Vec<CheckpointMatch>,
+}
+
+impl GatherCheckpoint {
+    pub fn new(query_md5: &str, scaled: u64, threshold_hashes: u64, rank_by: RankBy) -> Self {
+        GatherCheckpoint {
+            query_md5: query_md5.to_string(),
+            scaled,
+            threshold_hashes,
+            rank_by: rank_by.to_string(),
+            output_bytes: 0,
+            complete: false,
+            matches: vec![],
+        }
+    }
+
+    /// The checkpoint path for 'gather_output'.
+    pub fn path(gather_output: &str) -> String {
+        format!("{}.checkpoint.json", gather_output)
+    }
+
+    /// Load the checkpoint for 'gather_output', or None if there isn't one.
+    pub fn load(gather_output: &str) -> Result<Option<Self>> {
+        let path = Self::path(gather_output);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open '{}'", path)),
+        };
+        let checkpoint = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to read gather checkpoint '{}'", path))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Whether gather has already finished for 'gather_output'.
+    pub fn is_complete(gather_output: &str) -> bool {
+        matches!(Self::load(gather_output), Ok(Some(c)) if c.complete)
+    }
+
+    /// Save the checkpoint for 'gather_output', via a temporary file that
+    /// is renamed into place so that a crash never leaves a partial
+    /// checkpoint.
+    pub fn save(&self, gather_output: &str) -> Result<()> {
+        let path = Self::path(gather_output);
+        let tmp = format!("{}.tmp", path);
+        {
+            let mut writer = BufWriter::new(
+                File::create(&tmp).with_context(|| format!("Failed to create '{}'", tmp))?,
+            );
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+        }
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write gather checkpoint '{}'", path))?;
+        Ok(())
+    }
+
+    /// Check that this checkpoint was written by a gather of the same
+    /// query, with the same parameters.
+    pub fn check(
+        &self,
+        gather_output: &str,
+        query_md5: &str,
+        scaled: u64,
+        threshold_hashes: u64,
+        rank_by: RankBy,
+    ) -> Result<()> {
+        let mut differences = vec![];
+        if self.query_md5 != query_md5 {
+            differences.push(format!("query md5 {} != {}", self.query_md5, query_md5));
+        }
+        if self.scaled != scaled {
+            differences.push(format!("scaled {} != {}", self.scaled, scaled));
+        }
+        if self.threshold_hashes != threshold_hashes {
+            differences.push(format!(
+                "threshold hashes {} != {}",
+                self.threshold_hashes, threshold_hashes
+            ));
+        }
+        if self.rank_by != rank_by.to_string() {
+            differences.push(format!("rank by {} != {}", self.rank_by, rank_by));
+        }
+        if !differences.is_empty() {
+            bail!(
+                "gather checkpoint '{}' was written by a different run ({}); remove it to start over.",
+                Self::path(gather_output),
+                differences.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
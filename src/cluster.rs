@@ -1,70 +1,114 @@
 use anyhow::{Context, Result};
 use rustworkx_core::connectivity::connected_components;
 use rustworkx_core::petgraph::graph::{NodeIndex, UnGraph};
+use rustworkx_core::petgraph::visit::EdgeRef;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 
-use crate::utils::MultiSearchResult;
+use crate::utils::{round_float, OutputOptions};
 
 // potential todo:
 // - eval DiGraph for directed similarity info (e.g. input containment_A, containment_B independently)
 // - explore if collect-first, add edges second style parallelization is worthwhile
 
+/// How to read the values in the column used for clustering.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Metric {
+    /// larger values are more similar; pairs at or above the threshold are linked.
+    #[default]
+    Similarity,
+    /// smaller values are more similar, e.g. 1 - ANI; pairs at or below the
+    /// threshold are linked.
+    Distance,
+}
+
+impl Metric {
+    fn links(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Metric::Similarity => value >= threshold,
+            Metric::Distance => value <= threshold,
+        }
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "similarity" => Ok(Metric::Similarity),
+            "distance" => Ok(Metric::Distance),
+            _ => Err(anyhow::anyhow!(
+                "unknown metric '{}'; must be one of similarity, distance",
+                s
+            )),
+        }
+    }
+}
+
+/// Build a graph with an edge between each pair whose value in 'column'
+/// passes 'threshold'. Any CSV with `query_name` and `match_name` columns
+/// will do, e.g. `pairwise` or `multisearch` output, or an edge list of
+/// distances from another tool.
 fn build_graph(
     file_path: &str,
-    similarity_measure: &str,
-    similarity_threshold: f64,
+    column: &str,
+    metric: Metric,
+    threshold: f64,
 ) -> Result<(UnGraph<String, f64>, HashMap<String, NodeIndex>)> {
     let mut reader = csv::Reader::from_path(file_path).context("Failed to open CSV file")?;
     let mut name_to_node: HashMap<String, NodeIndex> = HashMap::new();
     let mut graph = UnGraph::<String, f64>::new_undirected();
 
-    for result in reader.deserialize::<MultiSearchResult>() {
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV header")?
+        .clone();
+    let find_column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow::anyhow!("CSV file has no '{}' column", name))
+    };
+    let query_idx = find_column("query_name")?;
+    let match_idx = find_column("match_name")?;
+    // branchwater only writes ANI columns when ANI was estimated.
+    let value_idx = match find_column(column) {
+        Err(_) if column.ends_with("_ani") => {
+            bail!("{} is None. Did you estimate ANI?", column)
+        }
+        idx => idx?,
+    };
+
+    for result in reader.records() {
         let record = result.map_err(|e| anyhow::anyhow!("Error deserializing record: {}", e))?;
+        let query_name = &record[query_idx];
+        let match_name = &record[match_idx];
 
         // ignore self-matches reported via multisearch
-        if record.query_name == record.match_name {
+        if query_name == match_name {
             continue;
         }
 
-        let similarity = match similarity_measure {
-            "containment" => record.containment,
-            "max_containment" => record.max_containment,
-            "jaccard" => record.jaccard,
-            "average_containment_ani" => match record.average_containment_ani {
-                Some(value) => value,
-                None => {
-                    return Err(anyhow::anyhow!(
-                        "average_containment_ani is None. Did you estimate ANI?"
-                    ))
-                }
-            },
-            "max_containment_ani" => match record.max_containment_ani {
-                Some(value) => value,
-                None => {
-                    return Err(anyhow::anyhow!(
-                        "max_containment_ani is None. Did you estimate ANI?"
-                    ))
-                }
-            },
-            _ => {
-                return Err(anyhow::anyhow!(
-                    "Invalid similarity measure: {}",
-                    similarity_measure
-                ))
-            } // should not happen
+        let value = match &record[value_idx] {
+            "" if column.ends_with("_ani") => {
+                bail!("{} is None. Did you estimate ANI?", column)
+            }
+            value => value
+                .parse::<f64>()
+                .map_err(|_| anyhow::anyhow!("Invalid value '{}' in column '{}'", value, column))?,
         };
 
         let node1 = *name_to_node
-            .entry(record.query_name.clone())
-            .or_insert_with(|| graph.add_node(record.query_name.clone()));
+            .entry(query_name.to_string())
+            .or_insert_with(|| graph.add_node(query_name.to_string()));
         let node2 = *name_to_node
-            .entry(record.match_name.clone())
-            .or_insert_with(|| graph.add_node(record.match_name.clone()));
+            .entry(match_name.to_string())
+            .or_insert_with(|| graph.add_node(match_name.to_string()));
 
-        if similarity >= similarity_threshold {
-            graph.add_edge(node1, node2, similarity);
+        if metric.links(value, threshold) {
+            graph.add_edge(node1, node2, value);
         }
     }
 
@@ -79,25 +123,36 @@ fn build_graph(
     Ok((graph, name_to_node))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn cluster(
     pairwise_csv: String,
     output_clusters: String,
     similarity_column: String,
+    metric: Metric,
     similarity_threshold: f64,
     cluster_sizes: Option<String>,
+    output_graphml: Option<String>,
+    output_edges: Option<String>,
+    options: &OutputOptions,
 ) -> Result<()> {
-    let (graph, name_to_node) =
-        match build_graph(&pairwise_csv, &similarity_column, similarity_threshold) {
-            Ok(result) => result,
-            Err(e) => {
-                eprintln!("Error: {:?}", e); // print the underlying error.
-                bail!("Failed to build graph.");
-            }
-        };
+    let (graph, name_to_node) = match build_graph(
+        &pairwise_csv,
+        &similarity_column,
+        metric,
+        similarity_threshold,
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {:?}", e); // print the underlying error.
+            bail!("Failed to build graph.");
+        }
+    };
     let components = connected_components(&graph);
 
     // HashMap to count cluster sizes
     let mut size_counts: HashMap<usize, usize> = HashMap::new();
+    // the cluster of each node, for the graph outputs
+    let mut node_clusters: HashMap<NodeIndex, String> = HashMap::new();
 
     // Open file for components + names
     let mut file = File::create(output_clusters).context("Failed to create output file")?;
@@ -131,6 +186,10 @@ pub fn cluster(
         // add cluster to aggregated counts
         let count = size_counts.entry(component.len()).or_insert(0);
         *count += 1;
+
+        for node_id in component {
+            node_clusters.insert(*node_id, component_name.clone());
+        }
     }
 
     // write the sizes and counts
@@ -145,5 +204,104 @@ pub fn cluster(
         }
     }
 
+    if let Some(path) = output_graphml {
+        write_graphml(&path, &graph, &node_clusters, &similarity_column)
+            .context("Failed to write GraphML file")?;
+    }
+    if let Some(path) = output_edges {
+        write_edge_list(
+            &path,
+            &graph,
+            &node_clusters,
+            &similarity_column,
+            options.float_precision,
+        )
+        .context("Failed to write edge list file")?;
+    }
+
+    Ok(())
+}
+
+/// Write the thresholded graph as GraphML, e.g. for Cytoscape or Gephi,
+/// with each node's name and cluster, and each edge's value in 'column'.
+fn write_graphml(
+    path: &str,
+    graph: &UnGraph<String, f64>,
+    node_clusters: &HashMap<NodeIndex, String>,
+    column: &str,
+) -> Result<()> {
+    let mut file = std::io::BufWriter::new(File::create(path)?);
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        file,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        file,
+        r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#
+    )?;
+    writeln!(
+        file,
+        r#"  <key id="cluster" for="node" attr.name="cluster" attr.type="string"/>"#
+    )?;
+    writeln!(
+        file,
+        r#"  <key id="weight" for="edge" attr.name="{}" attr.type="double"/>"#,
+        xml_escape(column)
+    )?;
+    writeln!(file, r#"  <graph id="G" edgedefault="undirected">"#)?;
+    for node in graph.node_indices() {
+        writeln!(
+            file,
+            r#"    <node id="n{}"><data key="name">{}</data><data key="cluster">{}</data></node>"#,
+            node.index(),
+            xml_escape(&graph[node]),
+            node_clusters[&node]
+        )?;
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            file,
+            r#"    <edge source="n{}" target="n{}"><data key="weight">{}</data></edge>"#,
+            edge.source().index(),
+            edge.target().index(),
+            edge.weight()
+        )?;
+    }
+    writeln!(file, "  </graph>")?;
+    writeln!(file, "</graphml>")?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Write the thresholded graph as a CSV edge list, with the value in
+/// 'column', rounded to 'precision' decimal places if given, and the
+/// cluster of each edge.
+fn write_edge_list(
+    path: &str,
+    graph: &UnGraph<String, f64>,
+    node_clusters: &HashMap<NodeIndex, String>,
+    column: &str,
+    precision: Option<usize>,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["source", "target", column, "cluster"])?;
+    for edge in graph.edge_references() {
+        writer.write_record([
+            graph[edge.source()].as_str(),
+            graph[edge.target()].as_str(),
+            &round_float(*edge.weight(), precision).to_string(),
+            node_clusters[&edge.source()].as_str(),
+        ])?;
+    }
+    writer.flush()?;
     Ok(())
 }
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,208 @@
+/// compact: hold many sketches in memory as delta-encoded, varint-compressed
+/// hash arrays, for `multisearch --compact-mem`.
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use sourmash::collection::Collection;
+use sourmash::selection::{Select, Selection};
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+use crate::utils::{record_downsample, ReportType};
+
+/// The sorted hashes of a sketch, stored as the LEB128 varint of the
+/// difference between each hash and the one before it.
+pub struct CompactHashes {
+    bytes: Box<[u8]>,
+    len: usize,
+    scaled: u64,
+}
+
+impl CompactHashes {
+    pub fn new(mh: &KmerMinHash) -> Self {
+        let mut bytes = Vec::with_capacity(mh.size() * 6);
+        let mut prev = 0;
+        for &hash in mh.iter_mins() {
+            let mut delta = hash - prev;
+            while delta >= 0x80 {
+                bytes.push((delta as u8) | 0x80);
+                delta >>= 7;
+            }
+            bytes.push(delta as u8);
+            prev = hash;
+        }
+        CompactHashes {
+            bytes: bytes.into_boxed_slice(),
+            len: mh.size(),
+            scaled: mh.scaled(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    pub fn scaled(&self) -> u64 {
+        self.scaled
+    }
+
+    /// Decode the hashes, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        let mut pos = 0;
+        let mut prev = 0;
+        std::iter::from_fn(move || {
+            if pos >= self.bytes.len() {
+                return None;
+            }
+            let mut delta = 0;
+            let mut shift = 0;
+            loop {
+                let byte = self.bytes[pos];
+                pos += 1;
+                delta |= ((byte & 0x7f) as u64) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                shift += 7;
+            }
+            prev += delta;
+            Some(prev)
+        })
+    }
+
+    /// The number of hashes shared with 'other', which must have the same
+    /// scaled, as for `KmerMinHash::count_common`; the hashes are decoded
+    /// as they are compared.
+    pub fn count_common(&self, other: &KmerMinHash) -> Result<u64> {
+        if self.scaled != other.scaled() {
+            bail!(
+                "can't compare sketches with different scaled values ({} and {})",
+                self.scaled,
+                other.scaled()
+            );
+        }
+        let mut mine = self.iter().peekable();
+        let mut theirs = other.iter_mins().peekable();
+        let mut common = 0;
+        while let (Some(&a), Some(&&b)) = (mine.peek(), theirs.peek()) {
+            match a.cmp(&b) {
+                std::cmp::Ordering::Less => {
+                    mine.next();
+                }
+                std::cmp::Ordering::Greater => {
+                    theirs.next();
+                }
+                std::cmp::Ordering::Equal => {
+                    common += 1;
+                    mine.next();
+                    theirs.next();
+                }
+            }
+        }
+        Ok(common)
+    }
+}
+
+/// The hashes of a sketch held for search, as loaded or compacted.
+pub enum StoredMinHash {
+    Full(KmerMinHash),
+    Compact(CompactHashes),
+}
+
+impl StoredMinHash {
+    pub fn size(&self) -> usize {
+        match self {
+            StoredMinHash::Full(mh) => mh.size(),
+            StoredMinHash::Compact(hashes) => hashes.size(),
+        }
+    }
+
+    pub fn scaled(&self) -> u64 {
+        match self {
+            StoredMinHash::Full(mh) => mh.scaled(),
+            StoredMinHash::Compact(hashes) => hashes.scaled(),
+        }
+    }
+
+    /// The number of hashes shared with 'query', which must have the
+    /// same scaled.
+    pub fn count_common(&self, query: &KmerMinHash) -> Result<u64> {
+        match self {
+            StoredMinHash::Full(mh) => Ok(mh.count_common(query, false)?),
+            StoredMinHash::Compact(hashes) => hashes.count_common(query),
+        }
+    }
+}
+
+/// A sketch held in memory for search.
+pub struct StoredSignature {
+    pub name: String,
+    pub md5sum: String,
+    pub minhash: StoredMinHash,
+}
+
+/// Like `load_sketches`, but with 'compact', store each sketch as
+/// `CompactHashes` as soon as it is loaded, so that the full sketches are
+/// never all in memory at once.
+pub fn load_stored_sketches(
+    collection: Collection,
+    selection: &Selection,
+    report_type: ReportType,
+    compact: bool,
+) -> Result<Vec<StoredSignature>> {
+    let sketches = collection
+        .par_iter()
+        .filter_map(|(_idx, record)| {
+            let sig = collection.sig_from_record(record).ok()?;
+            let selected_sig = sig.clone().select(selection).ok()?;
+            let minhash = selected_sig.minhash()?;
+            if let Some(orig_mh) = sig.minhash() {
+                record_downsample(&report_type, &sig.name(), &sig.md5sum(), orig_mh, minhash);
+            }
+            let minhash = if compact {
+                StoredMinHash::Compact(CompactHashes::new(minhash))
+            } else {
+                StoredMinHash::Full(minhash.clone())
+            };
+
+            Some(StoredSignature {
+                name: sig.name(),
+                md5sum: sig.md5sum(),
+                minhash,
+            })
+        })
+        .collect();
+
+    Ok(sketches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sourmash::encodings::HashFunctions;
+
+    fn minhash(scaled: u64, hashes: &[u64]) -> KmerMinHash {
+        let mut mh = KmerMinHash::new(scaled, 31, HashFunctions::Murmur64Dna, 42, false, 0);
+        mh.add_many(hashes).unwrap();
+        mh
+    }
+
+    #[test]
+    fn count_common_matches_full_sketches() {
+        let a = minhash(1, &[1, 5, 300, 70000, 1 << 40]);
+        let b = minhash(1, &[5, 6, 300, 1 << 40, 1 << 50]);
+        let compact = StoredMinHash::Compact(CompactHashes::new(&a));
+        let full = StoredMinHash::Full(a.clone());
+        assert_eq!(compact.count_common(&b).unwrap(), 3);
+        assert_eq!(full.count_common(&b).unwrap(), 3);
+    }
+
+    #[test]
+    fn count_common_mismatched_scaled() {
+        let a = minhash(1, &[1, 5, 300]);
+        let b = minhash(10, &[1, 5, 300]);
+        assert!(StoredMinHash::Compact(CompactHashes::new(&a))
+            .count_common(&b)
+            .is_err());
+        assert!(StoredMinHash::Full(a).count_common(&b).is_err());
+    }
+}
@@ -0,0 +1,461 @@
+/// dryrun: load, validate and select a command's inputs, and report what
+/// would be processed, without running any comparisons (`--dry-run`).
+use anyhow::Result;
+
+use crate::check::collection_from_revindex;
+use crate::manysketch::{build_siginfo, params_for, parse_params_str, parse_row_params};
+use crate::utils::{
+    find_revindex_database, load_collection, load_fasta_fromfile, manifest_columns, Policies,
+    ReportType,
+};
+use sourmash::collection::Collection;
+use sourmash::selection::Selection;
+
+/// Bytes per hash held in memory by a loaded sketch.
+const BYTES_PER_HASH: usize = 8;
+/// Bytes per hash held in memory by a compacted sketch; delta-encoded
+/// hashes of typical FracMinHash sketches take 5-6 bytes each.
+const BYTES_PER_COMPACT_HASH: usize = 6;
+
+/// How a command holds the sketches of one of its inputs while it runs.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Residency {
+    /// All sketches are loaded into memory up front.
+    InMemory,
+    /// All sketches are loaded into memory up front, and compacted.
+    Compact,
+    /// Sketches are loaded one at a time, one per thread.
+    OneAtATime,
+    /// Sketches are searched on disk, in a RocksDB index.
+    Indexed,
+}
+
+/// The selected sketches of one input, as seen by a dry run.
+pub struct DryRunInput {
+    label: String,
+    path: String,
+    residency: Residency,
+    n_sketches: usize,
+    n_hashes: usize,
+    max_hashes: usize,
+}
+
+impl DryRunInput {
+    /// Load and select the collection at 'path', as the command would.
+    pub fn load(
+        label: &str,
+        path: &String,
+        selection: &Selection,
+        report_type: ReportType,
+        policies: &Policies,
+        residency: Residency,
+    ) -> Result<Self> {
+        let pathbuf: camino::Utf8PathBuf = path.into();
+        let (collection, residency) = if let Some(index) = find_revindex_database(&pathbuf) {
+            (collection_from_revindex(&index)?, Residency::Indexed)
+        } else {
+            (
+                load_collection(path, selection, report_type, policies)?,
+                residency,
+            )
+        };
+
+        Ok(Self::from_collection(
+            label,
+            path,
+            &collection,
+            selection,
+            residency,
+        ))
+    }
+
+    /// Summarize an already loaded and selected collection.
+    pub fn from_collection(
+        label: &str,
+        path: &str,
+        collection: &Collection,
+        selection: &Selection,
+        residency: Residency,
+    ) -> Self {
+        let mut input = DryRunInput {
+            label: label.to_string(),
+            path: path.to_string(),
+            residency,
+            n_sketches: collection.len(),
+            n_hashes: 0,
+            max_hashes: 0,
+        };
+        for n_hashes in effective_sizes(collection, selection) {
+            input.n_hashes += n_hashes;
+            input.max_hashes = input.max_hashes.max(n_hashes);
+        }
+        input
+    }
+
+    pub fn n_sketches(&self) -> usize {
+        self.n_sketches
+    }
+
+    /// Estimated bytes of sketches held in memory for this input.
+    fn memory(&self, n_threads: usize) -> usize {
+        match self.residency {
+            Residency::InMemory => self.n_hashes * BYTES_PER_HASH,
+            Residency::Compact => self.n_hashes * BYTES_PER_COMPACT_HASH,
+            Residency::OneAtATime => self.max_hashes * BYTES_PER_HASH * n_threads,
+            Residency::Indexed => 0,
+        }
+    }
+}
+
+/// The number of hashes in each sketch of 'collection' once downsampled to
+/// the scaled in 'selection', estimated from the manifest.
+pub(crate) fn effective_sizes(collection: &Collection, selection: &Selection) -> Vec<usize> {
+    let target_scaled = selection.scaled().unwrap_or(0) as u64;
+    // writing the manifest to memory doesn't fail; without sizes, every
+    // sketch counts as empty.
+    let rows = manifest_columns(collection.manifest(), &["n_hashes", "scaled"])
+        .unwrap_or_else(|_| vec![vec![0, 0]; collection.len()]);
+    rows.into_iter()
+        .map(|row| {
+            let (n_hashes, scaled) = (row[0], row[1]);
+            if scaled > 0 && target_scaled > scaled {
+                (n_hashes * scaled / target_scaled) as usize
+            } else {
+                n_hashes as usize
+            }
+        })
+        .collect()
+}
+
+/// Format a number of bytes for humans.
+pub fn format_bytes(n_bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = n_bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Peak memory is estimated as this multiple of the loaded sketches, to
+/// allow for the copies made while loading and selecting them.
+const PEAK_MEMORY_FACTOR: usize = 2;
+
+/// Range of nanoseconds per hash to intersect two sketches, from small
+/// sketches that fit in cache to large ones that don't.
+const NS_PER_HASH: (f64, f64) = (2.0, 20.0);
+
+/// A simple cost model for comparing the sketches of 'inputs': memory is
+/// the hashes held in memory, and runtime is the number of comparisons
+/// times the average number of hashes per comparison.
+pub struct CostEstimate {
+    peak_memory: usize,
+    runtime_secs: Option<(f64, f64)>,
+    n_threads: usize,
+}
+
+impl CostEstimate {
+    pub fn new(inputs: &[DryRunInput], n_comparisons: Option<usize>) -> Self {
+        let n_threads = rayon::current_num_threads();
+        let sketch_memory: usize = inputs.iter().map(|input| input.memory(n_threads)).sum();
+
+        // each comparison merges one sketch from each input (or two from
+        // the same input).
+        let mean_hashes =
+            |input: &DryRunInput| input.n_hashes as f64 / input.n_sketches.max(1) as f64;
+        let hashes_per_comparison = match inputs {
+            [single] => 2.0 * mean_hashes(single),
+            _ => inputs.iter().map(mean_hashes).sum(),
+        };
+        let runtime_secs = n_comparisons.map(|n| {
+            let total_ns = n as f64 * hashes_per_comparison / n_threads as f64;
+            (
+                total_ns * NS_PER_HASH.0 / 1e9,
+                total_ns * NS_PER_HASH.1 / 1e9,
+            )
+        });
+
+        CostEstimate {
+            peak_memory: sketch_memory * PEAK_MEMORY_FACTOR,
+            runtime_secs,
+            n_threads,
+        }
+    }
+
+    pub fn report(&self) {
+        eprintln!(
+            "estimated peak memory: {} (using {} threads)",
+            format_bytes(self.peak_memory),
+            self.n_threads
+        );
+        if let Some((low, high)) = self.runtime_secs {
+            eprintln!(
+                "estimated runtime: {} to {}",
+                format_duration(low),
+                format_duration(high)
+            );
+        }
+    }
+
+    /// Fail if the estimated peak memory exceeds 'max_mem' bytes.
+    pub fn check_max_mem(&self, max_mem: Option<usize>) -> Result<()> {
+        match max_mem {
+            Some(max_mem) if self.peak_memory > max_mem => bail!(
+                "estimated peak memory of {} exceeds --max-mem of {}; use fewer or smaller sketches (e.g. a larger --scaled), or raise --max-mem.",
+                format_bytes(self.peak_memory),
+                format_bytes(max_mem)
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Before a real run, check the estimated peak memory of comparing
+/// 'inputs' against `--max-mem`, if given.
+pub fn check_max_mem(
+    inputs: &[DryRunInput],
+    n_comparisons: usize,
+    max_mem: Option<usize>,
+) -> Result<()> {
+    if max_mem.is_none() {
+        return Ok(());
+    }
+    let cost = CostEstimate::new(inputs, Some(n_comparisons));
+    cost.report();
+    cost.check_max_mem(max_mem)
+}
+
+/// Like `check_max_mem`, for comparing every sketch in 'query' to every
+/// sketch in 'against', with both held in memory, as `multisearch` does.
+/// 'against_residency' is `Residency::Compact` with `--compact-mem`.
+pub fn check_search_max_mem(
+    query: (&str, &Collection),
+    against: (&str, &Collection),
+    against_residency: Residency,
+    selection: &Selection,
+    max_mem: Option<usize>,
+) -> Result<()> {
+    let inputs = [
+        DryRunInput::from_collection("query", query.0, query.1, selection, Residency::InMemory),
+        DryRunInput::from_collection(
+            "against",
+            against.0,
+            against.1,
+            selection,
+            against_residency,
+        ),
+    ];
+    check_max_mem(&inputs, query.1.len() * against.1.len(), max_mem)
+}
+
+/// Format a number of seconds for humans.
+fn format_duration(secs: f64) -> String {
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else if secs < 3600.0 {
+        format!("{:.1}m", secs / 60.0)
+    } else {
+        format!("{:.1}h", secs / 3600.0)
+    }
+}
+
+/// Parse a memory size such as '500M', '16G' or '1T' (powers of 1024), or
+/// a number of bytes, for `--max-mem`.
+pub fn parse_memory_size(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier: usize = match unit.to_ascii_uppercase() {
+                'B' => 1,
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => bail!("unknown unit '{}' in memory size '{}'", unit, size),
+            };
+            (&size[..i], multiplier)
+        }
+        _ => (size, 1),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("cannot parse memory size '{}'", size))?;
+    Ok((number * multiplier as f64) as usize)
+}
+
+/// Report what 'command' would process: its sketch parameters, its
+/// inputs, the number of comparisons (if known), and the estimated memory
+/// and runtime. Fails if the estimated memory exceeds 'max_mem'.
+pub fn report(
+    command: &str,
+    selection: &Selection,
+    inputs: &[DryRunInput],
+    n_comparisons: Option<usize>,
+    max_mem: Option<usize>,
+) -> Result<()> {
+    eprintln!("Dry run of '{}'; nothing will be computed.", command);
+    eprintln!(
+        "ksize: {} / scaled: {} / moltype: {}",
+        selection.ksize().map_or("any".into(), |k| k.to_string()),
+        selection.scaled().map_or("any".into(), |s| s.to_string()),
+        selection.moltype().map_or("any".into(), |m| m.to_string()),
+    );
+    for input in inputs {
+        let held = match input.residency {
+            Residency::InMemory => "all loaded into memory",
+            Residency::Compact => "all loaded into memory, compacted",
+            Residency::OneAtATime => "loaded one at a time",
+            Residency::Indexed => "searched in a RocksDB index",
+        };
+        eprintln!(
+            "{} '{}': {} sketches, {} hashes ({})",
+            input.label, input.path, input.n_sketches, input.n_hashes, held
+        );
+    }
+    if let Some(n_comparisons) = n_comparisons {
+        eprintln!("comparisons: {}", n_comparisons);
+    }
+    let cost = CostEstimate::new(inputs, n_comparisons);
+    cost.report();
+    cost.check_max_mem(max_mem)
+}
+
+/// Dry run for a command that holds one input in memory ('query') and
+/// compares it to every sketch of another ('against'), e.g. `manysearch`.
+pub fn search(
+    command: &str,
+    query: (&String, Residency),
+    against: (&String, Residency),
+    selection: &Selection,
+    policies: &Policies,
+    max_mem: Option<usize>,
+) -> Result<()> {
+    let query = DryRunInput::load(
+        "query",
+        query.0,
+        selection,
+        ReportType::Query,
+        policies,
+        query.1,
+    )?;
+    let against = DryRunInput::load(
+        "against",
+        against.0,
+        selection,
+        ReportType::Against,
+        policies,
+        against.1,
+    )?;
+    let n_comparisons = query.n_sketches() * against.n_sketches();
+    report(
+        command,
+        selection,
+        &[query, against],
+        Some(n_comparisons),
+        max_mem,
+    )
+}
+
+/// Dry run for `pairwise`: all pairs within 'siglist', or every sketch in
+/// 'siglist' against every sketch in 'against'.
+pub fn pairwise(
+    siglist: &String,
+    against: Option<&String>,
+    selection: &Selection,
+    policies: &Policies,
+    max_mem: Option<usize>,
+) -> Result<()> {
+    let sigs = DryRunInput::load(
+        "sketches",
+        siglist,
+        selection,
+        ReportType::General,
+        policies,
+        Residency::InMemory,
+    )?;
+    match against {
+        Some(against) => {
+            let against = DryRunInput::load(
+                "against",
+                against,
+                selection,
+                ReportType::Against,
+                policies,
+                Residency::InMemory,
+            )?;
+            let n_comparisons = sigs.n_sketches() * against.n_sketches();
+            report(
+                "pairwise",
+                selection,
+                &[sigs, against],
+                Some(n_comparisons),
+                max_mem,
+            )
+        }
+        None => {
+            let n_comparisons = pairwise_comparisons(sigs.n_sketches());
+            report("pairwise", selection, &[sigs], Some(n_comparisons), max_mem)
+        }
+    }
+}
+
+/// The number of pairs among 'n' sketches.
+pub fn pairwise_comparisons(n: usize) -> usize {
+    n * n.saturating_sub(1) / 2
+}
+
+/// Dry run for `index`: the sketches that would be indexed.
+pub fn index(siglist: &String, selection: &Selection, policies: &Policies) -> Result<()> {
+    let sigs = DryRunInput::load(
+        "sketches",
+        siglist,
+        selection,
+        ReportType::General,
+        policies,
+        Residency::OneAtATime,
+    )?;
+    report("index", selection, &[sigs], None, None)
+}
+
+/// Dry run for `manysketch`: the files that would be read, and the number
+/// of sketches that would be built from them.
+pub fn manysketch(filelist: String, param_str: String, force: bool) -> Result<()> {
+    let (fileinfo, n_fastas) = load_fasta_fromfile(filelist, force)?;
+    let params = parse_params_str(param_str).map_err(|e| anyhow::anyhow!(e))?;
+    let row_params = parse_row_params(&fileinfo)?;
+
+    let mut n_sketches = 0;
+    let mut n_skipped = 0;
+    for fastadata in fileinfo.iter() {
+        let row = params_for(fastadata, &params, &row_params);
+        let n = build_siginfo(row, &fastadata.input_type).len();
+        if n == 0 {
+            n_skipped += fastadata.paths.len();
+        }
+        n_sketches += n;
+    }
+
+    eprintln!("Dry run of 'manysketch'; nothing will be computed.");
+    eprintln!("parameter sets: {}", params.len());
+    if !row_params.is_empty() {
+        let n_rows = fileinfo.iter().filter(|f| f.param_string.is_some()).count();
+        eprintln!(
+            "{} inputs override the parameters with 'param_string'",
+            n_rows
+        );
+    }
+    eprintln!("inputs: {} samples in {} files", fileinfo.len(), n_fastas);
+    if n_skipped > 0 {
+        eprintln!(
+            "{} files have no parameter sets for their moltype, and would be skipped",
+            n_skipped
+        );
+    }
+    eprintln!(
+        "sketches to build: {} (with --singleton, that many for each FASTA record instead of each sample)",
+        n_sketches
+    );
+    Ok(())
+}
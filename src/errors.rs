@@ -0,0 +1,109 @@
+/// errors: stable, machine-readable codes for the failures that workflow
+/// managers may want to handle, e.g. by retrying or by skipping an input.
+use std::fmt;
+
+/// Why a command failed. The code is the process exit status of the
+/// command line, and the `code` attribute of a Python `BranchwaterError`.
+///
+/// Codes are stable across releases; new codes may be added. They start at
+/// 10 so as not to collide with 1 (any other failure) or 2 (bad arguments).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Any failure without a more specific code.
+    Other = 1,
+    /// An input file or index does not exist.
+    InputNotFound = 10,
+    /// Some input sketches could not be loaded.
+    InputLoadFailed = 11,
+    /// No sketches in an input match the selected ksize, scaled and moltype.
+    NoCompatibleSketches = 12,
+    /// Sketches would need downsampling, and `--strict-scaled` forbids it.
+    ScaledMismatch = 13,
+    /// An index could not be opened or read.
+    IndexCorrupt = 14,
+    /// An index was built with an incompatible schema version.
+    IndexVersionMismatch = 15,
+    /// An output file could not be created or written.
+    OutputWriteFailed = 16,
+    /// Sketches were built with a different hash seed than requested.
+    SeedMismatch = 17,
+}
+
+impl ErrorCode {
+    /// All codes, in order.
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::Other,
+        ErrorCode::InputNotFound,
+        ErrorCode::InputLoadFailed,
+        ErrorCode::NoCompatibleSketches,
+        ErrorCode::ScaledMismatch,
+        ErrorCode::IndexCorrupt,
+        ErrorCode::IndexVersionMismatch,
+        ErrorCode::OutputWriteFailed,
+        ErrorCode::SeedMismatch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Other => "Other",
+            ErrorCode::InputNotFound => "InputNotFound",
+            ErrorCode::InputLoadFailed => "InputLoadFailed",
+            ErrorCode::NoCompatibleSketches => "NoCompatibleSketches",
+            ErrorCode::ScaledMismatch => "ScaledMismatch",
+            ErrorCode::IndexCorrupt => "IndexCorrupt",
+            ErrorCode::IndexVersionMismatch => "IndexVersionMismatch",
+            ErrorCode::OutputWriteFailed => "OutputWriteFailed",
+            ErrorCode::SeedMismatch => "SeedMismatch",
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        self as u8
+    }
+}
+
+/// An error with a stable code; the message is for humans.
+#[derive(Debug)]
+pub struct BranchwaterError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl BranchwaterError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        BranchwaterError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Prefix the message of 'error' with 'context', keeping its code.
+    pub fn context(context: &str, error: &anyhow::Error) -> Self {
+        BranchwaterError::new(error_code(&**error), format!("{}: {}", context, error))
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+}
+
+impl fmt::Display for BranchwaterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for BranchwaterError {}
+
+/// The code of the first `BranchwaterError` in the chain of 'error', or
+/// `ErrorCode::Other` if there is none.
+pub fn error_code(error: &(dyn std::error::Error + 'static)) -> ErrorCode {
+    let mut next = Some(error);
+    while let Some(e) = next {
+        if let Some(e) = e.downcast_ref::<BranchwaterError>() {
+            return e.code();
+        }
+        next = e.source();
+    }
+    ErrorCode::Other
+}
@@ -2,29 +2,47 @@
 use anyhow::Result;
 use sourmash::prelude::Select;
 use sourmash::selection::Selection;
+use sourmash::signature::SigsTrait;
+use std::sync::mpsc::SyncSender;
 
+use crate::checkpoint::Checkpointing;
 use crate::utils::{
-    consume_query_by_gather, load_collection, load_sketches_above_threshold, write_prefetch,
+    consume_query_by_gather, load_collection, load_sketches_above_threshold, record_downsample,
+    report_downsampling, start_downsample_log, write_prefetch, BranchwaterGatherResult,
+    GatherOptions, GatherOutputs, OutputFormat, OutputOptions, Policies, QuerySizeFilter,
     ReportType,
 };
 
+/// Gather 'query_filepath' against 'against_filepath'. Results are written
+/// to 'gather_output' (or stdout) in 'output_format', as given in
+/// 'options', or sent to 'results_channel' as they are found, if given.
 #[allow(clippy::too_many_arguments)]
 pub fn fastgather(
     query_filepath: String,
     against_filepath: String,
     threshold_bp: usize,
+    prefetch_threshold_bp: Option<usize>,
     scaled: usize,
     selection: &Selection,
     gather_output: Option<String>,
     prefetch_output: Option<String>,
-    allow_failed_sigpaths: bool,
+    remaining_output: Option<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    gather: &GatherOptions,
+    downsampled_output: Option<String>,
+    max_matchlist_mem: Option<usize>,
+    checkpoint: Option<Checkpointing>,
+    results_channel: Option<SyncSender<BranchwaterGatherResult>>,
+    output_format: OutputFormat,
+    options: &OutputOptions,
 ) -> Result<()> {
-    let query_collection = load_collection(
-        &query_filepath,
-        selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
+    start_downsample_log();
+
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
 
     if query_collection.len() != 1 {
         bail!(
@@ -41,33 +59,58 @@ pub fn fastgather(
             bail!("No query sketch matching selection parameters.");
         }
     };
+    if let Some(orig_query_mh) = query_sig.minhash() {
+        record_downsample(
+            &ReportType::Query,
+            &query_sig.name(),
+            &query_sig.md5sum(),
+            orig_query_mh,
+            query_mh,
+        );
+    }
+
+    // skip the query if it is too small to gather meaningfully
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+    let query_ok = size_filter.check(&query_sig.name(), &query_sig.md5sum(), query_mh.size());
+    size_filter.report(skipped_output, options)?;
+    if !query_ok {
+        return Ok(());
+    }
+
     // load collection to match against.
-    let against_collection = load_collection(
-        &against_filepath,
-        selection,
-        ReportType::Against,
-        allow_failed_sigpaths,
-    )?;
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
 
     // calculate the minimum number of hashes based on desired threshold
-    let threshold_hashes: u64 = {
-        let x = threshold_bp / scaled;
-        if x > 0 {
-            x
-        } else {
-            1
-        }
-    }
-    .try_into()?;
+    let to_hashes = |bp: usize| -> Result<u64> { Ok(std::cmp::max(bp / scaled, 1).try_into()?) };
+    let threshold_hashes = to_hashes(threshold_bp)?;
 
     eprintln!(
         "using threshold overlap: {} {}",
         threshold_hashes, threshold_bp
     );
 
+    // prefetch may keep more candidates than gather reports.
+    let prefetch_threshold_hashes = match prefetch_threshold_bp {
+        Some(prefetch_threshold_bp) => {
+            let prefetch_threshold_hashes = to_hashes(prefetch_threshold_bp)?;
+            eprintln!(
+                "using prefetch threshold overlap: {} {}",
+                prefetch_threshold_hashes, prefetch_threshold_bp
+            );
+            prefetch_threshold_hashes
+        }
+        None => threshold_hashes,
+    };
+
     // load a set of sketches, filtering for those with overlaps > threshold
-    let result = load_sketches_above_threshold(against_collection, query_mh, threshold_hashes)?;
-    let matchlist = result.0;
+    let result = load_sketches_above_threshold(
+        against_collection,
+        query_mh,
+        prefetch_threshold_hashes,
+        max_matchlist_mem,
+    )?;
+    let mut matchlist = result.0;
     let skipped_paths = result.1;
     let failed_paths = result.2;
     if skipped_paths > 0 {
@@ -89,17 +132,32 @@ pub fn fastgather(
     }
 
     if prefetch_output.is_some() {
-        write_prefetch(&query_sig, prefetch_output, &matchlist).ok();
+        write_prefetch(&query_sig, query_mh, prefetch_output, &matchlist, options).ok();
+    }
+    if prefetch_threshold_hashes < threshold_hashes {
+        matchlist.retain_above(threshold_hashes);
     }
 
     // run the gather!
+    let outputs = GatherOutputs {
+        gather: gather_output,
+        remaining: remaining_output,
+        columns,
+        format: output_format,
+        command: "fastgather",
+        results_channel,
+        checkpoint,
+        ..GatherOutputs::default()
+    };
     consume_query_by_gather(
         query_sig,
         scaled as u64,
         matchlist,
         threshold_hashes,
-        gather_output,
-    )
-    .ok();
+        gather,
+        outputs,
+        options,
+    )?;
+    report_downsampling(downsampled_output, options)?;
     Ok(())
 }
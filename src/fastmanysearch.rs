@@ -0,0 +1,201 @@
+/// fastmanysearch: sketch FASTA/FASTQ samples on the fly and search them
+/// against a RocksDB index, without writing the sketches to disk.
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf as PathBuf;
+use needletail::parse_fastx_file;
+use rayon::prelude::*;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::cmd::ComputeParameters;
+use sourmash::selection::Selection;
+use sourmash::signature::{Signature, SigsTrait};
+
+use crate::errors::BranchwaterError;
+use crate::mastiff_manysearch::search_revindex;
+use crate::utils::{
+    csvwriter_thread_with_extras, load_fasta_fromfile, open_revindex_cached,
+    resolve_revindex_database, FastaData, OutputOptions, SearchResult, WriterExtras,
+};
+
+/// Build a single DNA sketch from all of the files for one sample.
+fn sketch_sample(sample: &FastaData, ksize: u32, scaled: u64) -> Result<Signature> {
+    let cp = ComputeParameters::builder()
+        .ksizes(vec![ksize])
+        .scaled(scaled)
+        .protein(false)
+        .dna(true)
+        .num_hashes(0)
+        .track_abundance(false)
+        .build();
+    let mut sig = Signature::from_params(&cp);
+    sig.set_name(&sample.name);
+    // sourmash sets filename to last filename if merging files
+    if let Some(last_filename) = sample.paths.last() {
+        sig.set_filename(last_filename.as_str());
+    }
+
+    for filename in sample.paths.iter() {
+        let mut reader = parse_fastx_file(filename)
+            .map_err(|err| anyhow!("Error opening file {}: {:?}", filename, err))?;
+
+        while let Some(record_result) = reader.next() {
+            match record_result {
+                Ok(record) => sig.add_sequence(&record.seq(), true)?,
+                Err(err) => eprintln!("Error while processing record: {:?}", err),
+            }
+        }
+    }
+
+    Ok(sig)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn fastmanysearch(
+    samples_path: String,
+    index: PathBuf,
+    selection: &Selection,
+    minimum_containment: f64,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    options: &OutputOptions,
+) -> Result<()> {
+    let index = resolve_revindex_database(&index)?;
+    let ksize = selection.ksize().unwrap();
+    let scaled = selection.scaled().unwrap() as u64;
+
+    let (samples, n_files) = match load_fasta_fromfile(samples_path, false) {
+        Ok((samples, n_files)) => (samples, n_files),
+        Err(e) => bail!("Could not load fromfile csv. Underlying error: {}", e),
+    };
+
+    // if no files to process, exit with error
+    if n_files == 0 {
+        bail!("No files to load, exiting.");
+    }
+
+    // Open database once
+    let db = open_revindex_cached(&index)?;
+
+    println!("Loaded DB");
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) = std::sync::mpsc::sync_channel::<SearchResult>(rayon::current_num_threads());
+
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let extras = WriterExtras {
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
+
+    //
+    // Main loop: iterate (in parallel) over all samples, sketching them
+    // and searching them against the database. Stuff results into the
+    // writer thread above.
+    //
+
+    let processed_samples = AtomicUsize::new(0);
+    let skipped_samples = AtomicUsize::new(0);
+    let failed_samples = AtomicUsize::new(0);
+
+    let send_result = samples
+        .par_iter()
+        .filter_map(|sample| {
+            let i = processed_samples.fetch_add(1, atomic::Ordering::SeqCst);
+            if i % 1000 == 0 && i > 0 {
+                eprintln!("Processed {} samples", i);
+            }
+
+            if sample.input_type != "dna" {
+                eprintln!(
+                    "WARNING: skipping sample '{}' - only DNA input is supported.",
+                    sample.name
+                );
+                let _ = skipped_samples.fetch_add(1, atomic::Ordering::SeqCst);
+                return None;
+            }
+
+            let query_sig = match sketch_sample(sample, ksize, scaled) {
+                Ok(sig) => sig,
+                Err(err) => {
+                    eprintln!("Sketching error: {}", err);
+                    eprintln!("WARNING: could not sketch sample '{}'", sample.name);
+                    let _ = failed_samples.fetch_add(1, atomic::Ordering::SeqCst);
+                    return None;
+                }
+            };
+
+            let mut results = vec![];
+            if let Some(query_mh) = query_sig.minhash() {
+                let query_size = query_mh.size();
+                if query_size == 0 {
+                    eprintln!("WARNING: no hashes in sample '{}'", sample.name);
+                    let _ = skipped_samples.fetch_add(1, atomic::Ordering::SeqCst);
+                    return None;
+                }
+                results = search_revindex(
+                    &db,
+                    query_sig.name(),
+                    query_sig.md5sum(),
+                    query_mh,
+                    minimum_containment,
+                );
+            }
+            if results.is_empty() {
+                None
+            } else {
+                Some(results)
+            }
+        })
+        .flatten()
+        .try_for_each_with(
+            send,
+            |s: &mut std::sync::mpsc::SyncSender<SearchResult>, results| {
+                if let Err(e) = s.send(results) {
+                    Err(format!("Unable to send internal data: {:?}", e))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+    // do some cleanup and error handling -
+    if let Err(e) = send_result {
+        eprintln!("Error during parallel processing: {}", e);
+    }
+
+    // join the writer thread
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+
+    // done!
+    let i: usize = processed_samples.load(atomic::Ordering::SeqCst);
+    eprintln!("DONE. Processed {} samples", i);
+
+    let skipped_samples = skipped_samples.load(atomic::Ordering::SeqCst);
+    let failed_samples = failed_samples.load(atomic::Ordering::SeqCst);
+
+    if failed_samples == i {
+        bail!("Could not sketch any samples.");
+    }
+    if skipped_samples > 0 {
+        eprintln!(
+            "WARNING: skipped {} samples - no compatible sequences.",
+            skipped_samples
+        );
+    }
+    if failed_samples > 0 {
+        eprintln!(
+            "WARNING: {} samples failed to load. See error messages above.",
+            failed_samples
+        );
+    }
+
+    Ok(())
+}
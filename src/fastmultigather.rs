@@ -3,34 +3,134 @@ use anyhow::Result;
 use rayon::prelude::*;
 
 use sourmash::selection::Selection;
+use sourmash::signature::SigsTrait;
 
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
-use std::collections::BinaryHeap;
-
 use camino::Utf8Path as PathBuf;
 
+use crate::checkpoint::Checkpointing;
+use crate::matchlist::MatchList;
+use crate::progress::Progress;
+use crate::report::RunReport;
+use crate::sketchcache::load_sketches_cached;
+use crate::taxonomy::Lineages;
 use crate::utils::{
-    consume_query_by_gather, load_collection, load_sketches, write_prefetch, PrefetchResult,
-    ReportType,
+    consume_query_by_gather, load_collection, load_done_query_md5s, par_iter_scheduled,
+    report_downsampling, skip_done_queries, start_downsample_log, write_prefetch, GatherOptions,
+    GatherOutputs, OutputDir, OutputFormat, OutputIndex, OutputOptions, Policies, PrefetchResult,
+    QuerySizeFilter, ReportType, Schedule, SkipReason,
 };
 
+/// Where `fastmultigather` writes its outputs: files named for each query,
+/// in the current directory or in 'output_dir', an index of the files of
+/// each completed query, and reports on the whole run.
+#[derive(Default)]
+pub struct MultiGatherOutputs {
+    /// Save the query sketch remaining after each gather rank.
+    pub save_remaining: bool,
+    /// Save the gather rank that claimed each query hash.
+    pub save_coverage: bool,
+    /// Only these columns of the gather results are written.
+    pub columns: Option<Vec<String>>,
+    /// A shared directory for the outputs of every query, with an index.
+    pub output_dir: Option<String>,
+    /// The prefix of the job-wide outputs in 'output_dir'.
+    pub job_prefix: Option<String>,
+    /// A lineages CSV, for a Krona summary of each query.
+    pub lineages: Option<String>,
+    /// A CSV of the queries skipped as too small or empty.
+    pub skipped_output: Option<String>,
+    /// A CSV of the sketches downsampled on loading.
+    pub downsampled_output: Option<String>,
+    pub format: OutputFormat,
+    /// An HTML report summarizing the results of every query.
+    pub report: Option<RunReport>,
+    /// Checkpoint each gather output, to resume or skip it later.
+    pub checkpoint: Option<Checkpointing>,
+}
+
+/// The index of the outputs written to the current directory.
+const INDEX: &str = "fastmultigather.index.csv";
+
+#[allow(clippy::too_many_arguments)]
 pub fn fastmultigather(
     query_filepath: String,
     against_filepath: String,
     threshold_bp: usize,
     scaled: usize,
     selection: &Selection,
-    allow_failed_sigpaths: bool,
+    policies: &Policies,
+    min_query_hashes: usize,
+    mut skip_done: Vec<String>,
+    resume: bool,
+    cache_dir: Option<String>,
+    max_matchlist_mem: Option<usize>,
+    schedule: Schedule,
+    gather: &GatherOptions,
+    outputs: MultiGatherOutputs,
+    options: &OutputOptions,
 ) -> Result<()> {
+    let MultiGatherOutputs {
+        save_remaining,
+        save_coverage,
+        columns,
+        output_dir,
+        job_prefix,
+        lineages,
+        skipped_output,
+        downsampled_output,
+        format: output_format,
+        report,
+        checkpoint,
+    } = outputs;
+    if matches!(output_format, OutputFormat::Parquet | OutputFormat::Sqlite) && checkpoint.is_some()
+    {
+        bail!(
+            "--checkpoint-every can't be used with --output-format {}.",
+            output_format.extension()
+        );
+    }
+    start_downsample_log();
+
+    // write per-query outputs into a shared directory, if requested
+    let output_dir = match output_dir {
+        Some(dir) => Some(OutputDir::new(&dir, job_prefix)?),
+        None if job_prefix.is_some() => bail!("--job-prefix requires --output-dir"),
+        None => None,
+    };
+
+    // load lineages for Krona output, if requested
+    let lineages = lineages.map(|path| Lineages::from_csv(&path)).transpose()?;
+
     // load query collection
-    let query_collection = load_collection(
-        &query_filepath,
-        selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
+    // with --resume, skip the queries that a previous run completed, as
+    // listed in its index; with --checkpoint-every, each query instead
+    // resumes from its own checkpoint.
+    if resume && checkpoint.is_none() {
+        match &output_dir {
+            Some(dir) => skip_done.extend(dir.previous_indexes()?),
+            None if PathBuf::new(INDEX).exists() => skip_done.push(INDEX.to_string()),
+            None => (),
+        }
+    }
+    // skip queries already searched in a previous run
+    let done = load_done_query_md5s(&skip_done)?;
+    let query_collection = skip_done_queries(query_collection, &done);
+
+    // each completed query's outputs are recorded in an index, which
+    // --output-dir keeps per job.
+    let cwd_index = match &output_dir {
+        Some(_) => None,
+        None => Some(OutputIndex::open(INDEX, resume)?),
+    };
+    let index = match &output_dir {
+        Some(dir) => dir.index(),
+        None => cwd_index.as_ref().unwrap(),
+    };
 
     let threshold_hashes: u64 = {
         let x = threshold_bp / scaled;
@@ -46,75 +146,184 @@ pub fn fastmultigather(
     println!("threshold overlap: {} {}", threshold_hashes, threshold_bp);
 
     // load against collection
-    let against_collection = load_collection(
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
+    // load against sketches into memory, downsampling on the way, or
+    // from the sketch cache of a previous run
+    let against = load_sketches_cached(
         &against_filepath,
+        against_collection,
         selection,
         ReportType::Against,
-        allow_failed_sigpaths,
+        cache_dir.as_ref(),
     )?;
-    // load against sketches into memory, downsampling on the way
-    let against = load_sketches(against_collection, selection, ReportType::Against).unwrap();
 
     // Iterate over all queries => do prefetch and gather!
     let processed_queries = AtomicUsize::new(0);
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+    let progress = Progress::new(query_collection.len(), "queries");
 
-    query_collection.par_iter().for_each(|(_idx, record)| {
+    par_iter_scheduled(&query_collection, selection, schedule).for_each(|(_idx, record)| {
         // increment counter of # of queries. q: could we instead use the _idx from par_iter(), or will it vary based on thread?
         let _i = processed_queries.fetch_add(1, atomic::Ordering::SeqCst);
+        progress.inc();
         // Load query sig (downsampling happens here)
         match query_collection.sig_from_record(record) {
             Ok(query_sig) => {
                 let name = query_sig.name();
                 let prefix = name.split(' ').next().unwrap_or_default().to_string();
-                let location = PathBuf::new(&prefix).file_name().unwrap();
+                // with --anonymize-queries, output filenames don't name the query
+                let location = options.anonymize_query(PathBuf::new(&prefix).file_name().unwrap());
                 if let Some(query_mh) = query_sig.minhash() {
-                    let matchlist: BinaryHeap<PrefetchResult> = against
-                        .iter()
-                        .filter_map(|against| {
-                            let mut mm: Option<PrefetchResult> = None;
-                            if let Ok(overlap) = against.minhash.count_common(query_mh, false) {
-                                if overlap >= threshold_hashes {
-                                    let result = PrefetchResult {
-                                        name: against.name.clone(),
-                                        md5sum: against.md5sum.clone(),
-                                        minhash: against.minhash.clone(),
-                                        location: against.location.clone(),
-                                        overlap,
-                                    };
-                                    mm = Some(result);
+                    // an empty query can't match anything; report it up front.
+                    if query_mh.size() == 0 {
+                        size_filter.skip(
+                            &name,
+                            &query_sig.md5sum(),
+                            0,
+                            SkipReason::NoHashesAfterDownsampling,
+                        );
+                        return;
+                    }
+                    if !size_filter.check(&name, &query_sig.md5sum(), query_mh.size()) {
+                        return;
+                    }
+                    let mut matchlist = MatchList::new(max_matchlist_mem);
+                    for against in against.iter() {
+                        if let Ok(overlap) = against.minhash.count_common(query_mh, false) {
+                            if overlap >= threshold_hashes {
+                                let result = PrefetchResult {
+                                    name: against.name.clone(),
+                                    md5sum: against.md5sum.clone(),
+                                    minhash: against.minhash.clone(),
+                                    location: against.location.clone(),
+                                    overlap,
+                                };
+                                if let Err(e) = matchlist.push(result) {
+                                    eprintln!("Error spilling matches for '{}': {}", location, e);
+                                    let _ = failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                                    return;
                                 }
                             }
-                            mm
-                        })
-                        .collect();
+                        }
+                    }
                     if !matchlist.is_empty() {
-                        let prefetch_output = format!("{}.prefetch.csv", location);
-                        let gather_output = format!("{}.gather.csv", location);
+                        let output_path = |suffix: &str| -> Result<String> {
+                            match &output_dir {
+                                Some(dir) => dir.reserve(&location, suffix),
+                                None => Ok(format!("{}.{}", location, suffix)),
+                            }
+                        };
+                        let outputs = (|| -> Result<_> {
+                            Ok((
+                                output_path("prefetch.csv")?,
+                                output_path(&format!("gather.{}", output_format.extension()))?,
+                                save_remaining
+                                    .then(|| output_path("remaining.zip"))
+                                    .transpose()?,
+                                save_coverage
+                                    .then(|| output_path("coverage.csv"))
+                                    .transpose()?,
+                                lineages
+                                    .as_ref()
+                                    .map(|_| output_path("krona.tsv"))
+                                    .transpose()?,
+                            ))
+                        })();
+                        let (
+                            prefetch_output,
+                            gather_output,
+                            remaining_output,
+                            coverage_output,
+                            krona_output,
+                        ) = match outputs {
+                            Ok(outputs) => outputs,
+                            Err(e) => {
+                                eprintln!("Error writing outputs for '{}': {}", location, e);
+                                let _ = failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                                return;
+                            }
+                        };
 
                         // Save initial list of matches to prefetch output
-                        write_prefetch(&query_sig, Some(prefetch_output), &matchlist).ok();
+                        let prefetch_ok = write_prefetch(
+                            &query_sig,
+                            query_mh,
+                            Some(prefetch_output.clone()),
+                            &matchlist,
+                            options,
+                        )
+                        .is_ok();
 
                         // Now, do the gather!
-                        consume_query_by_gather(
+                        let gather_outputs = GatherOutputs {
+                            gather: Some(gather_output.clone()),
+                            remaining: remaining_output.clone(),
+                            coverage: coverage_output.clone(),
+                            krona: krona_output.clone().zip(lineages.as_ref()),
+                            columns: columns.clone(),
+                            format: output_format,
+                            command: "fastmultigather",
+                            results_channel: None,
+                            report: report.as_ref(),
+                            checkpoint,
+                        };
+                        match consume_query_by_gather(
                             query_sig.clone(),
                             scaled as u64,
                             matchlist,
                             threshold_hashes,
-                            Some(gather_output),
-                        )
-                        .ok();
+                            gather,
+                            gather_outputs,
+                            options,
+                        ) {
+                            Ok(_) => {
+                                let mut produced = vec![("gather", gather_output)];
+                                if prefetch_ok {
+                                    produced.push(("prefetch", prefetch_output));
+                                }
+                                if let Some(path) = remaining_output {
+                                    produced.push(("remaining", path));
+                                }
+                                if let Some(path) = coverage_output {
+                                    produced.push(("coverage", path));
+                                }
+                                if let Some(path) = krona_output {
+                                    produced.push(("krona", path));
+                                }
+                                if let Err(e) = index.record(
+                                    &options.anonymize_query(&name),
+                                    &query_sig.md5sum(),
+                                    produced,
+                                ) {
+                                    eprintln!(
+                                        "Error recording outputs for '{}' in '{}': {}",
+                                        location,
+                                        index.path(),
+                                        e
+                                    );
+                                    let _ = failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Error gathering matches for '{}': {}", location, e)
+                            }
+                        }
                     } else {
                         println!("No matches to '{}'", location);
+                        if let Some(report) = &report {
+                            report.add_query(&name, &query_sig.md5sum());
+                        }
                     }
                 } else {
-                    // different warning here? Could not load sig from record??
-                    eprintln!(
-                        "WARNING: no compatible sketches in path '{}'",
-                        record.internal_location()
+                    size_filter.skip(
+                        &name,
+                        &query_sig.md5sum(),
+                        0,
+                        SkipReason::NoCompatibleSketch,
                     );
-                    let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                 }
             }
             Err(_) => {
@@ -127,6 +336,7 @@ pub fn fastmultigather(
             }
         }
     });
+    progress.finish();
 
     println!(
         "DONE. Processed {} queries total.",
@@ -149,5 +359,22 @@ pub fn fastmultigather(
         );
     }
 
+    // queries that were skipped are always reported, next to the outputs.
+    let skipped_output = match (skipped_output, &output_dir) {
+        (Some(path), _) => Some(path),
+        _ if size_filter.n_skipped() == 0 => None,
+        (None, Some(dir)) => Some(dir.path("skipped_queries.csv")),
+        (None, None) => Some("skipped_queries.csv".to_string()),
+    };
+    size_filter.report(skipped_output, options)?;
+
+    if let Some(dir) = output_dir {
+        dir.finish()?;
+    }
+    report_downsampling(downsampled_output, options)?;
+    if let Some(report) = report {
+        report.write()?;
+    }
+
     Ok(())
 }
@@ -1,30 +1,134 @@
+use anyhow::anyhow;
 use sourmash::index::revindex::RevIndex;
 use sourmash::prelude::*;
+use sourmash::signature::Signature;
 use std::path::Path;
 
-use crate::utils::{load_collection, ReportType};
+use crate::progress::Progress;
+use crate::utils::{
+    clear_revindex_cache, load_collection, read_index_stamp, resolve_revindex_database, sigwriter,
+    write_index_stamp, Policies, ReportType, ZipMessage, INDEX_SCHEMA_VERSION,
+};
 
 pub fn index<P: AsRef<Path>>(
     siglist: String,
     selection: &Selection,
     output: P,
     colors: bool,
-    allow_failed_sigpaths: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+    policies: &Policies,
+) -> anyhow::Result<()> {
     println!("Loading siglist");
 
-    let collection = load_collection(
-        &siglist,
-        selection,
-        ReportType::General,
-        allow_failed_sigpaths,
-    )?;
+    let collection = load_collection(&siglist, selection, ReportType::General, policies)?;
 
-    RevIndex::create(
+    // any handle cached for a previous database at this path is now stale
+    clear_revindex_cache(Some(output.as_ref()));
+
+    // RevIndex::create reports no progress, so show how long it has run.
+    let progress = Progress::spinner(&format!("indexing {} sketches", collection.len()));
+    let created = RevIndex::create(
         output.as_ref(),
         collection.select(selection)?.try_into()?,
         colors,
-    )?;
+    );
+    progress.finish();
+    created?;
+    write_index_stamp(output.as_ref())?;
+
+    Ok(())
+}
+
+/// Upgrade an existing index to the current schema in place, where that is
+/// possible without rebuilding it. Currently the only supported upgrade is
+/// stamping an unstamped index whose RocksDB layout is still readable.
+pub fn index_migrate(index: camino::Utf8PathBuf) -> anyhow::Result<()> {
+    let index = resolve_revindex_database(&index)?;
+
+    match read_index_stamp(&index)? {
+        Some(stamp) if stamp.schema_version == INDEX_SCHEMA_VERSION => {
+            eprintln!(
+                "Index '{}' is already at schema version {}; nothing to do.",
+                index, INDEX_SCHEMA_VERSION
+            );
+        }
+        Some(stamp) => bail!(
+            "no migration is available from schema version {} to {}; please rebuild the index with 'sourmash scripts index'.",
+            stamp.schema_version,
+            INDEX_SCHEMA_VERSION
+        ),
+        None => {
+            // make sure the current sourmash core can actually read it.
+            clear_revindex_cache(Some(&index));
+            if let Err(e) = RevIndex::open(&index, true, None) {
+                bail!(
+                    "cannot migrate index '{}' ({}); please rebuild the index with 'sourmash scripts index'.",
+                    index,
+                    e
+                );
+            }
+            write_index_stamp(&index)?;
+            eprintln!(
+                "Stamped index '{}' with schema version {}.",
+                index, INDEX_SCHEMA_VERSION
+            );
+        }
+    }
 
     Ok(())
 }
+
+/// Build a RevIndex from a stream of signatures in JSON format, e.g. as
+/// yielded by a Python iterator. The signatures are saved to the
+/// `sketches_output` zip file, which the index refers to for storage.
+pub fn index_from_iter<I, P>(
+    sigs: I,
+    selection: &Selection,
+    output: P,
+    sketches_output: String,
+    colors: bool,
+    policies: &Policies,
+) -> anyhow::Result<()>
+where
+    I: Iterator<Item = anyhow::Result<String>>,
+    P: AsRef<Path>,
+{
+    if !sketches_output.ends_with(".zip") {
+        bail!("Sketches output must be a zip file.");
+    }
+
+    // set up a channel and a thread that writes the signatures to a zip file
+    let (send, recv) = std::sync::mpsc::channel::<ZipMessage>();
+    let thrd = sigwriter(recv, sketches_output.clone());
+
+    let mut n_sigs = 0;
+    for (i, sig_json) in sigs.enumerate() {
+        let sigs = sig_json
+            .and_then(|json| Ok(Signature::from_reader(json.as_bytes())?))
+            .map_err(|e| anyhow!("Error reading signature {} from iterator: {}", i, e));
+        let sigs = match sigs {
+            Ok(sigs) => sigs,
+            Err(e) => {
+                drop(send);
+                let _ = thrd.join();
+                return Err(e);
+            }
+        };
+        n_sigs += sigs.len();
+        send.send(ZipMessage::SignatureData(sigs))?;
+    }
+    send.send(ZipMessage::WriteManifest)?;
+    drop(send);
+
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+
+    if n_sigs == 0 {
+        bail!("No signatures read from iterator, exiting.");
+    }
+    eprintln!(
+        "Saved {} signatures from iterator to '{}'",
+        n_sigs, sketches_output
+    );
+
+    index(sketches_output, selection, output, colors, policies)
+}
@@ -0,0 +1,183 @@
+/// indexset: search a directory of ksize-specific RocksDB indexes, e.g.
+/// one each for k=21, k=31 and k=51, as one logical database.
+use anyhow::Result;
+use camino::Utf8PathBuf as PathBuf;
+use std::collections::BTreeMap;
+
+use sourmash::encodings::HashFunctions;
+use sourmash::selection::Selection;
+
+use crate::check::collection_from_revindex;
+use crate::errors::{BranchwaterError, ErrorCode};
+use crate::utils::{is_revindex_database, revindexes_in};
+
+/// The RocksDB indexes directly inside a directory, by ksize and moltype.
+pub struct IndexSet {
+    path: PathBuf,
+    members: BTreeMap<(u32, String), PathBuf>,
+}
+
+impl IndexSet {
+    /// Is 'path' a directory of RocksDB indexes, rather than an index?
+    pub fn is_index_set(path: &PathBuf) -> bool {
+        path.is_dir() && !is_revindex_database(path) && !revindexes_in(path).is_empty()
+    }
+
+    /// Find the indexes in 'path', and the ksize and moltype of each from
+    /// its manifest. Each index must hold a single ksize and moltype, and
+    /// no two indexes may share one.
+    pub fn open(path: &PathBuf) -> Result<Self> {
+        let mut members = BTreeMap::new();
+        for index in revindexes_in(path) {
+            let collection = collection_from_revindex(&index).map_err(|e| {
+                BranchwaterError::new(
+                    ErrorCode::IndexCorrupt,
+                    format!("could not read manifest of index '{}': {}", index, e),
+                )
+            })?;
+            let mut params: Vec<(u32, String)> = collection
+                .manifest()
+                .iter()
+                .map(|record| (record.ksize(), record.moltype().to_string()))
+                .collect();
+            params.sort();
+            params.dedup();
+
+            let key = match params.as_slice() {
+                [key] => key.clone(),
+                [] => {
+                    eprintln!("WARNING: index '{}' is empty; ignoring.", index);
+                    continue;
+                }
+                _ => bail!(
+                    "index '{}' in index set '{}' holds more than one ksize or moltype; each index in a set must hold exactly one.",
+                    index,
+                    path
+                ),
+            };
+            if let Some(other) = members.get(&key) {
+                bail!(
+                    "indexes '{}' and '{}' in index set '{}' both hold k={} {}.",
+                    other,
+                    index,
+                    path,
+                    key.0,
+                    key.1
+                );
+            }
+            members.insert(key, index);
+        }
+
+        if members.is_empty() {
+            bail!(BranchwaterError::new(
+                ErrorCode::NoCompatibleSketches,
+                format!("index set '{}' contains no non-empty indexes", path)
+            ));
+        }
+
+        Ok(IndexSet {
+            path: path.clone(),
+            members,
+        })
+    }
+
+    /// The index for 'ksize' and 'moltype'.
+    pub fn index_for(&self, ksize: u32, moltype: HashFunctions) -> Result<PathBuf> {
+        let moltype = moltype.to_string();
+        match self.members.get(&(ksize, moltype.clone())) {
+            Some(index) => Ok(index.clone()),
+            None => bail!(BranchwaterError::new(
+                ErrorCode::NoCompatibleSketches,
+                format!(
+                    "index set '{}' has no index for k={} {}; available: {}",
+                    self.path,
+                    ksize,
+                    moltype,
+                    self.describe()
+                )
+            )),
+        }
+    }
+
+    /// Each ksize with an index for 'moltype', and that index.
+    pub fn indexes_for_moltype(&self, moltype: HashFunctions) -> Result<Vec<(u32, PathBuf)>> {
+        let moltype = moltype.to_string();
+        let indexes: Vec<(u32, PathBuf)> = self
+            .members
+            .iter()
+            .filter(|((_, m), _)| *m == moltype)
+            .map(|((k, _), index)| (*k, index.clone()))
+            .collect();
+        if indexes.is_empty() {
+            bail!(BranchwaterError::new(
+                ErrorCode::NoCompatibleSketches,
+                format!(
+                    "index set '{}' has no {} indexes; available: {}",
+                    self.path,
+                    moltype,
+                    self.describe()
+                )
+            ));
+        }
+        Ok(indexes)
+    }
+
+    fn describe(&self) -> String {
+        self.members
+            .keys()
+            .map(|(k, m)| format!("k={} {}", k, m))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Tag an output path with a ksize, e.g. 'results.csv' becomes
+/// 'results.k21.csv'. Standard output ('-') is left alone.
+pub fn output_for_ksize(path: &str, ksize: u32) -> String {
+    if path == "-" {
+        return path.to_string();
+    }
+    let path = PathBuf::from(path);
+    let name = path.file_name().unwrap_or_default();
+    let (name, gz) = match name.strip_suffix(".gz") {
+        Some(name) => (name, ".gz"),
+        None => (name, ""),
+    };
+    let tagged = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}.k{}.{}{}", stem, ksize, ext, gz),
+        _ => format!("{}.k{}{}", name, ksize, gz),
+    };
+    path.with_file_name(tagged).to_string()
+}
+
+/// The indexes to search when 'path' is an index set: the one matching
+/// 'selection', or with 'all_ksizes', one per ksize of the selected
+/// moltype. Returns `None` if 'path' is not an index set, or, without
+/// 'all_ksizes', if it holds just one index, which is then searched as
+/// if it were given directly (see `find_revindex_database`).
+pub fn resolve_index_set(
+    path: &PathBuf,
+    selection: &Selection,
+    all_ksizes: bool,
+) -> Result<Option<Vec<(u32, PathBuf)>>> {
+    if !all_ksizes && revindexes_in(path).len() == 1 {
+        return Ok(None);
+    }
+    if !IndexSet::is_index_set(path) {
+        if all_ksizes {
+            bail!(
+                "--all-ksizes requires a directory of RocksDB indexes, but '{}' is not one.",
+                path
+            );
+        }
+        return Ok(None);
+    }
+    let set = IndexSet::open(path)?;
+    let moltype = selection.moltype().expect("moltype is always selected");
+    if all_ksizes {
+        Ok(Some(set.indexes_for_moltype(moltype)?))
+    } else {
+        let ksize = selection.ksize().expect("ksize is always selected");
+        Ok(Some(vec![(ksize, set.index_for(ksize, moltype)?)]))
+    }
+}
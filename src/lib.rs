@@ -1,322 +1,139 @@
-/// Python interface Rust code for sourmash_plugin_branchwater.
-use pyo3::prelude::*;
+//! sourmash_plugin_branchwater: fast, parallel sketch search, gather,
+//! indexing and sketching for sourmash.
+//!
+//! The crate has two features:
+//!
+//! * `python` (default) builds the pyo3 extension module behind the
+//!   `sourmash scripts` commands. It implies `lib`.
+//! * `lib` exposes a Rust API for loading collections, running gather,
+//!   and writing results, without depending on pyo3:
+//!
+//! ```toml
+//! [dependencies]
+//! sourmash_plugin_branchwater = { version = "0.9", default-features = false, features = ["lib"] }
+//! ```
+//!
+//! The API is organized as:
+//!
+//! * [`collection`]: load sketches from zipfiles, manifests, pathlists,
+//!   signature files and RocksDB indexes, with the same loading policies
+//!   as the command line.
+//! * [`gather`]: prefetch and greedy min-set-cov gather of a query against
+//!   loaded sketches.
+//! * [`writers`]: stream results to CSV, or sketches to zipfiles, from a
+//!   dedicated writer thread.
+//!
+//! Errors with a stable [`ErrorCode`] are returned as [`BranchwaterError`]s,
+//! which can be found with `anyhow::Error::downcast_ref` or [`error_code`].
+//!
+//! All functions use the global rayon thread pool.
+
+#![cfg_attr(not(feature = "python"), allow(dead_code))]
 
 #[macro_use]
 extern crate simple_error;
 
-mod utils;
-use crate::utils::build_selection;
-use crate::utils::is_revindex_database;
+mod abundance;
+mod betadiversity;
+mod biom;
 mod check;
+mod checkpoint;
 mod cluster;
+mod compact;
+mod dryrun;
+mod errors;
 mod fastgather;
+mod fastmanysearch;
 mod fastmultigather;
 mod index;
+mod indexset;
 mod manysearch;
 mod manysketch;
 mod mastiff_manygather;
 mod mastiff_manysearch;
+mod matchlist;
 mod multisearch;
 mod pairwise;
-
-use camino::Utf8PathBuf as PathBuf;
-
-#[pyfunction]
-fn do_manysearch(
-    querylist_path: String,
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let againstfile_path: PathBuf = siglist_path.clone().into();
-    let selection = build_selection(ksize, scaled, &moltype);
-    eprintln!("selection scaled: {:?}", selection.scaled());
-    let allow_failed_sigpaths = true;
-
-    // if siglist_path is revindex, run mastiff_manysearch; otherwise run manysearch
-    if is_revindex_database(&againstfile_path) {
-        match mastiff_manysearch::mastiff_manysearch(
-            querylist_path,
-            againstfile_path,
-            &selection,
-            threshold,
-            output_path,
-            allow_failed_sigpaths,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    } else {
-        match manysearch::manysearch(
-            querylist_path,
-            siglist_path,
-            &selection,
-            threshold,
-            output_path,
-            allow_failed_sigpaths,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-fn do_fastgather(
-    query_filename: String,
-    siglist_path: String,
-    threshold_bp: usize,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    output_path_prefetch: Option<String>,
-    output_path_gather: Option<String>,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    match fastgather::fastgather(
-        query_filename,
-        siglist_path,
-        threshold_bp,
-        scaled,
-        &selection,
-        output_path_prefetch,
-        output_path_gather,
-        allow_failed_sigpaths,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-fn do_fastmultigather(
-    query_filenames: String,
-    siglist_path: String,
-    threshold_bp: usize,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let againstfile_path: camino::Utf8PathBuf = siglist_path.clone().into();
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    // if a siglist path is a revindex, run mastiff_manygather. If not, run multigather
-    if is_revindex_database(&againstfile_path) {
-        match mastiff_manygather::mastiff_manygather(
-            query_filenames,
-            againstfile_path,
-            &selection,
-            threshold_bp,
-            output_path,
-            allow_failed_sigpaths,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    } else {
-        if output_path.is_some() {
-            bail!("output path specified, but not running fastmultigather against a rocksdb. See issue #239");
-        }
-        match fastmultigather::fastmultigather(
-            query_filenames,
-            siglist_path,
-            threshold_bp,
-            scaled,
-            &selection,
-            allow_failed_sigpaths,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    }
-}
-
-#[pyfunction]
-fn set_global_thread_pool(num_threads: usize) -> PyResult<usize> {
-    if std::panic::catch_unwind(|| {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-    })
-    .is_ok()
-    {
-        Ok(rayon::current_num_threads())
-    } else {
-        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-            "Could not set the number of threads. Global thread pool might already be initialized.",
-        ))
-    }
-}
-
-#[pyfunction]
-fn do_index(
-    siglist: String,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    output: String,
-    colors: bool,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = false;
-    match index::index(siglist, &selection, output, colors, allow_failed_sigpaths) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-fn do_check(index: String, quick: bool) -> anyhow::Result<u8> {
-    let idx: PathBuf = index.into();
-    match check::check(idx, quick) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-fn do_multisearch(
-    querylist_path: String,
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    estimate_ani: bool,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    match multisearch::multisearch(
-        querylist_path,
-        siglist_path,
-        threshold,
-        &selection,
-        allow_failed_sigpaths,
-        estimate_ani,
-        output_path,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-fn do_pairwise(
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: usize,
-    moltype: String,
-    estimate_ani: bool,
-    write_all: bool,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-    match pairwise::pairwise(
-        siglist_path,
-        threshold,
-        &selection,
-        allow_failed_sigpaths,
-        estimate_ani,
-        write_all,
-        output_path,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-fn do_manysketch(
-    filelist: String,
-    param_str: String,
-    output: String,
-    singleton: bool,
-    force: bool,
-) -> anyhow::Result<u8> {
-    match manysketch::manysketch(filelist, param_str, output, singleton, force) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
+mod parquet_output;
+mod progress;
+mod rarefy;
+mod report;
+#[macro_use]
+mod schema;
+mod setops;
+mod simulate;
+mod sketchcache;
+mod taxonomy;
+mod timeseries;
+mod utils;
+mod weighted_containment;
+
+#[cfg(feature = "python")]
+mod bindings;
+#[cfg(feature = "sqlite")]
+mod sqlite_output;
+
+#[cfg(feature = "lib")]
+pub use crate::errors::{error_code, BranchwaterError, ErrorCode};
+
+/// Loading sketch collections.
+///
+/// [`load_collection`] loads a collection from any supported input,
+/// keeping only the sketches compatible with a [`Selection`] (see
+/// [`build_selection`]); [`load_sketches`] then loads the selected
+/// sketches into memory.
+#[cfg(feature = "lib")]
+pub mod collection {
+    pub use crate::utils::{
+        build_num_selection, build_selection, find_revindex_database, is_revindex_database,
+        load_collection, load_sketches, open_revindex, open_revindex_cached, parse_moltype_search,
+        Policies, ReportType, SmallSignature,
+    };
+    pub use sourmash::collection::Collection;
+    pub use sourmash::selection::Selection;
 }
 
-#[pyfunction]
-fn do_cluster(
-    pairwise_csv: String,
-    output_clusters: String,
-    similarity_column: String,
-    similarity_threshold: f64,
-    cluster_sizes: Option<String>,
-) -> anyhow::Result<u8> {
-    match cluster::cluster(
-        pairwise_csv,
-        output_clusters,
-        similarity_column,
-        similarity_threshold,
-        cluster_sizes,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
+/// Prefetch and gather.
+///
+/// [`load_sketches_above_threshold`] and [`prefetch`] find the sketches
+/// that overlap a query by at least a threshold number of hashes, held in
+/// a [`MatchList`] that can spill to disk under a memory cap, and
+/// [`consume_query_by_gather`] runs greedy min-set-cov over those
+/// matches, as given in [`GatherOptions`], writing gather results to
+/// [`GatherOutputs`] as CSV, Parquet or JSON Lines, and optionally a
+/// Krona summary of the query's composition using [`Lineages`]. With
+/// [`Checkpointing`], progress is saved as a [`GatherCheckpoint`] so that
+/// an interrupted gather can be resumed.
+#[cfg(feature = "lib")]
+pub mod gather {
+    pub use crate::checkpoint::{Checkpointing, GatherCheckpoint};
+    pub use crate::matchlist::MatchList;
+    pub use crate::taxonomy::Lineages;
+    pub use crate::utils::{
+        branchwater_calculate_gather_stats, consume_query_by_gather, load_sketches_above_threshold,
+        prefetch, write_prefetch, BranchwaterGatherResult, GatherOptions, GatherOutputs,
+        PrefetchResult, RankBy, SpikeIn,
+    };
 }
 
-#[pymodule]
-fn sourmash_plugin_branchwater(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(do_manysearch, m)?)?;
-    m.add_function(wrap_pyfunction!(do_fastgather, m)?)?;
-    m.add_function(wrap_pyfunction!(do_fastmultigather, m)?)?;
-    m.add_function(wrap_pyfunction!(do_index, m)?)?;
-    m.add_function(wrap_pyfunction!(do_check, m)?)?;
-    m.add_function(wrap_pyfunction!(do_manysketch, m)?)?;
-    m.add_function(wrap_pyfunction!(set_global_thread_pool, m)?)?;
-    m.add_function(wrap_pyfunction!(do_multisearch, m)?)?;
-    m.add_function(wrap_pyfunction!(do_pairwise, m)?)?;
-    m.add_function(wrap_pyfunction!(do_cluster, m)?)?;
-    Ok(())
+/// Writing results.
+///
+/// [`csvwriter_thread`] spawns a thread that writes each result sent on a
+/// channel as a CSV row to one or more outputs, or as Parquet or JSON
+/// Lines with [`OutputFormat`]; [`jsonlwriter_thread`] writes JSON Lines
+/// directly. With the `sqlite` feature, `SqliteResultWriter` writes
+/// results to a table of a SQLite database. [`OutputOptions`], such as
+/// float rounding, are given to each writer. [`write_signatures`] saves
+/// sketches to a zipfile.
+#[cfg(feature = "lib")]
+pub mod writers {
+    pub use crate::parquet_output::ParquetResultWriter;
+    pub use crate::schema::ResultSchema;
+    #[cfg(feature = "sqlite")]
+    pub use crate::sqlite_output::SqliteResultWriter;
+    pub use crate::utils::{
+        csvwriter_thread, csvwriter_thread_with_extras, jsonlwriter_thread, open_outputs,
+        write_signatures, AgainstMetadata, JsonlWriter, MultiSearchResult, OutputFormat,
+        OutputOptions, QueryAnonymizer, QueryGroupSummary, SearchResult, WriterExtras,
+    };
 }
@@ -3,57 +3,89 @@
 ///
 /// Note: this function loads all _queries_ into memory, and iterates over
 /// database once.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
-use crate::utils::{csvwriter_thread, load_collection, load_sketches, ReportType, SearchResult};
-use sourmash::ani_utils::ani_from_containment;
-use sourmash::selection::Selection;
-use sourmash::signature::SigsTrait;
-
-pub fn manysearch(
-    query_filepath: String,
-    against_filepath: String,
-    selection: &Selection,
-    threshold: f64,
-    output: Option<String>,
-    allow_failed_sigpaths: bool,
-) -> Result<()> {
-    // Load query collection
-    let query_collection = load_collection(
-        &query_filepath,
-        selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
-    // load all query sketches into memory, downsampling on the way
-    let query_sketchlist = load_sketches(query_collection, selection, ReportType::Query).unwrap();
+use std::sync::mpsc::SyncSender;
+use std::sync::OnceLock;
 
-    // Against: Load all _paths_, not signatures, into memory.
-    let against_collection = load_collection(
-        &against_filepath,
-        selection,
-        ReportType::Against,
-        allow_failed_sigpaths,
-    )?;
+use crate::errors::BranchwaterError;
+use crate::utils::{
+    collapse_identical, csvwriter_thread_with_extras, load_collection, load_sketches,
+    n_hashes_at_scaled, report_downsampling, start_downsample_log, unique_est_kmers,
+    NegativeControls, OutputFormat, OutputOptions, Policies, QuerySizeFilter, ReportType,
+    ScreenResult, SearchResult, SmallSignature, WriterExtras,
+};
+use sourmash::ani_utils::ani_from_containment;
+use sourmash::collection::Collection;
+use sourmash::selection::{Select, Selection};
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
 
-    // set up a multi-producer, single-consumer channel.
-    let (send, recv) = std::sync::mpsc::sync_channel::<SearchResult>(rayon::current_num_threads());
+/// Compare 'query' against one search sketch, tagging the result with
+/// 'moltype', if given.
+fn compare(
+    query: &SmallSignature,
+    against_sig: &Signature,
+    against_mh: &KmerMinHash,
+    moltype: Option<&String>,
+) -> SearchResult {
+    let overlap = query.minhash.count_common(against_mh, true).unwrap() as f64;
+    let scaled = query.minhash.scaled().max(against_mh.scaled());
+    let query_size = query.minhash.size() as f64;
+    let target_size = against_mh.size() as f64;
+    let containment_query_in_target = overlap / query_size;
+    let containment_target_in_query = overlap / target_size;
+    let max_containment = containment_query_in_target.max(containment_target_in_query);
+    let jaccard = overlap / (target_size + query_size - overlap);
 
-    // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let qani = ani_from_containment(containment_query_in_target, against_mh.ksize() as f64);
+    let mani = ani_from_containment(containment_target_in_query, against_mh.ksize() as f64);
 
-    //
-    // Main loop: iterate (in parallel) over all search signature paths,
-    // loading them individually and searching them. Stuff results into
-    // the writer thread above.
-    //
+    SearchResult {
+        query_name: query.name.clone(),
+        query_md5: query.md5sum.clone(),
+        match_name: against_sig.name(),
+        containment: containment_query_in_target,
+        intersect_hashes: overlap as usize,
+        match_md5: Some(against_sig.md5sum()),
+        jaccard: Some(jaccard),
+        max_containment: Some(max_containment),
+        query_unique_est_kmers: Some(unique_est_kmers(
+            n_hashes_at_scaled(&query.minhash, scaled),
+            scaled,
+        )),
+        match_unique_est_kmers: Some(unique_est_kmers(
+            n_hashes_at_scaled(against_mh, scaled),
+            scaled,
+        )),
+        query_containment_ani: Some(qani),
+        match_containment_ani: Some(mani),
+        average_containment_ani: Some((qani + mani) / 2.),
+        max_containment_ani: Some(f64::max(qani, mani)),
+        moltype: moltype.cloned(),
+        in_negative_control: None,
+        match_aliases: None,
+    }
+}
 
+/// Search all queries against each sketch in 'against_collection', sending
+/// results above 'threshold' to 'send'. Rows are tagged with 'moltype', if
+/// given, and with the names in 'aliases' of the match's md5sum, if given.
+/// Returns the number of search sketches processed and skipped.
+fn search_against(
+    query_sketchlist: &[SmallSignature],
+    against_collection: &Collection,
+    threshold: f64,
+    moltype: Option<&String>,
+    aliases: Option<&HashMap<String, String>>,
+    send: &SyncSender<SearchResult>,
+) -> (usize, usize) {
     let processed_sigs = AtomicUsize::new(0);
     let skipped_paths = AtomicUsize::new(0);
-    let failed_paths = AtomicUsize::new(0);
 
     let send = against_collection
         .par_iter()
@@ -69,45 +101,14 @@ pub fn manysearch(
             match against_collection.sig_from_record(record) {
                 Ok(against_sig) => {
                     if let Some(against_mh) = against_sig.minhash() {
+                        // keyed by the md5sum before any downsampling
+                        let match_aliases =
+                            aliases.map(|a| a.get(record.md5()).cloned().unwrap_or_default());
                         for query in query_sketchlist.iter() {
-                            let overlap =
-                                query.minhash.count_common(against_mh, true).unwrap() as f64;
-                            let query_size = query.minhash.size() as f64;
-                            let target_size = against_mh.size() as f64;
-                            let containment_query_in_target = overlap / query_size;
-                            let containment_target_in_query = overlap / target_size;
-                            let max_containment =
-                                containment_query_in_target.max(containment_target_in_query);
-                            let jaccard = overlap / (target_size + query_size - overlap);
-
-                            let qani = ani_from_containment(
-                                containment_query_in_target,
-                                against_mh.ksize() as f64,
-                            );
-                            let mani = ani_from_containment(
-                                containment_target_in_query,
-                                against_mh.ksize() as f64,
-                            );
-                            let query_containment_ani = Some(qani);
-                            let match_containment_ani = Some(mani);
-                            let average_containment_ani = Some((qani + mani) / 2.);
-                            let max_containment_ani = Some(f64::max(qani, mani));
-
-                            if containment_query_in_target > threshold {
-                                results.push(SearchResult {
-                                    query_name: query.name.clone(),
-                                    query_md5: query.md5sum.clone(),
-                                    match_name: against_sig.name(),
-                                    containment: containment_query_in_target,
-                                    intersect_hashes: overlap as usize,
-                                    match_md5: Some(against_sig.md5sum()),
-                                    jaccard: Some(jaccard),
-                                    max_containment: Some(max_containment),
-                                    query_containment_ani,
-                                    match_containment_ani,
-                                    average_containment_ani,
-                                    max_containment_ani,
-                                });
+                            let mut result = compare(query, &against_sig, against_mh, moltype);
+                            if result.containment > threshold {
+                                result.match_aliases = match_aliases.clone();
+                                results.push(result);
                             }
                         }
                     } else {
@@ -131,23 +132,157 @@ pub fn manysearch(
             Some(results)
         })
         .flatten()
-        .try_for_each_with(send, |s, m| s.send(m));
+        .try_for_each_with(send.clone(), |s, m| s.send(m));
 
-    // do some cleanup and error handling -
     if let Err(e) = send {
         eprintln!("Unable to send internal data: {:?}", e);
     }
 
-    if let Err(e) = thrd.join() {
-        eprintln!("Unable to join internal thread: {:?}", e);
+    (processed_sigs.into_inner(), skipped_paths.into_inner())
+}
+
+/// Search queries against 'against_filepath' with 'selection', and then
+/// against each of 'extra_searches', a list of selections (usually of other
+/// moltypes) and the collections to search with them. The query collection
+/// is loaded once; when there are extra searches, each output row is
+/// tagged with the moltype of its search. With 'negative_controls', results
+/// are held until the search finishes, and matches also found by a control
+/// query are flagged or removed. With 'collapse_identical', sketches with
+/// identical content are searched once, and reported as one match with the
+/// names of the others in `match_aliases`.
+#[allow(clippy::too_many_arguments)]
+pub fn manysearch(
+    query_filepath: String,
+    against_filepath: String,
+    selection: &Selection,
+    threshold: f64,
+    output: Vec<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    extras: WriterExtras,
+    extra_searches: Vec<(Selection, String)>,
+    downsampled_output: Option<String>,
+    negative_controls: Option<NegativeControls>,
+    collapse: bool,
+) -> Result<()> {
+    start_downsample_log();
+
+    let tag_moltype = !extra_searches.is_empty();
+    let mut searches = vec![(selection.clone(), against_filepath)];
+    searches.extend(extra_searches);
+
+    // Load query collection; with extra searches, keep all sketches, and
+    // select each search's queries below.
+    let query_selection = if tag_moltype {
+        Selection::default()
+    } else {
+        selection.clone()
+    };
+    let query_collection = load_collection(
+        &query_filepath,
+        &query_selection,
+        ReportType::Query,
+        policies,
+    )?;
+
+    // skip queries that are too small to search meaningfully
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) = std::sync::mpsc::sync_channel::<SearchResult>(rayon::current_num_threads());
+
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let options = extras.options.clone();
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
+
+    // with negative controls, hold results in between search and writer.
+    let (send, controls_thrd) = match negative_controls {
+        Some(controls) => {
+            let (held_send, controls_thrd) = controls.start(send);
+            (held_send, Some(controls_thrd))
+        }
+        None => (send, None),
+    };
+
+    let mut processed_sigs = 0;
+    let mut skipped_paths = 0;
+
+    for (search_selection, against_filepath) in searches.iter() {
+        let moltype = search_selection.moltype().map(|m| m.to_string());
+        let moltype = if tag_moltype { moltype } else { None };
+
+        // load all query sketches for this search into memory, downsampling on the way
+        let queries = Collection::new(
+            query_collection.manifest().clone(),
+            query_collection.storage().clone(),
+        )
+        .select(search_selection)?;
+        if tag_moltype && queries.is_empty() {
+            eprintln!(
+                "WARNING: no {} query sketches; skipping search of '{}'.",
+                moltype.as_deref().unwrap_or_default(),
+                against_filepath
+            );
+            continue;
+        }
+        let query_sketchlist: Vec<_> = load_sketches(queries, search_selection, ReportType::Query)?
+            .into_iter()
+            .filter(|query| size_filter.check(&query.name, &query.md5sum, query.minhash.size()))
+            .collect();
+
+        // Against: Load all _paths_, not signatures, into memory.
+        let against_collection = load_collection(
+            against_filepath,
+            search_selection,
+            ReportType::Against,
+            policies,
+        )?;
+        let (against_collection, aliases) = if collapse {
+            let (collection, aliases) = collapse_identical(against_collection);
+            (collection, Some(aliases))
+        } else {
+            (against_collection, None)
+        };
+
+        //
+        // Main loop: iterate (in parallel) over all search signature paths,
+        // loading them individually and searching them. Stuff results into
+        // the writer thread above.
+        //
+        let (n_processed, n_skipped) = search_against(
+            &query_sketchlist,
+            &against_collection,
+            threshold,
+            moltype.as_ref(),
+            aliases.as_ref(),
+            &send,
+        );
+        processed_sigs += n_processed;
+        skipped_paths += n_skipped;
+    }
+    drop(send);
+
+    if let Some(controls_thrd) = controls_thrd {
+        if let Err(e) = controls_thrd
+            .join()
+            .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+        {
+            bail!("Error applying negative controls: {}", e);
+        }
     }
 
-    // done!
-    let i: usize = processed_sigs.fetch_max(0, atomic::Ordering::SeqCst);
-    eprintln!("DONE. Processed {} search sigs", i);
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
 
-    let skipped_paths = skipped_paths.load(atomic::Ordering::SeqCst);
-    let failed_paths = failed_paths.load(atomic::Ordering::SeqCst);
+    // done!
+    eprintln!("DONE. Processed {} search sigs", processed_sigs);
 
     if skipped_paths > 0 {
         eprintln!(
@@ -155,12 +290,153 @@ pub fn manysearch(
             skipped_paths
         );
     }
-    if failed_paths > 0 {
+
+    size_filter.report(skipped_output, &options)?;
+    report_downsampling(downsampled_output, &options)?;
+
+    Ok(())
+}
+
+/// Screen each query against 'against_filepath', stopping the search for a
+/// query at its first match above 'threshold', and stopping entirely once
+/// every query has matched. Writes one row per query, with whether it
+/// matched and the first match found; with more than one thread, which
+/// match is found first may vary between runs.
+#[allow(clippy::too_many_arguments)]
+pub fn screen(
+    query_filepath: String,
+    against_filepath: String,
+    selection: &Selection,
+    threshold: f64,
+    output: Vec<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    downsampled_output: Option<String>,
+    output_format: OutputFormat,
+    options: &OutputOptions,
+) -> Result<()> {
+    start_downsample_log();
+
+    // load all query sketches into memory, downsampling on the way
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+    let queries: Vec<_> = load_sketches(query_collection, selection, ReportType::Query)?
+        .into_iter()
+        .filter(|query| size_filter.check(&query.name, &query.md5sum, query.minhash.size()))
+        .collect();
+
+    // Against: Load all _paths_, not signatures, into memory.
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
+
+    let first_match: Vec<OnceLock<SearchResult>> =
+        queries.iter().map(|_| OnceLock::new()).collect();
+    let n_found = AtomicUsize::new(0);
+    let processed_sigs = AtomicUsize::new(0);
+    let skipped_paths = AtomicUsize::new(0);
+
+    against_collection.par_iter().for_each(|(_idx, record)| {
+        // every query has matched; don't load any more search sketches.
+        if n_found.load(atomic::Ordering::SeqCst) == queries.len() {
+            return;
+        }
+        let i = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+        if i % 1000 == 0 && i > 0 {
+            eprintln!("Processed {} search sigs", i);
+        }
+
+        // against downsampling happens here
+        match against_collection.sig_from_record(record) {
+            Ok(against_sig) => {
+                if let Some(against_mh) = against_sig.minhash() {
+                    for (query, first) in queries.iter().zip(first_match.iter()) {
+                        if first.get().is_some() {
+                            continue;
+                        }
+                        let result = compare(query, &against_sig, against_mh, None);
+                        if result.containment > threshold && first.set(result).is_ok() {
+                            n_found.fetch_add(1, atomic::Ordering::SeqCst);
+                        }
+                    }
+                } else {
+                    eprintln!(
+                        "WARNING: no compatible sketches in path '{}'",
+                        record.internal_location()
+                    );
+                    let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+            }
+            Err(err) => {
+                eprintln!("Sketch loading error: {}", err);
+                eprintln!(
+                    "WARNING: no compatible sketches in path '{}'",
+                    record.internal_location()
+                );
+                let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+        }
+    });
+
+    // write one row per query, in query order.
+    let (send, recv) = std::sync::mpsc::sync_channel::<ScreenResult>(rayon::current_num_threads());
+    let extras = WriterExtras {
+        format: output_format,
+        command: "manysearch",
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
+
+    for (query, first) in queries.iter().zip(first_match) {
+        let first = first.into_inner();
+        let result = ScreenResult {
+            query_name: query.name.clone(),
+            query_md5: query.md5sum.clone(),
+            present: first.is_some(),
+            match_name: first.as_ref().map(|m| m.match_name.clone()),
+            match_md5: first.as_ref().and_then(|m| m.match_md5.clone()),
+            containment: first.as_ref().map(|m| m.containment),
+            intersect_hashes: first.as_ref().map(|m| m.intersect_hashes),
+            query_containment_ani: first.as_ref().and_then(|m| m.query_containment_ani),
+        };
+        if let Err(e) = send.send(result) {
+            eprintln!("Unable to send internal data: {:?}", e);
+            break;
+        }
+    }
+    drop(send);
+
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+
+    // done!
+    let n_found = n_found.into_inner();
+    eprintln!(
+        "DONE. Processed {} of {} search sigs; {} of {} queries matched.",
+        processed_sigs.into_inner(),
+        against_collection.len(),
+        n_found,
+        queries.len()
+    );
+
+    let skipped_paths = skipped_paths.into_inner();
+    if skipped_paths > 0 {
         eprintln!(
-            "WARNING: {} search paths failed to load. See error messages above.",
-            failed_paths
+            "WARNING: skipped {} search paths - no compatible signatures.",
+            skipped_paths
         );
     }
 
+    size_filter.report(skipped_output, options)?;
+    report_downsampling(downsampled_output, options)?;
+
     Ok(())
 }
@@ -2,15 +2,20 @@
 use anyhow::{anyhow, Result};
 use rayon::prelude::*;
 
-use crate::utils::{load_fasta_fromfile, sigwriter, Params, ZipMessage};
+use crate::progress::Progress;
+use crate::utils::{
+    channel_capacity, check_output_template, load_fasta_fromfile, sigwriter, FastaData,
+    NameSanitizer, Params, ZipMessage,
+};
 use camino::Utf8Path as Path;
 use needletail::parse_fastx_file;
 use sourmash::cmd::ComputeParameters;
 use sourmash::signature::Signature;
+use std::collections::HashMap;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
-fn parse_params_str(params_strs: String) -> Result<Vec<Params>, String> {
+pub fn parse_params_str(params_strs: String) -> Result<Vec<Params>, String> {
     let mut unique_params: std::collections::HashSet<Params> = std::collections::HashSet::new();
 
     // split params_strs by _ and iterate over each param
@@ -79,7 +84,42 @@ fn parse_params_str(params_strs: String) -> Result<Vec<Params>, String> {
     Ok(unique_params.into_iter().collect())
 }
 
-fn build_siginfo(params: &[Params], moltype: &str) -> Vec<Signature> {
+/// Parse the distinct `param_string` overrides of the inputs, so that
+/// every row is validated before any sketching starts.
+pub fn parse_row_params(fileinfo: &[FastaData]) -> Result<HashMap<String, Vec<Params>>> {
+    let mut row_params = HashMap::new();
+    for fastadata in fileinfo {
+        if let Some(param_string) = &fastadata.param_string {
+            if !row_params.contains_key(param_string) {
+                let params = parse_params_str(param_string.clone()).map_err(|e| {
+                    anyhow!(
+                        "Error parsing param_string '{}' for '{}': {}",
+                        param_string,
+                        fastadata.name,
+                        e
+                    )
+                })?;
+                row_params.insert(param_string.clone(), params);
+            }
+        }
+    }
+    Ok(row_params)
+}
+
+/// The parameters to sketch 'fastadata' with: its own `param_string` if
+/// given, else the global 'params'.
+pub fn params_for<'a>(
+    fastadata: &FastaData,
+    params: &'a [Params],
+    row_params: &'a HashMap<String, Vec<Params>>,
+) -> &'a [Params] {
+    match &fastadata.param_string {
+        Some(param_string) => &row_params[param_string],
+        None => params,
+    }
+}
+
+pub fn build_siginfo(params: &[Params], moltype: &str) -> Vec<Signature> {
     let mut sigs = Vec::new();
 
     for param in params.iter().cloned() {
@@ -113,12 +153,80 @@ fn build_siginfo(params: &[Params], moltype: &str) -> Vec<Signature> {
     sigs
 }
 
+/// Aim for at least this many sketching tasks per thread when batching
+/// input files, so that a few large files don't leave threads idle.
+const TASKS_PER_THREAD: usize = 16;
+
+/// The most input files sketched in one task.
+const MAX_FILES_PER_TASK: usize = 64;
+
+/// Sequences at least this long are added to each sketch in parallel.
+/// Shorter sequences (e.g. short reads) are added serially, since the
+/// per-sequence cost is then dominated by thread overhead.
+const PAR_SEQUENCE_LEN: usize = 100_000;
+
+/// Add one sequence to every sketch in 'sigs'. For long sequences with
+/// several parameter sets, the sketches are filled in parallel; each
+/// sketch only sees its own hashes, so the results are the same either
+/// way.
+fn add_to_sigs(sigs: &mut [Signature], seq: &[u8], moltype: &str) -> Result<()> {
+    let add = |sig: &mut Signature| -> Result<()> {
+        if moltype == "protein" {
+            sig.add_protein(seq)?;
+        } else {
+            // with force=true, skips k-mers containing invalid bases like 'N'
+            sig.add_sequence(seq, true)?;
+        }
+        Ok(())
+    };
+
+    if sigs.len() > 1 && seq.len() >= PAR_SEQUENCE_LEN {
+        sigs.par_iter_mut().try_for_each(add)
+    } else {
+        sigs.iter_mut().try_for_each(add)
+    }
+}
+
+/// Sketch in-memory sequences, one `(name, sequence, moltype)` record per
+/// sketch set, with the parameters in 'param_str'. Records are sketched in
+/// parallel; the returned signatures are in record order. Records with no
+/// parameters matching their moltype are skipped.
+pub fn sketch_records(
+    records: Vec<(String, Vec<u8>, String)>,
+    param_str: String,
+) -> Result<Vec<Signature>> {
+    let params_vec =
+        parse_params_str(param_str).map_err(|e| anyhow!("Error parsing params string: {}", e))?;
+
+    let sigs = records
+        .par_iter()
+        .map(|(name, seq, moltype)| -> Result<Vec<Signature>> {
+            let mut sigs = build_siginfo(&params_vec, moltype);
+            for sig in sigs.iter_mut() {
+                sig.set_name(name);
+            }
+            add_to_sigs(&mut sigs, seq, moltype)?;
+            Ok(sigs)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let sigs: Vec<Signature> = sigs.into_iter().flatten().collect();
+    if sigs.is_empty() {
+        bail!("No records compatible with provided sketch parameters: no signatures created.");
+    }
+    Ok(sigs)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn manysketch(
     filelist: String,
     param_str: String,
     output: String,
     singleton: bool,
     force: bool,
+    capacity: usize,
+    name_sanitizer: Option<NameSanitizer>,
+    modified_names_output: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let (fileinfo, n_fastas) = match load_fasta_fromfile(filelist, force) {
         Ok((file_info, n_fastas)) => (file_info, n_fastas),
@@ -130,6 +238,9 @@ pub fn manysketch(
         bail!("No files to load, exiting.");
     }
 
+    // rows may override the param string
+    let row_params = parse_row_params(&fileinfo)?;
+
     // if output doesn't end in zip, bail
     if Path::new(&output)
         .extension()
@@ -137,9 +248,11 @@ pub fn manysketch(
     {
         bail!("Output must be a zip file.");
     }
+    // the output may be a template, giving one zip file per parameter set
+    check_output_template(&output)?;
 
     // set up a multi-producer, single-consumer channel that receives Signature
-    let (send, recv) = std::sync::mpsc::sync_channel::<ZipMessage>(rayon::current_num_threads());
+    let (send, recv) = std::sync::mpsc::sync_channel::<ZipMessage>(channel_capacity(capacity));
     // need to use Arc so we can write the manifest after all sigs have written
     let send = std::sync::Arc::new(send);
 
@@ -160,26 +273,46 @@ pub fn manysketch(
     let processed_fastas = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
     let skipped_paths: AtomicUsize = AtomicUsize::new(0);
+    let invalid_names = AtomicUsize::new(0);
 
     // set reporting threshold at every 5% or every 1 fasta, whichever is larger)
     let reporting_threshold = std::cmp::max(n_fastas / 20, 1);
+    let progress = Progress::new(n_fastas, "files");
+
+    let clean_name = |name: &str| match &name_sanitizer {
+        Some(sanitizer) => sanitizer.sanitize(name),
+        None => name.to_string(),
+    };
+
+    // with many small files, hand each task a batch of files to amortize
+    // per-task overhead, while keeping enough tasks to balance the load.
+    let files_per_task = (fileinfo.len() / (rayon::current_num_threads() * TASKS_PER_THREAD))
+        .clamp(1, MAX_FILES_PER_TASK);
 
     let send_result = fileinfo
         .par_iter()
+        .with_min_len(files_per_task)
         .filter_map(|fastadata| {
             let name = &fastadata.name;
             let filenames = &fastadata.paths;
             let moltype = &fastadata.input_type;
             // build sig templates for these sketches from params, check if there are sigs to build
-            let sig_templates = build_siginfo(&params_vec, moltype);
+            let params = params_for(fastadata, &params_vec, &row_params);
+            let sig_templates = build_siginfo(params, moltype);
             // if no sigs to build, skip this iteration
             if sig_templates.is_empty() {
                 skipped_paths.fetch_add(filenames.len(), atomic::Ordering::SeqCst);
                 processed_fastas.fetch_add(1, atomic::Ordering::SeqCst);
+                progress.inc();
                 return None;
             }
 
             let mut sigs = sig_templates.clone();
+            let name = if singleton {
+                name.clone()
+            } else {
+                clean_name(name)
+            };
             // have name / filename been set for each sig yet?
             let mut set_name = false;
             // if merging multiple files, sourmash sets filename as last filename
@@ -188,8 +321,9 @@ pub fn manysketch(
             for filename in filenames {
                 // increment processed_fastas counter; make 1-based for % reporting
                 let i = processed_fastas.fetch_add(1, atomic::Ordering::SeqCst);
+                progress.inc();
                 // progress report at threshold
-                if (i + 1) % reporting_threshold == 0 {
+                if !progress.is_shown() && (i + 1) % reporting_threshold == 0 {
                     let percent_processed = (((i + 1) as f64 / n_fastas as f64) * 100.0).round();
                     eprintln!(
                         "Starting file {}/{} ({}%)",
@@ -213,28 +347,33 @@ pub fn manysketch(
                 while let Some(record_result) = reader.next() {
                     match record_result {
                         Ok(record) => {
+                            let record_name = if singleton {
+                                let record_id = String::from_utf8_lossy(record.id());
+                                if let std::borrow::Cow::Owned(_) = record_id {
+                                    eprintln!(
+                                        "WARNING: record name '{}' in '{}' is not valid UTF-8; invalid bytes replaced.",
+                                        record_id, filename
+                                    );
+                                    invalid_names.fetch_add(1, atomic::Ordering::SeqCst);
+                                }
+                                clean_name(&record_id)
+                            } else {
+                                String::new()
+                            };
                             // do we need to normalize to make sure all the bases are consistently capitalized?
                             // let norm_seq = record.normalize(false);
                             sigs.iter_mut().for_each(|sig| {
                                 if singleton {
-                                    let record_name = std::str::from_utf8(record.id())
-                                        .expect("could not get record id");
-                                    sig.set_name(record_name);
+                                    sig.set_name(&record_name);
                                     sig.set_filename(filename.as_str());
                                 } else if !set_name {
-                                    sig.set_name(name);
+                                    sig.set_name(&name);
                                     // sourmash sets filename to last filename if merging fastas
                                     sig.set_filename(last_filename.as_str());
                                 };
-                                if moltype == "protein" {
-                                    sig.add_protein(&record.seq())
-                                        .expect("Failed to add protein");
-                                } else {
-                                    sig.add_sequence(&record.seq(), true)
-                                        .expect("Failed to add sequence");
-                                    // if not force, panics with 'N' in dna sequence
-                                }
                             });
+                            add_to_sigs(&mut sigs, &record.seq(), moltype)
+                                .expect("Failed to add sequence");
                             if !set_name {
                                 set_name = true;
                             }
@@ -242,12 +381,13 @@ pub fn manysketch(
                         Err(err) => eprintln!("Error while processing record: {:?}", err),
                     }
                     if singleton {
-                        // write sigs immediately to avoid memory issues
-                        if let Err(e) = send.send(ZipMessage::SignatureData(sigs.clone())) {
+                        // write sigs immediately to avoid memory issues, and
+                        // start the next record from fresh templates.
+                        let record_sigs = std::mem::replace(&mut sigs, sig_templates.clone());
+                        if let Err(e) = send.send(ZipMessage::SignatureData(record_sigs)) {
                             eprintln!("Unable to send internal data: {:?}", e);
                             return None;
                         }
-                        sigs = sig_templates.clone();
                     }
                 }
             }
@@ -289,6 +429,7 @@ pub fn manysketch(
     }
 
     // done!
+    progress.finish();
     let i: usize = processed_fastas.load(atomic::Ordering::SeqCst);
     eprintln!("DONE. Processed {} fasta files", i);
 
@@ -315,5 +456,17 @@ pub fn manysketch(
         );
     }
 
+    let invalid_names = invalid_names.load(atomic::Ordering::SeqCst);
+    if invalid_names > 0 {
+        eprintln!(
+            "WARNING: {} FASTA record names were not valid UTF-8.",
+            invalid_names
+        );
+    }
+
+    if let Some(sanitizer) = name_sanitizer {
+        sanitizer.report(modified_names_output)?;
+    }
+
     Ok(())
 }
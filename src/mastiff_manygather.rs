@@ -1,45 +1,58 @@
 /// mastiff_manygather: mastiff-indexed version of fastmultigather.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf as PathBuf;
 use rayon::prelude::*;
-use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::index::revindex::RevIndexOps;
 use sourmash::prelude::*;
 use sourmash::signature::SigsTrait;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
+use crate::errors::BranchwaterError;
 use crate::utils::{
-    csvwriter_thread, is_revindex_database, load_collection, BranchwaterGatherResult, ReportType,
+    channel_capacity, check_index_seed, csvwriter_thread_with_extras, load_collection,
+    load_done_query_md5s, open_revindex_cached, par_iter_scheduled, resolve_revindex_database,
+    skip_done_queries, BranchwaterGatherResult, Policies, QuerySizeFilter, ReportType, Schedule,
+    SkipReason, WriterExtras,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub fn mastiff_manygather(
     queries_file: String,
     index: PathBuf,
     selection: &Selection,
     threshold_bp: usize,
-    output: Option<String>,
-    allow_failed_sigpaths: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
-    }
+    output: Vec<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    capacity: usize,
+    skip_done: Vec<String>,
+    extras: WriterExtras,
+    ignore_abundance: bool,
+    report_no_matches: bool,
+    schedule: Schedule,
+) -> Result<()> {
+    let index = resolve_revindex_database(&index)?;
     // Open database once
-    let db = RevIndex::open(index, true, None)?;
+    let db = open_revindex_cached(&index)?;
+    check_index_seed(&index, policies.hash_seed)?;
     println!("Loaded DB");
 
-    let query_collection = load_collection(
-        &queries_file,
-        selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
+    let query_collection = load_collection(&queries_file, selection, ReportType::Query, policies)?;
+    // skip queries already searched in a previous run
+    let done = load_done_query_md5s(&skip_done)?;
+    let query_collection = skip_done_queries(query_collection, &done);
 
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
-        std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(rayon::current_num_threads());
+        std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(channel_capacity(capacity));
 
     // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let options = extras.options.clone();
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
 
     //
     // Main loop: iterate (in parallel) over all search signature paths,
@@ -48,11 +61,10 @@ pub fn mastiff_manygather(
     //
 
     let processed_sigs = AtomicUsize::new(0);
-    let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
 
-    let send = query_collection
-        .par_iter()
+    let send = par_iter_scheduled(&query_collection, selection, schedule)
         .filter_map(|(_idx, record)| {
             let threshold = threshold_bp / selection.scaled()? as usize;
             let ksize = selection.ksize()?;
@@ -63,6 +75,29 @@ pub fn mastiff_manygather(
                     let mut results = vec![];
                     if let Some(query_mh) = query_sig.minhash() {
                         let _ = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                        // treat the query as flat, if requested
+                        let mut query_mh = query_mh.clone();
+                        if ignore_abundance {
+                            query_mh.disable_abundance();
+                        }
+                        let query_mh = &query_mh;
+                        // an empty query can't match anything; report it up front.
+                        if query_mh.size() == 0 {
+                            size_filter.skip(
+                                &query_sig.name(),
+                                &query_sig.md5sum(),
+                                0,
+                                SkipReason::NoHashesAfterDownsampling,
+                            );
+                            return None;
+                        }
+                        if !size_filter.check(
+                            &query_sig.name(),
+                            &query_sig.md5sum(),
+                            query_mh.size(),
+                        ) {
+                            return None;
+                        }
                         // Gather!
                         let (counter, query_colors, hash_to_color) =
                             db.prepare_gather_counters(query_mh);
@@ -76,7 +111,14 @@ pub fn mastiff_manygather(
                             Some(selection.clone()),
                         );
                         if let Ok(matches) = matches {
+                            let scaled = query_mh.scaled() as usize;
                             for match_ in &matches {
+                                // sourmash reports these in bp; convert back
+                                // to hashes at the common scaled.
+                                let match_intersect_hashes = match_.intersect_bp() / scaled;
+                                let match_n_hashes =
+                                    (match_intersect_hashes as f64 / match_.f_match_orig()).round()
+                                        as usize;
                                 results.push(BranchwaterGatherResult {
                                     intersect_bp: match_.intersect_bp(),
                                     f_orig_query: match_.f_orig_query(),
@@ -109,6 +151,10 @@ pub fn mastiff_manygather(
                                     n_unique_weighted_found: match_.n_unique_weighted_found(),
                                     sum_weighted_found: match_.sum_weighted_found(),
                                     total_weighted_hashes: match_.total_weighted_hashes(),
+                                    match_n_hashes,
+                                    match_intersect_hashes,
+                                    match_intersect_hashes_at_rank: match_.unique_intersect_bp()
+                                        / scaled,
 
                                     query_containment_ani_ci_low: match_
                                         .query_containment_ani_ci_low(),
@@ -118,17 +164,27 @@ pub fn mastiff_manygather(
                                         .match_containment_ani_ci_low(),
                                     match_containment_ani_ci_high: match_
                                         .match_containment_ani_ci_high(),
+                                    normalized_abundance: None,
                                 });
                             }
+                            if matches.is_empty() && report_no_matches {
+                                results.push(BranchwaterGatherResult::no_match(
+                                    query_sig.filename(),
+                                    query_sig.name(),
+                                    query_sig.md5sum(),
+                                    query_mh,
+                                ));
+                            }
                         } else {
                             eprintln!("Error gathering matches: {:?}", matches.err());
                         }
                     } else {
-                        eprintln!(
-                            "WARNING: no compatible sketches in path '{}'",
-                            query_sig.filename()
+                        size_filter.skip(
+                            &query_sig.name(),
+                            &query_sig.md5sum(),
+                            0,
+                            SkipReason::NoCompatibleSketch,
                         );
-                        let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                     }
 
                     if results.is_empty() {
@@ -152,23 +208,19 @@ pub fn mastiff_manygather(
         eprintln!("Unable to send internal data: {:?}", e);
     }
 
-    if let Err(e) = thrd.join() {
-        eprintln!("Unable to join internal thread: {:?}", e);
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
     }
 
     // done!
     let i: usize = processed_sigs.fetch_max(0, atomic::Ordering::SeqCst);
     eprintln!("DONE. Processed {} search sigs", i);
 
-    let skipped_paths = skipped_paths.load(atomic::Ordering::SeqCst);
     let failed_paths = failed_paths.load(atomic::Ordering::SeqCst);
 
-    if skipped_paths > 0 {
-        eprintln!(
-            "WARNING: skipped {} query paths - no compatible signatures.",
-            skipped_paths
-        );
-    }
     if failed_paths > 0 {
         eprintln!(
             "WARNING: {} query paths failed to load. See error messages above.",
@@ -176,5 +228,7 @@ pub fn mastiff_manygather(
         );
     }
 
+    size_filter.report(skipped_output, &options)?;
+
     Ok(())
 }
@@ -1,48 +1,101 @@
 /// mastiff_manysearch: mastiff-indexed version of manysearch.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf as PathBuf;
 use rayon::prelude::*;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::sync_channel;
 
 use sourmash::ani_utils::ani_from_containment;
 use sourmash::index::revindex::{RevIndex, RevIndexOps};
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
 
+use crate::errors::BranchwaterError;
 use crate::utils::{
-    csvwriter_thread, is_revindex_database, load_collection, ReportType, SearchResult,
+    check_index_seed, csvwriter_thread_with_extras, load_collection, open_revindex_cached,
+    resolve_revindex_database, unique_est_kmers, Policies, QuerySizeFilter, ReportType,
+    SearchResult, WriterExtras,
 };
 
+/// Search 'query_mh' against the RevIndex 'db', returning a result for
+/// each match that contains at least 'minimum_containment' of the query.
+pub(crate) fn search_revindex(
+    db: &RevIndex,
+    query_name: String,
+    query_md5: String,
+    query_mh: &KmerMinHash,
+    minimum_containment: f64,
+) -> Vec<SearchResult> {
+    let query_size = query_mh.size();
+    let counter = db.counter_for_query(query_mh);
+    let matches = db.matches_from_counter(counter, minimum_containment as usize);
+
+    // filter the matches for containment
+    matches
+        .into_iter()
+        .filter_map(|(path, overlap)| {
+            let containment = overlap as f64 / query_size as f64;
+            if containment < minimum_containment {
+                return None;
+            }
+            let query_containment_ani =
+                Some(ani_from_containment(containment, query_mh.ksize() as f64));
+
+            Some(SearchResult {
+                query_name: query_name.clone(),
+                query_md5: query_md5.clone(),
+                match_name: path,
+                containment,
+                intersect_hashes: overlap,
+                match_md5: None,
+                jaccard: None,
+                max_containment: None,
+                query_unique_est_kmers: Some(unique_est_kmers(query_size, query_mh.scaled())),
+                match_unique_est_kmers: None,
+                query_containment_ani,
+                match_containment_ani: None,
+                average_containment_ani: None,
+                max_containment_ani: None,
+                moltype: None,
+                in_negative_control: None,
+                match_aliases: None,
+            })
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn mastiff_manysearch(
     queries_path: String,
     index: PathBuf,
     selection: &Selection,
     minimum_containment: f64,
-    output: Option<String>,
-    allow_failed_sigpaths: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
-    }
+    output: Vec<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    extras: WriterExtras,
+) -> Result<()> {
+    let index = resolve_revindex_database(&index)?;
     // Open database once
-    let db = RevIndex::open(index, true, None)?;
+    let db = open_revindex_cached(&index)?;
+    check_index_seed(&index, policies.hash_seed)?;
 
     println!("Loaded DB");
 
     // Load query paths
-    let query_collection = load_collection(
-        &queries_path,
-        selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
+    let query_collection = load_collection(&queries_path, selection, ReportType::Query, policies)?;
 
     // set up a multi-producer, single-consumer channel.
-    let (send, recv) = std::sync::mpsc::sync_channel::<SearchResult>(rayon::current_num_threads());
+    let (send, recv) = sync_channel::<SearchResult>(rayon::current_num_threads());
 
     // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let options = extras.options.clone();
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
 
     //
     // Main loop: iterate (in parallel) over all search signature paths,
@@ -53,6 +106,7 @@ pub fn mastiff_manysearch(
     let processed_sigs = AtomicUsize::new(0);
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
 
     let send_result = query_collection
         .par_iter()
@@ -68,35 +122,16 @@ pub fn mastiff_manysearch(
                 Ok(query_sig) => {
                     if let Some(query_mh) = query_sig.minhash() {
                         let query_size = query_mh.size();
-                        let counter = db.counter_for_query(query_mh);
-                        let matches =
-                            db.matches_from_counter(counter, minimum_containment as usize);
-
-                        // filter the matches for containment
-                        for (path, overlap) in matches {
-                            let containment = overlap as f64 / query_size as f64;
-                            if containment >= minimum_containment {
-                                let query_containment_ani = Some(ani_from_containment(
-                                    containment,
-                                    query_mh.ksize() as f64,
-                                ));
-
-                                results.push(SearchResult {
-                                    query_name: query_sig.name(),
-                                    query_md5: query_sig.md5sum(),
-                                    match_name: path.clone(),
-                                    containment,
-                                    intersect_hashes: overlap,
-                                    match_md5: None,
-                                    jaccard: None,
-                                    max_containment: None,
-                                    query_containment_ani,
-                                    match_containment_ani: None,
-                                    average_containment_ani: None,
-                                    max_containment_ani: None,
-                                });
-                            }
+                        if !size_filter.check(&query_sig.name(), &query_sig.md5sum(), query_size) {
+                            return None;
                         }
+                        results = search_revindex(
+                            &db,
+                            query_sig.name(),
+                            query_sig.md5sum(),
+                            query_mh,
+                            minimum_containment,
+                        );
                     } else {
                         eprintln!(
                             "WARNING: no compatible sketches in path '{}'",
@@ -136,8 +171,11 @@ pub fn mastiff_manysearch(
     }
 
     // join the writer thread
-    if let Err(e) = thrd.join() {
-        eprintln!("Unable to join internal thread: {:?}", e);
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
     }
 
     // done!
@@ -160,5 +198,7 @@ pub fn mastiff_manysearch(
         );
     }
 
+    size_filter.report(skipped_output, &options)?;
+
     Ok(())
 }
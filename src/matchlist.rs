@@ -0,0 +1,299 @@
+/// matchlist: the prefetch matches of a gather query, optionally held
+/// under a memory cap by spilling the lowest-overlap matches to a
+/// temporary file (`--max-matchlist-mem`).
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use sourmash::encodings::HashFunctions;
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+use crate::sketchcache::{read_str, read_u64, write_str, write_u64};
+use crate::utils::{prefetch, update_overlap, PrefetchResult};
+
+/// The heap position of a match without its sketch; ordered exactly as
+/// `PrefetchResult`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct MatchKey {
+    overlap: u64,
+    md5sum: Reverse<String>,
+    name: Reverse<String>,
+    location: Reverse<String>,
+}
+
+impl MatchKey {
+    pub(crate) fn of(m: &PrefetchResult) -> Self {
+        MatchKey {
+            overlap: m.overlap,
+            md5sum: Reverse(m.md5sum.clone()),
+            name: Reverse(m.name.clone()),
+            location: Reverse(m.location.clone()),
+        }
+    }
+}
+
+/// Matches spilled to a temporary file.
+struct Spill {
+    file: tempfile::NamedTempFile,
+    writer: BufWriter<File>,
+    n_matches: usize,
+    /// The largest key of any match when it was spilled. Overlaps only
+    /// shrink as gather removes hashes from the query, so no spilled match
+    /// can rank above it.
+    ceiling: MatchKey,
+}
+
+impl Spill {
+    fn new() -> Result<Self> {
+        let file = tempfile::NamedTempFile::new()
+            .context("Failed to create a temporary file for spilled matches")?;
+        let writer = BufWriter::new(file.reopen()?);
+        Ok(Spill {
+            file,
+            writer,
+            n_matches: 0,
+            ceiling: MatchKey {
+                overlap: 0,
+                md5sum: Reverse(String::new()),
+                name: Reverse(String::new()),
+                location: Reverse(String::new()),
+            },
+        })
+    }
+
+    fn write(&mut self, m: &PrefetchResult) -> Result<()> {
+        let w = &mut self.writer;
+        write_str(w, &m.name)?;
+        write_str(w, &m.md5sum)?;
+        write_str(w, &m.location)?;
+        write_u64(w, m.overlap)?;
+        let mh = &m.minhash;
+        write_u64(w, mh.ksize() as u64)?;
+        write_str(w, &mh.hash_function().to_string())?;
+        write_u64(w, mh.seed())?;
+        write_u64(w, mh.scaled())?;
+        let abunds = mh.abunds();
+        w.write_all(&[abunds.is_some() as u8])?;
+        write_u64(w, mh.size() as u64)?;
+        for hash in mh.iter_mins() {
+            write_u64(w, *hash)?;
+        }
+        for abund in abunds.unwrap_or_default() {
+            write_u64(w, abund)?;
+        }
+
+        self.n_matches += 1;
+        let key = MatchKey::of(m);
+        if key > self.ceiling {
+            self.ceiling = key;
+        }
+        Ok(())
+    }
+
+    /// Call 'f' on each spilled match, in the order they were spilled.
+    fn for_each(&self, mut f: impl FnMut(PrefetchResult) -> Result<()>) -> Result<()> {
+        let mut r = BufReader::new(self.file.reopen()?);
+        for _ in 0..self.n_matches {
+            f(read_match(&mut r)?)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_match(r: &mut impl Read) -> Result<PrefetchResult> {
+    let name = read_str(r)?;
+    let md5sum = read_str(r)?;
+    let location = read_str(r)?;
+    let overlap = read_u64(r)?;
+    let ksize = read_u64(r)? as u32;
+    let moltype = read_str(r)?;
+    let hash_function = match moltype.as_str() {
+        "DNA" | "protein" | "dayhoff" | "hp" => HashFunctions::try_from(moltype.as_str())?,
+        _ => bail!("unknown moltype '{}' in spilled matches", moltype),
+    };
+    let seed = read_u64(r)?;
+    let scaled = read_u64(r)?;
+    let mut with_abundance = [0u8; 1];
+    r.read_exact(&mut with_abundance)?;
+    let with_abundance = with_abundance[0] != 0;
+    let n_hashes = read_u64(r)? as usize;
+    let hashes = (0..n_hashes)
+        .map(|_| read_u64(r))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut minhash = KmerMinHash::new(scaled, ksize, hash_function, seed, with_abundance, 0);
+    if with_abundance {
+        let abunds = (0..n_hashes)
+            .map(|_| read_u64(r))
+            .collect::<Result<Vec<_>>>()?;
+        let pairs: Vec<(u64, u64)> = hashes.into_iter().zip(abunds).collect();
+        minhash.add_many_with_abund(&pairs)?;
+    } else {
+        minhash.add_many(&hashes)?;
+    }
+
+    Ok(PrefetchResult {
+        name,
+        md5sum,
+        location,
+        minhash,
+        overlap,
+    })
+}
+
+/// Approximate memory held by a match, mostly its sketch.
+fn match_bytes(m: &PrefetchResult) -> usize {
+    let per_hash = if m.minhash.track_abundance() { 16 } else { 8 };
+    std::mem::size_of::<PrefetchResult>()
+        + m.name.len()
+        + m.md5sum.len()
+        + m.location.len()
+        + m.minhash.size() * per_hash
+}
+
+/// The matches of a query, ordered as gather consumes them: largest
+/// overlap first.
+///
+/// With a memory cap, the matches with the smallest overlaps are spilled
+/// to a temporary file whenever the in-memory matches exceed it, and are
+/// reloaded once one of them could be the next-best match. Spilling is
+/// only exact when ranking matches by overlap.
+pub struct MatchList {
+    heap: BinaryHeap<PrefetchResult>,
+    heap_bytes: usize,
+    max_bytes: Option<usize>,
+    spill: Option<Spill>,
+}
+
+impl MatchList {
+    /// An empty match list, holding at most about 'max_bytes' of matches
+    /// in memory if given.
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        MatchList {
+            heap: BinaryHeap::new(),
+            heap_bytes: 0,
+            max_bytes,
+            spill: None,
+        }
+    }
+
+    pub fn from_heap(heap: BinaryHeap<PrefetchResult>, max_bytes: Option<usize>) -> Result<Self> {
+        let mut matchlist = MatchList {
+            heap_bytes: heap.iter().map(match_bytes).sum(),
+            heap,
+            max_bytes,
+            spill: None,
+        };
+        matchlist.limit()?;
+        Ok(matchlist)
+    }
+
+    pub fn push(&mut self, m: PrefetchResult) -> Result<()> {
+        self.heap_bytes += match_bytes(&m);
+        self.heap.push(m);
+        self.limit()
+    }
+
+    /// The number of matches, in memory and spilled.
+    pub fn len(&self) -> usize {
+        self.heap.len() + self.n_spilled()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of matches currently spilled to disk.
+    pub fn n_spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.n_matches)
+    }
+
+    /// The matches held in memory, which include the next-best match after
+    /// `reload_if_needed`.
+    pub fn in_memory(&self) -> &BinaryHeap<PrefetchResult> {
+        &self.heap
+    }
+
+    /// Call 'f' on every match: those in memory, then those spilled.
+    pub fn for_each(&self, mut f: impl FnMut(&PrefetchResult) -> Result<()>) -> Result<()> {
+        for m in self.heap.iter() {
+            f(m)?;
+        }
+        if let Some(spill) = &self.spill {
+            spill.for_each(|m| f(&m))?;
+        }
+        Ok(())
+    }
+
+    /// Recalculate the overlaps of the in-memory matches with the remaining
+    /// 'query_mh', dropping those below 'threshold_hashes'. Spilled matches
+    /// are updated when they are reloaded.
+    pub fn update_overlaps(&mut self, query_mh: &KmerMinHash, threshold_hashes: u64) {
+        self.heap = prefetch(query_mh, std::mem::take(&mut self.heap), threshold_hashes);
+        self.heap_bytes = self.heap.iter().map(match_bytes).sum();
+    }
+
+    /// Drop the in-memory matches with overlaps below 'threshold_hashes'.
+    /// Spilled matches are checked when they are reloaded.
+    pub fn retain_above(&mut self, threshold_hashes: u64) {
+        self.heap.retain(|m| m.overlap >= threshold_hashes);
+        self.heap_bytes = self.heap.iter().map(match_bytes).sum();
+    }
+
+    /// Reload the spilled matches if any of them could outrank the best
+    /// in-memory match, updating their overlaps with 'query_mh'. Matches
+    /// over the memory cap are spilled again.
+    pub fn reload_if_needed(
+        &mut self,
+        query_mh: &KmerMinHash,
+        threshold_hashes: u64,
+    ) -> Result<()> {
+        let needed = match (&self.spill, self.heap.peek()) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(spill), Some(best)) => MatchKey::of(best) < spill.ceiling,
+        };
+        if !needed {
+            return Ok(());
+        }
+
+        let spill = self.spill.take().unwrap();
+        spill.for_each(|m| {
+            if let Some(m) = update_overlap(query_mh, m, threshold_hashes) {
+                self.push(m)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Spill the lowest-overlap matches until the in-memory matches use
+    /// at most half the cap, so that spills are infrequent. The best match
+    /// is always kept in memory.
+    fn limit(&mut self) -> Result<()> {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) if self.heap_bytes > max_bytes => max_bytes,
+            _ => return Ok(()),
+        };
+        let mut spill = match self.spill.take() {
+            Some(spill) => spill,
+            None => Spill::new()?,
+        };
+
+        // ascending, so the matches to spill come first
+        let mut sorted = std::mem::take(&mut self.heap).into_sorted_vec();
+        let mut n_spill = 0;
+        while self.heap_bytes > max_bytes / 2 && n_spill + 1 < sorted.len() {
+            spill.write(&sorted[n_spill])?;
+            self.heap_bytes -= match_bytes(&sorted[n_spill]);
+            n_spill += 1;
+        }
+        spill.writer.flush()?;
+
+        self.heap = BinaryHeap::from(sorted.split_off(n_spill));
+        self.spill = Some(spill);
+        Ok(())
+    }
+}
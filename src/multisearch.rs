@@ -1,55 +1,101 @@
 /// multisearch: massively parallel in-memory sketch search.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rayon::prelude::*;
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+use std::collections::BTreeSet;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
+use crate::compact::load_stored_sketches;
+use crate::dryrun::{check_search_max_mem, Residency};
+use crate::errors::BranchwaterError;
+use crate::progress::Progress;
 use crate::utils::{
-    csvwriter_thread, load_collection, load_sketches, MultiSearchResult, ReportType,
+    csvwriter_thread_with_extras, load_collection, load_sketches, report_downsampling,
+    start_downsample_log, unique_est_kmers, AniEstimator, MultiSearchResult, NumSearchResult,
+    OutputFormat, OutputOptions, Policies, QuerySizeFilter, ReportType, SmallSignature,
+    WriterExtras,
 };
-use sourmash::ani_utils::ani_from_containment;
 
 /// Search many queries against a list of signatures.
 ///
 /// Note: this function loads all _queries_ into memory, and iterates over
-/// database once.
+/// database once. With 'compact_mem', the search sketches are held in
+/// memory as compressed hash arrays.
 
+#[allow(clippy::too_many_arguments)]
 pub fn multisearch(
     query_filepath: String,
     against_filepath: String,
     threshold: f64,
     selection: &Selection,
-    allow_failed_sigpaths: bool,
+    policies: &Policies,
     estimate_ani: bool,
-    output: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Load all queries into memory at once.
+    min_ani: Option<f64>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    downsampled_output: Option<String>,
+    max_mem: Option<usize>,
+    compact_mem: bool,
+    output_format: OutputFormat,
+    options: &OutputOptions,
+) -> Result<()> {
+    if min_ani.is_some() && !estimate_ani {
+        bail!("a minimum ANI can only be used when estimating ANI (--ani)");
+    }
+    start_downsample_log();
 
-    let query_collection = load_collection(
-        &query_filepath,
+    // Load all queries and against sketches into memory at once.
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
+    let against_residency = if compact_mem {
+        Residency::Compact
+    } else {
+        Residency::InMemory
+    };
+    check_search_max_mem(
+        (&query_filepath, &query_collection),
+        (&against_filepath, &against_collection),
+        against_residency,
         selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
+        max_mem,
     )?;
+
     let queries = load_sketches(query_collection, selection, ReportType::Query).unwrap();
 
-    // Load all against sketches into memory at once.
-    let against_collection = load_collection(
-        &against_filepath,
+    // skip queries that are too small to search meaningfully
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+    let queries: Vec<_> = queries
+        .into_iter()
+        .filter(|query| size_filter.check(&query.name, &query.md5sum, query.minhash.size()))
+        .collect();
+
+    let against = load_stored_sketches(
+        against_collection,
         selection,
         ReportType::Against,
-        allow_failed_sigpaths,
+        compact_mem,
     )?;
-    let against = load_sketches(against_collection, selection, ReportType::Against).unwrap();
 
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
         std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
 
     // // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let extras = WriterExtras {
+        format: output_format,
+        command: "multisearch",
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
 
     //
     // Main loop: iterate (in parallel) over all search signature paths,
@@ -58,20 +104,30 @@ pub fn multisearch(
     //
 
     let processed_cmp = AtomicUsize::new(0);
-    let ksize = selection.ksize().unwrap() as f64;
+    let progress = Progress::new(queries.len() * against.len(), "comparisons");
+    let ksize = selection.ksize().unwrap();
+    let moltype = selection.moltype().unwrap().to_string();
+    let ani_estimator = AniEstimator::new(ksize as f64);
 
-    let send = against
+    // ANI from containment is monotonic in containment, so a minimum ANI
+    // gives a minimum max_containment; pairs below it can skip the ANI math.
+    let min_ani_containment = min_ani.map(|ani| ani_estimator.min_containment(ani));
+
+    let searched = against
         .par_iter()
-        .filter_map(|against| {
+        .map(|against| -> Result<Vec<MultiSearchResult>> {
             let mut results = vec![];
             // search for matches & save containment.
             for query in queries.iter() {
                 let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
-                if i % 100000 == 0 && i > 0 {
+                progress.inc();
+                if !progress.is_shown() && i % 100000 == 0 && i > 0 {
                     eprintln!("Processed {} comparisons", i);
                 }
 
-                let overlap = query.minhash.count_common(&against.minhash, false).unwrap() as f64;
+                let overlap = against.minhash.count_common(&query.minhash)? as f64;
+                // both sketches are at the same scaled, or count_common fails.
+                let scaled = query.minhash.scaled().max(against.minhash.scaled());
                 // use downsampled sizes
                 let query_size = query.minhash.size() as f64;
                 let target_size = against.minhash.size() as f64;
@@ -82,6 +138,11 @@ pub fn multisearch(
                     let containment_target_in_query = overlap / target_size;
                     let max_containment =
                         containment_query_in_target.max(containment_target_in_query);
+                    if let Some(min_containment) = min_ani_containment {
+                        if max_containment < min_containment {
+                            continue;
+                        }
+                    }
                     let jaccard = overlap / (target_size + query_size - overlap);
                     let mut query_containment_ani = None;
                     let mut match_containment_ani = None;
@@ -90,12 +151,17 @@ pub fn multisearch(
 
                     // estimate ANI values
                     if estimate_ani {
-                        let qani = ani_from_containment(containment_query_in_target, ksize);
-                        let mani = ani_from_containment(containment_target_in_query, ksize);
+                        let (qani, mani, avg_ani, max_ani) = ani_estimator
+                            .pair(containment_query_in_target, containment_target_in_query);
                         query_containment_ani = Some(qani);
                         match_containment_ani = Some(mani);
-                        average_containment_ani = Some((qani + mani) / 2.);
-                        max_containment_ani = Some(f64::max(qani, mani));
+                        average_containment_ani = Some(avg_ani);
+                        max_containment_ani = Some(max_ani);
+                    }
+                    if let (Some(min_ani), Some(max_ani)) = (min_ani, max_containment_ani) {
+                        if max_ani < min_ani {
+                            continue;
+                        }
                     }
 
                     results.push(MultiSearchResult {
@@ -107,6 +173,11 @@ pub fn multisearch(
                         max_containment,
                         jaccard,
                         intersect_hashes: overlap,
+                        ksize,
+                        scaled,
+                        moltype: moltype.clone(),
+                        query_unique_est_kmers: unique_est_kmers(query.minhash.size(), scaled),
+                        match_unique_est_kmers: unique_est_kmers(against.minhash.size(), scaled),
                         query_containment_ani,
                         match_containment_ani,
                         average_containment_ani,
@@ -114,6 +185,183 @@ pub fn multisearch(
                     })
                 }
             }
+            Ok(results)
+        })
+        .try_for_each_with(send, |s, results| -> Result<()> {
+            for m in results? {
+                s.send(m)?;
+            }
+            Ok(())
+        });
+
+    // do some cleanup and error handling -
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+    searched?;
+
+    // done!
+    progress.finish();
+    let i: usize = processed_cmp.fetch_max(0, atomic::Ordering::SeqCst);
+    eprintln!("DONE. Processed {} comparisons", i);
+
+    size_filter.report(skipped_output, options)?;
+    report_downsampling(downsampled_output, options)?;
+
+    Ok(())
+}
+
+/// Estimate jaccard similarity between two `num` sketches from the
+/// smallest `num` hashes of their union.
+fn num_jaccard(query: &KmerMinHash, against: &KmerMinHash) -> f64 {
+    let num = std::cmp::min(query.num(), against.num()) as usize;
+    let mut query_iter = query.iter_mins().peekable();
+    let mut against_iter = against.iter_mins().peekable();
+
+    let mut n_union = 0;
+    let mut n_common = 0;
+    while n_union < num {
+        match (query_iter.peek(), against_iter.peek()) {
+            (Some(q), Some(a)) if q == a => {
+                n_common += 1;
+                query_iter.next();
+                against_iter.next();
+            }
+            (Some(q), Some(a)) if q < a => {
+                query_iter.next();
+            }
+            (_, Some(_)) => {
+                against_iter.next();
+            }
+            (Some(_), None) => {
+                query_iter.next();
+            }
+            (None, None) => break,
+        }
+        n_union += 1;
+    }
+
+    if n_union == 0 {
+        0.0
+    } else {
+        n_common as f64 / n_union as f64
+    }
+}
+
+/// Check that all `num` sketches were built with the same num and seed,
+/// so that their jaccard similarities are comparable.
+fn check_num_compatible(queries: &[SmallSignature], against: &[SmallSignature]) -> Result<()> {
+    let sketches = || queries.iter().chain(against.iter());
+
+    let nums: BTreeSet<u32> = sketches().map(|ss| ss.minhash.num()).collect();
+    if nums.len() > 1 {
+        bail!(
+            "query and search sketches must share a num; found num values {:?}",
+            nums
+        );
+    }
+    let seeds: BTreeSet<u64> = sketches().map(|ss| ss.minhash.seed()).collect();
+    if seeds.len() > 1 {
+        bail!(
+            "query and search sketches must share a seed; found seeds {:?}",
+            seeds
+        );
+    }
+    Ok(())
+}
+
+/// Search many `num` sketch queries against many `num` sketches. Only
+/// jaccard similarity can be estimated from `num` sketches, so no
+/// containment or ANI values are reported.
+#[allow(clippy::too_many_arguments)]
+pub fn multisearch_num(
+    query_filepath: String,
+    against_filepath: String,
+    threshold: f64,
+    selection: &Selection,
+    policies: &Policies,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    min_query_hashes: usize,
+    skipped_output: Option<String>,
+    max_mem: Option<usize>,
+    output_format: OutputFormat,
+    options: &OutputOptions,
+) -> Result<()> {
+    // Load all queries and against sketches into memory at once.
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
+    check_search_max_mem(
+        (&query_filepath, &query_collection),
+        (&against_filepath, &against_collection),
+        Residency::InMemory,
+        selection,
+        max_mem,
+    )?;
+
+    let queries = load_sketches(query_collection, selection, ReportType::Query).unwrap();
+
+    // skip queries that are too small to search meaningfully
+    let size_filter = QuerySizeFilter::new(min_query_hashes);
+    let queries: Vec<_> = queries
+        .into_iter()
+        .filter(|query| size_filter.check(&query.name, &query.md5sum, query.minhash.size()))
+        .collect();
+
+    let against = load_sketches(against_collection, selection, ReportType::Against).unwrap();
+
+    check_num_compatible(&queries, &against)?;
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<NumSearchResult>(rayon::current_num_threads());
+
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let extras = WriterExtras {
+        format: output_format,
+        command: "multisearch",
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
+
+    let processed_cmp = AtomicUsize::new(0);
+    let progress = Progress::new(queries.len() * against.len(), "comparisons");
+    let ksize = selection.ksize().unwrap();
+    let num = selection.num().unwrap();
+    let moltype = selection.moltype().unwrap().to_string();
+
+    let send = against
+        .par_iter()
+        .filter_map(|against| {
+            let mut results = vec![];
+            for query in queries.iter() {
+                let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
+                progress.inc();
+                if !progress.is_shown() && i % 100000 == 0 && i > 0 {
+                    eprintln!("Processed {} comparisons", i);
+                }
+
+                let jaccard = num_jaccard(&query.minhash, &against.minhash);
+                if jaccard > threshold {
+                    results.push(NumSearchResult {
+                        query_name: query.name.clone(),
+                        query_md5: query.md5sum.clone(),
+                        match_name: against.name.clone(),
+                        match_md5: against.md5sum.clone(),
+                        jaccard,
+                        ksize,
+                        num,
+                        moltype: moltype.clone(),
+                    })
+                }
+            }
             if results.is_empty() {
                 None
             } else {
@@ -128,13 +376,19 @@ pub fn multisearch(
         eprintln!("Unable to send internal data: {:?}", e);
     }
 
-    if let Err(e) = thrd.join() {
-        eprintln!("Unable to join internal thread: {:?}", e);
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
     }
 
     // done!
+    progress.finish();
     let i: usize = processed_cmp.fetch_max(0, atomic::Ordering::SeqCst);
     eprintln!("DONE. Processed {} comparisons", i);
 
+    size_filter.report(skipped_output, options)?;
+
     Ok(())
 }
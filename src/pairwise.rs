@@ -1,85 +1,280 @@
 /// pairwise: massively parallel in-memory pairwise comparisons.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rayon::prelude::*;
+use std::collections::HashMap;
+use std::hash::Hasher;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
+use crate::dryrun::{check_max_mem, pairwise_comparisons, DryRunInput, Residency};
+use crate::errors::BranchwaterError;
+use crate::progress::Progress;
 use crate::utils::{
-    csvwriter_thread, load_collection, load_sketches, MultiSearchResult, ReportType,
+    csvwriter_thread_with_extras, load_collection, load_sketches, report_downsampling,
+    start_downsample_log, unique_est_kmers, AniEstimator, MultiSearchResult, OutputFormat,
+    OutputOptions, Policies, ReportType, SmallSignature, WriterExtras,
 };
-use sourmash::ani_utils::ani_from_containment;
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
 
-/// Perform pairwise comparisons of all signatures in a list.
+/// Locality-sensitive hashing (LSH) bands, for approximate all-vs-all
+/// comparisons that only compare sketches likely to be similar.
+///
+/// The hashes of each sketch are split into 'bands' partitions by
+/// `hash % bands`, and each band is keyed by the 'rows' smallest hashes in
+/// its partition. Two sketches are compared only if they share the key of
+/// at least one band. A pair with jaccard similarity J shares a band with
+/// probability about J^rows, so it is compared with probability about
+/// 1 - (1 - J^rows)^bands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LshBands {
+    pub bands: usize,
+    pub rows: usize,
+}
+
+impl LshBands {
+    /// LSH bands from the command line options; both are 0 to compare all pairs.
+    pub fn from_options(bands: usize, rows: usize) -> Result<Option<Self>> {
+        match (bands, rows) {
+            (0, 0) => Ok(None),
+            (0, _) | (_, 0) => bail!("--lsh-bands and --lsh-rows must be given together"),
+            (bands, rows) => Ok(Some(LshBands { bands, rows })),
+        }
+    }
+
+    /// The key of each band of 'mh' with at least one hash.
+    fn band_keys(&self, mh: &KmerMinHash) -> Vec<u64> {
+        let mut bands: Vec<Vec<u64>> = vec![Vec::with_capacity(self.rows); self.bands];
+        let mut n_full = 0;
+        // hashes are in increasing order, so each band gets its smallest.
+        for &hash in mh.iter_mins() {
+            let band = &mut bands[(hash % self.bands as u64) as usize];
+            if band.len() < self.rows {
+                band.push(hash);
+                if band.len() == self.rows {
+                    n_full += 1;
+                    if n_full == self.bands {
+                        break;
+                    }
+                }
+            }
+        }
+
+        bands
+            .iter()
+            .enumerate()
+            .filter(|(_, band)| !band.is_empty())
+            .map(|(i, band)| {
+                let mut hasher = twox_hash::XxHash64::with_seed(i as u64);
+                for hash in band {
+                    hasher.write_u64(*hash);
+                }
+                hasher.finish()
+            })
+            .collect()
+    }
+
+    /// The pairs of 'sketches', by index, that share at least one band.
+    fn candidates(&self, sketches: &[SmallSignature]) -> Vec<(usize, usize)> {
+        let keys: Vec<Vec<u64>> = sketches
+            .par_iter()
+            .map(|sketch| self.band_keys(&sketch.minhash))
+            .collect();
+
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (idx, sketch_keys) in keys.iter().enumerate() {
+            for key in sketch_keys {
+                buckets.entry(*key).or_default().push(idx);
+            }
+        }
+
+        let mut pairs: Vec<(usize, usize)> = buckets
+            .values()
+            .flat_map(|bucket| {
+                bucket
+                    .iter()
+                    .enumerate()
+                    .flat_map(move |(i, a)| bucket[i + 1..].iter().map(move |b| (*a, *b)))
+            })
+            .collect();
+        pairs.par_sort_unstable();
+        pairs.dedup();
+        pairs
+    }
+}
+
+/// Perform pairwise comparisons of all signatures in a list, or, if
+/// `against` is given, of every signature in `siglist` against every
+/// signature in `against` (rectangular mode; no within-list comparisons).
 ///
 /// Note: this function loads all _signatures_ into memory.
 
+#[allow(clippy::too_many_arguments)]
 pub fn pairwise(
     siglist: String,
+    against: Option<String>,
     threshold: f64,
     selection: &Selection,
-    allow_failed_sigpaths: bool,
+    policies: &Policies,
     estimate_ani: bool,
+    min_ani: Option<f64>,
     write_all: bool,
-    output: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    downsampled_output: Option<String>,
+    max_mem: Option<usize>,
+    lsh: Option<LshBands>,
+    output_format: OutputFormat,
+    options: &OutputOptions,
+) -> Result<()> {
+    if min_ani.is_some() && !estimate_ani {
+        bail!("a minimum ANI can only be used when estimating ANI (--ani)");
+    }
+    if lsh.is_some() && against.is_some() {
+        bail!("--lsh-bands is only supported for all-vs-all comparisons, not with --against");
+    }
+    start_downsample_log();
+
     // Load all sigs into memory at once.
-    let collection = load_collection(
+    let collection = load_collection(&siglist, selection, ReportType::General, policies)?;
+
+    let against_collection = match &against {
+        Some(against_path) => {
+            let against_collection =
+                load_collection(against_path, selection, ReportType::Against, policies)?;
+            if collection.is_empty() || against_collection.is_empty() {
+                bail!(
+                    "Pairwise requires at least one sketch in each input. Check inputs: '{:?}', '{:?}'",
+                    &siglist,
+                    against_path
+                )
+            }
+            if write_all {
+                eprintln!("WARNING: --write-all is ignored when comparing two collections.");
+            }
+            Some(against_collection)
+        }
+        None => {
+            if collection.len() <= 1 {
+                bail!(
+                    "Pairwise requires two or more sketches. Check input: '{:?}'",
+                    &siglist
+                )
+            }
+            None
+        }
+    };
+
+    // Refuse to start if we expect to need more memory than allowed.
+    let mut inputs = vec![DryRunInput::from_collection(
+        "sketches",
         &siglist,
+        &collection,
         selection,
-        ReportType::General,
-        allow_failed_sigpaths,
-    )?;
-
-    if collection.len() <= 1 {
-        bail!(
-            "Pairwise requires two or more sketches. Check input: '{:?}'",
-            &siglist
-        )
-    }
+        Residency::InMemory,
+    )];
+    let n_comparisons = match (&against, &against_collection) {
+        (Some(against_path), Some(against_collection)) => {
+            inputs.push(DryRunInput::from_collection(
+                "against",
+                against_path,
+                against_collection,
+                selection,
+                Residency::InMemory,
+            ));
+            collection.len() * against_collection.len()
+        }
+        _ => pairwise_comparisons(collection.len()),
+    };
+    check_max_mem(&inputs, n_comparisons, max_mem)?;
+
+    let against_sketches = against_collection.map(|against_collection| {
+        load_sketches(against_collection, selection, ReportType::Against).unwrap()
+    });
     let sketches = load_sketches(collection, selection, ReportType::General).unwrap();
 
+    // with LSH, only compare the pairs that share a band.
+    let candidates = lsh.map(|lsh| {
+        let candidates = lsh.candidates(&sketches);
+        eprintln!(
+            "LSH ({} bands of {} rows): comparing {} of {} pairs.",
+            lsh.bands,
+            lsh.rows,
+            candidates.len(),
+            n_comparisons
+        );
+        candidates
+    });
+
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
         std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
 
     // // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let extras = WriterExtras {
+        format: output_format,
+        command: "pairwise",
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, query_buckets, extras);
 
     //
     // Main loop: iterate (in parallel) over all signature,
     // Results written to the writer thread above.
 
     let processed_cmp = AtomicUsize::new(0);
-    let ksize = selection.ksize().unwrap() as f64;
-
-    sketches.par_iter().enumerate().for_each(|(idx, query)| {
-        for against in sketches.iter().skip(idx + 1) {
-            let overlap = query.minhash.count_common(&against.minhash, false).unwrap() as f64;
-            let query1_size = query.minhash.size() as f64;
-            let query2_size = against.minhash.size() as f64;
-
-            let containment_q1_in_q2 = overlap / query1_size;
-            let containment_q2_in_q1 = overlap / query2_size;
-
-            if containment_q1_in_q2 > threshold || containment_q2_in_q1 > threshold {
-                let max_containment = containment_q1_in_q2.max(containment_q2_in_q1);
-                let jaccard = overlap / (query1_size + query2_size - overlap);
-                let mut query_containment_ani = None;
-                let mut match_containment_ani = None;
-                let mut average_containment_ani = None;
-                let mut max_containment_ani = None;
-
-                // estimate ANI values
-                if estimate_ani {
-                    let qani = ani_from_containment(containment_q1_in_q2, ksize);
-                    let mani = ani_from_containment(containment_q2_in_q1, ksize);
-                    query_containment_ani = Some(qani);
-                    match_containment_ani = Some(mani);
-                    average_containment_ani = Some((qani + mani) / 2.);
-                    max_containment_ani = Some(f64::max(qani, mani));
-                }
+    let progress = Progress::new(
+        candidates.as_ref().map_or(n_comparisons, |c| c.len()),
+        "comparisons",
+    );
+    let ksize = selection.ksize().unwrap();
+    let moltype = selection.moltype().unwrap().to_string();
+    let ani_estimator = AniEstimator::new(ksize as f64);
+
+    // ANI from containment is monotonic in containment, so a minimum ANI
+    // gives a minimum max_containment; pairs below it can skip the ANI math.
+    let min_ani_containment = min_ani.map(|ani| ani_estimator.min_containment(ani));
+
+    // compare a single pair of sketches, sending a result if either
+    // containment is above threshold, and the ANI is above 'min_ani'.
+    let compare = |query: &SmallSignature, against: &SmallSignature| {
+        let overlap = query.minhash.count_common(&against.minhash, false).unwrap() as f64;
+        // both sketches are at the same scaled, or count_common fails.
+        let scaled = query.minhash.scaled().max(against.minhash.scaled());
+        let query1_size = query.minhash.size() as f64;
+        let query2_size = against.minhash.size() as f64;
+
+        let containment_q1_in_q2 = overlap / query1_size;
+        let containment_q2_in_q1 = overlap / query2_size;
+
+        let max_containment = containment_q1_in_q2.max(containment_q2_in_q1);
+        let below_min_ani = matches!(min_ani_containment, Some(min) if max_containment < min);
+
+        if (containment_q1_in_q2 > threshold || containment_q2_in_q1 > threshold) && !below_min_ani
+        {
+            let jaccard = overlap / (query1_size + query2_size - overlap);
+            let mut query_containment_ani = None;
+            let mut match_containment_ani = None;
+            let mut average_containment_ani = None;
+            let mut max_containment_ani = None;
+
+            // estimate ANI values
+            if estimate_ani {
+                let (qani, mani, avg_ani, max_ani) =
+                    ani_estimator.pair(containment_q1_in_q2, containment_q2_in_q1);
+                query_containment_ani = Some(qani);
+                match_containment_ani = Some(mani);
+                average_containment_ani = Some(avg_ani);
+                max_containment_ani = Some(max_ani);
+            }
+            let passes_min_ani = match (min_ani, max_containment_ani) {
+                (Some(min_ani), Some(max_ani)) => max_ani >= min_ani,
+                _ => true,
+            };
+            if passes_min_ani {
                 send.send(MultiSearchResult {
                     query_name: query.name.clone(),
                     query_md5: query.md5sum.clone(),
@@ -89,6 +284,11 @@ pub fn pairwise(
                     max_containment,
                     jaccard,
                     intersect_hashes: overlap,
+                    ksize,
+                    scaled,
+                    moltype: moltype.clone(),
+                    query_unique_est_kmers: unique_est_kmers(query.minhash.size(), scaled),
+                    match_unique_est_kmers: unique_est_kmers(against.minhash.size(), scaled),
                     query_containment_ani,
                     match_containment_ani,
                     average_containment_ani,
@@ -96,53 +296,91 @@ pub fn pairwise(
                 })
                 .unwrap();
             }
+        }
 
-            let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
-            if i % 100000 == 0 && i > 0 {
-                eprintln!("Processed {} comparisons", i);
-            }
+        let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
+        progress.inc();
+        if !progress.is_shown() && i % 100000 == 0 && i > 0 {
+            eprintln!("Processed {} comparisons", i);
         }
-        if write_all {
-            let mut query_containment_ani = None;
-            let mut match_containment_ani = None;
-            let mut average_containment_ani = None;
-            let mut max_containment_ani = None;
+    };
 
-            if estimate_ani {
-                query_containment_ani = Some(1.0);
-                match_containment_ani = Some(1.0);
-                average_containment_ani = Some(1.0);
-                max_containment_ani = Some(1.0);
-            }
+    // with --write-all, report each sketch as a match to itself.
+    let write_self = |query: &SmallSignature| {
+        let mut query_containment_ani = None;
+        let mut match_containment_ani = None;
+        let mut average_containment_ani = None;
+        let mut max_containment_ani = None;
 
-            send.send(MultiSearchResult {
-                query_name: query.name.clone(),
-                query_md5: query.md5sum.clone(),
-                match_name: query.name.clone(),
-                match_md5: query.md5sum.clone(),
-                containment: 1.0,
-                max_containment: 1.0,
-                jaccard: 1.0,
-                intersect_hashes: query.minhash.size() as f64,
-                query_containment_ani,
-                match_containment_ani,
-                average_containment_ani,
-                max_containment_ani,
-            })
-            .unwrap();
+        if estimate_ani {
+            query_containment_ani = Some(1.0);
+            match_containment_ani = Some(1.0);
+            average_containment_ani = Some(1.0);
+            max_containment_ani = Some(1.0);
         }
-    });
+
+        send.send(MultiSearchResult {
+            query_name: query.name.clone(),
+            query_md5: query.md5sum.clone(),
+            match_name: query.name.clone(),
+            match_md5: query.md5sum.clone(),
+            containment: 1.0,
+            max_containment: 1.0,
+            jaccard: 1.0,
+            intersect_hashes: query.minhash.size() as f64,
+            ksize,
+            scaled: query.minhash.scaled(),
+            moltype: moltype.clone(),
+            query_unique_est_kmers: unique_est_kmers(query.minhash.size(), query.minhash.scaled()),
+            match_unique_est_kmers: unique_est_kmers(query.minhash.size(), query.minhash.scaled()),
+            query_containment_ani,
+            match_containment_ani,
+            average_containment_ani,
+            max_containment_ani,
+        })
+        .unwrap();
+    };
+
+    if let Some(against_sketches) = &against_sketches {
+        // rectangular mode: every query against every sketch in the second list.
+        sketches.par_iter().for_each(|query| {
+            for against in against_sketches.iter() {
+                compare(query, against);
+            }
+        });
+    } else if let Some(candidates) = &candidates {
+        candidates
+            .par_iter()
+            .for_each(|&(i, j)| compare(&sketches[i], &sketches[j]));
+        if write_all {
+            sketches.par_iter().for_each(|query| write_self(query));
+        }
+    } else {
+        sketches.par_iter().enumerate().for_each(|(idx, query)| {
+            for against in sketches.iter().skip(idx + 1) {
+                compare(query, against);
+            }
+            if write_all {
+                write_self(query);
+            }
+        });
+    }
 
     // do some cleanup and error handling -
     drop(send); // close the channel
 
-    if let Err(e) = thrd.join() {
-        eprintln!("Unable to join internal thread: {:?}", e);
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
     }
 
     // done!
+    progress.finish();
     let i: usize = processed_cmp.load(atomic::Ordering::SeqCst);
     eprintln!("DONE. Processed {} comparisons", i);
+    report_downsampling(downsampled_output, options)?;
 
     Ok(())
 }
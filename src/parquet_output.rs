@@ -0,0 +1,229 @@
+/// parquet_output: write results as Apache Parquet (`--output-format
+/// parquet`), with a column type for each result field taken from its
+/// `ResultSchema`.
+///
+/// Note: fields are serialized to strings exactly as they are for CSV, and
+/// then parsed into typed columns, so that `--float-precision`, `--columns`
+/// and `--anonymize-queries` apply in the same way to both formats.
+use anyhow::{anyhow, Result};
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::schema::{serialize_fields, ResultSchema};
+use crate::utils::{align_fields, select_fields, AgainstMetadata, OutputOptions};
+
+/// Rows buffered before each record batch is written.
+const BATCH_ROWS: usize = 65536;
+
+/// The Arrow type of a Rust result field type, and whether it is nullable.
+fn arrow_type(rust_type: &str) -> (DataType, bool) {
+    let rust_type: String = rust_type.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(inner) = rust_type
+        .strip_prefix("Option<")
+        .and_then(|t| t.strip_suffix('>'))
+    {
+        return (arrow_type(inner).0, true);
+    }
+    let data_type = match rust_type.as_str() {
+        "String" => DataType::Utf8,
+        "f64" | "f32" => DataType::Float64,
+        "usize" | "u64" | "u32" | "u8" => DataType::UInt64,
+        "i64" | "i32" => DataType::Int64,
+        "bool" => DataType::Boolean,
+        other => panic!("no Arrow type for result field type '{}'", other),
+    };
+    (data_type, false)
+}
+
+/// One output column, accumulating values until the next batch.
+enum ColumnBuilder {
+    Utf8(StringBuilder),
+    Float64(Float64Builder),
+    UInt64(UInt64Builder),
+    Int64(Int64Builder),
+    Boolean(BooleanBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Float64 => ColumnBuilder::Float64(Float64Builder::new()),
+            DataType::UInt64 => ColumnBuilder::UInt64(UInt64Builder::new()),
+            DataType::Int64 => ColumnBuilder::Int64(Int64Builder::new()),
+            DataType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            _ => ColumnBuilder::Utf8(StringBuilder::new()),
+        }
+    }
+
+    /// Append a serialized value; empty and missing values are null.
+    fn append(&mut self, column: &str, value: Option<&str>) -> Result<()> {
+        let value = value.filter(|v| !v.is_empty());
+        let invalid = |v: &str| anyhow!("Invalid value '{}' in column '{}'", v, column);
+        match self {
+            ColumnBuilder::Utf8(b) => b.append_option(value),
+            ColumnBuilder::Float64(b) => b.append_option(
+                value
+                    .map(|v| v.parse().map_err(|_| invalid(v)))
+                    .transpose()?,
+            ),
+            ColumnBuilder::UInt64(b) => b.append_option(
+                value
+                    .map(|v| v.parse().map_err(|_| invalid(v)))
+                    .transpose()?,
+            ),
+            ColumnBuilder::Int64(b) => b.append_option(
+                value
+                    .map(|v| v.parse().map_err(|_| invalid(v)))
+                    .transpose()?,
+            ),
+            ColumnBuilder::Boolean(b) => b.append_option(
+                value
+                    .map(|v| v.parse().map_err(|_| invalid(v)))
+                    .transpose()?,
+            ),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Utf8(b) => Arc::new(b.finish()),
+            ColumnBuilder::Float64(b) => Arc::new(b.finish()),
+            ColumnBuilder::UInt64(b) => Arc::new(b.finish()),
+            ColumnBuilder::Int64(b) => Arc::new(b.finish()),
+            ColumnBuilder::Boolean(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Writes results of type 'T' to a Parquet file. Every column of 'T' (or
+/// of `--columns`) is in the schema, whether or not it was calculated;
+/// columns that weren't are null.
+pub struct ParquetResultWriter<T> {
+    writer: ArrowWriter<Box<dyn Write + Send>>,
+    schema: SchemaRef,
+    /// the field names of 'T', in output order.
+    schema_fields: Vec<&'static str>,
+    /// the position in 'schema_fields' of each output column, or None
+    /// for metadata.
+    columns: Vec<Option<usize>>,
+    /// the position in 'schema_fields' of the metadata key column.
+    key_index: usize,
+    metadata: Option<Arc<AgainstMetadata>>,
+    options: OutputOptions,
+    builders: Vec<ColumnBuilder>,
+    n_buffered: usize,
+    _result: PhantomData<T>,
+}
+
+impl<T: Serialize + ResultSchema> ParquetResultWriter<T> {
+    /// Write to 'out', keeping only 'columns' if given, and appending the
+    /// columns of 'metadata' as strings; floats are rounded as given in
+    /// 'options'.
+    pub fn new(
+        out: Box<dyn Write + Send>,
+        columns: Option<Vec<String>>,
+        metadata: Option<Arc<AgainstMetadata>>,
+        options: OutputOptions,
+    ) -> Result<Self> {
+        let selected = select_fields::<T>(columns.as_ref())?;
+        let all_fields: Vec<&'static str> = T::fields().into_iter().map(|(name, _)| name).collect();
+        let position = |name: &str| all_fields.iter().position(|field| *field == name);
+
+        let mut arrow_fields = vec![];
+        let mut output_columns = vec![];
+        for (name, rust_type) in selected.iter() {
+            let (data_type, nullable) = arrow_type(rust_type);
+            arrow_fields.push(Field::new(*name, data_type, nullable));
+            output_columns.push(position(name));
+        }
+        let mut key_index = 0;
+        if let Some(metadata) = &metadata {
+            let selected: Vec<&str> = selected.iter().map(|(name, _)| *name).collect();
+            metadata.check_fields(&all_fields, &selected)?;
+            key_index = position(metadata.key_column).unwrap();
+            for col in metadata.columns.iter() {
+                arrow_fields.push(Field::new(col, DataType::Utf8, true));
+                output_columns.push(None);
+            }
+        }
+
+        let schema = Arc::new(Schema::new(arrow_fields));
+        let builders = schema
+            .fields()
+            .iter()
+            .map(|f| ColumnBuilder::new(f.data_type()))
+            .collect();
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(out, schema.clone(), Some(props))?;
+
+        Ok(ParquetResultWriter {
+            writer,
+            schema,
+            schema_fields: all_fields,
+            columns: output_columns,
+            key_index,
+            metadata,
+            options,
+            builders,
+            n_buffered: 0,
+            _result: PhantomData,
+        })
+    }
+
+    /// Add one result, writing a record batch if enough rows are buffered.
+    pub fn write(&mut self, item: &T) -> Result<()> {
+        let mut fields = serialize_fields(item, self.options.float_precision)?;
+        self.options.anonymize_fields(&mut fields);
+        let values = align_fields(&self.schema_fields, fields);
+        let value = |i: usize| values[i].as_deref();
+
+        let metadata_row = self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| value(self.key_index).and_then(|key| metadata.rows.get(key)));
+        let mut metadata_values = metadata_row.into_iter().flatten();
+
+        for (column, builder) in self.columns.iter().zip(self.builders.iter_mut()) {
+            match column {
+                Some(i) => builder.append(self.schema_fields[*i], value(*i))?,
+                None => builder.append("", metadata_values.next().map(|v| v.as_str()))?,
+            }
+        }
+
+        self.n_buffered += 1;
+        if self.n_buffered >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        let arrays = self.builders.iter_mut().map(|b| b.finish()).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        self.n_buffered = 0;
+        Ok(())
+    }
+
+    /// Write any buffered rows and the Parquet footer.
+    pub fn finish(mut self) -> Result<()> {
+        if self.n_buffered > 0 {
+            self.write_batch()?;
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+/// progress: optional terminal progress bars for long-running loops
+/// (`--progress`).
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Show progress bars for the commands that follow, when stderr is a
+/// terminal.
+pub fn set_progress(enabled: bool) {
+    PROGRESS.store(enabled, atomic::Ordering::SeqCst);
+}
+
+fn progress_enabled() -> bool {
+    PROGRESS.load(atomic::Ordering::SeqCst) && std::io::stderr().is_terminal()
+}
+
+/// Progress through a loop of a known number of items. When progress bars
+/// are disabled, or stderr is not a terminal, this does nothing, and the
+/// loop should print its own periodic progress lines instead.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// A progress bar with an ETA, counting 'total' 'unit's, e.g. comparisons.
+    pub fn new(total: usize, unit: &str) -> Self {
+        let bar = progress_enabled().then(|| {
+            let style = ProgressStyle::with_template(&format!(
+                "{{elapsed_precise}} [{{wide_bar}}] {{human_pos}}/{{human_len}} {} (ETA {{eta}})",
+                unit
+            ))
+            .expect("valid progress bar template")
+            .progress_chars("=> ");
+            ProgressBar::new(total as u64).with_style(style)
+        });
+        Progress { bar }
+    }
+
+    /// A spinner with the elapsed time, for work whose progress can't be
+    /// counted, e.g. building an index.
+    pub fn spinner(message: &str) -> Self {
+        let bar = progress_enabled().then(|| {
+            let style = ProgressStyle::with_template("{elapsed_precise} {spinner} {msg}")
+                .expect("valid progress bar template");
+            let bar = ProgressBar::new_spinner()
+                .with_style(style)
+                .with_message(message.to_string());
+            bar.enable_steady_tick(Duration::from_millis(200));
+            bar
+        });
+        Progress { bar }
+    }
+
+    /// Whether a progress bar is shown; if so, loops should skip their
+    /// periodic progress lines.
+    pub fn is_shown(&self) -> bool {
+        self.bar.is_some()
+    }
+
+    pub fn inc(&self) {
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+
+    /// Remove the progress bar, before the final summary is printed.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -4,9 +4,14 @@ import argparse
 from sourmash.plugins import CommandLinePlugin
 from sourmash.logging import notify
 import os
+import csv
+import secrets
 import importlib.metadata
 
 from . import sourmash_plugin_branchwater
+from .sourmash_plugin_branchwater import BranchwaterError, ERROR_CODES
+from .config import apply_config, get_defaults
+from .runinfo import add_run_summary_arg, write_run_summary
 
 __version__ = importlib.metadata.version("sourmash_plugin_branchwater")
 
@@ -36,6 +41,163 @@ def set_thread_pool(user_cores):
     return actual_rayon_cores
 
 
+def add_hash_seed_arg(p):
+    p.add_argument('--hash-seed', default=42, type=int,
+                   help='hash seed that the sketches were built with; sketches built with a different seed are an error (default: 42, as in sourmash)')
+
+
+def add_mixed_scaled_arg(p):
+    p.add_argument('--mixed-scaled', default='harmonize',
+                   choices=['harmonize', 'error'],
+                   help="what to do when the sketches in a collection have different scaled values: 'harmonize' downsamples them all to the largest scaled, with a warning; 'error' lists the sketches that would be downsampled and exits (default: harmonize)")
+
+
+def add_checkpoint_args(p):
+    p.add_argument('--checkpoint-every', default=None, type=int,
+                   help="flush the gather output every N results, and save the completed ranks to '<gather output>.checkpoint.json' (default: no checkpoints)")
+    p.add_argument('--resume', action='store_true',
+                   help='resume gather from the checkpoint of an interrupted run with the same parameters, rather than starting over (requires --checkpoint-every, except for fastmultigather, where --resume alone skips the queries listed in the output index of a previous run)')
+
+
+def add_output_format_arg(p):
+    p.add_argument('--output-format', default='csv', choices=['csv', 'parquet', 'jsonl', 'sqlite'],
+                   help="write results as CSV, as Apache Parquet with typed columns, as JSON Lines with one JSON object per result, or to a table named for the command in a SQLite database (requires branchwater built with the 'sqlite' feature); Parquet and SQLite are written to a single, uncompressed output file (default: csv)")
+
+
+def add_output_checksums_arg(p):
+    p.add_argument('--output-checksums', action='store_true',
+                   help="report an xxh64 checksum of each CSV output file, and save it to a '<output>.xxh64' file for checking with 'xxhsum -c'")
+
+
+def float_precision(value):
+    precision = int(value)
+    if precision < 0:
+        raise argparse.ArgumentTypeError(f"must be 0 or more, not {precision}")
+    return precision
+
+
+def add_float_precision_arg(p):
+    p.add_argument('--float-precision', default=None, type=float_precision, metavar='N',
+                   help='round floats in all outputs to N decimal places, for smaller files and stable diffs (default: full precision)')
+
+
+def add_anonymize_queries_args(p):
+    p.add_argument('--anonymize-queries', metavar='MAPPING_CSV', default=None,
+                   help='replace query names and filenames in all outputs with salted hashes, and save the private mapping from original to anonymized names to this CSV file')
+    p.add_argument('--anonymize-salt', default=None,
+                   help='salt for --anonymize-queries, to give the same anonymized names across runs (default: a new random salt for each run)')
+
+
+def anonymize_salt(args):
+    "The salt for --anonymize-queries, or None if not anonymizing."
+    if getattr(args, 'anonymize_queries', None):
+        return args.anonymize_salt or secrets.token_hex(16)
+    if getattr(args, 'anonymize_salt', None):
+        notify("WARNING: --anonymize-salt is ignored without --anonymize-queries")
+    return None
+
+
+def output_options(args):
+    "How a command writes its outputs, from the output arguments in 'args'."
+    return sourmash_plugin_branchwater.OutputOptions(
+        float_precision=getattr(args, 'float_precision', None),
+        anonymize_salt=anonymize_salt(args),
+        checksums=getattr(args, 'output_checksums', False))
+
+
+def write_anonymized_queries(args, options):
+    "Write the private (original, anonymized) query name mapping, if anonymizing."
+    if not args.anonymize_queries:
+        return
+    names = options.anonymized_queries()
+    # the mapping undoes the anonymization, so keep it private to the user.
+    fd = os.open(args.anonymize_queries, os.O_WRONLY | os.O_CREAT | os.O_TRUNC, 0o600)
+    with os.fdopen(fd, 'wt', newline='') as fp:
+        w = csv.writer(fp)
+        w.writerow(['original', 'anonymized'])
+        w.writerows(names)
+    notify(f"wrote {len(names)} anonymized query names to '{args.anonymize_queries}'")
+
+
+def add_progress_arg(p):
+    p.add_argument('--progress', action='store_true',
+                   help='show a progress bar with an estimated time remaining, when writing to a terminal')
+
+
+def set_progress(args):
+    sourmash_plugin_branchwater.set_progress(args.progress)
+
+
+def build_revindex_from_iter(sigs, output, *, ksize=None, scaled=None,
+                             moltype=None, sketches_output=None,
+                             colors=False):
+    """Build a RocksDB inverted index from an iterable of signatures.
+
+    'sigs' may yield sourmash SourmashSignature objects or JSON strings.
+    The signatures are also saved to 'sketches_output' (a zip file,
+    defaulting to '<output>.sketches.zip'), which the index uses as storage.
+
+    'ksize', 'scaled', and 'moltype' default to the 'index' settings in
+    branchwater.toml, if any, and otherwise to 31, 1000, and DNA.
+
+    Returns 0 on success, or an exit code from ERROR_CODES on failure.
+    """
+    from sourmash import save_signatures
+
+    defaults = get_defaults('index')
+    if ksize is None:
+        ksize = defaults.get('ksize', 31)
+    if scaled is None:
+        scaled = defaults.get('scaled', 1000)
+    if moltype is None:
+        moltype = defaults.get('moltype', 'DNA')
+
+    if sketches_output is None:
+        sketches_output = output.rstrip('/') + '.sketches.zip'
+
+    def sigs_as_json():
+        for ss in sigs:
+            if isinstance(ss, str):
+                yield ss
+            else:
+                json_str = save_signatures([ss])
+                if isinstance(json_str, bytes):
+                    json_str = json_str.decode('utf-8')
+                yield json_str
+
+    return sourmash_plugin_branchwater.do_index_from_iter(sigs_as_json(),
+                                                         ksize,
+                                                         scaled,
+                                                         moltype,
+                                                         output,
+                                                         sketches_output,
+                                                         colors)
+
+
+def sketch_records(records, param_str='dna,k=31,scaled=1000'):
+    """Sketch in-memory sequences, without reading or writing any files.
+
+    'records' is an iterable of (name, sequence, moltype) tuples, where
+    'sequence' is a str or bytes and 'moltype' is 'dna' or 'protein'.
+    'param_str' is as for manysketch. Records are sketched in parallel.
+
+    Returns a list of SourmashSignature objects, in record order. Raises
+    BranchwaterError on failure.
+    """
+    from sourmash import load_signatures
+
+    def as_bytes(seq):
+        return seq.encode('utf-8') if isinstance(seq, str) else bytes(seq)
+
+    records = [ (name, as_bytes(seq), moltype)
+                for (name, seq, moltype) in records ]
+    sigs = []
+    for json_str in sourmash_plugin_branchwater.do_sketch_records(records,
+                                                                  param_str):
+        sigs.extend(load_signatures(json_str))
+    return sigs
+
+
 class Branchwater_Manysearch(CommandLinePlugin):
     command = 'manysearch'
     description = 'search many metagenomes for contained genomes'
@@ -43,11 +205,13 @@ class Branchwater_Manysearch(CommandLinePlugin):
     def __init__(self, p):
         super().__init__(p)
         p.add_argument('query_paths',
-                       help="input file of sketches")
+                       help="input file of sketches (sig, zip, manifest, or pathlist)")
         p.add_argument('against_paths',
                        help="input file of sketches")
-        p.add_argument('-o', '--output', required=True,
-                       help='CSV output file for matches')
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for matches; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
         p.add_argument('-t', '--threshold', default=0.01, type=float,
                        help='containment threshold for reporting matches')
         p.add_argument('-k', '--ksize', default=31, type=int,
@@ -58,6 +222,44 @@ class Branchwater_Manysearch(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                        help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        p.add_argument('--min-query-hashes', default=0, type=int,
+                       help='skip queries with fewer than this many hashes (default: 0, no minimum)')
+        p.add_argument('--output-skipped-queries', default=None,
+                       help='save a CSV of queries skipped by --min-query-hashes to this file')
+        p.add_argument('--query-groups', default=None,
+                       help='a CSV with query_name and group columns; summarize results per group and match to --query-groups-output')
+        p.add_argument('--query-groups-output', default=None,
+                       help='CSV output file for the per-group summary: number of queries matching, max containment, and mean ANI')
+        p.add_argument('--against-metadata', default=None,
+                       help="a CSV of metadata for the search sketches, keyed by an 'md5' column (joined on match_md5) or a 'match_name' or 'name' column (joined on match_name); its columns are appended to each result row")
+        p.add_argument('--against-metadata-columns', default=None,
+                       help='comma-separated list of --against-metadata columns to append (default: all columns other than the key)')
+        p.add_argument('--moltype-search', action='append', default=[],
+                       help="also search the queries against another collection with other sketch parameters, given as 'moltype[,k=K][,scaled=S]:path' (e.g. 'protein,k=10,scaled=200:protein.zip'); may be given more than once. Output rows are tagged with a moltype column. Not supported when searching a rocksdb")
+        p.add_argument('--screen', action='store_true',
+                       help='screening mode: stop searching for each query at its first match above the threshold, and write one row per query with whether it matched and the first match found (not supported when searching a rocksdb)')
+        p.add_argument('--negative-controls', default=None,
+                       help="a text file listing negative control queries, by name or md5, one per line; matches also found by a control query are flagged in an 'in_negative_control' column (not supported when searching a rocksdb)")
+        p.add_argument('--subtract-negative-controls', action='store_true',
+                       help='remove matches also found by a negative control query from the results of the other queries, instead of flagging them')
+        p.add_argument('--output-downsampled', default=None,
+                       help='save a CSV of each sketch that was downsampled to a larger scaled for comparison, with its original and target scaled and hash counts (not supported when searching a rocksdb)')
+        p.add_argument('--collapse-identical', action='store_true',
+                       help="search sketches with identical content (the same md5) only once, reporting them as one match with the names of the others in a ';'-separated 'match_aliases' column (not supported when searching a rocksdb)")
+        p.add_argument('--all-ksizes', action='store_true',
+                       help='when searching a directory of RocksDB indexes with one index per ksize, search every ksize rather than just -k, writing each ksize\'s results to outputs tagged with the ksize, e.g. results.k21.csv')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the queries and search sketches, report what would be searched, and exit without searching')
+        add_output_checksums_arg(p)
+        add_output_format_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         print_version()
@@ -68,15 +270,92 @@ class Branchwater_Manysearch(CommandLinePlugin):
         notify(f"searching all sketches in '{args.query_paths}' against '{args.against_paths}' using {num_threads} threads")
 
         super().main(args)
+        options = output_options(args)
         status = sourmash_plugin_branchwater.do_manysearch(args.query_paths,
                                                            args.against_paths,
                                                            args.threshold,
                                                            args.ksize,
                                                            args.scaled,
                                                            args.moltype,
-                                                           args.output)
+                                                           args.min_query_hashes,
+                                                           args.output,
+                                                           args.columns,
+                                                           args.output_skipped_queries,
+                                                           query_buckets=args.query_buckets,
+                                                           query_groups=args.query_groups,
+                                                           query_groups_output=args.query_groups_output,
+                                                           against_metadata=args.against_metadata,
+                                                           against_metadata_columns=args.against_metadata_columns,
+                                                           moltype_searches=args.moltype_search,
+                                                           screen=args.screen,
+                                                           negative_controls=args.negative_controls,
+                                                           subtract_negative_controls=args.subtract_negative_controls,
+                                                           output_downsampled=args.output_downsampled,
+                                                           all_ksizes=args.all_ksizes,
+                                                           dry_run=args.dry_run,
+                                                           hash_seed=args.hash_seed,
+                                                           mixed_scaled=args.mixed_scaled,
+                                                           collapse_identical=args.collapse_identical,
+                                                           output_format=args.output_format,
+                                                           output_options=options)
+        if status == 0 and not args.dry_run:
+            notify(f"...manysearch is done! results in '{', '.join(args.output)}'")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Fastmanysearch(CommandLinePlugin):
+    command = 'fastmanysearch'
+    description = 'sketch FASTA/FASTQ samples on the fly and search them against a rocksdb index'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('fromfile_csv', help="a csv file containing paths to FASTA/FASTQ files. \
+                        Columns must be: 'name,genome_filename,protein_filename' or 'name,read1,read2'")
+        p.add_argument('index', help="rocksdb index to search")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for matches; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
+        p.add_argument('-t', '--threshold', default=0.01, type=float,
+                       help='containment threshold for reporting matches')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size at which to sketch samples')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor at which to sketch samples')
+        p.add_argument('-c', '--cores', default=0, type=int,
+                       help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        add_output_checksums_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / threshold: {args.threshold}")
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"sketching and searching all samples in '{args.fromfile_csv}' against '{args.index}' using {num_threads} threads")
+
+        super().main(args)
+        options = output_options(args)
+        status = sourmash_plugin_branchwater.do_fastmanysearch(args.fromfile_csv,
+                                                               args.index,
+                                                               args.threshold,
+                                                               args.ksize,
+                                                               args.scaled,
+                                                               args.output,
+                                                               args.columns,
+                                                               query_buckets=args.query_buckets,
+                                                               output_options=options)
         if status == 0:
-            notify(f"...manysearch is done! results in '{args.output}'")
+            notify(f"...fastmanysearch is done! results in '{', '.join(args.output)}'")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
         return status
 
 
@@ -92,8 +371,12 @@ class Branchwater_Fastgather(CommandLinePlugin):
                        help="save gather output (minimum metagenome cover) to this file")
         p.add_argument('--output-prefetch',
                        help="save prefetch output (all overlaps) to this file")
+        p.add_argument('--output-remaining',
+                       help="save the remaining query sketch after each gather rank to this zip file")
         p.add_argument('-t', '--threshold-bp', default=50000, type=float,
                        help='threshold in estimated base pairs, for reporting matches (default: 50kb)')
+        p.add_argument('--prefetch-threshold-bp', default=None, type=float,
+                       help='threshold in estimated base pairs for keeping prefetch candidates, if lower than --threshold-bp; more candidates are written to --output-prefetch, while gather still reports matches above --threshold-bp (default: --threshold-bp)')
         p.add_argument('-k', '--ksize', default=31, type=int,
                        help='k-mer size at which to do comparisons (default: 31)')
         p.add_argument('-s', '--scaled', default=1000, type=int,
@@ -102,6 +385,35 @@ class Branchwater_Fastgather(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                 help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        p.add_argument('--min-query-hashes', default=0, type=int,
+                       help='skip queries with fewer than this many hashes (default: 0, no minimum)')
+        p.add_argument('--output-skipped-queries', default=None,
+                       help='save a CSV of queries skipped by --min-query-hashes to this file')
+        p.add_argument('--rank-by', default='overlap',
+                       choices=['overlap', 'f_unique_weighted', 'ani'],
+                       help='criterion for choosing the next-best gather match: the most hashes in common with the remaining query (overlap), the most abundance-weighted hashes in common (f_unique_weighted), or the highest containment ANI of the match (ani); default: overlap')
+        p.add_argument('--spike-in', default=None,
+                       help='name, accession, or md5 of a spike-in reference of known abundance; adds a normalized_abundance column, the n_unique_weighted_found of each match relative to that of the spike-in (requires a query with abundances)')
+        p.add_argument('--spike-in-amount', default=1.0, type=float,
+                       help='known abundance of the spike-in, by which normalized_abundance is multiplied (default: 1)')
+        p.add_argument('--ignore-abundance', action='store_true',
+                       help='treat queries as flat, and do not calculate abundance-weighted statistics; faster for queries with abundances')
+        p.add_argument('--output-downsampled', default=None,
+                       help='save a CSV of each sketch that was downsampled to a larger scaled for comparison, with its original and target scaled and hash counts')
+        p.add_argument('--max-matchlist-mem', default=None,
+                       help='keep at most this much of the prefetch matches in memory, e.g. 500M or 16G, spilling those with the smallest overlaps to a temporary file (requires --rank-by overlap; default: no limit)')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the query and search sketches, report what would be gathered, and exit without gathering')
+        add_checkpoint_args(p)
+        add_output_format_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
 
     def main(self, args):
@@ -114,18 +426,44 @@ class Branchwater_Fastgather(CommandLinePlugin):
 
         notify(f"gathering all sketches in '{args.query_sig}' against '{args.against_paths}' using {num_threads} threads")
         super().main(args)
+        prefetch_threshold_bp = None
+        if args.prefetch_threshold_bp is not None:
+            prefetch_threshold_bp = int(args.prefetch_threshold_bp)
+        options = output_options(args)
         status = sourmash_plugin_branchwater.do_fastgather(args.query_sig,
                                                            args.against_paths,
                                                            int(args.threshold_bp),
                                                            args.ksize,
                                                            args.scaled,
                                                            args.moltype,
+                                                           args.min_query_hashes,
                                                            args.output_gather,
-                                                           args.output_prefetch)
-        if status == 0:
+                                                           args.output_prefetch,
+                                                           args.output_remaining,
+                                                           args.columns,
+                                                           args.output_skipped_queries,
+                                                           rank_by=args.rank_by,
+                                                           spike_in=args.spike_in,
+                                                           spike_in_amount=args.spike_in_amount,
+                                                           ignore_abundance=args.ignore_abundance,
+                                                           output_downsampled=args.output_downsampled,
+                                                           dry_run=args.dry_run,
+                                                           hash_seed=args.hash_seed,
+                                                           mixed_scaled=args.mixed_scaled,
+                                                           max_matchlist_mem=args.max_matchlist_mem,
+                                                           prefetch_threshold_bp=prefetch_threshold_bp,
+                                                           checkpoint_every=args.checkpoint_every,
+                                                           resume=args.resume,
+                                                           output_format=args.output_format,
+                                                           output_options=options)
+        if status == 0 and not args.dry_run:
             notify(f"...fastgather is done! gather results in '{args.output_gather}'")
             if args.output_prefetch:
                 notify(f"prefetch results in '{args.output_prefetch}'")
+            if args.output_remaining:
+                notify(f"remaining query sketches in '{args.output_remaining}'")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
         return status
 
 
@@ -148,7 +486,76 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                 help='number of cores to use (default is all available)')
-        p.add_argument('-o', '--output', help='CSV output file for matches')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        p.add_argument('--min-query-hashes', default=0, type=int,
+                       help='skip queries with fewer than this many hashes (default: 0, no minimum)')
+        p.add_argument('--output-skipped-queries', default=None,
+                       help='save a CSV of queries skipped by --min-query-hashes to this file')
+        p.add_argument('-o', '--output', action='append',
+                       help='CSV output file for matches against a rocksdb; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
+        p.add_argument('--save-remaining', action='store_true',
+                       help='save the remaining query sketch after each gather rank to {signame}.remaining.zip')
+        p.add_argument('--save-coverage', action='store_true',
+                       help='save the gather rank that claimed each query hash to {signame}.coverage.csv')
+        p.add_argument('--channel-capacity', default=0, type=int,
+                       help='number of results to queue for the output writer when searching a rocksdb (default: number of cores)')
+        p.add_argument('--skip-done', action='append', default=[],
+                       help='skip queries whose md5 is in the query_md5 column of this previous output CSV; may be given more than once')
+        p.add_argument('--query-groups', default=None,
+                       help='a CSV with query_name and group columns; when searching a rocksdb, summarize results per group and match to --query-groups-output')
+        p.add_argument('--query-groups-output', default=None,
+                       help='CSV output file for the per-group summary: number of queries matching, max containment, and mean ANI')
+        p.add_argument('--against-metadata', default=None,
+                       help="a CSV of metadata for the search sketches, keyed by an 'md5' column (joined on match_md5) or a 'match_name' or 'name' column (joined on match_name); its columns are appended to each result row")
+        p.add_argument('--against-metadata-columns', default=None,
+                       help='comma-separated list of --against-metadata columns to append (default: all columns other than the key)')
+        p.add_argument('--output-dir', default=None,
+                       help='write per-query outputs into this directory, which may be shared by concurrent jobs; files are named {job-prefix}.{signame}.gather.csv etc, and a {job-prefix}.index.csv lists the completed outputs')
+        p.add_argument('--job-prefix', default=None,
+                       help='prefix for files written to --output-dir; must be unique per job (default: generated from the time and process id)')
+        p.add_argument('--rank-by', default='overlap',
+                       choices=['overlap', 'f_unique_weighted', 'ani'],
+                       help='criterion for choosing the next-best gather match; not supported when searching a rocksdb: the most hashes in common with the remaining query (overlap), the most abundance-weighted hashes in common (f_unique_weighted), or the highest containment ANI of the match (ani); default: overlap')
+        p.add_argument('--schedule', default='size',
+                       choices=['size', 'input-order'],
+                       help='the order in which queries are started: the largest queries first, so that they don\'t finish last on a batch of mixed sizes (size), or the order of the query collection (input-order); default: size')
+        p.add_argument('--spike-in', default=None,
+                       help='name, accession, or md5 of a spike-in reference of known abundance; adds a normalized_abundance column, the n_unique_weighted_found of each match relative to that of the spike-in (requires queries with abundances; not supported when searching a rocksdb)')
+        p.add_argument('--spike-in-amount', default=1.0, type=float,
+                       help='known abundance of the spike-in, by which normalized_abundance is multiplied (default: 1)')
+        p.add_argument('--ignore-abundance', action='store_true',
+                       help='treat queries as flat, and do not calculate abundance-weighted statistics; faster for queries with abundances')
+        p.add_argument('--report-no-matches', action='store_true',
+                       help='write a row with empty match columns for each query with no matches, so that every query appears in the output (only supported when searching a rocksdb)')
+        p.add_argument('--output-downsampled', default=None,
+                       help='save a CSV of each sketch that was downsampled to a larger scaled for comparison, with its original and target scaled and hash counts (not supported when searching a rocksdb)')
+        p.add_argument('--all-ksizes', action='store_true',
+                       help='when searching a directory of RocksDB indexes with one index per ksize, search every ksize rather than just -k, writing each ksize\'s results to outputs tagged with the ksize, e.g. results.k21.csv')
+        p.add_argument('--cache-dir', default=None,
+                       help='cache the selected search sketches in this directory, so that repeated runs against the same database skip parsing them (not supported when searching a rocksdb)')
+        p.add_argument('--lineages', default=None,
+                       help='a lineages CSV, as used by sourmash tax; write a Krona text file, {signame}.krona.tsv, summarizing the taxonomic composition of each query (not supported when searching a rocksdb)')
+        p.add_argument('--output-biom', default=None,
+                       help='also write a JSON BIOM table of the weighted fraction of each query (sample) found by each match (feature), e.g. for QIIME or phyloseq (only supported when searching a rocksdb)')
+        p.add_argument('--report', default=None,
+                       help='also write a self-contained HTML report of the run, with the run parameters and the top matches and composition of each query, e.g. to share with collaborators (cannot be used with --resume or --skip-done)')
+        p.add_argument('--max-matchlist-mem', default=None,
+                       help='keep at most this much of each query\'s prefetch matches in memory, e.g. 500M or 16G, spilling those with the smallest overlaps to a temporary file (requires --rank-by overlap; not supported when searching a rocksdb; default: no limit)')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the queries and search sketches, report what would be gathered, and exit without gathering')
+        add_checkpoint_args(p)
+        add_output_checksums_arg(p)
+        add_output_format_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_progress_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
 
     def main(self, args):
@@ -160,15 +567,53 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
 
         notify(f"gathering all sketches in '{args.query_paths}' against '{args.against_paths}' using {num_threads} threads")
         super().main(args)
+        options = output_options(args)
+        set_progress(args)
         status = sourmash_plugin_branchwater.do_fastmultigather(args.query_paths,
                                                                 args.against_paths,
                                                                 int(args.threshold_bp),
                                                                 args.ksize,
                                                                 args.scaled,
                                                                 args.moltype,
-                                                                args.output)
-        if status == 0:
+                                                                args.save_remaining,
+                                                                args.save_coverage,
+                                                                args.min_query_hashes,
+                                                                args.channel_capacity,
+                                                                args.output,
+                                                                args.columns,
+                                                                args.output_skipped_queries,
+                                                                query_buckets=args.query_buckets,
+                                                                skip_done=args.skip_done,
+                                                                query_groups=args.query_groups,
+                                                                query_groups_output=args.query_groups_output,
+                                                                against_metadata=args.against_metadata,
+                                                                against_metadata_columns=args.against_metadata_columns,
+                                                                output_dir=args.output_dir,
+                                                                job_prefix=args.job_prefix,
+                                                                rank_by=args.rank_by,
+                                                                spike_in=args.spike_in,
+                                                                spike_in_amount=args.spike_in_amount,
+                                                                ignore_abundance=args.ignore_abundance,
+                                                                report_no_matches=args.report_no_matches,
+                                                                output_downsampled=args.output_downsampled,
+                                                                all_ksizes=args.all_ksizes,
+                                                                dry_run=args.dry_run,
+                                                                hash_seed=args.hash_seed,
+                                                                mixed_scaled=args.mixed_scaled,
+                                                                cache_dir=args.cache_dir,
+                                                                lineages=args.lineages,
+                                                                output_biom=args.output_biom,
+                                                                max_matchlist_mem=args.max_matchlist_mem,
+                                                                checkpoint_every=args.checkpoint_every,
+                                                                resume=args.resume,
+                                                                output_format=args.output_format,
+                                                                report=args.report,
+                                                                schedule=args.schedule,
+                                                                output_options=options)
+        if status == 0 and not args.dry_run:
             notify(f"...fastmultigather is done!")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
         return status
 
 
@@ -190,6 +635,13 @@ class Branchwater_Index(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                        help='number of cores to use (default is all available)')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the sketches, report what would be indexed, and exit without indexing')
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_progress_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} ")
@@ -200,14 +652,19 @@ class Branchwater_Index(CommandLinePlugin):
         notify(f"indexing all sketches in '{args.siglist}'")
 
         super().main(args)
+        set_progress(args)
         status = sourmash_plugin_branchwater.do_index(args.siglist,
                                                       args.ksize,
                                                       args.scaled,
                                                       args.moltype,
                                                       args.output,
-                                                      False) # colors - currently must be false?
-        if status == 0:
+                                                      False, # colors - currently must be false?
+                                                      dry_run=args.dry_run,
+                                                      hash_seed=args.hash_seed,
+                                                      mixed_scaled=args.mixed_scaled)
+        if status == 0 and not args.dry_run:
             notify(f"...index is done! results in '{args.output}'")
+        write_run_summary(self.command, args, status)
         return status
 
 class Branchwater_Check(CommandLinePlugin):
@@ -219,13 +676,42 @@ class Branchwater_Check(CommandLinePlugin):
         p.add_argument('index',
                        help='index file')
         p.add_argument('--quick', action='store_true')
+        p.add_argument('--deep', action='store_true',
+                       help='also load every sketch from storage and verify its md5sum against the manifest')
+        p.add_argument('-o', '--output', default=None,
+                       help='with --deep, write a CSV of datasets that failed to load or had mismatched md5sums')
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         notify(f"checking index '{args.index}'")
         super().main(args)
-        status = sourmash_plugin_branchwater.do_check(args.index, args.quick)
+        status = sourmash_plugin_branchwater.do_check(args.index, args.quick,
+                                                      deep=args.deep,
+                                                      output=args.output)
         if status == 0:
             notify(f"...index is ok!")
+        write_run_summary(self.command, args, status)
+        return status
+
+class Branchwater_IndexMigrate(CommandLinePlugin):
+    command = 'index_migrate'
+    description = 'Upgrade a Branchwater RevIndex to the current schema'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('index',
+                       help='index file')
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        notify(f"migrating index '{args.index}'")
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_index_migrate(args.index)
+        if status == 0:
+            notify(f"...index migration is done!")
+        write_run_summary(self.command, args, status)
         return status
 
 
@@ -239,8 +725,10 @@ class Branchwater_Multisearch(CommandLinePlugin):
                        help="input file of sketches")
         p.add_argument('against_paths',
                        help="input file of sketches")
-        p.add_argument('-o', '--output', required=True,
-                       help='CSV output file for matches')
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for matches; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
         p.add_argument('-t', '--threshold', default=0.01, type=float,
                        help='containment threshold for reporting matches')
         p.add_argument('-k', '--ksize', default=31, type=int,
@@ -251,8 +739,35 @@ class Branchwater_Multisearch(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                        help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        p.add_argument('--min-query-hashes', default=0, type=int,
+                       help='skip queries with fewer than this many hashes (default: 0, no minimum)')
+        p.add_argument('--output-skipped-queries', default=None,
+                       help='save a CSV of queries skipped by --min-query-hashes to this file')
         p.add_argument('-a', '--ani', action='store_true',
                        help='estimate ANI from containment')
+        p.add_argument('--min-ani', default=None, type=float,
+                       help='with --ani, only report matches with max_containment_ani at or above this value, e.g. 0.95')
+        p.add_argument('-n', '--num', default=0, type=int,
+                       help='search num sketches of this size, reporting jaccard only (default: 0, use scaled sketches)')
+        p.add_argument('--output-downsampled', default=None,
+                       help='save a CSV of each sketch that was downsampled to a larger scaled for comparison, with its original and target scaled and hash counts')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the queries and search sketches, report what would be compared, and exit without comparing')
+        p.add_argument('--max-mem', default=None,
+                       help='abort before loading sketches if the estimated peak memory exceeds this, e.g. 500M or 16G (default: no limit)')
+        p.add_argument('--compact-mem', action='store_true',
+                       help='hold search sketches in memory as compressed hash arrays, trading some speed for a smaller memory footprint')
+        add_output_checksums_arg(p)
+        add_output_format_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_progress_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         print_version()
@@ -264,6 +779,8 @@ class Branchwater_Multisearch(CommandLinePlugin):
         notify(f"searching all sketches in '{args.query_paths}' against '{args.against_paths}' using {num_threads} threads")
 
         super().main(args)
+        options = output_options(args)
+        set_progress(args)
         status = sourmash_plugin_branchwater.do_multisearch(args.query_paths,
                                                             args.against_paths,
                                                             args.threshold,
@@ -271,9 +788,25 @@ class Branchwater_Multisearch(CommandLinePlugin):
                                                             args.scaled,
                                                             args.moltype,
                                                             args.ani,
-                                                            args.output)
-        if status == 0:
-            notify(f"...multisearch is done! results in '{args.output}'")
+                                                            args.num,
+                                                            args.min_query_hashes,
+                                                            args.output,
+                                                            args.columns,
+                                                            args.output_skipped_queries,
+                                                            query_buckets=args.query_buckets,
+                                                            min_ani=args.min_ani,
+                                                            output_downsampled=args.output_downsampled,
+                                                            dry_run=args.dry_run,
+                                                            max_mem=args.max_mem,
+                                                            hash_seed=args.hash_seed,
+                                                            mixed_scaled=args.mixed_scaled,
+                                                            compact_mem=args.compact_mem,
+                                                            output_format=args.output_format,
+                                                            output_options=options)
+        if status == 0 and not args.dry_run:
+            notify(f"...multisearch is done! results in '{', '.join(args.output)}'")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
         return status
     
 class Branchwater_Pairwise(CommandLinePlugin):
@@ -284,8 +817,12 @@ class Branchwater_Pairwise(CommandLinePlugin):
         super().__init__(p)
         p.add_argument('sig_paths',
                        help="input file of sketches")
-        p.add_argument('-o', '--output', required=True,
-                       help='CSV output file for matches')
+        p.add_argument('--against', default=None,
+                       help="a second input file of sketches; if given, compare each sketch in sig_paths against each sketch in this file, with no comparisons within either file")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for matches; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
         p.add_argument('-t', '--threshold', default=0.01, type=float,
                        help='containment threshold for reporting matches')
         p.add_argument('-k', '--ksize', default=31, type=int,
@@ -296,10 +833,32 @@ class Branchwater_Pairwise(CommandLinePlugin):
                        help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
         p.add_argument('-c', '--cores', default=0, type=int,
                        help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
         p.add_argument('-a', '--ani', action='store_true',
                        help='estimate ANI from containment')
+        p.add_argument('--min-ani', default=None, type=float,
+                       help='with --ani, only report pairs with max_containment_ani at or above this value, e.g. 0.95')
         p.add_argument('--write-all', action="store_true",
                        help="write self comparisons for all sketches")
+        p.add_argument('--output-downsampled', default=None,
+                       help='save a CSV of each sketch that was downsampled to a larger scaled for comparison, with its original and target scaled and hash counts')
+        p.add_argument('--dry-run', action='store_true',
+                       help='load and select the sketches, report what would be compared, and exit without comparing')
+        p.add_argument('--max-mem', default=None,
+                       help='abort before loading sketches if the estimated peak memory exceeds this, e.g. 500M or 16G (default: no limit)')
+        add_output_checksums_arg(p)
+        p.add_argument('--lsh-bands', default=0, type=int,
+                       help='for large all-vs-all comparisons, only compare sketches that share at least one of this many LSH bands of hashes; requires --lsh-rows (default: 0, compare all pairs)')
+        p.add_argument('--lsh-rows', default=0, type=int,
+                       help='number of hashes in each LSH band; more rows compare fewer, more similar pairs (default: 0, compare all pairs)')
+        add_output_format_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_progress_arg(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         print_version()
@@ -308,9 +867,14 @@ class Branchwater_Pairwise(CommandLinePlugin):
 
         num_threads = set_thread_pool(args.cores)
 
-        notify(f"pairwise-comparing all sketches in '{args.sig_paths}' using {num_threads} threads")
+        if args.against:
+            notify(f"pairwise-comparing all sketches in '{args.sig_paths}' against all sketches in '{args.against}' using {num_threads} threads")
+        else:
+            notify(f"pairwise-comparing all sketches in '{args.sig_paths}' using {num_threads} threads")
 
         super().main(args)
+        options = output_options(args)
+        set_progress(args)
         status = sourmash_plugin_branchwater.do_pairwise(args.sig_paths,
                                                             args.threshold,
                                                             args.ksize,
@@ -318,9 +882,294 @@ class Branchwater_Pairwise(CommandLinePlugin):
                                                             args.moltype,
                                                             args.ani,
                                                             args.write_all,
-                                                            args.output)
+                                                            args.output,
+                                                            args.columns,
+                                                            query_buckets=args.query_buckets,
+                                                            against_path=args.against,
+                                                            output_downsampled=args.output_downsampled,
+                                                            dry_run=args.dry_run,
+                                                            max_mem=args.max_mem,
+                                                            hash_seed=args.hash_seed,
+                                                            mixed_scaled=args.mixed_scaled,
+                                                            lsh_bands=args.lsh_bands,
+                                                            lsh_rows=args.lsh_rows,
+                                                            output_format=args.output_format,
+                                                            min_ani=args.min_ani,
+                                                            output_options=options)
+        if status == 0 and not args.dry_run:
+            notify(f"...pairwise is done! results in '{', '.join(args.output)}'")
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Betadiversity(CommandLinePlugin):
+    command = 'betadiversity'
+    description = 'abundance-weighted pairwise dissimilarity between metagenome sketches'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('sig_paths',
+                       help="input file of abundance-tracking sketches")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for comparisons; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size at which to select sketches')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor at which to do comparisons')
+        p.add_argument('-m', '--moltype', default='DNA', choices = ["DNA", "protein", "dayhoff", "hp"],
+                       help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
+        p.add_argument('-c', '--cores', default=0, type=int,
+                       help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        add_output_checksums_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}")
+        args.moltype = args.moltype.lower()
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"computing beta diversity between all sketches in '{args.sig_paths}' using {num_threads} threads")
+
+        super().main(args)
+        options = output_options(args)
+        status = sourmash_plugin_branchwater.do_betadiversity(args.sig_paths,
+                                                                 args.ksize,
+                                                                 args.scaled,
+                                                                 args.moltype,
+                                                                 args.output,
+                                                                 args.columns,
+                                                                 query_buckets=args.query_buckets,
+                                                                 hash_seed=args.hash_seed,
+                                                                 mixed_scaled=args.mixed_scaled,
+                                                                 output_options=options)
+        if status == 0:
+            notify(f"...betadiversity is done! results in '{', '.join(args.output)}'")
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_WeightedContainment(CommandLinePlugin):
+    command = 'weighted_containment'
+    description = 'abundance-weighted containment of each metagenome sketch in every other'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('sig_paths',
+                       help="input file of abundance-tracking sketches")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for comparisons; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('-t', '--threshold', default=0.0, type=float,
+                       help='report pairs where either weighted containment is above this threshold (default: 0.0, all pairs with shared hashes)')
+        p.add_argument('--query-buckets', default=0, type=int,
+                       help='write output as hive-partitioned directories, bucketed by a hash of query_name into this many partitions, e.g. {output}/query_bucket=0/part-0.csv (default: 0, no partitioning)')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size at which to select sketches')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor at which to do comparisons')
+        p.add_argument('-m', '--moltype', default='DNA', choices = ["DNA", "protein", "dayhoff", "hp"],
+                       help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
+        p.add_argument('-c', '--cores', default=0, type=int,
+                       help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        add_output_checksums_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_progress_arg(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold: {args.threshold}")
+        args.moltype = args.moltype.lower()
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"computing weighted containment between all sketches in '{args.sig_paths}' using {num_threads} threads")
+
+        super().main(args)
+        options = output_options(args)
+        set_progress(args)
+        status = sourmash_plugin_branchwater.do_weighted_containment(args.sig_paths,
+                                                                        args.threshold,
+                                                                        args.ksize,
+                                                                        args.scaled,
+                                                                        args.moltype,
+                                                                        args.output,
+                                                                        args.columns,
+                                                                        query_buckets=args.query_buckets,
+                                                                        hash_seed=args.hash_seed,
+                                                                        mixed_scaled=args.mixed_scaled,
+                                                                        output_options=options)
+        if status == 0:
+            notify(f"...weighted_containment is done! results in '{', '.join(args.output)}'")
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Rarefy(CommandLinePlugin):
+    command = 'rarefy'
+    description = 'count search matches as queries are subsampled to lower sequencing depths'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('query_paths',
+                       help="input file of sketches (sig, zip, manifest, or pathlist)")
+        p.add_argument('against_paths',
+                       help="input file of sketches")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for match counts; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('-t', '--threshold', default=0.01, type=float,
+                       help='containment threshold for counting matches')
+        p.add_argument('--fractions', default='1,0.5,0.25,0.1,0.05,0.01',
+                       help='comma-separated list of fractions of each query to keep (default: 1,0.5,0.25,0.1,0.05,0.01)')
+        p.add_argument('--replicates', default=3, type=int,
+                       help='number of random subsamples at each fraction (default: 3)')
+        p.add_argument('--seed', default=42, type=int,
+                       help='random seed for subsampling (default: 42)')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size at which to select sketches')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor at which to do comparisons')
+        p.add_argument('-m', '--moltype', default='DNA', choices = ["DNA", "protein", "dayhoff", "hp"],
+                       help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
+        p.add_argument('-c', '--cores', default=0, type=int,
+                       help='number of cores to use (default is all available)')
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        add_output_checksums_arg(p)
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_anonymize_queries_args(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold: {args.threshold}")
+        args.moltype = args.moltype.lower()
+
+        try:
+            fractions = [ float(f) for f in args.fractions.split(',') ]
+        except ValueError:
+            notify(f"ERROR: cannot parse --fractions '{args.fractions}'; it must be a comma-separated list of numbers.")
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"subsampling queries in '{args.query_paths}' to fractions {fractions} ({args.replicates} replicates) and searching '{args.against_paths}' using {num_threads} threads")
+
+        super().main(args)
+        options = output_options(args)
+        status = sourmash_plugin_branchwater.do_rarefy(args.query_paths,
+                                                          args.against_paths,
+                                                          args.threshold,
+                                                          args.ksize,
+                                                          args.scaled,
+                                                          args.moltype,
+                                                          fractions,
+                                                          args.replicates,
+                                                          args.seed,
+                                                          args.output,
+                                                          args.columns,
+                                                          hash_seed=args.hash_seed,
+                                                          mixed_scaled=args.mixed_scaled,
+                                                          output_options=options)
         if status == 0:
-            notify(f"...pairwise is done! results in '{args.output}'")
+            notify(f"...rarefy is done! results in '{', '.join(args.output)}'")
+        write_anonymized_queries(args, options)
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Timeseries(CommandLinePlugin):
+    command = 'timeseries'
+    description = 'build per (query, match) time series of containment from manysearch results at many timepoints'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('timepoints_csv',
+                       help="CSV with 'timepoint' and 'path' columns, listing the manysearch output (optionally gzipped) for each timepoint, in time order")
+        p.add_argument('-o', '--output', required=True, action='append',
+                       help='CSV output file for the time series; may be given more than once, and ending in .gz compresses the output')
+        p.add_argument('--present-only', action='store_true',
+                       help="only write the timepoints where each match was found, rather than a row for every timepoint with 'present' false and containment 0 where it was not")
+        p.add_argument('--columns', default=None,
+                       help='comma-separated list of columns to write to the output CSV (default: all columns)')
+        add_output_checksums_arg(p)
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"building time series from the search outputs listed in '{args.timepoints_csv}'")
+
+        super().main(args)
+        options = output_options(args)
+        status = sourmash_plugin_branchwater.do_timeseries(args.timepoints_csv,
+                                                           args.output,
+                                                           columns=args.columns,
+                                                           fill_missing=not args.present_only,
+                                                           output_options=options)
+        if status == 0:
+            notify(f"...timeseries is done! results in '{', '.join(args.output)}'")
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Simulate(CommandLinePlugin):
+    command = 'simulate'
+    description = 'build synthetic sketches with known containment/ANI between them'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('spec_csv',
+                       help="CSV describing the sketches to build, with columns 'name,n_hashes' and optionally 'parent' and one of 'containment' or 'ani'")
+        p.add_argument('-o', '--output', required=True,
+                       help='output zip file or signature file for the simulated sketches')
+        p.add_argument('--output-truth', default=None,
+                       help='save a CSV of each simulated sketch with its md5 and its actual containment and ANI in its parent')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size of the simulated sketches')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor of the simulated sketches')
+        p.add_argument('-m', '--moltype', default='DNA', choices = ["DNA", "protein", "dayhoff", "hp"],
+                       help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
+        p.add_argument('--seed', default=42, type=int,
+                       help='random seed for choosing hashes (default: 42)')
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / seed: {args.seed}")
+        args.moltype = args.moltype.lower()
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_simulate(args.spec_csv,
+                                                            args.ksize,
+                                                            args.scaled,
+                                                            args.moltype,
+                                                            args.seed,
+                                                            args.output,
+                                                            truth_output=args.output_truth)
+        if status == 0:
+            notify(f"...simulate is done! sketches in '{args.output}'")
+        write_run_summary(self.command, args, status)
         return status
 
 
@@ -333,7 +1182,7 @@ class Branchwater_Manysketch(CommandLinePlugin):
         p.add_argument('fromfile_csv', help="a csv file containing paths to FASTA files. \
                         Columns must be: 'name,genome_filename,protein_filename' or 'name,read1,read2'")
         p.add_argument('-o', '--output', required=True,
-                       help='output zip file for the signatures')
+                       help='output zip file for the signatures; may be a template using {moltype}, {ksize}, and {scaled}, e.g. out.{moltype}.k{ksize}.zip, to write each parameter set to its own zip file')
         p.add_argument('-p', '--param-string', action='append', type=str, default=[],
                           help='parameter string for sketching (default: k=31,scaled=1000)')
         p.add_argument('-c', '--cores', default=0, type=int,
@@ -342,6 +1191,19 @@ class Branchwater_Manysketch(CommandLinePlugin):
                        help='build one sketch per FASTA record, i.e. multiple sketches per FASTA file')
         p.add_argument('-f', '--force', action="store_true",
                        help='allow use of individual FASTA files in more than more sketch')
+        p.add_argument('--sanitize-names', action="store_true",
+                       help='replace newlines and other control characters in sketch names with spaces, and truncate long names')
+        p.add_argument('--max-name-length', default=1024, type=int,
+                       help='with --sanitize-names, truncate sketch names to this many characters; 0 for no limit (default: 1024)')
+        p.add_argument('--output-modified-names',
+                       help='with --sanitize-names, save original and sanitized names to this CSV file')
+        p.add_argument('--channel-capacity', default=0, type=int,
+                       help='number of sketches to queue for the zip writer (default: number of cores)')
+        p.add_argument('--dry-run', action='store_true',
+                       help='read the input file list and parameters, report what would be sketched, and exit without sketching')
+        add_progress_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         print_version()
@@ -359,13 +1221,20 @@ class Branchwater_Manysketch(CommandLinePlugin):
         notify(f"sketching all files in '{args.fromfile_csv}' using {num_threads} threads")
 
         super().main(args)
+        set_progress(args)
         status = sourmash_plugin_branchwater.do_manysketch(args.fromfile_csv,
                                                            args.param_string,
                                                            args.output,
                                                            args.singleton,
-                                                           args.force)
-        if status == 0:
+                                                           args.force,
+                                                           args.sanitize_names,
+                                                           args.max_name_length,
+                                                           args.channel_capacity,
+                                                           args.output_modified_names,
+                                                           dry_run=args.dry_run)
+        if status == 0 and not args.dry_run:
             notify(f"...manysketch is done! results in '{args.output}'")
+        write_run_summary(self.command, args, status)
         return status
 
 class Branchwater_Cluster(CommandLinePlugin):
@@ -374,18 +1243,26 @@ class Branchwater_Cluster(CommandLinePlugin):
 
     def __init__(self, p):
         super().__init__(p)
-        p.add_argument('pairwise_csv', help="a csv file containing similarity information. \
-                        Currently, only a branchwater 'pairwise' or 'multisearch' file will work")
+        p.add_argument('pairwise_csv', help="a csv file containing similarity information, \
+                        e.g. from branchwater 'pairwise' or 'multisearch', or any CSV with query_name, match_name and similarity or distance columns")
         p.add_argument('-o', '--output', required=True,
                        help='output csv file for the clusters')
         p.add_argument('--cluster-sizes', default=None,
                        help='output file for the cluster size histogram')
+        p.add_argument('--output-graphml', default=None,
+                       help='write the thresholded comparison graph to this GraphML file, with the cluster of each node, e.g. for Cytoscape or Gephi')
+        p.add_argument('--output-edges', default=None,
+                       help='write the thresholded comparison graph to this CSV edge list, with the cluster of each edge')
         p.add_argument('--similarity-column', type=str, default='average_containment_ani',
-                       choices=['containment', 'max_containment', 'jaccard', 'average_containment_ani', 'max_containment_ani'],
-                       help='column to use as similarity measure')
-        p.add_argument('-t', '--threshold',  type=float, default=0.95, help="similarity threshold for clustering. Default: 95%% ANI (0.95)")
+                       help='column to use as similarity (or, with --metric distance, distance) measure, e.g. containment, max_containment, jaccard, average_containment_ani, or max_containment_ani (default: average_containment_ani)')
+        p.add_argument('--metric', default='similarity', choices=['similarity', 'distance'],
+                       help='whether larger values in --similarity-column are more similar (similarity), or less similar, e.g. 1 - ANI (distance); default: similarity')
+        p.add_argument('-t', '--threshold',  type=float, default=None, help="threshold for clustering; pairs at or above it are linked for similarities, and at or below it for distances. Default: 95%% ANI (0.95 similarity, or 0.05 distance)")
         p.add_argument('-c', '--cores', default=0, type=int,
                        help='number of cores to use (default is all available)')
+        add_float_precision_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
 
     def main(self, args):
         print_version()
@@ -394,13 +1271,81 @@ class Branchwater_Cluster(CommandLinePlugin):
 
         notify(f"generating clusters for comparisons in '{args.pairwise_csv}' using {num_threads} threads")
 
+        if args.threshold is None:
+            args.threshold = 0.95 if args.metric == 'similarity' else 0.05
+
         super().main(args)
+        options = output_options(args)
         status = sourmash_plugin_branchwater.do_cluster(args.pairwise_csv,
                                                         args.output,
                                                         args.similarity_column,
                                                         args.threshold,
-                                                        args.cluster_sizes)
+                                                        args.cluster_sizes,
+                                                        metric=args.metric,
+                                                        output_graphml=args.output_graphml,
+                                                        output_edges=args.output_edges,
+                                                        output_options=options)
         if status == 0:
             notify(f"...clustering is done! results in '{args.output}'")
             notify(f"                       cluster counts in '{args.cluster_sizes}'")
+        write_run_summary(self.command, args, status)
+        return status
+
+
+class Branchwater_Setops(CommandLinePlugin):
+    command = 'setops'
+    description = 'union, intersection, or subtraction of sketches between two collections'
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument('collection_a',
+                       help="input file of sketches; each sketch is combined with the sketches in collection_b")
+        p.add_argument('collection_b',
+                       help="input file of sketches; all sketches are merged before the operation")
+        p.add_argument('--op', '--operation', dest='operation', required=True,
+                       choices=['union', 'intersect', 'subtract'],
+                       help='set operation to apply')
+        p.add_argument('-o', '--output', required=True,
+                       help='output file for the new sketches; a zip file, or a .sig / .sig.gz signature file')
+        p.add_argument('-k', '--ksize', default=31, type=int,
+                       help='k-mer size at which to select sketches')
+        p.add_argument('-s', '--scaled', default=1000, type=int,
+                       help='scaled factor at which to do set operations')
+        p.add_argument('-m', '--moltype', default='DNA', choices = ["DNA", "protein", "dayhoff", "hp"],
+                       help = 'molecule type (DNA, protein, dayhoff, or hp; default DNA)')
+        p.add_argument('--merge', action='store_true',
+                       help='merge all sketches in collection_a first, and write a single output sketch')
+        p.add_argument('--name', default=None,
+                       help='name for the merged output sketch (only used with --merge)')
+        p.add_argument('-c', '--cores', default=0, type=int,
+                       help='number of cores to use (default is all available)')
+        add_hash_seed_arg(p)
+        add_mixed_scaled_arg(p)
+        add_run_summary_arg(p)
+        apply_config(p, self.command)
+
+    def main(self, args):
+        print_version()
+        notify(f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / operation: {args.operation}")
+        args.moltype = args.moltype.lower()
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"computing '{args.operation}' of sketches in '{args.collection_a}' and '{args.collection_b}' using {num_threads} threads")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_sig_setops(args.collection_a,
+                                                           args.collection_b,
+                                                           args.operation,
+                                                           args.ksize,
+                                                           args.scaled,
+                                                           args.moltype,
+                                                           args.merge,
+                                                           args.name,
+                                                           args.output,
+                                                           hash_seed=args.hash_seed,
+                                                           mixed_scaled=args.mixed_scaled)
+        if status == 0:
+            notify(f"...setops is done! sketches in '{args.output}'")
+        write_run_summary(self.command, args, status)
         return status
@@ -0,0 +1,111 @@
+"""
+Site-wide default parameters, loaded from a 'branchwater.toml' config file.
+
+The config file is found via the BRANCHWATER_CONFIG environment variable,
+or else 'branchwater.toml' in the current directory. Top-level settings
+apply to every command that has a matching option; a table named after a
+command applies only to that command:
+
+    ksize = 21
+    scaled = 1000
+    moltype = "DNA"
+    cores = 8
+    search_paths = ["/data/sketches", "/data/indexes"]
+
+    [manysearch]
+    columns = "query_name,match_name,containment"
+
+Settings are the option names, with '-' replaced by '_' (e.g. 'cores',
+'query_buckets'). Explicit command-line arguments always take precedence.
+"""
+import os
+
+try:
+    import tomllib
+except ImportError:                     # Python < 3.11
+    import tomli as tomllib
+
+from sourmash.logging import notify
+
+CONFIG_ENV = 'BRANCHWATER_CONFIG'
+CONFIG_FILE = 'branchwater.toml'
+
+# positional/optional arguments that name input files, and so are looked
+# up in 'search_paths' when they don't exist as given.
+INPUT_PATH_ARGS = {'query_paths', 'against_paths', 'query_sig', 'sig_paths',
+                   'siglist', 'fromfile_csv', 'index', 'pairwise_csv',
+                   'collection_a', 'collection_b', 'against', 'timepoints_csv'}
+
+
+def find_config():
+    "Return the path of the config file to use, or None."
+    path = os.environ.get(CONFIG_ENV)
+    if path:
+        if not os.path.exists(path):
+            raise ValueError(f"{CONFIG_ENV} is set to '{path}', which does not exist")
+        return path
+    if os.path.exists(CONFIG_FILE):
+        return CONFIG_FILE
+    return None
+
+
+def load_config():
+    "Load the config file, if any, as a dictionary."
+    path = find_config()
+    if path is None:
+        return {}
+    try:
+        with open(path, 'rb') as fp:
+            return tomllib.load(fp)
+    except tomllib.TOMLDecodeError as e:
+        raise ValueError(f"cannot parse branchwater config '{path}': {e}")
+
+
+def get_defaults(command=None, config=None):
+    """Return the default settings for 'command': the top-level settings,
+    updated with the command's own table."""
+    if config is None:
+        config = load_config()
+    defaults = { k: v for k, v in config.items() if not isinstance(v, dict) }
+    if command is not None:
+        defaults.update(config.get(command, {}))
+    return defaults
+
+
+def resolve_path(path, search_paths):
+    "Find 'path' in 'search_paths' if it doesn't exist as given."
+    if os.path.isabs(path) or os.path.exists(path):
+        return path
+    for search_path in search_paths:
+        candidate = os.path.join(search_path, path)
+        if os.path.exists(candidate):
+            return candidate
+    return path
+
+
+def apply_config(p, command):
+    """Set the defaults of argparse parser 'p' for 'command' from the
+    config file, and look up its input paths in 'search_paths'."""
+    try:
+        config = load_config()
+    except ValueError as e:
+        # don't break every sourmash command over a bad config file.
+        notify(f"WARNING: {e}; ignoring it")
+        return
+    defaults = get_defaults(command, config)
+    search_paths = defaults.pop('search_paths', [])
+    if isinstance(search_paths, str):
+        search_paths = [search_paths]
+
+    actions = { action.dest: action for action in p._actions }
+    for key in config.get(command, {}):
+        if key not in actions and key != 'search_paths':
+            notify(f"WARNING: ignoring unknown '{command}' setting '{key}' in branchwater config")
+
+    p.set_defaults(**{ k: v for k, v in defaults.items() if k in actions })
+
+    if search_paths:
+        for dest in INPUT_PATH_ARGS & set(actions):
+            action = actions[dest]
+            convert = action.type or str
+            action.type = lambda value, convert=convert: convert(resolve_path(value, search_paths))
@@ -0,0 +1,152 @@
+"""
+Typed dataclasses for the rows of branchwater result CSVs.
+
+The fields of each dataclass are generated at import time from the Rust
+result structs, which are the single source of truth for them; the
+matching type stubs in 'results.pyi' are generated from the same
+definitions, with:
+
+    python -m sourmash_plugin_branchwater.results > results.pyi
+
+For example, to read 'multisearch' or 'pairwise' output:
+
+    from sourmash_plugin_branchwater.results import MultiSearchResult, read_results
+
+    for row in read_results('results.csv', MultiSearchResult):
+        print(row.query_name, row.match_name, row.containment)
+
+'iter_gather' runs gather and yields each BranchwaterGatherResult as it
+is found, e.g. to stream partial results during a long gather.
+"""
+import csv
+import dataclasses
+import gzip
+import json
+from typing import Optional
+
+from . import sourmash_plugin_branchwater
+
+_SCHEMAS = dict(sourmash_plugin_branchwater.result_schemas())
+
+_TYPES = {
+    'str': str,
+    'float': float,
+    'int': int,
+    'bool': bool,
+}
+
+
+def _annotation(py_type):
+    if py_type.startswith('Optional['):
+        return Optional[_annotation(py_type[len('Optional['):-1])]
+    return _TYPES[py_type]
+
+
+def _make_dataclass(name, fields):
+    cls = dataclasses.make_dataclass(
+        name,
+        [ (field, _annotation(py_type)) for field, py_type in fields ],
+        frozen=True,
+    )
+    cls.__module__ = __name__
+    return cls
+
+
+SearchResult = _make_dataclass('SearchResult', _SCHEMAS['SearchResult'])
+ScreenResult = _make_dataclass('ScreenResult', _SCHEMAS['ScreenResult'])
+NumSearchResult = _make_dataclass('NumSearchResult', _SCHEMAS['NumSearchResult'])
+MultiSearchResult = _make_dataclass('MultiSearchResult', _SCHEMAS['MultiSearchResult'])
+BranchwaterPrefetchResult = _make_dataclass('BranchwaterPrefetchResult',
+                                            _SCHEMAS['BranchwaterPrefetchResult'])
+BranchwaterGatherResult = _make_dataclass('BranchwaterGatherResult',
+                                          _SCHEMAS['BranchwaterGatherResult'])
+BetaDiversityResult = _make_dataclass('BetaDiversityResult', _SCHEMAS['BetaDiversityResult'])
+WeightedContainmentResult = _make_dataclass('WeightedContainmentResult',
+                                            _SCHEMAS['WeightedContainmentResult'])
+RarefactionResult = _make_dataclass('RarefactionResult', _SCHEMAS['RarefactionResult'])
+TimeSeriesResult = _make_dataclass('TimeSeriesResult', _SCHEMAS['TimeSeriesResult'])
+
+
+def _convert(value, py_type):
+    if py_type.startswith('Optional['):
+        if value is None or value == '':
+            return None
+        py_type = py_type[len('Optional['):-1]
+    if py_type == 'bool':
+        return value.lower() == 'true'
+    if py_type == 'int':
+        return int(float(value))
+    return _TYPES[py_type](value)
+
+
+def read_results(path, cls):
+    """
+    Read the rows of the result CSV 'path' (optionally gzipped) as instances
+    of the result dataclass 'cls'. Columns that were not written, e.g. ANI
+    columns without '--ani', are None if they are optional; required columns
+    missing from the CSV, e.g. because of '--columns', are an error.
+    """
+    fields = _SCHEMAS[cls.__name__]
+    opener = gzip.open if path.endswith('.gz') else open
+    with opener(path, 'rt', newline='') as fp:
+        reader = csv.DictReader(fp)
+        header = reader.fieldnames or []
+        missing = [ field for field, py_type in fields
+                    if field not in header and not py_type.startswith('Optional[') ]
+        if missing and header:
+            raise ValueError(f"'{path}' has no {', '.join(missing)} column(s) for {cls.__name__}")
+        for row in reader:
+            yield cls(**{ field: _convert(row.get(field), py_type)
+                          for field, py_type in fields })
+
+
+def iter_gather(query_path, against_path, *, threshold_bp=50000, ksize=31,
+                scaled=1000, moltype='DNA', rank_by='overlap',
+                ignore_abundance=False, hash_seed=42):
+    """
+    Gather the single query sketch in 'query_path' against the sketches in
+    'against_path', as 'fastgather' does, yielding each result as a
+    BranchwaterGatherResult as soon as its rank is done. Gather runs in a
+    background thread, without holding the GIL, and stops when the
+    iterator is closed or garbage collected; errors are raised from the
+    iterator.
+    """
+    fields = _SCHEMAS['BranchwaterGatherResult']
+    results = sourmash_plugin_branchwater.gather_iter(query_path, against_path,
+                                                      int(threshold_bp), ksize,
+                                                      scaled, moltype.lower(),
+                                                      rank_by=rank_by,
+                                                      ignore_abundance=ignore_abundance,
+                                                      hash_seed=hash_seed)
+    for row in results:
+        row = json.loads(row)
+        yield BranchwaterGatherResult(**{ field: row.get(field)
+                                          for field, py_type in fields })
+
+
+def render_stubs():
+    "Render the type stubs for this module, as in 'results.pyi'."
+    lines = [
+        '# Generated by `python -m sourmash_plugin_branchwater.results`; do not edit.',
+        'from dataclasses import dataclass',
+        'from typing import Iterator, Optional, Type, TypeVar',
+        '',
+        "T = TypeVar('T')",
+        '',
+    ]
+    for name, fields in _SCHEMAS.items():
+        lines.append('')
+        lines.append('@dataclass(frozen=True)')
+        lines.append(f'class {name}:')
+        for field, py_type in fields:
+            lines.append(f'    {field}: {py_type}')
+        lines.append('')
+    lines.append('')
+    lines.append('def read_results(path: str, cls: Type[T]) -> Iterator[T]: ...')
+    lines.append('def iter_gather(query_path: str, against_path: str, *, threshold_bp: int = ..., ksize: int = ..., scaled: int = ..., moltype: str = ..., rank_by: str = ..., ignore_abundance: bool = ..., hash_seed: int = ...) -> Iterator[BranchwaterGatherResult]: ...')
+    lines.append('def render_stubs() -> str: ...')
+    return '\n'.join(lines) + '\n'
+
+
+if __name__ == '__main__':
+    print(render_stubs(), end='')
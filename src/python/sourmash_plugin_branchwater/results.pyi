@@ -0,0 +1,194 @@
+# Generated by `python -m sourmash_plugin_branchwater.results`; do not edit.
+from dataclasses import dataclass
+from typing import Iterator, Optional, Type, TypeVar
+
+T = TypeVar('T')
+
+
+@dataclass(frozen=True)
+class SearchResult:
+    query_name: str
+    query_md5: str
+    match_name: str
+    containment: float
+    intersect_hashes: int
+    match_md5: Optional[str]
+    jaccard: Optional[float]
+    max_containment: Optional[float]
+    query_unique_est_kmers: Optional[int]
+    match_unique_est_kmers: Optional[int]
+    query_containment_ani: Optional[float]
+    match_containment_ani: Optional[float]
+    average_containment_ani: Optional[float]
+    max_containment_ani: Optional[float]
+    moltype: Optional[str]
+    in_negative_control: Optional[bool]
+    match_aliases: Optional[str]
+
+
+@dataclass(frozen=True)
+class ScreenResult:
+    query_name: str
+    query_md5: str
+    present: bool
+    match_name: Optional[str]
+    match_md5: Optional[str]
+    containment: Optional[float]
+    intersect_hashes: Optional[int]
+    query_containment_ani: Optional[float]
+
+
+@dataclass(frozen=True)
+class NumSearchResult:
+    query_name: str
+    query_md5: str
+    match_name: str
+    match_md5: str
+    jaccard: float
+    ksize: int
+    num: int
+    moltype: str
+
+
+@dataclass(frozen=True)
+class MultiSearchResult:
+    query_name: str
+    query_md5: str
+    match_name: str
+    match_md5: str
+    containment: float
+    max_containment: float
+    jaccard: float
+    intersect_hashes: float
+    ksize: int
+    scaled: int
+    moltype: str
+    query_unique_est_kmers: int
+    match_unique_est_kmers: int
+    query_containment_ani: Optional[float]
+    match_containment_ani: Optional[float]
+    average_containment_ani: Optional[float]
+    max_containment_ani: Optional[float]
+
+
+@dataclass(frozen=True)
+class BranchwaterPrefetchResult:
+    intersect_bp: int
+    jaccard: float
+    max_containment: float
+    f_query_match: float
+    f_match_query: float
+    f_match: float
+    match_filename: str
+    match_name: str
+    match_md5: str
+    match_bp: int
+    query_filename: str
+    query_name: str
+    query_md5: str
+    query_bp: int
+    ksize: int
+    moltype: str
+    scaled: int
+    query_n_hashes: int
+    query_containment_ani: float
+    match_containment_ani: float
+    average_containment_ani: float
+    max_containment_ani: float
+
+
+@dataclass(frozen=True)
+class BranchwaterGatherResult:
+    intersect_bp: int
+    f_orig_query: float
+    f_match: float
+    f_unique_to_query: float
+    f_unique_weighted: float
+    average_abund: float
+    median_abund: float
+    std_abund: float
+    match_filename: str
+    match_name: str
+    match_md5: str
+    f_match_orig: float
+    unique_intersect_bp: int
+    gather_result_rank: int
+    remaining_bp: int
+    query_filename: str
+    query_name: str
+    query_md5: str
+    query_bp: int
+    ksize: int
+    moltype: str
+    scaled: int
+    query_n_hashes: int
+    query_abundance: bool
+    query_containment_ani: float
+    match_containment_ani: float
+    average_containment_ani: float
+    max_containment_ani: float
+    n_unique_weighted_found: int
+    sum_weighted_found: int
+    total_weighted_hashes: int
+    match_n_hashes: int
+    match_intersect_hashes: int
+    match_intersect_hashes_at_rank: int
+    query_containment_ani_ci_low: Optional[float]
+    query_containment_ani_ci_high: Optional[float]
+    match_containment_ani_ci_low: Optional[float]
+    match_containment_ani_ci_high: Optional[float]
+    normalized_abundance: Optional[float]
+
+
+@dataclass(frozen=True)
+class BetaDiversityResult:
+    query_name: str
+    query_md5: str
+    match_name: str
+    match_md5: str
+    bray_curtis: float
+    weighted_jaccard: float
+    intersect_hashes: int
+    query_sum_abunds: int
+    match_sum_abunds: int
+
+
+@dataclass(frozen=True)
+class WeightedContainmentResult:
+    query_name: str
+    query_md5: str
+    match_name: str
+    match_md5: str
+    query_weighted_containment: float
+    match_weighted_containment: float
+    max_weighted_containment: float
+    intersect_hashes: int
+    query_sum_abunds: int
+    match_sum_abunds: int
+
+
+@dataclass(frozen=True)
+class RarefactionResult:
+    query_name: str
+    query_md5: str
+    fraction: float
+    replicate: int
+    query_n_hashes: int
+    n_matches: int
+
+
+@dataclass(frozen=True)
+class TimeSeriesResult:
+    query_name: str
+    match_name: str
+    timepoint: str
+    timepoint_index: int
+    present: bool
+    containment: float
+    intersect_hashes: int
+    query_containment_ani: Optional[float]
+
+
+def read_results(path: str, cls: Type[T]) -> Iterator[T]: ...
+def iter_gather(query_path: str, against_path: str, *, threshold_bp: int = ..., ksize: int = ..., scaled: int = ..., moltype: str = ..., rank_by: str = ..., ignore_abundance: bool = ..., hash_seed: int = ...) -> Iterator[BranchwaterGatherResult]: ...
+def render_stubs() -> str: ...
@@ -0,0 +1,92 @@
+"""
+Run summaries: a JSON record of how a branchwater command was run.
+
+With '--run-summary <file>', every command writes the effective sketch
+parameters, what its threshold means, all of its (resolved) arguments, and
+the versions of branchwater and sourmash, so that results shared between
+collaborators can be traced back to how they were produced:
+
+    {
+      "command": "manysearch",
+      "status": 0,
+      "finished": "2024-05-01T12:00:00+00:00",
+      "versions": {"sourmash_plugin_branchwater": "0.9.6", ...},
+      "parameters": {"ksize": 31, "scaled": 1000, "moltype": "dna",
+                     "threshold": 0.01, "threshold_semantics": "..."},
+      "arguments": {...}
+    }
+"""
+import datetime
+import importlib.metadata
+import json
+import platform
+
+from sourmash.logging import notify
+
+# the sketch parameters of a run, where a command has them.
+PARAMETER_ARGS = ('ksize', 'scaled', 'moltype', 'param_string', 'threshold',
+                  'threshold_bp', 'prefetch_threshold_bp')
+
+# arguments that would undo anonymization, and are not recorded.
+PRIVATE_ARGS = ('anonymize_salt',)
+
+THRESHOLD_SEMANTICS = {
+    'manysearch': 'matches are reported when the containment of the query in the match is above threshold',
+    'fastmanysearch': 'matches are reported when the containment of the query in the match is above threshold',
+    'fastgather': 'matches are reported when their estimated overlap with the query is at least threshold_bp base pairs; prefetch candidates are kept when it is at least prefetch_threshold_bp, if given',
+    'fastmultigather': 'matches are reported when their estimated overlap with the query is at least threshold_bp base pairs',
+    'multisearch': 'pairs are reported when the containment of the query in the match is above threshold',
+    'pairwise': 'pairs are reported when either containment is above threshold',
+    'weighted_containment': 'pairs are reported when either weighted containment is above threshold',
+    'rarefy': 'matches are counted when the containment of the subsampled query in the match is above threshold',
+    'cluster': 'pairs are linked when at or above threshold for similarities, or at or below it for distances',
+}
+
+
+def add_run_summary_arg(p):
+    p.add_argument('--run-summary', default=None,
+                   help='save a JSON summary of this run to this file: the effective ksize, scaled, moltype, and threshold, all arguments, and the branchwater and sourmash versions')
+
+
+def versions():
+    "The versions of the software that produced a run."
+    def version(package):
+        try:
+            return importlib.metadata.version(package)
+        except importlib.metadata.PackageNotFoundError:
+            return None
+
+    return {
+        'sourmash_plugin_branchwater': version('sourmash_plugin_branchwater'),
+        'sourmash': version('sourmash'),
+        'python': platform.python_version(),
+    }
+
+
+def run_summary(command, args, status):
+    "The summary of running 'command' with 'args', exiting with 'status'."
+    arguments = { k: v for k, v in sorted(vars(args).items())
+                  if not callable(v) and k not in PRIVATE_ARGS }
+    parameters = { k: arguments[k] for k in PARAMETER_ARGS if k in arguments }
+    if command in THRESHOLD_SEMANTICS:
+        parameters['threshold_semantics'] = THRESHOLD_SEMANTICS[command]
+
+    return {
+        'command': command,
+        'status': status,
+        'finished': datetime.datetime.now(datetime.timezone.utc).isoformat(timespec='seconds'),
+        'versions': versions(),
+        'parameters': parameters,
+        'arguments': arguments,
+    }
+
+
+def write_run_summary(command, args, status):
+    "Write the summary of this run to '--run-summary', if given."
+    path = getattr(args, 'run_summary', None)
+    if not path:
+        return
+    with open(path, 'wt') as fp:
+        json.dump(run_summary(command, args, status), fp, indent=2, default=str)
+        fp.write('\n')
+    notify(f"wrote run summary to '{path}'")
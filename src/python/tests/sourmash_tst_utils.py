@@ -13,6 +13,9 @@ import traceback
 from io import open  # pylint: disable=redefined-builtin
 from io import StringIO
 
+import pytest
+import sourmash
+
 
 def scriptpath(scriptname='sourmash'):
     """Return the path to the scripts, in both dev and install situations."""
@@ -224,3 +227,33 @@ def in_thisdir(fn):
         return fn(*newargs, **kwargs)
 
     return wrapper
+
+
+def make_abund_sig(filename, name, abunds):
+    "Save a k=31 signature with the given {hash: abundance} to 'filename'."
+    mh = sourmash.MinHash(n=0, ksize=31, scaled=1000, track_abundance=True)
+    mh.set_abundances(abunds)
+    ss = sourmash.SourmashSignature(mh, name=name)
+    with open(filename, 'wt') as fp:
+        sourmash.save_signatures([ss], fp)
+    return filename
+
+
+def get_hashes():
+    "Return the sorted k=31 hashes of the '2.fa.sig.gz' test signature."
+    sig2 = os.path.join(os.path.dirname(__file__), 'test-data', '2.fa.sig.gz')
+    mh = sourmash.load_one_signature(sig2, ksize=31).minhash
+    return sorted(mh.hashes)
+
+
+def run_sqlite_output(runtmp, capfd, *args):
+    """Run a command with '--output-format sqlite', skipping the test if
+    branchwater was built without the 'sqlite' feature. On failure, the
+    captured stderr is the message of the SourmashCommandFailed."""
+    try:
+        return runtmp.sourmash(*args, '--output-format', 'sqlite')
+    except SourmashCommandFailed:
+        err = capfd.readouterr().err
+        if 'built without SQLite output' in err:
+            pytest.skip("branchwater was built without the 'sqlite' feature")
+        raise SourmashCommandFailed(err)
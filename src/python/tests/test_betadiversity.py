@@ -0,0 +1,141 @@
+import os
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'betadiversity')
+
+    assert 'usage:  betadiversity' in runtmp.last_result.err
+
+
+def test_betadiversity_values(runtmp):
+    # check bray-curtis and weighted jaccard against values calculated here
+    hashes = utils.get_hashes()[:30]
+    a = { h: i % 5 + 1 for i, h in enumerate(hashes[:20]) }
+    b = { h: i % 3 + 1 for i, h in enumerate(hashes[10:]) }
+
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a', a)
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b', b)
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'betadiversity', sig_list, '-o', output)
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 1
+    assert set(df.columns) == {'query_name', 'query_md5', 'match_name',
+                               'match_md5', 'bray_curtis',
+                               'weighted_jaccard', 'intersect_hashes',
+                               'query_sum_abunds', 'match_sum_abunds'}
+
+    row = df.iloc[0]
+    assert {row['query_name'], row['match_name']} == {'a', 'b'}
+
+    union = set(a) | set(b)
+    sum_min = sum(min(a.get(h, 0), b.get(h, 0)) for h in union)
+    sum_max = sum(max(a.get(h, 0), b.get(h, 0)) for h in union)
+    sum_a, sum_b = sum(a.values()), sum(b.values())
+
+    assert row['intersect_hashes'] == 10
+    assert sorted([row['query_sum_abunds'], row['match_sum_abunds']]) == sorted([sum_a, sum_b])
+    assert row['bray_curtis'] == pytest.approx(1 - 2 * sum_min / (sum_a + sum_b))
+    assert row['weighted_jaccard'] == pytest.approx(sum_min / sum_max)
+
+
+def test_betadiversity_identical_and_disjoint(runtmp):
+    hashes = utils.get_hashes()[:20]
+    a = { h: 2 for h in hashes[:10] }
+    b = { h: 3 for h in hashes[10:] }
+
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a', a)
+    sig_a2 = utils.make_abund_sig(runtmp.output('a2.sig'), 'a2', a)
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b', b)
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_a2, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'betadiversity', sig_list, '-o', output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    # every pair is written once, including pairs with nothing in common
+    assert len(df) == 3
+
+    results = { frozenset([r['query_name'], r['match_name']]): r
+                for r in df.to_dict(orient='records') }
+
+    same = results[frozenset(['a', 'a2'])]
+    assert same['bray_curtis'] == pytest.approx(0.0)
+    assert same['weighted_jaccard'] == pytest.approx(1.0)
+    assert same['intersect_hashes'] == 10
+
+    for other in ('a', 'a2'):
+        disjoint = results[frozenset([other, 'b'])]
+        assert disjoint['bray_curtis'] == pytest.approx(1.0)
+        assert disjoint['weighted_jaccard'] == pytest.approx(0.0)
+        assert disjoint['intersect_hashes'] == 0
+
+
+def test_betadiversity_columns(runtmp):
+    hashes = utils.get_hashes()[:20]
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a',
+                           { h: 1 for h in hashes[:15] })
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b',
+                           { h: 4 for h in hashes[5:] })
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'betadiversity', sig_list, '-o', output,
+                    '--columns', 'query_name,match_name,bray_curtis')
+
+    df = pandas.read_csv(output)
+    assert list(df.columns) == ['query_name', 'match_name', 'bray_curtis']
+
+
+def test_betadiversity_flat_sketches(runtmp):
+    # sketches without abundances are an error
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'betadiversity', sig_list, '-o', output)
+
+    print(runtmp.last_result.err)
+    assert 'requires abundance-tracking sketches' in runtmp.last_result.err
+    assert not os.path.exists(output)
+
+
+def test_betadiversity_single_sketch(runtmp):
+    hashes = utils.get_hashes()[:10]
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a',
+                           { h: 1 for h in hashes })
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a])
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'betadiversity', sig_list, '-o', output)
+
+    assert 'requires two or more sketches' in runtmp.last_result.err
@@ -496,3 +496,119 @@ def test_bad_file(runtmp, capfd):
     print(captured.err)
 
     assert "Error: Failed to build graph" in captured.err
+
+
+def make_distance_csv(filename, pairwise_csv, column):
+    # write an external-style edge list of distances, 1 - 'column'
+    with open(pairwise_csv, newline='') as fp, open(filename, 'w', newline='') as out:
+        w = csv.writer(out)
+        w.writerow(['query_name', 'match_name', 'distance'])
+        for row in csv.DictReader(fp):
+            w.writerow([row['query_name'], row['match_name'],
+                        round(1 - float(row[column]), 6)])
+
+
+def test_cluster_distance(runtmp):
+    # --metric distance links pairs at or below the threshold
+    pairwise_csv = get_test_data('cluster.pairwise.csv')
+    distance_csv = runtmp.output('distances.csv')
+    make_distance_csv(distance_csv, pairwise_csv, 'max_containment')
+
+    output = runtmp.output('clusters.csv')
+    sizes = runtmp.output('sizes.csv')
+
+    runtmp.sourmash('scripts', 'cluster', distance_csv, '-o', output,
+                    '--similarity-column', 'distance', '--metric', 'distance',
+                    '--cluster-sizes', sizes, '--threshold', '0.1')
+
+    # same clusters as max_containment >= 0.9
+    with open(output, mode='r', newline='') as csvfile:
+        rows = list(csv.DictReader(csvfile))
+    assert len(rows) == 2
+    expected_node_sets = [
+    set("n1;n2;n3;n4;n5".split(';')),
+    set("n6;n7".split(';')),
+    ]
+    for row in rows:
+        assert set(row['nodes'].split(';')) in expected_node_sets
+
+
+def test_cluster_distance_default_threshold(runtmp):
+    # the default threshold for distances is 0.05, i.e. 95% ANI
+    pairwise_csv = get_test_data('cluster.pairwise.csv')
+    distance_csv = runtmp.output('distances.csv')
+    make_distance_csv(distance_csv, pairwise_csv, 'average_containment_ani')
+
+    similarity_output = runtmp.output('similarity.csv')
+    distance_output = runtmp.output('distance.csv')
+
+    runtmp.sourmash('scripts', 'cluster', pairwise_csv, '-o', similarity_output)
+    runtmp.sourmash('scripts', 'cluster', distance_csv, '-o', distance_output,
+                    '--similarity-column', 'distance', '--metric', 'distance')
+
+    def clusters(path):
+        with open(path, newline='') as csvfile:
+            return {frozenset(row['nodes'].split(';')) for row in csv.DictReader(csvfile)}
+
+    assert clusters(similarity_output) == clusters(distance_output)
+
+
+def test_cluster_missing_column(runtmp, capfd):
+    pairwise_csv = get_test_data('cluster.pairwise.csv')
+    output = runtmp.output('clusters.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'cluster', pairwise_csv, '-o', output,
+                        '--similarity-column', 'distance', '--metric', 'distance')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "CSV file has no 'distance' column" in captured.err
+
+
+def test_cluster_graph_outputs(runtmp):
+    # --output-graphml and --output-edges write the thresholded graph
+    import xml.etree.ElementTree as ET
+
+    pairwise_csv = get_test_data('cluster.pairwise.csv')
+    output = runtmp.output('clusters.csv')
+    graphml = runtmp.output('clusters.graphml')
+    edges = runtmp.output('edges.csv')
+
+    runtmp.sourmash('scripts', 'cluster', pairwise_csv, '-o', output,
+                    '--similarity-column', "max_containment", '--threshold', '0.9',
+                    '--output-graphml', graphml, '--output-edges', edges)
+
+    with open(output, newline='') as csvfile:
+        clusters = {row['cluster']: set(row['nodes'].split(';'))
+                    for row in csv.DictReader(csvfile)}
+    cluster_of = {node: name for name, nodes in clusters.items() for node in nodes}
+    assert len(cluster_of) == 7
+
+    # edge list: every edge passes the threshold, within one cluster
+    with open(edges, newline='') as csvfile:
+        reader = csv.DictReader(csvfile)
+        rows = list(reader)
+        assert reader.fieldnames == ['source', 'target', 'max_containment', 'cluster']
+    assert rows
+    for row in rows:
+        assert float(row['max_containment']) >= 0.9
+        assert cluster_of[row['source']] == row['cluster']
+        assert cluster_of[row['target']] == row['cluster']
+
+    # GraphML: the same nodes, clusters and edges
+    ns = {'g': 'http://graphml.graphdrawing.org/xmlns'}
+    graph = ET.parse(graphml).getroot().find('g:graph', ns)
+    node_names = {}
+    for node in graph.findall('g:node', ns):
+        data = {d.get('key'): d.text for d in node.findall('g:data', ns)}
+        node_names[node.get('id')] = data['name']
+        assert cluster_of[data['name']] == data['cluster']
+    assert set(node_names.values()) == set(cluster_of)
+
+    graph_edges = graph.findall('g:edge', ns)
+    assert len(graph_edges) == len(rows)
+    for edge in graph_edges:
+        source = node_names[edge.get('source')]
+        target = node_names[edge.get('target')]
+        assert cluster_of[source] == cluster_of[target]
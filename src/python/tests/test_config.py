@@ -0,0 +1,213 @@
+"""
+Test branchwater.toml config file support.
+"""
+import os
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from sourmash_plugin_branchwater import config, build_revindex_from_iter, ERROR_CODES
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def write_config(filename, text):
+    with open(filename, 'wt') as fp:
+        fp.write(text)
+
+
+@pytest.fixture
+def no_config(monkeypatch):
+    monkeypatch.delenv(config.CONFIG_ENV, raising=False)
+
+
+def test_get_defaults(runtmp, monkeypatch):
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, """
+ksize = 21
+cores = 4
+
+[manysearch]
+ksize = 51
+columns = "query_name,match_name"
+""")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    assert config.get_defaults() == {'ksize': 21, 'cores': 4}
+    assert config.get_defaults('pairwise') == {'ksize': 21, 'cores': 4}
+    assert config.get_defaults('manysearch') == {'ksize': 51, 'cores': 4,
+                                                 'columns': "query_name,match_name"}
+
+
+def test_no_config(runtmp, no_config):
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        assert config.load_config() == {}
+    finally:
+        os.chdir(cwd)
+
+
+def test_missing_config_env(runtmp, monkeypatch):
+    monkeypatch.setenv(config.CONFIG_ENV, runtmp.output('nope.toml'))
+
+    with pytest.raises(ValueError, match='does not exist'):
+        config.load_config()
+
+
+def test_config_defaults_cli(runtmp, monkeypatch):
+    # config settings are used as defaults by the CLI
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, """
+scaled = 2000
+moltype = "DNA"
+""")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output)
+    print(runtmp.last_result.err)
+    assert 'ksize: 31 / scaled: 2000' in runtmp.last_result.err
+
+    # same as giving the settings explicitly, without a config
+    monkeypatch.delenv(config.CONFIG_ENV)
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output2,
+                    '-s', '2000')
+
+    df = pandas.read_csv(output)
+    df2 = pandas.read_csv(output2)
+    assert len(df) == 1
+    assert list(df['intersect_hashes']) == list(df2['intersect_hashes'])
+
+
+def test_config_explicit_args_win(runtmp, monkeypatch):
+    # explicit arguments take precedence over the config
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, "scaled = 2000\n")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output,
+                    '-s', '1000')
+
+    assert 'scaled: 1000' in runtmp.last_result.err
+
+
+def test_config_in_cwd(runtmp, no_config):
+    # branchwater.toml in the working directory is used; per-command
+    # tables override the top-level settings.
+    write_config(runtmp.output('branchwater.toml'), """
+scaled = 2000
+
+[pairwise]
+scaled = 5000
+columns = "query_name,match_name,containment"
+""")
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output)
+
+    df = pandas.read_csv(output)
+    assert list(df.columns) == ['query_name', 'match_name', 'containment']
+    assert 'scaled: 5000' in runtmp.last_result.err
+
+
+def test_config_search_paths(runtmp, monkeypatch):
+    # input files are found in search_paths
+    sigdir = os.path.dirname(get_test_data('2.fa.sig.gz'))
+
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, f"""
+search_paths = ["{runtmp.output('nothing-here')}", "{sigdir}"]
+""")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', '2.fa.sig.gz', '47.fa.sig.gz',
+                    '-o', output, '-t', '-1')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+
+
+def test_config_unknown_setting(runtmp, monkeypatch):
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, """
+[pairwise]
+not_an_option = 5
+""")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz')])
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', runtmp.output('out.csv'))
+
+    assert "ignoring unknown 'pairwise' setting 'not_an_option'" in runtmp.last_result.err
+
+
+def test_config_bad_toml(runtmp, monkeypatch):
+    # a bad config is reported and ignored
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, "ksize = = 21\n")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz')])
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', runtmp.output('out.csv'))
+
+    assert 'cannot parse branchwater config' in runtmp.last_result.err
+    assert 'ksize: 31' in runtmp.last_result.err
+
+
+def test_config_build_revindex_from_iter(runtmp, monkeypatch):
+    # the Python API uses the 'index' settings too
+    import sourmash
+
+    config_file = runtmp.output('site.toml')
+    write_config(config_file, """
+[index]
+ksize = 21
+""")
+    monkeypatch.setenv(config.CONFIG_ENV, config_file)
+
+    sigs = list(sourmash.load_file_as_signatures(get_test_data('2.fa.sig.gz')))
+
+    # there are no k=21 sketches to index...
+    status = build_revindex_from_iter(sigs, runtmp.output('db.rocksdb'),
+                                      sketches_output=runtmp.output('sk.zip'))
+    assert status == ERROR_CODES['NoCompatibleSketches']
+
+    # ...but an explicit ksize wins.
+    status = build_revindex_from_iter(sigs, runtmp.output('db2.rocksdb'),
+                                      sketches_output=runtmp.output('sk2.zip'),
+                                      ksize=31)
+    assert status == 0
@@ -0,0 +1,145 @@
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_assembly_csv(filename, genome_paths):
+    names = [os.path.basename(x).split('.fa')[0] for x in genome_paths]
+    with open(filename, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        for name, genome_path in zip(names, genome_paths):
+            fp.write("{},{},\n".format(name, genome_path))
+
+
+def make_reads_csv(filename, reads_tuples = []):
+    # reads tuples should be (name,read1,read2)
+    with open(filename, 'wt') as fp:
+        fp.write("name,read1,read2\n")
+        for (name, read1, read2) in reads_tuples:
+            fp.write("{},{},{}\n".format(name, read1, read2))
+
+
+def build_index(runtmp):
+    # sketch and index short.fa, short2.fa, and short3.fa
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    make_assembly_csv(fa_csv, [fa1, fa2, fa3])
+
+    db_zip = runtmp.output('db.zip')
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', db_zip,
+                    '--param-str', "dna,k=31,scaled=1")
+
+    db = runtmp.output('db.rocksdb')
+    runtmp.sourmash('scripts', 'index', db_zip, '-o', db,
+                    '-k', '31', '--scaled', '1')
+    return db
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmanysearch')
+
+    assert 'usage:  fastmanysearch' in runtmp.last_result.err
+
+
+def test_simple(runtmp):
+    # sketch reads on the fly and search them against an index
+    db = build_index(runtmp)
+
+    reads_csv = runtmp.output('reads.csv')
+    fa1 = get_test_data('short.fa')
+    make_reads_csv(reads_csv, [("sample1", fa1, "")])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'fastmanysearch', reads_csv, db,
+                    '-o', output, '-s', '1', '-t', '0.5')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert set(df['query_name']) == {'sample1'}
+    match = df[df['match_name'] == 'short']
+    assert len(match) == 1
+    assert match['containment'].iloc[0] == 1.0
+
+    # no sketches should have been written
+    written = [f for f in os.listdir(runtmp.output('')) if '.sig' in f]
+    assert not written
+
+
+def test_matches_manysearch(runtmp):
+    # results should match sketching with manysketch and running manysearch
+    db = build_index(runtmp)
+
+    reads_csv = runtmp.output('reads.csv')
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    make_reads_csv(reads_csv, [("sample1", fa1, fa2)])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmanysearch', reads_csv, db,
+                    '-o', output, '-s', '1')
+
+    query_zip = runtmp.output('query.zip')
+    runtmp.sourmash('scripts', 'manysketch', reads_csv, '-o', query_zip,
+                    '--param-str', "dna,k=31,scaled=1")
+
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_zip, db,
+                    '-o', output2, '-s', '1')
+
+    df = pandas.read_csv(output).sort_values('match_name')
+    df2 = pandas.read_csv(output2).sort_values('match_name')
+    assert list(df['match_name']) == list(df2['match_name'])
+    assert list(df['intersect_hashes']) == list(df2['intersect_hashes'])
+    assert list(df['query_md5']) == list(df2['query_md5'])
+
+
+def test_not_rocksdb(runtmp, capfd):
+    # the database must be a rocksdb index
+    reads_csv = runtmp.output('reads.csv')
+    fa1 = get_test_data('short.fa')
+    make_reads_csv(reads_csv, [("sample1", fa1, "")])
+
+    against = get_test_data('2.fa.sig.gz')
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmanysearch', reads_csv, against,
+                        '-o', output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "is not a valid RevIndex database" in captured.err
+
+
+def test_missing_reads(runtmp, capfd):
+    # a sample whose reads can't be opened is reported
+    db = build_index(runtmp)
+
+    reads_csv = runtmp.output('reads.csv')
+    make_reads_csv(reads_csv, [("sample1", "no-exist.fq", "")])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmanysearch', reads_csv, db,
+                        '-o', output, '-s', '1')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: could not sketch sample 'sample1'" in captured.err
+    assert "Could not sketch any samples." in captured.err
@@ -1,11 +1,22 @@
 import os
 import pytest
 import pandas
+import sqlite3
 
 import sourmash
+import sourmash_plugin_branchwater
 from . import sourmash_tst_utils as utils
 
 
+# columns in prefetch output; same as 'sourmash prefetch', plus 'f_match'
+prefetch_keys = {'intersect_bp', 'jaccard', 'max_containment', 'f_query_match',
+                 'f_match_query', 'f_match', 'match_filename', 'match_name', 'match_md5',
+                 'match_bp', 'query_filename', 'query_name', 'query_md5',
+                 'query_bp', 'ksize', 'moltype', 'scaled', 'query_n_hashes',
+                 'query_containment_ani', 'match_containment_ani',
+                 'average_containment_ani', 'max_containment_ani'}
+
+
 def get_test_data(filename):
     thisdir = os.path.dirname(__file__)
     return os.path.join(thisdir, 'test-data', filename)
@@ -90,7 +101,7 @@ def test_simple_with_prefetch(runtmp, zip_against):
     df = pandas.read_csv(p_output)
     assert len(df) == 3
     keys = set(df.keys())
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
 
 @pytest.mark.parametrize('zip_against', [False, True])
@@ -414,7 +425,7 @@ def test_md5s(runtmp, zip_against):
     keys = set(df.keys())
 
     # prefetch output has no rank.
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
     md5s = list(df['match_md5'])
     print(md5s)
@@ -463,7 +474,132 @@ def test_csv_columns_vs_sourmash_prefetch(runtmp, zip_against):
     sp_keys = set(sourmash_prefetch_df.keys())
     print(g_keys - sp_keys)
     diff_keys = g_keys - sp_keys
-    assert diff_keys == set(['unique_intersect_bp', 'median_abund', 'f_match_orig', 'std_abund', 'average_abund', 'f_unique_to_query', 'remaining_bp', 'f_unique_weighted', 'sum_weighted_found', 'total_weighted_hashes', 'n_unique_weighted_found', 'f_orig_query', 'f_match'])
+    assert diff_keys == set(['unique_intersect_bp', 'median_abund', 'f_match_orig', 'std_abund', 'average_abund', 'f_unique_to_query', 'remaining_bp', 'f_unique_weighted', 'sum_weighted_found', 'total_weighted_hashes', 'n_unique_weighted_found', 'f_orig_query', 'f_match', 'match_n_hashes', 'match_intersect_hashes', 'match_intersect_hashes_at_rank'])
+
+
+def test_prefetch_csv_vs_sourmash_prefetch(runtmp):
+    # prefetch output columns and values should match sourmash prefetch
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000')
+
+    sp_output = runtmp.output('sourmash-prefetch.csv')
+    runtmp.sourmash('prefetch', query, against_list,
+                    '-o', sp_output, '--scaled', '100000')
+
+    df = pandas.read_csv(p_output).sort_values('match_md5').reset_index()
+    sp_df = pandas.read_csv(sp_output).sort_values('match_md5').reset_index()
+    assert (prefetch_keys - {'f_match'}).issubset(set(sp_df.keys()))
+    assert len(df) == len(sp_df) == 3
+
+    for col in ('match_md5', 'intersect_bp', 'match_bp', 'query_bp',
+                'ksize', 'scaled', 'query_n_hashes', 'moltype'):
+        assert list(df[col]) == list(sp_df[col]), col
+    for col in ('jaccard', 'max_containment', 'f_query_match',
+                'f_match_query', 'query_containment_ani',
+                'match_containment_ani', 'average_containment_ani',
+                'max_containment_ani'):
+        assert all(abs(df[col] - sp_df[col]) < 1e-4), col
+
+
+def test_prefetch_csv_as_picklist(runtmp):
+    # prefetch output can be used directly as a sourmash picklist
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000')
+
+    picklist_out = runtmp.output('picked.zip')
+    runtmp.sourmash('sig', 'cat', against_list, '-o', picklist_out,
+                    '--picklist', f'{p_output}::prefetch')
+
+    idx = sourmash.load_file_as_index(picklist_out)
+    assert len(idx) == 3
+
+
+def test_prefetch_csv_f_match_and_ani(runtmp):
+    # prefetch output includes f_match and ANI, so they needn't be
+    # recomputed when the CSV is used to build a picklist.
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000')
+
+    df = pandas.read_csv(p_output)
+    assert len(df) == 3
+    assert list(df['f_match']) == list(df['f_match_query'])
+    for col in ('query_containment_ani', 'match_containment_ani',
+                'average_containment_ani', 'max_containment_ani'):
+        assert all(df[col] > 0), col
+
+    # the first gather match has the same f_match as in prefetch.
+    g_df = pandas.read_csv(g_output)
+    top = g_df[g_df['gather_result_rank'] == 0].iloc[0]
+    p_row = df[df['match_md5'] == top['match_md5']].iloc[0]
+    assert abs(p_row['f_match'] - top['f_match']) < 1e-4
+
+
+@pytest.mark.parametrize('name', ['name, with a comma',
+                                  'name with "quotes"',
+                                  'name with\na newline'])
+def test_prefetch_csv_pathological_names(runtmp, name):
+    # prefetch output should be properly quoted
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    renamed = runtmp.output('renamed.sig.gz')
+    runtmp.sourmash('sig', 'rename', sig47, name, '-o', renamed)
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, renamed])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000')
+
+    df = pandas.read_csv(p_output)
+    print(df)
+    assert len(df) == 2
+    assert set(df.keys()) == prefetch_keys
+    assert name in set(df['match_name'])
 
 
 @pytest.mark.parametrize('zip_against', [False, True])
@@ -719,7 +855,8 @@ def test_simple_full_output(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
 
     md5s = set(df['match_md5'])
@@ -856,3 +993,871 @@ def test_fullres_vs_sourmash_gather(runtmp):
     fg_total_weighted_hashes= set(gather_df['total_weighted_hashes'])
     g_total_weighted_hashes = set(sourmash_gather_df['total_weighted_hashes'])
     assert fg_total_weighted_hashes == g_total_weighted_hashes == set([73489])
+
+
+def test_output_remaining(runtmp):
+    # save the remaining query sketch after each gather rank
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    r_output = runtmp.output('remaining.zip')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-remaining', r_output,
+                    '-s', '100000')
+    assert os.path.exists(g_output)
+    assert os.path.exists(r_output)
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+
+    idx = sourmash.load_file_as_index(r_output)
+    sigs = list(idx.signatures())
+    assert len(sigs) == 3
+
+    # one remaining sketch per rank, each smaller than the last
+    remaining_bp = list(df['remaining_bp'])
+    sizes = {}
+    for ss in sigs:
+        assert 'remaining after rank' in ss.name
+        rank = int(ss.name.split(' ')[-1])
+        sizes[rank] = len(ss.minhash) * ss.minhash.scaled
+    assert sorted(sizes) == [0, 1, 2]
+    assert [sizes[r] for r in range(3)] == remaining_bp
+
+
+def test_simple_columns(runtmp):
+    # select a subset of gather output columns
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000',
+                    '--columns', 'query_name,match_name,f_unique_weighted')
+    assert os.path.exists(g_output)
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+    assert list(df.columns) == ['query_name', 'match_name', 'f_unique_weighted']
+
+
+def test_min_query_hashes(runtmp, capfd):
+    # a query with too few hashes is skipped rather than gathered
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    skipped = runtmp.output('skipped.csv')
+
+    # SRR606249 has 4200 hashes at scaled=1000
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--min-query-hashes', '5000',
+                    '--output-skipped-queries', skipped)
+    assert not os.path.exists(g_output)
+
+    skipped_df = pandas.read_csv(skipped)
+    assert len(skipped_df) == 1
+    row = skipped_df.iloc[0]
+    assert row['query_name'] == 'SRR606249'
+    assert row['query_n_hashes'] == 4200
+    assert row['min_query_hashes'] == 5000
+
+
+def test_rank_by_overlap_default(runtmp):
+    # --rank-by overlap is the same as the default
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    g_output2 = runtmp.output('gather2.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output2, '-s', '100000', '--rank-by', 'overlap')
+
+    df = pandas.read_csv(g_output)
+    df2 = pandas.read_csv(g_output2)
+    assert len(df) == 3
+    assert list(df['match_md5']) == list(df2['match_md5'])
+    assert list(df['gather_result_rank']) == [0, 1, 2]
+
+
+def test_rank_by_ani(runtmp):
+    # --rank-by ani picks the match with the highest containment ANI first
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000', '--rank-by', 'ani')
+
+    df = pandas.read_csv(g_output)
+    p_df = pandas.read_csv(p_output)
+    assert len(df) == 3
+    assert list(df['gather_result_rank']) == [0, 1, 2]
+
+    best = p_df.loc[p_df['match_containment_ani'].idxmax()]
+    assert df.iloc[0]['match_md5'] == best['match_md5']
+
+
+def test_rank_by_f_unique_weighted(runtmp):
+    # --rank-by f_unique_weighted picks the match with the most
+    # abundance-weighted overlap first
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    w_output = runtmp.output('weighted.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', w_output, '-s', '100000',
+                    '--rank-by', 'f_unique_weighted')
+
+    df = pandas.read_csv(g_output)
+    w_df = pandas.read_csv(w_output)
+    assert len(w_df) == 3
+    assert set(w_df['match_md5']) == set(df['match_md5'])
+    assert w_df.iloc[0]['f_unique_weighted'] >= df.iloc[0]['f_unique_weighted']
+    assert w_df.iloc[0]['f_unique_weighted'] == w_df['f_unique_weighted'].max()
+
+
+def test_rank_by_bad_value(runtmp):
+    # rank_by is checked when called directly, too
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    status = sourmash_plugin_branchwater.do_fastgather(query, sig2, 0, 31,
+                                                       100000, 'DNA', 0,
+                                                       runtmp.output('g.csv'),
+                                                       None, None, None, None,
+                                                       rank_by='foo')
+    assert status == 1
+
+
+def test_equal_overlap_tie_breaking(runtmp):
+    # matches with equal overlap are chosen in a stable order, regardless
+    # of database order or number of threads
+    query = get_test_data('47.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    copy_a = runtmp.output('aaa.sig.gz')
+    copy_z = runtmp.output('zzz.sig.gz')
+    runtmp.sourmash('sig', 'rename', sig47, 'aaa', '-o', copy_a)
+    runtmp.sourmash('sig', 'rename', sig47, 'zzz', '-o', copy_z)
+
+    outputs = []
+    for n, (paths, cores) in enumerate([([copy_z, copy_a, sig63], '1'),
+                                        ([copy_a, copy_z, sig63], '1'),
+                                        ([sig63, copy_z, copy_a], '4')]):
+        against_list = runtmp.output(f'against{n}.txt')
+        make_file_list(against_list, paths)
+
+        g_output = runtmp.output(f'gather{n}.csv')
+        p_output = runtmp.output(f'prefetch{n}.csv')
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', g_output, '--output-prefetch', p_output,
+                        '-c', cores)
+        outputs.append((pandas.read_csv(g_output), pandas.read_csv(p_output)))
+
+    g_df, p_df = outputs[0]
+    assert g_df.iloc[0]['match_name'] == 'aaa'
+    assert 'zzz' not in set(g_df['match_name'])
+    assert list(p_df['match_name'][:2]) == ['aaa', 'zzz']
+
+    for other_g_df, other_p_df in outputs[1:]:
+        assert list(other_g_df['match_name']) == list(g_df['match_name'])
+        assert list(other_g_df['intersect_bp']) == list(g_df['intersect_bp'])
+        assert list(other_p_df['match_name']) == list(p_df['match_name'])
+
+
+def test_output_downsampled(runtmp, capfd):
+    # search sketches downsampled to the query's scaled are reported
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    ds_output = runtmp.output('downsampled.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000',
+                    '--output-downsampled', ds_output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "NOTE: downsampled 3 search sketch(es) from scaled=1000 to scaled=100000" in captured.err
+
+    df = pandas.read_csv(ds_output)
+    search_df = df[df['sketch_type'] == 'search']
+    assert len(search_df) == 3
+    assert set(search_df['target_scaled']) == {100000}
+
+
+@pytest.mark.parametrize("amount", [None, "200"])
+def test_spike_in_normalization(runtmp, amount):
+    # --spike-in reports abundances relative to the spike-in match
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+
+    args = ['--spike-in', 'NC_009661.1']
+    if amount:
+        args += ['--spike-in-amount', amount]
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', *args)
+
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert len(df) == 3
+    assert list(df['gather_result_rank']) == [0, 1, 2]
+
+    spike = df[df['match_name'].str.startswith('NC_009661.1')].iloc[0]
+    scale = float(amount) if amount else 1.0
+    assert spike['normalized_abundance'] == pytest.approx(scale)
+    for _, row in df.iterrows():
+        expected = row['n_unique_weighted_found'] / spike['n_unique_weighted_found'] * scale
+        assert row['normalized_abundance'] == pytest.approx(expected)
+
+
+def test_spike_in_by_md5(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    md5_63 = sourmash.load_one_signature(sig63, ksize=31).md5sum()
+    g_output = runtmp.output('gather.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--spike-in', md5_63)
+
+    df = pandas.read_csv(g_output)
+    spike = df[df['match_md5'] == md5_63].iloc[0]
+    assert spike['normalized_abundance'] == pytest.approx(1.0)
+
+
+def test_spike_in_not_found(runtmp, capfd):
+    # a missing spike-in is a warning, and the column is left out
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47])
+
+    g_output = runtmp.output('gather.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--spike-in', 'no-such-spike')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: spike-in 'no-such-spike' was not found" in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 2
+    assert 'normalized_abundance' not in df.columns
+
+
+def test_spike_in_flat_query(runtmp):
+    # spike-in normalization needs query abundances
+    query = get_test_data('47.fa.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', g_output, '--spike-in', 'NC_009661.1')
+
+    assert 'spike-in normalization requires a query sketch with abundances' in runtmp.last_result.err
+
+
+def test_spike_in_bad_amount(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    g_output = runtmp.output('gather.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', g_output, '-s', '100000',
+                        '--spike-in', 'NC_009661.1', '--spike-in-amount', '0')
+
+    assert "the spike-in amount for 'NC_009661.1' must be greater than 0" in runtmp.last_result.err
+
+
+def test_match_hash_counts(runtmp):
+    # gather reports hash counts for each match, at the common scaled
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000')
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+    for row in df.itertuples():
+        assert row.match_intersect_hashes * row.scaled == row.intersect_bp
+        assert row.match_intersect_hashes_at_rank * row.scaled == row.unique_intersect_bp
+        assert row.match_intersect_hashes_at_rank <= row.match_intersect_hashes <= row.match_n_hashes
+        f_match_orig = row.match_intersect_hashes / row.match_n_hashes
+        assert round(f_match_orig, 6) == round(row.f_match_orig, 6)
+
+    # 47 and 63 overlap, so the later of the two is only partly assigned
+    # at its rank.
+    partial = df[df['match_intersect_hashes_at_rank'] < df['match_intersect_hashes']]
+    assert len(partial) == 1
+
+
+def test_ignore_abundance(runtmp):
+    # --ignore-abundance treats the query as flat
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    flat_output = runtmp.output('flat.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', flat_output, '-s', '100000', '--ignore-abundance')
+
+    df = pandas.read_csv(g_output)
+    flat_df = pandas.read_csv(flat_output)
+    print(flat_df)
+
+    # same matches, in the same order
+    assert list(flat_df['match_md5']) == list(df['match_md5'])
+    assert list(flat_df['intersect_bp']) == list(df['intersect_bp'])
+
+    assert not any(flat_df['query_abundance'])
+    assert set(flat_df['n_unique_weighted_found']) == {0}
+    assert set(flat_df['sum_weighted_found']) == {0}
+    assert set(flat_df['average_abund']) == {1.0}
+    assert set(flat_df['std_abund']) == {0.0}
+    assert list(flat_df['f_unique_weighted']) == list(flat_df['f_unique_to_query'])
+
+
+def test_ignore_abundance_spike_in(runtmp):
+    # spike-in normalization needs the query abundances
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig63 = get_test_data('63.fa.sig.gz')
+    make_file_list(against_list, [sig63])
+
+    g_output = runtmp.output('gather.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', g_output, '-s', '100000',
+                        '--ignore-abundance', '--spike-in', 'NC_009661.1')
+
+    assert '--spike-in requires query abundances' in runtmp.last_result.err
+
+
+def test_max_matchlist_mem(runtmp, capfd):
+    # spilling prefetch matches to disk doesn't change the results
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+    g_output2 = runtmp.output('gather2.csv')
+    p_output2 = runtmp.output('prefetch2.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000')
+    # a 1-byte cap spills all but the best match
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output2, '--output-prefetch', p_output2,
+                    '-s', '100000', '--max-matchlist-mem', '1')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'spilled 2 of 3 matches to disk' in captured.err
+
+    df = pandas.read_csv(g_output)
+    df2 = pandas.read_csv(g_output2)
+    assert len(df) == 3
+    assert list(df['match_md5']) == list(df2['match_md5'])
+    assert list(df['intersect_bp']) == list(df2['intersect_bp'])
+    assert list(df['f_unique_weighted']) == list(df2['f_unique_weighted'])
+
+    p_df = pandas.read_csv(p_output)
+    p_df2 = pandas.read_csv(p_output2)
+    assert p_df.equals(p_df2)
+
+
+def test_max_matchlist_mem_rank_by_ani(runtmp, capfd):
+    # spilled matches can only be reloaded in order when ranking by overlap
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', runtmp.output('gather.csv'), '-s', '100000',
+                        '--max-matchlist-mem', '100M', '--rank-by', 'ani')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert '--max-matchlist-mem requires --rank-by overlap' in captured.err
+
+
+def test_max_matchlist_mem_bad_size(runtmp, capfd):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', runtmp.output('gather.csv'), '-s', '100000',
+                        '--max-matchlist-mem', '10X')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "unknown unit 'X' in memory size '10X'" in captured.err
+
+
+def test_anonymize_queries(runtmp):
+    # both query_name and query_filename are anonymized
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+    mapping = runtmp.output('mapping.csv')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000', '--anonymize-queries', mapping)
+
+    ss = sourmash.load_one_signature(query, ksize=31)
+    mapping_df = pandas.read_csv(mapping)
+    to_anon = dict(zip(mapping_df['original'], mapping_df['anonymized']))
+    assert ss.name in to_anon
+
+    for output in (g_output, p_output):
+        df = pandas.read_csv(output)
+        assert len(df) == 3
+        assert set(df['query_name']) == {to_anon[ss.name]}
+        for filename in df['query_filename']:
+            assert filename.startswith('anon_')
+            assert ss.filename not in filename
+
+
+def test_prefetch_threshold_bp(runtmp):
+    # a lower prefetch threshold keeps more prefetch candidates, without
+    # changing the gather results.
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    p_output = runtmp.output('prefetch.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '--output-prefetch', p_output,
+                    '-s', '100000', '-t', '3000000')
+
+    g_output2 = runtmp.output('gather2.csv')
+    p_output2 = runtmp.output('prefetch2.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output2, '--output-prefetch', p_output2,
+                    '-s', '100000', '-t', '3000000',
+                    '--prefetch-threshold-bp', '0')
+    assert 'using prefetch threshold overlap: 1 0' in runtmp.last_result.err
+
+    # 2.fa only overlaps the query by 2.2 Mbp
+    assert len(pandas.read_csv(p_output)) == 2
+    p_df = pandas.read_csv(p_output2)
+    assert len(p_df) == 3
+    assert sig2 in set(p_df['match_filename'])
+
+    with open(g_output) as fp1, open(g_output2) as fp2:
+        assert fp1.read() == fp2.read()
+    g_df = pandas.read_csv(g_output2)
+    assert sig2 not in set(g_df['match_filename'])
+
+
+def test_prefetch_threshold_bp_above_threshold(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', runtmp.output('gather.csv'), '-s', '100000',
+                        '-t', '50000', '--prefetch-threshold-bp', '100000')
+
+    assert '--prefetch-threshold-bp (100000) must not be above --threshold-bp (50000)' in runtmp.last_result.err
+
+
+def interrupt_after_first_rank(g_output):
+    # make the gather output and checkpoint look like a run that died
+    # after the first rank, part way through writing the second.
+    import json
+    checkpoint_path = g_output + '.checkpoint.json'
+    with open(checkpoint_path) as fp:
+        checkpoint = json.load(fp)
+    assert checkpoint['complete']
+
+    with open(g_output, 'rb') as fp:
+        lines = fp.readlines()
+    checkpoint['matches'] = checkpoint['matches'][:1]
+    checkpoint['complete'] = False
+    checkpoint['output_bytes'] = len(lines[0]) + len(lines[1])
+    with open(checkpoint_path, 'wt') as fp:
+        json.dump(checkpoint, fp)
+
+    with open(g_output, 'wb') as fp:
+        fp.write(lines[0] + lines[1] + lines[2][:10])
+
+
+@pytest.mark.parametrize('rank_by', ['overlap', 'f_unique_weighted'])
+def test_checkpoint_resume(runtmp, capfd, rank_by):
+    # a resumed gather gives the same output as an uninterrupted one
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--rank-by', rank_by,
+                    '--checkpoint-every', '1')
+    with open(g_output, 'rb') as fp:
+        expected = fp.read()
+    assert len(pandas.read_csv(g_output)) == 3
+
+    interrupt_after_first_rank(g_output)
+    capfd.readouterr()
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--rank-by', rank_by,
+                    '--checkpoint-every', '1', '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'iter 1: resumed from checkpoint' in captured.err
+
+    with open(g_output, 'rb') as fp:
+        assert fp.read() == expected
+
+
+def test_checkpoint_resume_complete(runtmp, capfd):
+    # resuming a completed gather leaves its output alone
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    g_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--checkpoint-every', '2')
+    with open(g_output, 'rb') as fp:
+        expected = fp.read()
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--checkpoint-every', '2',
+                    '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'gather already completed' in captured.err
+    with open(g_output, 'rb') as fp:
+        assert fp.read() == expected
+
+
+def test_checkpoint_resume_different_run(runtmp, capfd):
+    # a checkpoint from a run with other parameters isn't used
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    g_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--checkpoint-every', '1')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', g_output, '-s', '100000', '-t', '200000',
+                        '--checkpoint-every', '1', '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'was written by a different run (threshold hashes 1 != 2)' in captured.err
+
+
+def test_resume_requires_checkpoint_every(runtmp, capfd):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', runtmp.output('gather.csv'), '-s', '100000',
+                        '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert '--resume requires --checkpoint-every.' in captured.err
+
+
+def test_output_format_parquet(runtmp):
+    # Parquet output has the same results as CSV, with typed columns
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    csv_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', csv_output, '-s', '100000')
+    pq_output = runtmp.output('gather.parquet')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', pq_output, '-s', '100000', '--output-format', 'parquet')
+
+    csv_df = pandas.read_csv(csv_output)
+    pq_df = pandas.read_parquet(pq_output)
+    print(pq_df.dtypes)
+    assert len(pq_df) == 3
+    assert pandas.api.types.is_unsigned_integer_dtype(pq_df['gather_result_rank'])
+    assert pandas.api.types.is_unsigned_integer_dtype(pq_df['intersect_bp'])
+    assert pandas.api.types.is_float_dtype(pq_df['f_unique_weighted'])
+    pandas.testing.assert_frame_equal(pq_df[csv_df.columns], csv_df,
+                                      check_dtype=False)
+
+
+def test_output_format_parquet_columns(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    output = runtmp.output('gather.parquet')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', output, '-s', '100000', '--output-format', 'parquet',
+                    '--columns', 'match_name,f_unique_weighted')
+
+    df = pandas.read_parquet(output)
+    assert list(df.columns) == ['match_name', 'f_unique_weighted']
+    assert df['match_name'][0].startswith('NC_009661.1')
+
+
+def test_output_format_parquet_gz(runtmp, capfd):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    output = runtmp.output('gather.parquet.gz')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', output, '-s', '100000',
+                        '--output-format', 'parquet')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Parquet outputs are compressed already; remove '.gz'" in captured.err
+
+
+def test_output_format_parquet_checkpoint(runtmp, capfd):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                        '-o', runtmp.output('gather.parquet'), '-s', '100000',
+                        '--output-format', 'parquet', '--checkpoint-every', '1')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--checkpoint-every can't be used with --output-format parquet." in captured.err
+
+
+def test_output_format_sqlite(runtmp, capfd):
+    # SQLite output has the same results as CSV, and leaves the tables of
+    # other commands in the database alone
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('results.db')
+    with sqlite3.connect(output) as conn:
+        conn.execute('CREATE TABLE manysearch (query_name TEXT)')
+        conn.execute("INSERT INTO manysearch VALUES ('earlier')")
+
+    csv_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', csv_output, '-s', '100000')
+    utils.run_sqlite_output(runtmp, capfd, 'scripts', 'fastgather', query,
+                            against_list, '-o', output, '-s', '100000')
+
+    with sqlite3.connect(output) as conn:
+        df = pandas.read_sql('SELECT * FROM fastgather', conn)
+        earlier = conn.execute('SELECT query_name FROM manysearch').fetchall()
+    print(df)
+    assert earlier == [('earlier',)]
+    assert len(df) == 3
+    csv_df = pandas.read_csv(csv_output)
+    pandas.testing.assert_frame_equal(df[csv_df.columns], csv_df,
+                                      check_dtype=False)
+
+
+def test_output_format_sqlite_checkpoint(runtmp, capfd):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed) as exc:
+        utils.run_sqlite_output(runtmp, capfd, 'scripts', 'fastgather',
+                                query, against_list,
+                                '-o', runtmp.output('results.db'),
+                                '-s', '100000', '--checkpoint-every', '1')
+
+    print(exc.value.message)
+    assert "--checkpoint-every can't be used with --output-format sqlite." in exc.value.message
+
+
+def test_output_format_jsonl(runtmp, capfd):
+    # JSON Lines output has the same results as CSV, and can be checkpointed
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    csv_output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', csv_output, '-s', '100000')
+    output = runtmp.output('gather.jsonl')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', output, '-s', '100000', '--output-format', 'jsonl',
+                    '--checkpoint-every', '1')
+
+    df = pandas.read_json(output, lines=True)
+    csv_df = pandas.read_csv(csv_output)
+    assert len(df) == 3
+    assert list(df['gather_result_rank']) == [0, 1, 2]
+    pandas.testing.assert_frame_equal(df, csv_df, check_dtype=False)
+
+    with open(output, 'rb') as fp:
+        expected = fp.read()
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', output, '-s', '100000', '--output-format', 'jsonl',
+                    '--checkpoint-every', '1', '--resume')
+
+    captured = capfd.readouterr()
+    assert 'gather already completed' in captured.err
+    with open(output, 'rb') as fp:
+        assert fp.read() == expected
@@ -0,0 +1,129 @@
+"""
+Test that gather outputs are the same at any number of threads.
+
+The rayon thread pool can only be set once per process, so each run is
+in its own subprocess.
+"""
+import os
+import shutil
+import subprocess
+import sys
+
+import pandas
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def run_with_cores(runtmp, cores, *args):
+    "Run 'sourmash scripts ...' with 'cores' threads, in a subprocess."
+    cmd = [sys.executable, '-m', 'sourmash', 'scripts', *args, '-c', str(cores)]
+    result = subprocess.run(cmd, cwd=runtmp.output(''), capture_output=True,
+                            text=True)
+    print(result.stderr)
+    assert result.returncode == 0, result.stderr
+
+
+def read_bytes(path):
+    with open(path, 'rb') as fp:
+        return fp.read()
+
+
+def make_tied_against(runtmp):
+    # two copies of 47 with the same name and md5 tie at every rank, and
+    # are only told apart by their location.
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    copies = []
+    for subdir in ('b', 'a'):
+        os.mkdir(runtmp.output(subdir))
+        copy = runtmp.output(os.path.join(subdir, '47.fa.sig.gz'))
+        shutil.copyfile(sig47, copy)
+        copies.append(copy)
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, *copies, sig63])
+    return against_list, sorted(copies)[0]
+
+
+def test_fastgather_threads(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list, first_copy = make_tied_against(runtmp)
+
+    outputs = {}
+    for cores in (1, 8):
+        g_output = runtmp.output(f'gather.c{cores}.csv')
+        p_output = runtmp.output(f'prefetch.c{cores}.csv')
+        run_with_cores(runtmp, cores, 'fastgather', query, against_list,
+                       '-o', g_output, '--output-prefetch', p_output,
+                       '-s', '100000')
+        outputs[cores] = (read_bytes(g_output), read_bytes(p_output))
+
+    assert outputs[1] == outputs[8]
+
+    # the tie is broken by location
+    df = pandas.read_csv(runtmp.output('gather.c1.csv'))
+    assert len(df) == 3
+    matches_47 = df[df['match_name'].str.startswith('NC_009661.1')]
+    assert list(matches_47['match_filename']) == [first_copy]
+
+
+def test_fastmultigather_threads(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('SRR606249.sig.gz'),
+                                get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+    against_list, _ = make_tied_against(runtmp)
+
+    outputs = {}
+    for cores in (1, 8):
+        run_with_cores(runtmp, cores, 'fastmultigather', query_list,
+                       against_list, '-s', '100000', '-t', '0')
+        outputs[cores] = {}
+        for filename in os.listdir(runtmp.output('')):
+            if filename.endswith(('.gather.csv', '.prefetch.csv')):
+                path = runtmp.output(filename)
+                outputs[cores][filename] = read_bytes(path)
+                os.unlink(path)
+
+    assert len(outputs[1]) == 6
+    assert outputs[1] == outputs[8]
+
+
+def test_fastmultigather_rocksdb_threads(runtmp):
+    # with a RocksDB index, rows of different queries may be interleaved
+    # differently, but each query's results are the same.
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+    db = runtmp.output('db.rocksdb')
+    runtmp.sourmash('scripts', 'index', against_list, '-o', db,
+                    '-k', '31', '--scaled', '100000')
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('SRR606249.sig.gz'),
+                                sig2, sig47, sig63])
+
+    dfs = {}
+    for cores in (1, 8):
+        output = runtmp.output(f'out.c{cores}.csv')
+        run_with_cores(runtmp, cores, 'fastmultigather', query_list, db,
+                       '-s', '100000', '-t', '0', '-o', output)
+        df = pandas.read_csv(output)
+        dfs[cores] = df.sort_values(['query_md5', 'gather_result_rank']).reset_index(drop=True)
+
+    assert len(dfs[1]) > 4
+    pandas.testing.assert_frame_equal(dfs[1], dfs[8])
@@ -1,4 +1,6 @@
 import os
+import json
+import shutil
 import pytest
 import pandas
 import sourmash
@@ -344,3 +346,301 @@ def test_index_check_quick(runtmp):
     print(runtmp.last_result.err)
 
     assert 'index is ok' in runtmp.last_result.err
+
+
+def test_index_check_deep(runtmp):
+    # deep check loads every dataset from storage
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    db_zip = runtmp.output('db.zip')
+    runtmp.sourmash('sig', 'cat', sig2, sig47, sig63, '-o', db_zip)
+
+    output = runtmp.output('db.rdb')
+    runtmp.sourmash('scripts', 'index', db_zip, '-o', output)
+
+    problems = runtmp.output('problems.csv')
+    runtmp.sourmash('scripts', 'check', '--deep', output, '-o', problems)
+    print(runtmp.last_result.err)
+
+    assert 'Deep check: all 3 datasets ok' in runtmp.last_result.err
+    assert 'index is ok' in runtmp.last_result.err
+
+    df = pandas.read_csv(problems)
+    assert len(df) == 0
+    assert list(df.columns) == ['internal_location', 'name', 'manifest_md5',
+                                'loaded_md5', 'problem']
+
+
+def test_index_check_deep_corrupted(runtmp):
+    # sketches changed or removed after indexing are reported
+    sig2 = runtmp.output('2.fa.sig.gz')
+    sig47 = runtmp.output('47.fa.sig.gz')
+    sig63 = runtmp.output('63.fa.sig.gz')
+    for name, dest in [('2.fa.sig.gz', sig2), ('47.fa.sig.gz', sig47),
+                       ('63.fa.sig.gz', sig63)]:
+        shutil.copyfile(get_test_data(name), dest)
+
+    siglist = runtmp.output('db-sigs.txt')
+    make_file_list(siglist, [sig2, sig47, sig63])
+
+    output = runtmp.output('db.rdb')
+    runtmp.sourmash('scripts', 'index', siglist, '-o', output)
+
+    # overwrite 47 with a different sketch, and remove 2
+    shutil.copyfile(get_test_data('63.fa.sig.gz'), sig47)
+    os.unlink(sig2)
+
+    problems = runtmp.output('problems.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'check', '--deep', output, '-o', problems)
+    print(runtmp.last_result.err)
+
+    assert '2 of 3 datasets failed the deep check' in runtmp.last_result.err
+
+    df = pandas.read_csv(problems, keep_default_na=False)
+    assert len(df) == 2
+    rows = { os.path.basename(row['internal_location']): row
+             for row in df.to_dict(orient='records') }
+
+    assert rows['47.fa.sig.gz']['problem'] == 'md5_mismatch'
+    assert rows['47.fa.sig.gz']['manifest_md5'] == '09a08691ce52952152f0e866a59f6261'
+    assert rows['47.fa.sig.gz']['loaded_md5'] == '38729c6374925585db28916b82a6f513'
+
+    assert rows['2.fa.sig.gz']['problem'].startswith('load_failed')
+    assert rows['2.fa.sig.gz']['loaded_md5'] == ''
+
+
+def test_index_check_output_requires_deep(runtmp):
+    output = build_small_index(runtmp)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'check', output,
+                        '-o', runtmp.output('problems.csv'))
+
+    assert 'only written by the deep check' in runtmp.last_result.err
+
+
+def build_small_index(runtmp):
+    siglist = runtmp.output('db-sigs.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(siglist, [sig2, sig47])
+
+    output = runtmp.output('db.rdb')
+
+    runtmp.sourmash('scripts', 'index', siglist,
+                    '-o', output)
+    return output
+
+
+def set_index_schema(index, schema_version):
+    stamp_file = os.path.join(index, 'BRANCHWATER_INDEX')
+    with open(stamp_file) as fp:
+        stamp = json.load(fp)
+    stamp['schema_version'] = schema_version
+    with open(stamp_file, 'w') as fp:
+        json.dump(stamp, fp)
+
+
+def test_index_writes_schema_stamp(runtmp):
+    output = build_small_index(runtmp)
+
+    with open(os.path.join(output, 'BRANCHWATER_INDEX')) as fp:
+        stamp = json.load(fp)
+
+    assert stamp['schema_version'] == 1
+    assert stamp['plugin_version']
+
+
+def test_index_check_newer_schema(runtmp):
+    # an index from a newer, incompatible version is refused up front
+    output = build_small_index(runtmp)
+    set_index_schema(output, 1000)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'check', output)
+
+    print(runtmp.last_result.err)
+    assert 'uses schema version 1000' in runtmp.last_result.err
+    assert 'Please upgrade the branchwater plugin' in runtmp.last_result.err
+
+
+def test_index_check_older_schema(runtmp):
+    output = build_small_index(runtmp)
+    set_index_schema(output, 0)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'check', output)
+
+    print(runtmp.last_result.err)
+    assert 'uses schema version 0' in runtmp.last_result.err
+    assert "rebuild the index with 'sourmash scripts index'" in runtmp.last_result.err
+    # index_migrate can't upgrade stamped indexes, so don't suggest it.
+    assert "index_migrate" not in runtmp.last_result.err
+
+
+def test_index_manysearch_newer_schema(runtmp):
+    # searches check the schema stamp too
+    output = build_small_index(runtmp)
+    set_index_schema(output, 1000)
+
+    query = get_test_data('SRR606249.sig.gz')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query, output,
+                        '-o', runtmp.output('out.csv'))
+
+    print(runtmp.last_result.err)
+    assert 'uses schema version 1000' in runtmp.last_result.err
+
+
+def test_index_migrate_unstamped(runtmp):
+    # indexes built before schema stamps can be stamped in place
+    output = build_small_index(runtmp)
+    os.unlink(os.path.join(output, 'BRANCHWATER_INDEX'))
+
+    runtmp.sourmash('scripts', 'check', output)
+    assert 'has no schema stamp' in runtmp.last_result.err
+
+    runtmp.sourmash('scripts', 'index_migrate', output)
+    print(runtmp.last_result.err)
+    assert 'Stamped index' in runtmp.last_result.err
+    assert os.path.exists(os.path.join(output, 'BRANCHWATER_INDEX'))
+
+    runtmp.sourmash('scripts', 'check', output)
+    assert 'has no schema stamp' not in runtmp.last_result.err
+    assert 'index is ok' in runtmp.last_result.err
+
+
+def test_index_migrate_current(runtmp):
+    output = build_small_index(runtmp)
+
+    runtmp.sourmash('scripts', 'index_migrate', output)
+    assert 'already at schema version 1' in runtmp.last_result.err
+
+
+def test_index_migrate_unsupported(runtmp):
+    output = build_small_index(runtmp)
+    set_index_schema(output, 0)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'index_migrate', output)
+
+    print(runtmp.last_result.err)
+    assert 'no migration is available from schema version 0 to 1' in runtmp.last_result.err
+
+
+def test_index_from_iter_sigs(runtmp):
+    # build an index from an iterator of SourmashSignature objects
+    from sourmash_plugin_branchwater import build_revindex_from_iter
+
+    sig_paths = [get_test_data(x) for x in
+                 ('2.fa.sig.gz', '47.fa.sig.gz', '63.fa.sig.gz')]
+    sigs = (ss for p in sig_paths
+            for ss in sourmash.load_file_as_signatures(p, ksize=31))
+
+    output = runtmp.output('db.rocksdb')
+    status = build_revindex_from_iter(sigs, output)
+    assert status == 0
+    assert os.path.exists(output)
+    assert os.path.exists(runtmp.output('db.rocksdb.sketches.zip'))
+
+    # index should be usable for search
+    query = get_test_data('2.fa.sig.gz')
+    csv_out = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query, output,
+                    '-o', csv_out, '-t', '0.01')
+    df = pandas.read_csv(csv_out)
+    print(df)
+    assert len(df) == 3
+
+
+def test_index_from_iter_json(runtmp):
+    # build an index from an iterator of JSON strings
+    from sourmash_plugin_branchwater import build_revindex_from_iter
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    json_strs = []
+    for p in (sig2, sig47):
+        ss = sourmash.load_one_signature(p, ksize=31)
+        json_str = sourmash.save_signatures([ss])
+        if isinstance(json_str, bytes):
+            json_str = json_str.decode('utf-8')
+        json_strs.append(json_str)
+
+    output = runtmp.output('db.rocksdb')
+    sketches = runtmp.output('sketches.zip')
+    status = build_revindex_from_iter(iter(json_strs), output,
+                                      sketches_output=sketches)
+    assert status == 0
+    assert os.path.exists(output)
+
+    idx = sourmash.load_file_as_index(sketches)
+    assert len(idx) == 2
+
+
+def test_index_from_iter_empty(runtmp, capfd):
+    # an empty iterator is an error
+    from sourmash_plugin_branchwater import build_revindex_from_iter
+
+    output = runtmp.output('db.rocksdb')
+    status = build_revindex_from_iter(iter([]), output)
+    assert status == 1
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "No signatures read from iterator" in captured.err
+
+
+def test_index_from_iter_bad_json(runtmp, capfd):
+    # invalid JSON is reported
+    from sourmash_plugin_branchwater import build_revindex_from_iter
+
+    output = runtmp.output('db.rocksdb')
+    status = build_revindex_from_iter(iter(["not a signature"]), output)
+    assert status == 1
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Error reading signature 0 from iterator" in captured.err
+
+
+def test_index_check_parent_dir(runtmp, capfd):
+    # check finds the single index in a directory
+    siglist = runtmp.output('db-sigs.txt')
+    make_file_list(siglist, [get_test_data('2.fa.sig.gz'),
+                             get_test_data('47.fa.sig.gz')])
+
+    os.mkdir(runtmp.output('parent'))
+    output = runtmp.output('parent/db.rdb')
+    runtmp.sourmash('scripts', 'index', siglist, '-o', output)
+
+    runtmp.sourmash('scripts', 'check', runtmp.output('parent'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert f"Using RocksDB index '{output}'" in captured.err
+    assert 'index is ok' in runtmp.last_result.err
+
+
+def test_index_check_several_indexes(runtmp, capfd):
+    # a directory of several indexes is ambiguous, and says what it holds
+    siglist = runtmp.output('db-sigs.txt')
+    make_file_list(siglist, [get_test_data('2.fa.sig.gz'),
+                             get_test_data('47.fa.sig.gz')])
+
+    os.mkdir(runtmp.output('parent'))
+    runtmp.sourmash('scripts', 'index', siglist, '-o', runtmp.output('parent/a.rdb'))
+    runtmp.sourmash('scripts', 'index', siglist, '-o', runtmp.output('parent/b.rdb'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'check', runtmp.output('parent'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "is not a valid RevIndex database: it is a directory containing 2 RocksDB indexes (a.rdb, b.rdb)" in captured.err
@@ -2,6 +2,7 @@
 Test 'sourmash scripts fastmultigather'
 """
 import os
+import shutil
 import pytest
 import pandas
 
@@ -9,6 +10,15 @@ import sourmash
 from . import sourmash_tst_utils as utils
 
 
+# columns in prefetch output; same as 'sourmash prefetch', plus 'f_match'
+prefetch_keys = {'intersect_bp', 'jaccard', 'max_containment', 'f_query_match',
+                 'f_match_query', 'f_match', 'match_filename', 'match_name', 'match_md5',
+                 'match_bp', 'query_filename', 'query_name', 'query_md5',
+                 'query_bp', 'ksize', 'moltype', 'scaled', 'query_n_hashes',
+                 'query_containment_ani', 'match_containment_ani',
+                 'average_containment_ani', 'max_containment_ani'}
+
+
 def get_test_data(filename):
     thisdir = os.path.dirname(__file__)
     return os.path.join(thisdir, 'test-data', filename)
@@ -75,7 +85,7 @@ def test_simple(runtmp, zip_against):
     df = pandas.read_csv(p_output)
     assert len(df) == 3
     keys = set(df.keys())
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
     assert os.path.exists(g_output)
     df = pandas.read_csv(g_output)
@@ -150,7 +160,7 @@ def test_simple_zip_query(runtmp):
     df = pandas.read_csv(p_output)
     assert len(df) == 3
     keys = set(df.keys())
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
     assert os.path.exists(g_output)
     df = pandas.read_csv(g_output)
@@ -193,7 +203,7 @@ def test_simple_read_manifests(runtmp):
     df = pandas.read_csv(p_output)
     assert len(df) == 3
     keys = set(df.keys())
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
     assert os.path.exists(g_output)
     df = pandas.read_csv(g_output)
@@ -235,7 +245,8 @@ def test_simple_indexed(runtmp, zip_query):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert  keys == expected_keys
 
 
@@ -268,7 +279,8 @@ def test_simple_indexed_query_manifest(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert  keys == expected_keys
 
 
@@ -612,7 +624,7 @@ def test_md5(runtmp, zip_query):
     df = pandas.read_csv(p_output)
     assert len(df) == 3
     keys = set(df.keys())
-    assert keys == {'query_filename', 'query_name', 'query_md5', 'match_name', 'match_md5', 'intersect_bp'}
+    assert keys == prefetch_keys
 
     md5s = set(df['match_md5'])
     for against_file in (sig2, sig47, sig63):
@@ -666,7 +678,8 @@ def test_md5_indexed(runtmp, zip_query):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
 
     md5s = set(df['match_md5'])
@@ -722,7 +735,7 @@ def test_csv_columns_vs_sourmash_prefetch(runtmp, zip_query, zip_against):
     sp_keys = set(sourmash_prefetch_df.keys())
     print(g_keys - sp_keys)
     diff_keys = g_keys - sp_keys
-    assert diff_keys == set(['remaining_bp', 'f_match_orig', 'f_unique_weighted', 'average_abund', 'unique_intersect_bp', 'std_abund', 'sum_weighted_found', 'median_abund', 'n_unique_weighted_found', 'f_unique_to_query', 'f_orig_query', 'total_weighted_hashes', 'f_match'])
+    assert diff_keys == set(['remaining_bp', 'f_match_orig', 'f_unique_weighted', 'average_abund', 'unique_intersect_bp', 'std_abund', 'sum_weighted_found', 'median_abund', 'n_unique_weighted_found', 'f_unique_to_query', 'f_orig_query', 'total_weighted_hashes', 'f_match', 'match_n_hashes', 'match_intersect_hashes', 'match_intersect_hashes_at_rank'])
 
 def test_csv_columns_vs_sourmash_gather_fullresults(runtmp):
     # the column names should be identical to sourmash gather cols
@@ -756,7 +769,8 @@ def test_csv_columns_vs_sourmash_gather_fullresults(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert g_keys == expected_keys
 
     sourmash_gather_df = pandas.read_csv(sg_output)
@@ -801,7 +815,8 @@ def test_csv_columns_vs_sourmash_gather_indexed(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert g_keys == expected_keys
 
     sourmash_gather_df = pandas.read_csv(sg_output)
@@ -910,7 +925,8 @@ def test_simple_protein_indexed(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
     print(df)
     # since we're just matching to identical sigs, the md5s should be the same
@@ -940,7 +956,8 @@ def test_simple_dayhoff_indexed(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
     print(df)
     # since we're just matching to identical sigs, the md5s should be the same
@@ -970,7 +987,8 @@ def test_simple_hp_indexed(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
     print(df)
     # since we're just matching to identical sigs, the md5s should be the same
@@ -1008,7 +1026,8 @@ def test_indexed_full_output(runtmp):
             'query_containment_ani', 'sum_weighted_found', 'f_orig_query', 'ksize', 'max_containment_ani',
             'std_abund', 'scaled', 'average_containment_ani', 'f_match', 'f_unique_to_query',
             'average_abund', 'unique_intersect_bp', 'median_abund', 'query_md5', 'match_md5', 'remaining_bp',
-            'f_unique_weighted'}
+            'f_unique_weighted', 'match_n_hashes', 'match_intersect_hashes',
+            'match_intersect_hashes_at_rank'}
     assert keys == expected_keys
     results = df.values.tolist()
 
@@ -1142,3 +1161,1499 @@ def test_nonindexed_full_vs_sourmash_gather(runtmp):
     fmg_total_weighted_hashes= set(gather_df['total_weighted_hashes'])
     g_total_weighted_hashes = set(sourmash_gather_df['total_weighted_hashes'])
     assert fmg_total_weighted_hashes == g_total_weighted_hashes == set([73489])
+
+
+def test_save_remaining(runtmp):
+    # save remaining query sketches for each query
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0', '--save-remaining')
+    finally:
+        os.chdir(cwd)
+
+    r_output = runtmp.output('SRR606249.remaining.zip')
+    assert os.path.exists(r_output)
+
+    idx = sourmash.load_file_as_index(r_output)
+    sigs = list(idx.signatures())
+    assert len(sigs) == 3
+
+
+def test_save_remaining_indexed(runtmp):
+    # saving remaining sketches is not supported for rocksdb
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    g_output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, db,
+                        '-s', '100000', '-t', '0', '-o', g_output,
+                        '--save-remaining')
+
+    print(runtmp.last_result.err)
+    assert 'saving remaining query sketches is not supported' in runtmp.last_result.err
+
+
+def test_save_coverage(runtmp):
+    # save which gather rank claimed each query hash
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0', '--save-coverage')
+    finally:
+        os.chdir(cwd)
+
+    c_output = runtmp.output('SRR606249.coverage.csv')
+    assert os.path.exists(c_output)
+    g_output = runtmp.output('SRR606249.gather.csv')
+
+    df = pandas.read_csv(c_output)
+    print(df)
+    assert set(df.keys()) == {'hash_index', 'hash', 'gather_result_rank'}
+    assert list(df['hash_index']) == list(range(len(df)))
+
+    # number of hashes claimed by each rank matches unique_intersect_bp
+    gather_df = pandas.read_csv(g_output)
+    claimed = df['gather_result_rank'].value_counts()
+    for _, row in gather_df.iterrows():
+        rank = row['gather_result_rank']
+        assert claimed[rank] * 100000 == row['unique_intersect_bp']
+
+
+def test_save_coverage_indexed(runtmp):
+    # saving coverage is not supported for rocksdb
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    g_output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, db,
+                        '-s', '100000', '-t', '0', '-o', g_output,
+                        '--save-coverage')
+
+    print(runtmp.last_result.err)
+    assert 'saving query hash coverage is not supported' in runtmp.last_result.err
+
+
+@pytest.mark.parametrize('capacity', ['1', '100'])
+def test_indexed_channel_capacity(runtmp, capacity):
+    # results are the same regardless of writer channel capacity
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query, sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('out.csv')
+    against_db = index_siglist(runtmp, against_list, runtmp.output('test.rocksdb'))
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output, '--channel-capacity', capacity)
+
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert len(df) == 5
+
+
+
+def test_indexed_skip_done(runtmp, capfd):
+    # queries already present in a previous output are skipped
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig2, sig47])
+
+    g_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output)
+    df = pandas.read_csv(g_output)
+    done_md5s = set(df['query_md5'])
+    assert len(done_md5s) == 2
+
+    # add a new query and rerun, skipping the ones already done
+    query_list2 = runtmp.output('query2.txt')
+    make_file_list(query_list2, [sig2, sig47, sig63])
+
+    g_output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list2,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output2, '--skip-done', g_output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Skipping 2 query sketches already present in previous output." in captured.err
+
+    df2 = pandas.read_csv(g_output2)
+    assert len(df2) > 0
+    new_md5s = set(df2['query_md5'])
+    assert not new_md5s & done_md5s
+    assert set(df2['query_name'].str.split().str[0]) == {'NC_011665.1'}
+
+
+def test_skip_done(runtmp, capfd):
+    # previous per-query gather CSVs can be used to skip queries
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0')
+        os.remove('NC_011665.1.gather.csv')
+        os.remove('NC_011665.1.prefetch.csv')
+
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0',
+                        '--skip-done', 'CP001071.1.gather.csv',
+                        '--skip-done', 'NC_009661.1.gather.csv')
+    finally:
+        os.chdir(cwd)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Skipping 2 query sketches already present in previous output." in captured.err
+    assert os.path.exists(runtmp.output('NC_011665.1.gather.csv'))
+
+
+def test_skip_done_missing_column(runtmp, capfd):
+    # --skip-done requires a query_md5 column
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    done_csv = runtmp.output('done.csv')
+    with open(done_csv, 'wt') as fp:
+        fp.write("query_name,match_name\nfoo,bar\n")
+
+    g_output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                        against_db, '-s', '100000', '-t', '0',
+                        '-o', g_output, '--skip-done', done_csv)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "has no 'query_md5' column" in captured.err
+
+
+def test_skip_done_same_as_output(runtmp, capfd):
+    # refuse to overwrite the previous output being skipped
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    g_output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                        against_db, '-s', '100000', '-t', '0',
+                        '-o', g_output, '--skip-done', g_output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "results would be overwritten" in captured.err
+
+
+def test_indexed_query_groups(runtmp):
+    # summarize gather results per group of queries
+    import csv
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    groups_csv = runtmp.output('groups.csv')
+    with open(groups_csv, 'w', newline='') as fp:
+        w = csv.writer(fp)
+        w.writerow(['query_name', 'group'])
+        for path in [sig2, sig47, sig63]:
+            ss = sourmash.load_one_signature(path, ksize=31)
+            w.writerow([ss.name, 'all'])
+
+    g_output = runtmp.output('out.csv')
+    summary = runtmp.output('summary.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output, '--query-groups', groups_csv,
+                    '--query-groups-output', summary)
+
+    df = pandas.read_csv(g_output)
+    sdf = pandas.read_csv(summary)
+    print(sdf)
+
+    assert set(sdf['group']) == {'all'}
+    assert set(sdf['match_name']) == set(df['match_name'])
+    counts = df['match_name'].value_counts()
+    for _, row in sdf.iterrows():
+        assert row['n_queries'] == counts[row['match_name']]
+        rows = df[df['match_name'] == row['match_name']]
+        assert round(row['max_containment'], 6) == round(rows['f_orig_query'].max(), 6)
+
+
+def test_query_groups_not_indexed(runtmp, capfd):
+    # query groups are only summarized when searching a rocksdb
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    groups_csv = runtmp.output('groups.csv')
+    with open(groups_csv, 'wt') as fp:
+        fp.write("query_name,group\nfoo,bar\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0',
+                        '--query-groups', groups_csv,
+                        '--query-groups-output', runtmp.output('summary.csv'))
+
+    print(runtmp.last_result.err)
+    assert 'summarizing query groups is only supported' in runtmp.last_result.err
+
+
+def test_indexed_against_metadata(runtmp):
+    # append against metadata to gather results
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    metadata_csv = runtmp.output('metadata.csv')
+    with open(metadata_csv, 'wt') as fp:
+        fp.write("md5,label\n")
+        for path, label in [(sig2, 'two'), (sig47, 'fortyseven'), (sig63, 'sixtythree')]:
+            ss = sourmash.load_one_signature(path, ksize=31)
+            fp.write(f"{ss.md5sum()},{label}\n")
+
+    g_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output, '--against-metadata', metadata_csv)
+
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert len(df) == 3
+    assert df.columns[-1] == 'label'
+    assert set(df['label']) == {'two', 'fortyseven', 'sixtythree'}
+
+
+def test_output_dir(runtmp):
+    # write per-query outputs into a shared directory, with an index CSV
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    outdir = runtmp.output('results')
+
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                    '-s', '100000', '-t', '0', '--save-coverage',
+                    '--output-dir', outdir, '--job-prefix', 'jobA')
+    print(runtmp.last_result.err)
+
+    g_output = os.path.join(outdir, 'jobA.SRR606249.gather.csv')
+    p_output = os.path.join(outdir, 'jobA.SRR606249.prefetch.csv')
+    c_output = os.path.join(outdir, 'jobA.SRR606249.coverage.csv')
+    assert os.path.exists(g_output)
+    assert os.path.exists(p_output)
+    assert os.path.exists(c_output)
+    # nothing is written to the working directory
+    assert not os.path.exists(runtmp.output('SRR606249.gather.csv'))
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+
+    index = pandas.read_csv(os.path.join(outdir, 'jobA.index.csv'))
+    print(index)
+    assert list(index.columns) == ['query_name', 'query_md5', 'output_type', 'path']
+    assert set(index['output_type']) == {'gather', 'prefetch', 'coverage'}
+    assert set(index['path']) == {g_output, p_output, c_output}
+    assert set(index['query_name']) == {'SRR606249'}
+
+    # no temporary index left behind
+    assert sorted(os.listdir(outdir)) == sorted(['jobA.index.csv'] +
+                                                [os.path.basename(p) for p in index['path']])
+
+
+def test_output_dir_concurrent_jobs(runtmp):
+    # jobs with different prefixes share a directory without collisions
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [query])
+
+    outdir = runtmp.output('results')
+
+    for prefix, against in [('job1', sig2), ('job2', sig47)]:
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against,
+                        '-s', '100000', '-t', '0',
+                        '--output-dir', outdir, '--job-prefix', prefix)
+
+    df1 = pandas.read_csv(os.path.join(outdir, 'job1.SRR606249.gather.csv'))
+    df2 = pandas.read_csv(os.path.join(outdir, 'job2.SRR606249.gather.csv'))
+    assert list(df1['match_name'].str.split(' ').str[0]) == ['CP001071.1']
+    assert list(df2['match_name'].str.split(' ').str[0]) == ['NC_009661.1']
+
+    for prefix in ['job1', 'job2']:
+        index = pandas.read_csv(os.path.join(outdir, f'{prefix}.index.csv'))
+        assert len(index) == 2
+        assert all(os.path.basename(p).startswith(prefix + '.') for p in index['path'])
+
+
+def test_output_dir_default_prefix(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    outdir = runtmp.output('results')
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, sig2,
+                    '-s', '100000', '-t', '0', '--output-dir', outdir)
+
+    indexes = [f for f in os.listdir(outdir) if f.endswith('.index.csv')]
+    assert len(indexes) == 1
+    prefix = indexes[0][:-len('.index.csv')]
+    assert prefix.startswith('job-')
+    assert os.path.exists(os.path.join(outdir, f'{prefix}.SRR606249.gather.csv'))
+
+
+def test_output_dir_reused_prefix(runtmp):
+    # a job prefix can't be reused in the same directory
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    outdir = runtmp.output('results')
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, sig2,
+                    '-s', '100000', '-t', '0',
+                    '--output-dir', outdir, '--job-prefix', 'jobA')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, sig2,
+                        '-s', '100000', '-t', '0',
+                        '--output-dir', outdir, '--job-prefix', 'jobA')
+
+    print(runtmp.last_result.err)
+    assert "job prefix 'jobA' has already been used" in runtmp.last_result.err
+
+
+def test_output_dir_name_collision(runtmp):
+    # two queries with the same name never overwrite each other's outputs
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_copy = runtmp.output('SRR606249-copy.sig.gz')
+    shutil.copyfile(query, query_copy)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [query, query_copy])
+
+    outdir = runtmp.output('results')
+
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, sig2,
+                    '-s', '100000', '-t', '0',
+                    '--output-dir', outdir, '--job-prefix', 'jobA')
+    print(runtmp.last_result.err)
+
+    assert 'could not create output' in runtmp.last_result.err
+
+    index = pandas.read_csv(os.path.join(outdir, 'jobA.index.csv'))
+    assert len(index) == 2
+    assert set(index['output_type']) == {'gather', 'prefetch'}
+
+
+def test_job_prefix_requires_output_dir(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, sig2,
+                        '-s', '100000', '-t', '0', '--job-prefix', 'jobA')
+
+    assert '--job-prefix requires --output-dir' in runtmp.last_result.err
+
+
+def test_output_dir_indexed(runtmp):
+    # --output-dir is for per-query outputs, which rocksdb searches don't write
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-t', '0',
+                        '--output-dir', runtmp.output('results'))
+
+    assert '--output-dir is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_rank_by_ani(runtmp):
+    # --rank-by is passed through to each query's gather
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '-t', '0', '--rank-by', 'ani')
+
+    g_output = runtmp.output('SRR606249.gather.csv')
+    p_output = runtmp.output('SRR606249.prefetch.csv')
+    df = pandas.read_csv(g_output)
+    p_df = pandas.read_csv(p_output)
+    assert len(df) == 3
+
+    best = p_df.loc[p_df['match_containment_ani'].idxmax()]
+    assert df.iloc[0]['match_md5'] == best['match_md5']
+
+
+def test_rank_by_indexed(runtmp):
+    # only overlap ranking is supported for rocksdb searches
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-t', '0', '--rank-by', 'ani')
+
+    assert '--rank-by ani is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_spike_in_normalization(runtmp):
+    # --spike-in is passed through to each query's gather
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '-t', '0', '--spike-in', 'NC_009661.1',
+                    '--spike-in-amount', '10')
+
+    g_output = runtmp.output('SRR606249.gather.csv')
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert len(df) == 3
+
+    spike = df[df['match_name'].str.startswith('NC_009661.1')].iloc[0]
+    for _, row in df.iterrows():
+        expected = row['n_unique_weighted_found'] / spike['n_unique_weighted_found'] * 10
+        assert row['normalized_abundance'] == pytest.approx(expected)
+
+
+def test_spike_in_indexed(runtmp):
+    # spike-in normalization is not supported for rocksdb searches
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-t', '0', '--spike-in', 'CP001071.1')
+
+    assert '--spike-in is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_equal_overlap_tie_breaking(runtmp):
+    # matches with equal overlap are chosen in a stable order, regardless
+    # of database order
+    query = get_test_data('47.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    copy_a = runtmp.output('aaa.sig.gz')
+    copy_z = runtmp.output('zzz.sig.gz')
+    runtmp.sourmash('sig', 'rename', sig47, 'aaa', '-o', copy_a)
+    runtmp.sourmash('sig', 'rename', sig47, 'zzz', '-o', copy_z)
+
+    results = []
+    for n, paths in enumerate([[copy_z, copy_a, sig63],
+                               [sig63, copy_a, copy_z]]):
+        against_list = runtmp.output(f'against{n}.txt')
+        make_file_list(against_list, paths)
+
+        outdir = runtmp.output(f'results{n}')
+        runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                        '--output-dir', outdir, '--job-prefix', 'job')
+
+        index = pandas.read_csv(os.path.join(outdir, 'job.index.csv'))
+        g_output = index[index['output_type'] == 'gather'].iloc[0]['path']
+        results.append(list(pandas.read_csv(g_output)['match_name']))
+
+    assert results[0][0] == 'aaa'
+    assert results[0] == results[1]
+
+
+@pytest.mark.parametrize('indexed', [False, True])
+def test_ignore_abundance(runtmp, indexed):
+    # --ignore-abundance treats queries as flat, with or without an index
+    query = get_test_data('SRR606249.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output('db'))
+        g_output = runtmp.output('out.csv')
+        output_params = ['-o', g_output]
+    else:
+        g_output = runtmp.output('SRR606249.gather.csv')
+        output_params = []
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '--ignore-abundance', *output_params)
+
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert len(df) == 3
+    assert not any(df['query_abundance'])
+    assert set(df['n_unique_weighted_found']) == {0}
+    assert set(df['average_abund']) == {1.0}
+
+
+def test_report_no_matches(runtmp):
+    # --report-no-matches writes a placeholder row for queries without hits
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [query, sig2])
+    make_file_list(against_list, [sig47])
+
+    g_output = runtmp.output('out.csv')
+    against_db = index_siglist(runtmp, against_list, runtmp.output('test.rocksdb'))
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output, '--report-no-matches')
+
+    df = pandas.read_csv(g_output, keep_default_na=False)
+    print(df)
+    assert len(df) == 2
+
+    no_match = df[df['match_name'] == '']
+    assert len(no_match) == 1
+    row = no_match.iloc[0]
+    assert row['query_name'].startswith('CP001071.1')
+    assert row['query_n_hashes'] > 0
+    assert row['match_md5'] == ''
+    assert row['intersect_bp'] == 0
+    assert row['f_unique_to_query'] == 0.0
+
+
+def test_report_no_matches_requires_rocksdb(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                        '-s', '100000', '--report-no-matches')
+
+    assert '--report-no-matches is only supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_index_set_all_ksizes(runtmp):
+    # fastmultigather searches each index of a directory with --all-ksizes
+    sigs = runtmp.output('sigs.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=21,k=31,scaled=1',
+                    get_test_data('short.fa'), get_test_data('short2.fa'),
+                    '-o', sigs)
+
+    index_set = runtmp.output('dbs')
+    os.mkdir(index_set)
+    for ksize in (21, 31):
+        index_siglist(runtmp, sigs, os.path.join(index_set, f'k{ksize}.rocksdb'),
+                      ksize=ksize, scaled=1)
+
+    g_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', sigs, index_set,
+                    '-s', '1', '-t', '0', '-o', g_output, '--all-ksizes')
+
+    for ksize in (21, 31):
+        df = pandas.read_csv(runtmp.output(f'out.k{ksize}.csv'))
+        assert len(df) >= 2
+        assert set(df['ksize']) == {ksize}
+
+
+def test_cache_dir(runtmp):
+    # a second run with --cache-dir loads the search sketches from the cache
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_zip = zip_siglist(runtmp, against_list, runtmp.output('against.zip'))
+
+    cache_dir = runtmp.output('cache')
+    g_output = runtmp.output('SRR606249.gather.csv')
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_zip,
+                    '-s', '100000', '-t', '0', '--cache-dir', cache_dir)
+    assert 'Saved 3 sketches' in runtmp.last_result.err
+    assert len(os.listdir(cache_dir)) == 1
+    first = pandas.read_csv(g_output)
+    os.unlink(g_output)
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_zip,
+                    '-s', '100000', '-t', '0', '--cache-dir', cache_dir)
+    assert 'Loaded 3 sketches' in runtmp.last_result.err
+    second = pandas.read_csv(g_output)
+    pandas.testing.assert_frame_equal(first, second)
+
+    # a different selection does not reuse the cache entry
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_zip,
+                    '-s', '200000', '-t', '0', '--cache-dir', cache_dir)
+    assert 'Saved 3 sketches' in runtmp.last_result.err
+    assert len(os.listdir(cache_dir)) == 2
+
+
+def test_cache_dir_rocksdb(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2])
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-o', runtmp.output('out.csv'),
+                        '--cache-dir', runtmp.output('cache'))
+
+    assert '--cache-dir is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_lineages_krona(runtmp):
+    # --lineages writes a Krona text file per query, summing the weighted
+    # fraction of the query found by each lineage.
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    # identifiers may be given with or without their version
+    lineages = runtmp.output('lineages.csv')
+    with open(lineages, 'wt') as fp:
+        fp.write("ident,superkingdom,phylum,class,order,family,genus,species\n")
+        fp.write("CP001071.1,d__Bacteria,p__Verrucomicrobiota,c__Verrucomicrobiae,o__Verrucomicrobiales,f__Akkermansiaceae,g__Akkermansia,s__Akkermansia muciniphila\n")
+        fp.write("NC_009661,d__Bacteria,p__Proteobacteria,c__Gammaproteobacteria,o__Enterobacterales,f__Shewanellaceae,g__Shewanella,s__Shewanella baltica\n")
+        fp.write("NC_011665.1,d__Bacteria,p__Proteobacteria,c__Gammaproteobacteria,o__Enterobacterales,f__Shewanellaceae,g__Shewanella,s__Shewanella baltica\n")
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '-t', '0', '--lineages', lineages)
+
+    g_output = runtmp.output('SRR606249.gather.csv')
+    krona_output = runtmp.output('SRR606249.krona.tsv')
+    assert os.path.exists(krona_output)
+
+    gather_df = pandas.read_csv(g_output)
+    krona_df = pandas.read_csv(krona_output, sep='\t')
+    print(krona_df)
+
+    assert list(krona_df.columns) == ['fraction', 'superkingdom', 'phylum',
+                                      'class', 'order', 'family', 'genus',
+                                      'species']
+    # the rest of the query is unclassified
+    assert round(krona_df['fraction'].sum(), 5) == 1.0
+    unclassified = krona_df[krona_df['species'] == 'unclassified']
+    assert len(unclassified) == 1
+    assert round(unclassified['fraction'].iloc[0], 5) == \
+        round(1 - gather_df['f_unique_weighted'].sum(), 5)
+
+    # each lineage is reported once
+    assert len(krona_df) == len(set(krona_df['species']))
+    classified = krona_df[krona_df['species'] != 'unclassified']
+    assert round(classified['fraction'].sum(), 5) == \
+        round(gather_df['f_unique_weighted'].sum(), 5)
+
+
+def test_lineages_bad_csv(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    lineages = runtmp.output('lineages.csv')
+    with open(lineages, 'wt') as fp:
+        fp.write("name,species\n")
+        fp.write("CP001071.1,s__Akkermansia muciniphila\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                        '-s', '100000', '--lineages', lineages)
+
+    assert "lineages CSV" in runtmp.last_result.err
+    assert "has no identifier column" in runtmp.last_result.err
+
+
+def test_lineages_rocksdb(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-o', runtmp.output('out.csv'),
+                        '--lineages', runtmp.output('lineages.csv'))
+
+    assert "--lineages is not supported when searching a rocksdb" in runtmp.last_result.err
+
+
+def test_indexed_output_biom(runtmp):
+    # write a matches x queries BIOM table of f_unique_weighted
+    import json
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    g_output = runtmp.output('out.csv')
+    biom_output = runtmp.output('table.biom')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                    against_db, '-s', '100000', '-t', '0',
+                    '-o', g_output, '--output-biom', biom_output)
+
+    df = pandas.read_csv(g_output)
+    with open(biom_output) as fp:
+        table = json.load(fp)
+
+    assert table['format'] == "Biological Observation Matrix 1.0.0"
+    assert table['matrix_type'] == 'sparse'
+    rows = [r['id'] for r in table['rows']]
+    columns = [c['id'] for c in table['columns']]
+    assert set(rows) == set(df['match_name'])
+    assert set(columns) == set(df['query_name'])
+    assert table['shape'] == [len(rows), len(columns)]
+
+    # every gather result is one entry of the table
+    assert len(table['data']) == len(df)
+    for row_idx, col_idx, value in table['data']:
+        match = df[(df['match_name'] == rows[row_idx]) &
+                   (df['query_name'] == columns[col_idx])]
+        assert len(match) == 1
+        assert round(value, 6) == round(match['f_unique_weighted'].iloc[0], 6)
+
+
+def test_output_biom_hdf5(runtmp):
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                        against_db, '-s', '100000', '-o', runtmp.output('out.csv'),
+                        '--output-biom', runtmp.output('table.h5'))
+
+    assert "HDF5 BIOM output is not supported" in runtmp.last_result.err
+
+
+def test_output_biom_not_indexed(runtmp):
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '--output-biom', runtmp.output('table.biom'))
+
+    assert "--output-biom is only supported when searching a rocksdb" in runtmp.last_result.err
+
+
+def load_report_data(filename):
+    # the report data is embedded in the HTML as JSON
+    import json
+    import re
+
+    with open(filename) as fp:
+        html = fp.read()
+    m = re.search(r'<script type="application/json" id="report-data">(.*?)</script>',
+                  html, re.S)
+    assert m
+    return json.loads(m.group(1))
+
+
+def test_report(runtmp):
+    # write a self-contained HTML report of the run
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query, sig2])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    report = runtmp.output('report.html')
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0', '--report', report)
+    finally:
+        os.chdir(cwd)
+
+    assert os.path.exists(report)
+    assert "Wrote a report on 2 queries" in runtmp.last_result.err
+    data = load_report_data(report)
+
+    params = dict(data['params'])
+    assert params['ksize'] == '31'
+    assert params['scaled'] == '100000'
+    assert params['threshold_bp'] == '0'
+
+    # the top matches of each query are those in its gather output
+    queries = {q['md5']: q for q in data['queries']}
+    for gather_csv in ('SRR606249.gather.csv', 'CP001071.1.gather.csv'):
+        df = pandas.read_csv(runtmp.output(gather_csv))
+        q = queries[df['query_md5'].iloc[0]]
+        assert q['name'] == df['query_name'].iloc[0]
+        assert q['n_matches'] == len(df)
+        assert [m['name'] for m in q['top']] == list(df['match_name'])
+        assert [m['rank'] for m in q['top']] == list(df['gather_result_rank'])
+        assert round(q['f_weighted_found'], 4) == round(df['f_unique_weighted'].sum(), 4)
+
+
+def test_indexed_report(runtmp):
+    # the report is built by the writer thread when searching a rocksdb
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    g_output = runtmp.output('out.csv')
+    report = runtmp.output('report.html')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_db,
+                    '-s', '100000', '-t', '0', '-o', g_output, '--report', report)
+
+    df = pandas.read_csv(g_output)
+    data = load_report_data(report)
+    assert len(data['queries']) == 1
+    q = data['queries'][0]
+    assert q['n_matches'] == len(df) == 3
+    assert set(m['name'] for m in q['top']) == set(df['match_name'])
+
+
+def test_report_query_without_matches(runtmp):
+    # queries with no matches are listed in the report
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig47])
+
+    report = runtmp.output('report.html')
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '--report', report)
+    finally:
+        os.chdir(cwd)
+
+    data = load_report_data(report)
+    assert len(data['queries']) == 1
+    assert data['queries'][0]['n_matches'] == 0
+    assert data['queries'][0]['top'] == []
+
+
+def test_report_resume(runtmp):
+    # a resumed run can't report on the queries of the first run
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '--checkpoint-every', '1', '--resume',
+                        '--report', runtmp.output('report.html'))
+
+    assert "--report only summarizes the queries gathered in this run" in runtmp.last_result.err
+
+
+def test_max_matchlist_mem(runtmp, capfd):
+    # spilling each query's prefetch matches to disk doesn't change the results
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '-t', '0')
+    runtmp.sourmash('scripts', 'fastmultigather', query, against_list,
+                    '-s', '100000', '-t', '0', '--max-matchlist-mem', '1',
+                    '--output-dir', runtmp.output('capped'),
+                    '--job-prefix', 'capped')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'spilled 2 of 3 matches to disk' in captured.err
+
+    g_df = pandas.read_csv(runtmp.output('SRR606249.gather.csv'))
+    g_df2 = pandas.read_csv(runtmp.output('capped/capped.SRR606249.gather.csv'))
+    assert len(g_df) == 3
+    assert list(g_df['match_md5']) == list(g_df2['match_md5'])
+    assert list(g_df['f_unique_weighted']) == list(g_df2['f_unique_weighted'])
+
+    p_df = pandas.read_csv(runtmp.output('SRR606249.prefetch.csv'))
+    p_df2 = pandas.read_csv(runtmp.output('capped/capped.SRR606249.prefetch.csv'))
+    assert p_df.equals(p_df2)
+
+
+def test_max_matchlist_mem_rocksdb(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query, db,
+                        '-s', '100000', '-o', runtmp.output('out.csv'),
+                        '--max-matchlist-mem', '1')
+
+    assert "--max-matchlist-mem is not supported when searching a rocksdb" in runtmp.last_result.err
+
+
+def make_empty_sig(filename):
+    # a sketch with no hashes at all, e.g. from a very short sequence.
+    with open(filename, 'wt') as fp:
+        fp.write('''[{"class": "sourmash_signature", "email": "", "hash_function": "0.murmur64",
+  "filename": "empty.fa", "name": "empty", "license": "CC0",
+  "signatures": [{"num": 0, "ksize": 31, "seed": 42, "max_hash": 18446744073709552,
+                  "mins": [], "md5sum": "c16a5320fa475530d9583c34fd356ef5",
+                  "molecule": "dna"}],
+  "version": 0.4}]''')
+    return filename
+
+
+def test_empty_query_skipped(runtmp, capfd):
+    # empty queries are skipped up front, and reported in skipped_queries.csv
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    empty = make_empty_sig(runtmp.output('empty.sig'))
+
+    make_file_list(query_list, [sig2, empty])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0')
+    finally:
+        os.chdir(cwd)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 1 queries with no hashes after downsampling." in captured.err
+    assert "No matches to 'empty'" not in captured.out
+
+    assert os.path.exists(runtmp.output('CP001071.1.gather.csv'))
+    assert not os.path.exists(runtmp.output('empty.gather.csv'))
+
+    skipped_df = pandas.read_csv(runtmp.output('skipped_queries.csv'))
+    assert len(skipped_df) == 1
+    row = skipped_df.iloc[0]
+    assert row['query_name'] == 'empty'
+    assert row['query_n_hashes'] == 0
+    assert row['reason'] == 'no_hashes_after_downsampling'
+
+
+def test_empty_query_skipped_indexed(runtmp, capfd):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    empty = make_empty_sig(runtmp.output('empty.sig'))
+
+    make_file_list(query_list, [sig2, empty])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    g_output = runtmp.output('out.csv')
+    skipped = runtmp.output('skipped.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, db,
+                    '-s', '100000', '-t', '0', '-o', g_output,
+                    '--output-skipped-queries', skipped)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 1 queries with no hashes after downsampling." in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert len(df) > 0
+    assert 'empty' not in set(df['query_name'])
+
+    skipped_df = pandas.read_csv(skipped)
+    assert list(skipped_df['query_name']) == ['empty']
+    assert list(skipped_df['reason']) == ['no_hashes_after_downsampling']
+
+
+def test_checkpoint_resume(runtmp, capfd):
+    # each query's gather is resumed from its own checkpoint
+    import json
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    def run(*extra):
+        cwd = os.getcwd()
+        try:
+            os.chdir(runtmp.output(''))
+            runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                            against_list, '-s', '100000', '-t', '0',
+                            '--checkpoint-every', '1', *extra)
+        finally:
+            os.chdir(cwd)
+
+    run()
+    g_output = runtmp.output('SRR606249.gather.csv')
+    with open(g_output, 'rb') as fp:
+        expected = fp.read()
+    with open(g_output + '.checkpoint.json') as fp:
+        checkpoint = json.load(fp)
+    assert checkpoint['complete']
+    assert len(checkpoint['matches']) == 3
+
+    # interrupt SRR606249 after its first rank
+    with open(g_output, 'rb') as fp:
+        lines = fp.readlines()
+    checkpoint['matches'] = checkpoint['matches'][:1]
+    checkpoint['complete'] = False
+    checkpoint['output_bytes'] = len(lines[0]) + len(lines[1])
+    with open(g_output + '.checkpoint.json', 'wt') as fp:
+        json.dump(checkpoint, fp)
+    with open(g_output, 'wb') as fp:
+        fp.write(lines[0] + lines[1])
+    capfd.readouterr()
+
+    run('--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'iter 1: resumed from checkpoint' in captured.err
+    assert "gather already completed in 'NC_009661.1.gather.csv'" in captured.err
+
+    with open(g_output, 'rb') as fp:
+        assert fp.read() == expected
+
+
+def test_resume_completed_queries(runtmp, capfd):
+    # --resume without --checkpoint-every skips the queries in the output
+    # index of a previous run, before their prefetch, and starts the
+    # others over
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    def run(*extra):
+        cwd = os.getcwd()
+        try:
+            os.chdir(runtmp.output(''))
+            runtmp.sourmash('scripts', 'fastmultigather', query_list,
+                            against_list, '-s', '100000', '-t', '0', *extra)
+        finally:
+            os.chdir(cwd)
+
+    # the index records each query's outputs when they are all written,
+    # with or without --resume
+    run()
+    index_csv = runtmp.output('fastmultigather.index.csv')
+    index = pandas.read_csv(index_csv, dtype=str)
+    print(index)
+    assert list(index.columns) == ['query_name', 'query_md5', 'output_type', 'path']
+    assert set(index['path']) == {'SRR606249.gather.csv', 'SRR606249.prefetch.csv',
+                                  'NC_009661.1.gather.csv', 'NC_009661.1.prefetch.csv'}
+    g_output = runtmp.output('SRR606249.gather.csv')
+    with open(g_output, 'rb') as fp:
+        expected = fp.read()
+
+    # SRR606249 was interrupted; NC_009661.1 finished
+    index = index[~index['path'].str.startswith('SRR606249.')]
+    index.to_csv(index_csv, index=False)
+    os.unlink(runtmp.output('NC_009661.1.prefetch.csv'))
+    capfd.readouterr()
+
+    run('--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Skipping 1 query sketches already present in previous output." in captured.err
+    # the completed query wasn't searched again
+    assert not os.path.exists(runtmp.output('NC_009661.1.prefetch.csv'))
+    with open(g_output, 'rb') as fp:
+        assert fp.read() == expected
+    # and the index now lists both
+    index = pandas.read_csv(index_csv)
+    assert set(index['path'].str.split('.').str[0]) == {'SRR606249', 'NC_009661'}
+
+
+def test_resume_output_dir_jobs(runtmp, capfd):
+    # with --output-dir, --resume skips the queries in the index of every
+    # job in the directory, including that of an interrupted job
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    outdir = runtmp.output('results')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                    '-s', '100000', '-t', '0',
+                    '--output-dir', outdir, '--job-prefix', 'jobA')
+
+    # jobA was interrupted after finishing NC_009661.1: its index was
+    # never renamed into place
+    index = pandas.read_csv(os.path.join(outdir, 'jobA.index.csv'), dtype=str)
+    index = index[index['query_name'].str.startswith('NC_009661.1')]
+    assert len(index) == 2
+    os.unlink(os.path.join(outdir, 'jobA.index.csv'))
+    index.to_csv(os.path.join(outdir, '.jobA.index.csv.tmp'), index=False)
+    capfd.readouterr()
+
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                    '-s', '100000', '-t', '0', '--resume',
+                    '--output-dir', outdir, '--job-prefix', 'jobB')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Skipping 1 query sketches already present in previous output." in captured.err
+    assert os.path.exists(os.path.join(outdir, 'jobB.SRR606249.gather.csv'))
+    assert not os.path.exists(os.path.join(outdir, 'jobB.NC_009661.1.gather.csv'))
+    index = pandas.read_csv(os.path.join(outdir, 'jobB.index.csv'))
+    assert set(index['query_name']) == {'SRR606249'}
+
+
+def test_resume_indexed(runtmp, capfd):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [get_test_data('SRR606249.sig.gz')])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_db,
+                        '-s', '100000', '-o', runtmp.output('out.csv'), '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--resume is not supported when searching a rocksdb" in captured.err
+
+
+def test_resume_output_dir(runtmp, capfd):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [get_test_data('SRR606249.sig.gz')])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '--output-dir', runtmp.output('out'),
+                        '--checkpoint-every', '1', '--resume')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert '--resume with --checkpoint-every cannot be used with --output-dir' in captured.err
+
+
+def test_output_format_parquet(runtmp):
+    # per-query gather outputs are named '.gather.parquet'
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    sig47 = get_test_data('47.fa.sig.gz')
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'), sig47,
+                                  get_test_data('63.fa.sig.gz')])
+
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                    '-s', '100000', '-t', '0', '--output-format', 'parquet')
+
+    g_output = runtmp.output('NC_009661.1.gather.parquet')
+    assert os.path.exists(g_output)
+    assert not os.path.exists(runtmp.output('NC_009661.1.gather.csv'))
+    # prefetch outputs are still CSV
+    assert os.path.exists(runtmp.output('NC_009661.1.prefetch.csv'))
+
+    df = pandas.read_parquet(g_output)
+    assert len(df) >= 1
+    assert df['match_name'][0].startswith('NC_009661.1')
+    assert df['f_match'][0] == 1.0
+
+
+def test_output_format_parquet_rocksdb(runtmp):
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63])
+    db = runtmp.output('db.rocksdb')
+    runtmp.sourmash('scripts', 'index', against_list, '-o', db,
+                    '-k', '31', '--scaled', '100000')
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, db,
+                    '-s', '100000', '-t', '0', '-o', csv_output)
+    pq_output = runtmp.output('out.parquet')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, db,
+                    '-s', '100000', '-t', '0', '-o', pq_output,
+                    '--output-format', 'parquet')
+
+    key = ['query_md5', 'gather_result_rank']
+    csv_df = pandas.read_csv(csv_output).sort_values(key).reset_index(drop=True)
+    pq_df = pandas.read_parquet(pq_output).sort_values(key).reset_index(drop=True)
+    assert len(pq_df) == len(csv_df)
+    pandas.testing.assert_frame_equal(pq_df[csv_df.columns], csv_df,
+                                      check_dtype=False)
+
+
+def test_output_format_jsonl(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    sig47 = get_test_data('47.fa.sig.gz')
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'), sig47,
+                                  get_test_data('63.fa.sig.gz')])
+
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                    '-s', '100000', '-t', '0', '--output-format', 'jsonl')
+
+    g_output = runtmp.output('NC_009661.1.gather.jsonl')
+    assert os.path.exists(g_output)
+    df = pandas.read_json(g_output, lines=True)
+    assert len(df) >= 1
+    assert df['match_name'][0].startswith('NC_009661.1')
+
+
+@pytest.mark.parametrize("schedule", ['size', 'input-order'])
+def test_schedule(runtmp, schedule):
+    # the order in which queries are started doesn't change the results
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2, query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    cwd = os.getcwd()
+    try:
+        os.chdir(runtmp.output(''))
+        runtmp.sourmash('scripts', 'fastmultigather', query_list, against_list,
+                        '-s', '100000', '-t', '0', '--schedule', schedule)
+    finally:
+        os.chdir(cwd)
+
+    df = pandas.read_csv(runtmp.output('SRR606249.gather.csv'))
+    assert len(df) == 3
+    for name in ('CP001071.1', 'NC_009661.1'):
+        df = pandas.read_csv(runtmp.output(f'{name}.gather.csv'))
+        assert len(df) == 1
+        assert df['match_name'].iloc[0].startswith(name)
+
+
+@pytest.mark.parametrize("schedule", ['size', 'input-order'])
+def test_indexed_schedule(runtmp, schedule):
+    # queries are scheduled the same way when searching a rocksdb
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [sig2, query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output('rocksdb'))
+
+    g_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'fastmultigather', query_list, against_db,
+                    '-s', '100000', '-t', '0', '-o', g_output,
+                    '--schedule', schedule)
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 5
+    assert set(df['query_name'].str.split().str[0]) == {'SRR606249', 'CP001071.1', 'NC_009661.1'}
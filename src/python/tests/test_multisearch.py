@@ -5,6 +5,7 @@ import pandas
 import sourmash
 
 from . import sourmash_tst_utils as utils
+from sourmash_plugin_branchwater import sourmash_plugin_branchwater
 
 
 def get_test_data(filename):
@@ -830,3 +831,715 @@ def test_simple_below_threshold(runtmp):
             assert float(row['match_containment_ani']) == 1.0
             assert float(row['average_containment_ani']) == 1.0
             assert float(row['max_containment_ani']) == 1.0
+
+
+def test_simple_columns(runtmp):
+    # select a subset of output columns
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--ani',
+                    '--columns', 'query_name,match_name,max_containment_ani')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    assert list(df.columns) == ['query_name', 'match_name', 'max_containment_ani']
+
+
+def test_simple_columns_bad(runtmp, capfd):
+    # request a column that doesn't exist
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--columns', 'query_name,no_such_column')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'unknown output column(s): no_such_column' in captured.err
+
+
+def test_simple_columns_bad_no_results(runtmp, capfd):
+    # a bad column fails even if there are no results to write
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--columns', 'query_name,no_such_column')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'unknown output column(s): no_such_column' in captured.err
+
+
+def test_policies_allow_empty_collection(runtmp, capfd):
+    # an empty collection is only a warning with allow_empty_collection
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    # no k=21 sketches, so nothing is selected
+    status = sourmash_plugin_branchwater.do_multisearch(query_list,
+                                                        against_list, 0.01,
+                                                        21, 1000, 'DNA',
+                                                        False, 0, 0,
+                                                        output, None, None,
+                                                        allow_empty_collection=True)
+    assert status == 0
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: no query signatures loaded." in captured.err
+    assert "WARNING: no search signatures loaded." in captured.err
+
+
+def test_policies_validate_names(runtmp, capfd):
+    # names with control characters are reported with validate_names
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    renamed = runtmp.output('renamed.sig.gz')
+    runtmp.sourmash('sig', 'rename', sig47, 'bad\nname', '-o', renamed)
+
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig2, renamed])
+
+    output = runtmp.output('out.csv')
+
+    status = sourmash_plugin_branchwater.do_multisearch(query_list,
+                                                        query_list, 0.01,
+                                                        31, 1000, 'DNA',
+                                                        False, 0, 0,
+                                                        output, None, None,
+                                                        validate_names=True)
+    assert status == 0
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: 1 query sketches have names with control characters" in captured.err
+    assert "WARNING: 1 search sketches have names with control characters" in captured.err
+    assert "bad\\nname" in captured.err
+
+
+def test_min_query_hashes(runtmp, capfd):
+    # queries with too few hashes are skipped and reported
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    skipped = runtmp.output('skipped.csv')
+
+    # 2.fa has 2701 hashes; 47.fa and 63.fa have over 5000.
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--min-query-hashes', '3000',
+                    '--output-skipped-queries', skipped)
+    assert os.path.exists(output)
+    assert os.path.exists(skipped)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+    assert not any(df['query_name'].str.startswith('CP001071.1'))
+
+    skipped_df = pandas.read_csv(skipped)
+    assert len(skipped_df) == 1
+    row = skipped_df.iloc[0]
+    assert row['query_name'].startswith('CP001071.1')
+    assert row['query_n_hashes'] == 2701
+    assert row['min_query_hashes'] == 3000
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 1 queries with fewer than 3000 hashes." in captured.err
+
+
+def test_num_sketches(runtmp):
+    # jaccard-only search of num sketches
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    sigs = runtmp.output('num.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,num=500', fa1, fa2, fa3,
+                    '-o', sigs)
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', sigs, sigs,
+                    '-o', output, '--num', '500', '-t', '0')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ['query_name', 'query_md5', 'match_name',
+                                'match_md5', 'jaccard', 'ksize', 'num',
+                                'moltype']
+    assert set(df['ksize']) == {31}
+    assert set(df['num']) == {500}
+    assert set(df['moltype']) == {'DNA'}
+    self_matches = df[df['query_md5'] == df['match_md5']]
+    assert len(self_matches) == 3
+    assert set(self_matches['jaccard']) == {1.0}
+    assert all(df['jaccard'] <= 1.0)
+
+
+def test_num_sketches_seed_mismatch(runtmp, capfd):
+    # num sketches must share a seed
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+
+    query = runtmp.output('query.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,num=500,seed=43', fa1,
+                    '-o', query)
+    against = runtmp.output('against.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,num=500', fa2,
+                    '-o', against)
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query, against,
+                        '-o', output, '--num', '500')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "query and search sketches must share a seed" in captured.err
+
+
+def test_num_sketches_scaled_input(runtmp, capfd):
+    # scaled sketches are not selected when searching num sketches
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, query_list,
+                        '-o', output, '--num', '500')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "the query collection has DNA k=31 sketches, but none are num=500 sketches." in captured.err
+
+
+def test_multiple_outputs(runtmp):
+    # the same results are written to each output; .gz outputs are compressed
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    output_gz = runtmp.output('out.csv.gz')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '-o', output_gz)
+
+    with open(output_gz, 'rb') as fp:
+        assert fp.read(2) == b'\x1f\x8b'
+
+    df = pandas.read_csv(output)
+    df_gz = pandas.read_csv(output_gz)
+    assert len(df) == 5
+    assert df.equals(df_gz)
+
+
+def test_parquet_output_without_format(runtmp, capfd):
+    # a .parquet output requires --output-format parquet
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+
+    output = runtmp.output('out.parquet')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, query_list,
+                        '-o', runtmp.output('out.csv'), '-o', output)
+
+    assert not os.path.exists(output)
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "is named as a Parquet file, but results are written as csv; use --output-format parquet." in captured.err
+
+
+def test_query_buckets(runtmp):
+    # output can be hive-partitioned by a hash of query_name
+    import glob
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output)
+
+    outdir = runtmp.output('partitioned')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', outdir, '--query-buckets', '16')
+
+    parts = glob.glob(os.path.join(outdir, 'query_bucket=*', 'part-0.csv'))
+    print(parts)
+    assert parts
+
+    dfs = []
+    for part in parts:
+        df = pandas.read_csv(part)
+        # all rows for a query are in the same bucket
+        bucket = os.path.basename(os.path.dirname(part))
+        df['bucket'] = bucket
+        dfs.append(df)
+    df = pandas.concat(dfs)
+
+    assert len(df) == len(pandas.read_csv(output))
+    for query_name, group in df.groupby('query_name'):
+        assert len(set(group['bucket'])) == 1
+
+
+
+def test_min_ani(runtmp):
+    # --min-ani drops matches below the ANI cutoff, and keeps the rest unchanged
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--ani')
+
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output2, '--ani', '--min-ani', '0.99')
+
+    df = pandas.read_csv(output)
+    df2 = pandas.read_csv(output2)
+    print(df)
+    print(df2)
+
+    expected = df[df['max_containment_ani'] >= 0.99]
+    assert len(expected) < len(df)
+    assert len(df2) == len(expected)
+    assert all(df2['max_containment_ani'] >= 0.99)
+
+    expected = expected.sort_values(['query_name', 'match_name'])
+    df2 = df2.sort_values(['query_name', 'match_name'])
+    assert list(expected['match_name']) == list(df2['match_name'])
+    assert list(expected['max_containment_ani']) == list(df2['max_containment_ani'])
+
+
+def test_min_ani_requires_ani(runtmp, capfd):
+    # --min-ani is an error without --ani
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--min-ani', '0.95')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "a minimum ANI can only be used when estimating ANI" in captured.err
+
+
+def test_dry_run(runtmp):
+    # --dry-run reports what would be compared, and writes no output
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--dry-run')
+
+    assert not os.path.exists(output)
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "Dry run of 'multisearch'; nothing will be computed." in err
+    assert "ksize: 31 / scaled: 1000 / moltype: DNA" in err
+    assert f"query '{query_list}': 2 sketches" in err
+    assert f"against '{against_list}': 3 sketches" in err
+    assert "comparisons: 6" in err
+    assert "estimated peak memory:" in err
+    assert "estimated runtime:" in err
+    assert "multisearch is done" not in err
+
+
+def test_dry_run_no_compatible(runtmp):
+    # --dry-run fails as a real run would when no sketches are selected
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--dry-run', '-k', '19')
+
+    assert 'No compatible query signatures' in runtmp.last_result.err
+
+
+def test_max_mem_exceeded(runtmp):
+    # --max-mem aborts before comparing if the estimate is too large
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--max-mem', '1K')
+
+    assert not os.path.exists(output)
+    err = runtmp.last_result.err
+    print(err)
+    assert "estimated peak memory:" in err
+    assert "exceeds --max-mem of 1.0 KB" in err
+
+
+def test_max_mem_ok(runtmp):
+    # --max-mem runs as usual when the estimate fits
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--max-mem', '16G')
+
+    assert os.path.exists(output)
+    assert "estimated peak memory:" in runtmp.last_result.err
+
+
+def test_max_mem_bad_size(runtmp):
+    # --max-mem must be a valid memory size
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                        '-o', output, '--max-mem', 'lots')
+
+    assert not os.path.exists(output)
+
+
+def test_selection_columns(runtmp):
+    # each row reports the ksize, scaled and moltype it was compared at
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--scaled', '2000')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    assert set(df['ksize']) == {31}
+    assert set(df['scaled']) == {2000}
+    assert set(df['moltype']) == {'DNA'}
+
+
+def test_hash_seed(runtmp):
+    # multisearch compares sketches built with a non-default seed with --hash-seed
+    sigs = runtmp.output('seed43.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,scaled=1,seed=43',
+                    get_test_data('short.fa'), get_test_data('short2.fa'),
+                    '-o', sigs)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', sigs, sigs,
+                        '-o', output, '--scaled', '1')
+
+    assert 'was built with hash seed 43' in runtmp.last_result.err
+
+    runtmp.sourmash('scripts', 'multisearch', sigs, sigs,
+                    '-o', output, '--scaled', '1', '--hash-seed', '43')
+    df = pandas.read_csv(output)
+    assert len(df) >= 2
+
+
+@pytest.mark.parametrize("zip_db", [False, True])
+def test_compact_mem(runtmp, zip_db):
+    # --compact-mem gives the same results as holding full sketches
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if zip_db:
+        against_list = zip_siglist(runtmp, against_list, runtmp.output('db.zip'))
+
+    output = runtmp.output('out.csv')
+    compact_output = runtmp.output('compact.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--ani')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', compact_output, '--ani', '--compact-mem')
+
+    keys = ['query_name', 'match_name']
+    df = pandas.read_csv(output).sort_values(keys).reset_index(drop=True)
+    compact_df = pandas.read_csv(compact_output).sort_values(keys).reset_index(drop=True)
+    assert len(compact_df) == 5
+    pandas.testing.assert_frame_equal(df, compact_df)
+
+
+def test_compact_mem_dry_run(runtmp):
+    # --dry-run with --compact-mem reports the search sketches as compacted
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2, sig47])
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', runtmp.output('out.csv'), '--dry-run', '--compact-mem')
+
+    err = runtmp.last_result.err
+    print(err)
+    assert f"against '{against_list}': 2 sketches" in err
+    assert "(all loaded into memory, compacted)" in err
+
+
+def test_unique_est_kmers(runtmp):
+    # unique k-mer estimates use n_hashes at the comparison scaled, not the
+    # scaled the sketches were built with.
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--scaled', '2000')
+
+    expected = {}
+    for filename in (sig2, sig47, sig63):
+        ss = sourmash.load_one_signature(filename, ksize=31)
+        mh = ss.minhash.downsample(scaled=2000)
+        expected[ss.name] = len(mh) * 2000
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    for idx, row in df.iterrows():
+        assert row['query_unique_est_kmers'] == expected[row['query_name']]
+        assert row['match_unique_est_kmers'] == expected[row['match_name']]
+
+
+def test_float_precision(runtmp):
+    # floats are rounded to --float-precision decimal places
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    full = runtmp.output('full.csv')
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', full, '--ani')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output, '--ani', '--float-precision', '3')
+
+    full_df = pandas.read_csv(full)
+    df = pandas.read_csv(output)
+    assert list(df.columns) == list(full_df.columns)
+    assert len(df) == len(full_df)
+
+    float_cols = ['containment', 'max_containment', 'jaccard',
+                  'query_containment_ani', 'match_containment_ani',
+                  'average_containment_ani', 'max_containment_ani']
+    with open(output, newline='') as fp:
+        for row in csv.DictReader(fp):
+            for col in float_cols:
+                decimals = row[col].partition('.')[2]
+                assert len(decimals) <= 3, (col, row[col])
+
+    for col in float_cols:
+        for rounded, value in zip(df[col], full_df[col]):
+            assert rounded == pytest.approx(value, abs=0.0005)
+    # integer columns are left alone
+    assert list(df['intersect_hashes']) == list(full_df['intersect_hashes'])
+
+
+def test_float_precision_bad(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, query_list,
+                        '-o', runtmp.output('out.csv'), '--float-precision', '-1')
+
+    assert 'must be 0 or more' in runtmp.last_result.err
+
+
+def test_output_format_parquet(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', csv_output, '--ani', '--float-precision', '3')
+    pq_output = runtmp.output('out.parquet')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', pq_output, '--ani', '--float-precision', '3',
+                    '--output-format', 'parquet')
+
+    key = ['query_name', 'match_name']
+    csv_df = pandas.read_csv(csv_output).sort_values(key).reset_index(drop=True)
+    pq_df = pandas.read_parquet(pq_output).sort_values(key).reset_index(drop=True)
+    assert pandas.api.types.is_float_dtype(pq_df['query_containment_ani'])
+    # --float-precision applies to Parquet output too
+    pandas.testing.assert_frame_equal(pq_df[csv_df.columns], csv_df,
+                                      check_dtype=False)
+
+
+def test_output_format_bad(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', query_list, query_list,
+                        '-o', runtmp.output('out.csv'), '--output-format', 'tsv')
+
+    assert "invalid choice: 'tsv'" in runtmp.last_result.err
@@ -1,10 +1,12 @@
 import os
 import csv
+import json
 import pytest
 import pandas
 import sourmash
 
 from . import sourmash_tst_utils as utils
+from sourmash_plugin_branchwater import sourmash_plugin_branchwater
 
 
 def get_test_data(filename):
@@ -624,3 +626,499 @@ def test_simple_below_threshold_write_all_no_ani(runtmp):
             assert float(row['jaccard']) == 1.0
             assert row['query_name'] == row['match_name']
             assert row['query_md5'] == row['match_md5']
+
+
+def test_simple_columns(runtmp):
+    # select a subset of output columns
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '-t', '-1',
+                    '--columns', 'query_name,match_name,jaccard')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+    assert list(df.columns) == ['query_name', 'match_name', 'jaccard']
+
+
+def test_policies_dedupe_md5(runtmp):
+    # duplicate sketches are only compared once with dedupe_md5
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63, sig2])
+
+    output = runtmp.output('out.csv')
+
+    status = sourmash_plugin_branchwater.do_pairwise(query_list, -1.0, 31,
+                                                     1000, 'DNA', False,
+                                                     False, output, None,
+                                                     dedupe_md5=True)
+    assert status == 0
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+
+
+def test_policies_strict_scaled(runtmp, capfd):
+    # sketches that would need downsampling are an error with strict_scaled
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    status = sourmash_plugin_branchwater.do_pairwise(query_list, -1.0, 31,
+                                                     10000, 'DNA', False,
+                                                     False, output, None,
+                                                     strict_scaled=True)
+    assert status == 1
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "refusing to downsample" in captured.err
+
+
+
+def test_against(runtmp):
+    # compare two different collections, with no within-collection comparisons
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '--against', against_list,
+                    '-o', output, '-t', '-1', '--ani')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 4
+    assert 'query_containment_ani' in df.columns
+    assert 'max_containment_ani' in df.columns
+
+    query_names = set(x.split()[0] for x in df['query_name'])
+    match_names = set(x.split()[0] for x in df['match_name'])
+    assert query_names == {'CP001071.1', 'NC_009661.1'}
+    assert match_names == {'NC_009661.1', 'NC_011665.1'}
+
+    # results should match multisearch on the same inputs
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, against_list,
+                    '-o', output2, '-t', '-1', '--ani')
+    df2 = pandas.read_csv(output2)
+    df2 = df2[df2['intersect_hashes'] > 0]
+
+    df = df.sort_values(['query_name', 'match_name']).reset_index()
+    df2 = df2.sort_values(['query_name', 'match_name']).reset_index()
+    merged = df.merge(df2, on=['query_name', 'match_name'], suffixes=('', '_ms'))
+    assert len(merged) == len(df2)
+    for col in ['containment', 'jaccard', 'max_containment', 'intersect_hashes']:
+        assert list(merged[col].round(4)) == list(merged[col + '_ms'].round(4))
+
+
+def test_against_write_all_ignored(runtmp):
+    # --write-all has no effect when comparing two collections
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '--against', against_list,
+                    '-o', output, '-t', '-1', '--write-all')
+
+    print(runtmp.last_result.err)
+    assert '--write-all is ignored' in runtmp.last_result.err
+
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+    assert not (df['query_name'] == df['match_name']).any()
+
+
+def test_against_single_sketches(runtmp):
+    # a single sketch in each collection is allowed in rectangular mode
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig63])
+
+    output = runtmp.output('out.csv')
+
+    status = sourmash_plugin_branchwater.do_pairwise(query_list, -1.0, 31,
+                                                     1000, 'DNA', False,
+                                                     False, output, None,
+                                                     against_path=against_list)
+    assert status == 0
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+
+
+def test_output_downsampled(runtmp):
+    # pairwise reports sketches downsampled to the comparison scaled
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('out.csv')
+    ds_output = runtmp.output('downsampled.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output,
+                    '-s', '2000', '--output-downsampled', ds_output)
+
+    df = pandas.read_csv(ds_output)
+    assert len(df) == 3
+    assert set(df['sketch_type']) == {'analysis'}
+    assert set(df['original_scaled']) == {1000}
+    assert set(df['target_scaled']) == {2000}
+
+
+def test_dry_run(runtmp):
+    # --dry-run reports the number of pairs, and writes no output
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '--dry-run')
+
+    assert not os.path.exists(output)
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "Dry run of 'pairwise'; nothing will be computed." in err
+    assert f"sketches '{query_list}': 3 sketches" in err
+    assert "(all loaded into memory)" in err
+    assert "comparisons: 3" in err
+
+
+def test_max_mem_exceeded(runtmp):
+    # --max-mem aborts before comparing if the estimate is too large
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list,
+                        '-o', output, '--max-mem', '1K')
+
+    assert not os.path.exists(output)
+    err = runtmp.last_result.err
+    print(err)
+    assert "estimated peak memory:" in err
+    assert "exceeds --max-mem of 1.0 KB" in err
+
+
+def test_max_mem_ok(runtmp):
+    # --max-mem runs as usual when the estimate fits
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '--max-mem', '16G')
+
+    assert os.path.exists(output)
+    assert "estimated peak memory:" in runtmp.last_result.err
+
+
+def test_max_mem_bad_size(runtmp):
+    # --max-mem must be a valid memory size
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list,
+                        '-o', output, '--max-mem', 'lots')
+
+    assert not os.path.exists(output)
+
+
+def test_selection_columns(runtmp):
+    # each row, including --write-all self rows, reports ksize/scaled/moltype
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '--write-all')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+    assert set(df['ksize']) == {31}
+    assert set(df['scaled']) == {1000}
+    assert set(df['moltype']) == {'DNA'}
+
+
+def test_progress_not_a_terminal(runtmp, capfd):
+    # --progress is accepted, and does nothing when stderr is not a terminal
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '--progress')
+    assert os.path.exists(output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "DONE. Processed 3 comparisons" in captured.err
+
+
+def test_lsh(runtmp, capfd):
+    # with LSH bands, only pairs sharing a band are compared: 47 and 63
+    # overlap, and 2 overlaps neither.
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '-t', '-1', '--lsh-bands', '50', '--lsh-rows', '1')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    names = {df['query_name'][0].split()[0], df['match_name'][0].split()[0]}
+    assert names == {'NC_009661.1', 'NC_011665.1'}
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "LSH (50 bands of 1 rows): comparing 1 of 3 pairs." in captured.err
+
+
+def test_lsh_write_all(runtmp):
+    # --write-all still reports each sketch against itself with LSH
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output, '-t', '-1',
+                    '--write-all', '--lsh-bands', '50', '--lsh-rows', '1')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+    assert len(df[df['query_name'] == df['match_name']]) == 3
+
+
+def test_lsh_requires_bands_and_rows(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list,
+                        '-o', runtmp.output('out.csv'), '--lsh-bands', '10')
+
+    assert '--lsh-bands and --lsh-rows must be given together' in runtmp.last_result.err
+
+
+def test_lsh_against(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('47.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list, '--against', against_list,
+                        '-o', runtmp.output('out.csv'), '--lsh-bands', '10', '--lsh-rows', '2')
+
+    assert '--lsh-bands is only supported for all-vs-all comparisons' in runtmp.last_result.err
+
+
+def test_output_format_jsonl(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', csv_output, '-t', '-1', '--ani')
+    output = runtmp.output('out.jsonl')
+    runtmp.sourmash('scripts', 'pairwise', query_list,
+                    '-o', output, '-t', '-1', '--ani',
+                    '--output-format', 'jsonl')
+
+    with open(output) as fp:
+        rows = [json.loads(line) for line in fp]
+    assert len(rows) == 3
+    assert all(isinstance(row['query_containment_ani'], float) for row in rows)
+
+    key = ['query_name', 'match_name']
+    df = pandas.read_json(output, lines=True).sort_values(key).reset_index(drop=True)
+    csv_df = pandas.read_csv(csv_output).sort_values(key).reset_index(drop=True)
+    pandas.testing.assert_frame_equal(df, csv_df, check_dtype=False)
+
+
+def test_output_format_jsonl_query_buckets(runtmp, capfd):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz')])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list,
+                        '-o', runtmp.output('out'), '--query-buckets', '2',
+                        '--output-format', 'jsonl')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert '--output-format jsonl cannot be used with --query-buckets.' in captured.err
+
+
+def test_min_ani(runtmp):
+    # --min-ani drops pairs below the ANI cutoff, and keeps the rest unchanged
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output,
+                    '-t', '-1', '--ani')
+
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output2,
+                    '-t', '-1', '--ani', '--min-ani', '0.95')
+
+    df = pandas.read_csv(output)
+    df2 = pandas.read_csv(output2)
+    print(df)
+    print(df2)
+
+    expected = df[df['max_containment_ani'] >= 0.95]
+    assert 0 < len(expected) < len(df)
+    assert len(df2) == len(expected)
+    assert all(df2['max_containment_ani'] >= 0.95)
+
+    expected = expected.sort_values(['query_name', 'match_name'])
+    df2 = df2.sort_values(['query_name', 'match_name'])
+    assert list(expected['match_name']) == list(df2['match_name'])
+    assert list(expected['max_containment_ani']) == list(df2['max_containment_ani'])
+
+
+def test_min_ani_write_all(runtmp):
+    # self comparisons are kept by --min-ani
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output,
+                    '--ani', '--min-ani', '0.999', '--write-all')
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 3
+    assert all(df['query_md5'] == df['match_md5'])
+    assert all(df['max_containment_ani'] == 1.0)
+
+
+def test_min_ani_requires_ani(runtmp, capfd):
+    # --min-ani is an error without --ani
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'pairwise', query_list,
+                        '-o', output, '--min-ani', '0.95')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "a minimum ANI can only be used when estimating ANI" in captured.err
@@ -0,0 +1,171 @@
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def make_lists(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    return query_list, against_list
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'rarefy')
+
+    assert 'usage:  rarefy' in runtmp.last_result.err
+
+
+def test_simple(runtmp):
+    query_list, against_list = make_lists(runtmp)
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                    '-o', output, '--fractions', '1,0.5,0.1',
+                    '--replicates', '3')
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert set(df.columns) == {'query_name', 'query_md5', 'fraction',
+                               'replicate', 'query_n_hashes', 'n_matches'}
+
+    # a fraction of 1 gets one replicate; the others get three.
+    assert len(df) == 3 * (1 + 3 + 3)
+    assert len(df[df['fraction'] == 1.0]) == 3
+
+    # at fraction 1, match counts are the same as manysearch
+    full = df[df['fraction'] == 1.0].copy()
+    full['name'] = full['query_name'].str.split().str[0]
+    full = full.set_index('name')
+    assert full.loc['NC_009661.1', 'n_matches'] == 2
+    assert full.loc['NC_011665.1', 'n_matches'] == 2
+    assert full.loc['CP001071.1', 'n_matches'] == 1
+
+    mh47 = sourmash.load_one_signature(get_test_data('47.fa.sig.gz'), ksize=31).minhash
+    name47 = full.loc['NC_009661.1', 'query_name']
+    assert full.loc['NC_009661.1', 'query_n_hashes'] == len(mh47)
+
+    # flat sketches keep about 'fraction' of their hashes
+    half = df[(df['fraction'] == 0.5) & (df['query_name'] == name47)]
+    for n in half['query_n_hashes']:
+        assert 0.4 * len(mh47) < n < 0.6 * len(mh47)
+
+
+def test_nested_subsamples(runtmp):
+    # within a replicate, smaller fractions keep a subset of the hashes,
+    # so they never have more hashes or matches than larger fractions.
+    query_list, against_list = make_lists(runtmp)
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                    '-o', output, '--fractions', '0.01,0.1,0.5,0.9',
+                    '--replicates', '2', '-t', '0')
+
+    df = pandas.read_csv(output)
+    print(df)
+
+    for (query_name, replicate), group in df.groupby(['query_name', 'replicate']):
+        group = group.sort_values('fraction')
+        assert list(group['query_n_hashes']) == sorted(group['query_n_hashes'])
+        assert list(group['n_matches']) == sorted(group['n_matches'])
+
+
+def test_seed(runtmp):
+    # the same seed gives the same subsamples; a different seed does not.
+    query_list, against_list = make_lists(runtmp)
+    out1 = runtmp.output('out1.csv')
+    out2 = runtmp.output('out2.csv')
+    out3 = runtmp.output('out3.csv')
+
+    for output, seed in ((out1, '1'), (out2, '1'), (out3, '2')):
+        runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                        '-o', output, '--fractions', '0.5',
+                        '--replicates', '2', '--seed', seed)
+
+    df1 = pandas.read_csv(out1)
+    df2 = pandas.read_csv(out2)
+    df3 = pandas.read_csv(out3)
+
+    assert list(df1['query_n_hashes']) == list(df2['query_n_hashes'])
+    assert list(df1['query_n_hashes']) != list(df3['query_n_hashes'])
+
+
+def test_abundance_subsampling(runtmp):
+    # hashes with high abundance are more likely to survive subsampling.
+    query = get_test_data('SRR606249.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'rarefy', query, sig47, '-o', output,
+                    '--fractions', '1,0.1', '--replicates', '1',
+                    '-s', '100000')
+
+    df = pandas.read_csv(output)
+    print(df)
+
+    mh = sourmash.load_one_signature(query, ksize=31).minhash
+    mh = mh.downsample(scaled=100000)
+    abunds = mh.hashes
+
+    expected = sum(1 - (1 - 0.1) ** a for a in abunds.values())
+    sub = df[df['fraction'] == 0.1].iloc[0]
+    assert sub['query_n_hashes'] == pytest.approx(expected, rel=0.2)
+    assert sub['query_n_hashes'] > 0.1 * len(mh)
+
+
+@pytest.mark.parametrize("fractions", ["0,0.5", "1.5", "-0.1"])
+def test_bad_fraction(runtmp, fractions):
+    query_list, against_list = make_lists(runtmp)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                        '-o', output, '--fractions', fractions)
+
+    print(runtmp.last_result.err)
+    assert 'is not between 0 and 1' in runtmp.last_result.err
+
+
+def test_unparseable_fractions(runtmp):
+    query_list, against_list = make_lists(runtmp)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                        '-o', output, '--fractions', '0.5,half')
+
+    assert "cannot parse --fractions '0.5,half'" in runtmp.last_result.err
+
+
+def test_zero_replicates(runtmp):
+    query_list, against_list = make_lists(runtmp)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'rarefy', query_list, against_list,
+                        '-o', output, '--replicates', '0')
+
+    assert 'the number of replicates must be at least 1' in runtmp.last_result.err
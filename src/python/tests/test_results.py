@@ -0,0 +1,130 @@
+import os
+import pytest
+import dataclasses
+
+from . import sourmash_tst_utils as utils
+from sourmash_plugin_branchwater import results
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def test_stubs_up_to_date():
+    # the checked-in stubs match the Rust result structs
+    stubs = os.path.join(os.path.dirname(results.__file__), 'results.pyi')
+    with open(stubs) as fp:
+        assert fp.read() == results.render_stubs(), \
+            "results.pyi is out of date; run 'python -m sourmash_plugin_branchwater.results > results.pyi'"
+
+
+def test_dataclass_fields():
+    fields = [ f.name for f in dataclasses.fields(results.MultiSearchResult) ]
+    assert fields[:4] == ['query_name', 'query_md5', 'match_name', 'match_md5']
+    assert 'query_containment_ani' in fields
+
+
+def test_read_multisearch(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz'),
+                                get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'multisearch', query_list, query_list,
+                    '-o', output)
+
+    rows = list(results.read_results(output, results.MultiSearchResult))
+    assert len(rows) == 5
+    for row in rows:
+        assert isinstance(row, results.MultiSearchResult)
+        assert isinstance(row.containment, float)
+        assert isinstance(row.ksize, int)
+        # ANI columns are only written with --ani
+        assert row.query_containment_ani is None
+        if row.query_md5 == row.match_md5:
+            assert row.containment == 1.0
+
+
+def test_read_gzipped_with_ani(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('out.csv.gz')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output, '--ani')
+
+    rows = list(results.read_results(output, results.MultiSearchResult))
+    assert len(rows) == 1
+    assert 0 < rows[0].max_containment_ani <= 1.0
+
+
+def test_read_missing_columns(runtmp):
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz'),
+                                get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'pairwise', query_list, '-o', output,
+                    '--columns', 'query_name,match_name,jaccard')
+
+    with pytest.raises(ValueError, match="has no query_md5"):
+        list(results.read_results(output, results.MultiSearchResult))
+
+
+def test_iter_gather(runtmp):
+    # results are the same as those written by fastgather
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    output = runtmp.output('gather.csv')
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', output, '-s', '100000')
+    expected = list(results.read_results(output, results.BranchwaterGatherResult))
+
+    rows = list(results.iter_gather(query, against_list, scaled=100000))
+    assert len(rows) == 3
+    for row in rows:
+        assert isinstance(row, results.BranchwaterGatherResult)
+    assert [ r.gather_result_rank for r in rows ] == [0, 1, 2]
+    assert [ r.match_md5 for r in rows ] == [ r.match_md5 for r in expected ]
+    for row, exp in zip(rows, expected):
+        assert row.intersect_bp == exp.intersect_bp
+        assert row.f_unique_weighted == pytest.approx(exp.f_unique_weighted)
+        assert row.normalized_abundance is None
+
+
+def test_iter_gather_close_early(runtmp):
+    # the caller can stop reading before gather finishes
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    it = results.iter_gather(query, against_list, scaled=100000)
+    first = next(it)
+    assert first.gather_result_rank == 0
+    it.close()
+
+
+def test_iter_gather_error(runtmp):
+    # errors are raised from the iterator
+    import sourmash_plugin_branchwater
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    it = results.iter_gather(runtmp.output('no-such-file'), against_list)
+    with pytest.raises(sourmash_plugin_branchwater.BranchwaterError) as exc:
+        list(it)
+    assert exc.value.name == 'InputNotFound'
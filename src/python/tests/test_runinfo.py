@@ -0,0 +1,135 @@
+"""
+Test --run-summary JSON run records.
+"""
+import json
+import os
+import pytest
+
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from sourmash_plugin_branchwater import runinfo
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def test_manysearch_run_summary(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+    summary = runtmp.output('run.json')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '-k', '21', '-t', '0.05',
+                    '--run-summary', summary)
+    assert os.path.exists(output)
+
+    with open(summary) as fp:
+        info = json.load(fp)
+    print(info)
+
+    assert info['command'] == 'manysearch'
+    assert info['status'] == 0
+    assert info['versions']['sourmash'] == sourmash.VERSION
+    assert info['versions']['sourmash_plugin_branchwater']
+
+    params = info['parameters']
+    assert params['ksize'] == 21
+    assert params['scaled'] == 1000
+    assert params['moltype'] == 'dna'
+    assert params['threshold'] == 0.05
+    assert params['threshold_semantics'] == runinfo.THRESHOLD_SEMANTICS['manysearch']
+
+    assert info['arguments']['output'] == [output]
+    assert info['arguments']['run_summary'] == summary
+
+
+def test_fastgather_run_summary(runtmp):
+    query = get_test_data('SRR606249.sig.gz')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output('gather.csv')
+    summary = runtmp.output('run.json')
+
+    runtmp.sourmash('scripts', 'fastgather', query, against_list,
+                    '-o', g_output, '-s', '100000', '--run-summary', summary)
+
+    with open(summary) as fp:
+        info = json.load(fp)
+
+    params = info['parameters']
+    assert params['scaled'] == 100000
+    assert params['threshold_bp'] == 50000
+    assert 'threshold' not in params
+    assert 'base pairs' in params['threshold_semantics']
+
+
+def test_manysketch_run_summary(runtmp):
+    # commands without a threshold record their sketch parameters
+    fa_csv = runtmp.output('db-fa.csv')
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        fp.write(f"short,{get_test_data('short.fa')},\n")
+
+    output = runtmp.output('db.zip')
+    summary = runtmp.output('run.json')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '-p', 'k=21,scaled=1', '--run-summary', summary)
+
+    with open(summary) as fp:
+        info = json.load(fp)
+
+    assert info['command'] == 'manysketch'
+    assert info['parameters'] == {'param_string': 'k=21,scaled=1'}
+
+
+def test_run_summary_failed(runtmp):
+    # a failed run is recorded with its exit status
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz')])
+
+    summary = runtmp.output('run.json')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'multisearch', runtmp.output('no-such-file'),
+                        against_list, '-o', runtmp.output('out.csv'),
+                        '--run-summary', summary)
+
+    with open(summary) as fp:
+        info = json.load(fp)
+
+    assert info['command'] == 'multisearch'
+    assert info['status'] != 0
+
+
+def test_no_run_summary(runtmp):
+    # nothing is written without --run-summary
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                    '-o', runtmp.output('out.csv'))
+
+    assert not [ f for f in os.listdir(runtmp.output('')) if f.endswith('.json') ]
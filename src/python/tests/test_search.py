@@ -1,6 +1,8 @@
 import os
+import json
 import pytest
 import pandas
+import sqlite3
 import sourmash
 
 from . import sourmash_tst_utils as utils
@@ -327,6 +329,49 @@ def test_sig_query(runtmp, capfd, indexed):
     runtmp.sourmash('scripts', 'manysearch', sig2, against_list,
                         '-o', output)
 
+    captured = capfd.readouterr()
+    print(captured.err)
+
+    assert "could not load sketches from path" not in captured.err
+
+    assert os.path.exists(output)
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    row = df.iloc[0]
+    assert row['query_name'].split()[0] == 'CP001071.1'
+    assert row['match_name'].split()[0] == 'CP001071.1'
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_bad_sig_query(runtmp, capfd, indexed):
+    # test with a single sig query that is not a valid signature file;
+    # should fail without falling back to loading it as a pathlist.
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    query_sig = runtmp.output('query.sig')
+    make_file_list(query_sig, [sig47])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_sig, against_list,
+                        '-o', output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+
+    assert "Failed to load query signatures from" in captured.err
+    assert "could not load sketches from path" not in captured.err
+
 
 @pytest.mark.parametrize("indexed", [False, True])
 def test_bad_query_2(runtmp, capfd, indexed):
@@ -427,8 +472,28 @@ def test_nomatch_against(runtmp, capfd):
                         '-o', output)
 
     captured = capfd.readouterr()
-    assert "No search signatures loaded, exiting." in captured.err
+    assert "No compatible search signatures: the search collection has DNA k=31 sketches, but none are compatible with scaled=1000" in captured.err
+
+
+def test_num_against(runtmp, capfd):
+    # num sketches can't be selected at any scaled; say so, rather than
+    # suggesting a larger scaled.
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+
+    against = runtmp.output('num.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,num=500',
+                    get_test_data('short.fa'), '-o', against)
+
+    output = runtmp.output('out.csv')
 
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against,
+                        '-o', output)
+
+    captured = capfd.readouterr()
+    assert "the search collection has DNA k=31 sketches, but these are num sketches (num=500)" in captured.err
+    assert "larger '-s/--scaled'" not in captured.err
 
 def test_bad_against(runtmp, capfd):
     # test with a bad against list (a missing file)
@@ -993,3 +1058,1481 @@ def test_simple_hp_indexed(runtmp):
                 assert cont == 0.5994
                 assert intersect_hashes == 1724
                 assert query_ani == 0.9911
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_min_query_hashes(runtmp, capfd, indexed):
+    # queries with too few hashes are skipped and reported
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    output = runtmp.output('out.csv')
+    skipped = runtmp.output('skipped.csv')
+
+    # 2.fa has 2701 hashes; 47.fa and 63.fa have over 5000.
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--min-query-hashes', '3000',
+                    '--output-skipped-queries', skipped)
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+    assert not any(df['query_name'].str.startswith('CP001071.1'))
+
+    skipped_df = pandas.read_csv(skipped)
+    assert len(skipped_df) == 1
+    assert skipped_df.iloc[0]['query_n_hashes'] == 2701
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 1 queries with fewer than 3000 hashes." in captured.err
+
+
+def test_indexed_reuses_handle_after_rebuild(runtmp):
+    # searches in one process share a cached index handle; rebuilding the
+    # index at the same path must not return stale results.
+    import shutil
+
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    siglist = runtmp.output('db-sigs.txt')
+    make_file_list(siglist, [sig2, sig47])
+    db = index_siglist(runtmp, siglist, runtmp.output('db.rocksdb'))
+
+    output = runtmp.output('out.csv')
+    for _ in range(2):
+        runtmp.sourmash('scripts', 'manysearch', query, db,
+                        '-o', output, '-t', '0.01')
+        df = pandas.read_csv(output)
+        assert len(df) == 2
+
+    # rebuild with more sketches
+    shutil.rmtree(db)
+    make_file_list(siglist, [sig2, sig47, sig63])
+    db = index_siglist(runtmp, siglist, db)
+
+    runtmp.sourmash('scripts', 'manysearch', query, db,
+                    '-o', output, '-t', '0.01')
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+
+
+def test_clear_index_cache(runtmp):
+    # clear_index_cache can be called with or without a path
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater
+
+    sourmash_plugin_branchwater.clear_index_cache()
+    sourmash_plugin_branchwater.clear_index_cache(runtmp.output('no-exist'))
+
+
+def test_reload_index(runtmp):
+    # reload_index picks up a rebuilt index without clearing the cache
+    import shutil
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater
+
+    query = get_test_data('SRR606249.sig.gz')
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    siglist = runtmp.output('db-sigs.txt')
+    make_file_list(siglist, [sig2, sig47])
+    db = index_siglist(runtmp, siglist, runtmp.output('db.rocksdb'))
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query, db,
+                    '-o', output, '-t', '0.01')
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+
+    # rebuild with more sketches, then reload
+    shutil.rmtree(db)
+    make_file_list(siglist, [sig2, sig47, sig63])
+    db = index_siglist(runtmp, siglist, db)
+    sourmash_plugin_branchwater.reload_index(db)
+
+    runtmp.sourmash('scripts', 'manysearch', query, db,
+                    '-o', output, '-t', '0.01')
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+
+
+def test_reload_index_no_exist(runtmp):
+    # reload_index raises if the index can't be opened
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater
+
+    with pytest.raises(Exception):
+        sourmash_plugin_branchwater.reload_index(runtmp.output('no-exist'))
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_multiple_outputs(runtmp, indexed):
+    # manysearch can write the same results to several outputs
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list,
+                                     runtmp.output('db.rocksdb'))
+
+    output = runtmp.output('out.csv')
+    output2 = runtmp.output('out2.csv.gz')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '-o', output2)
+
+    df = pandas.read_csv(output)
+    df2 = pandas.read_csv(output2)
+    assert len(df) == 5
+    assert df.equals(df2)
+
+
+
+def make_query_groups(filename, groups):
+    # groups is a dict of query sig path -> group name
+    import csv
+    with open(filename, 'w', newline='') as fp:
+        w = csv.writer(fp)
+        w.writerow(['query_name', 'group'])
+        for path, group in groups.items():
+            ss = sourmash.load_one_signature(path, ksize=31)
+            w.writerow([ss.name, group])
+
+
+def test_query_groups(runtmp):
+    # summarize results per group of queries
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    groups_csv = runtmp.output('groups.csv')
+    make_query_groups(groups_csv, {sig2: 'akkermansia',
+                                   sig47: 'shewanella',
+                                   sig63: 'shewanella'})
+
+    output = runtmp.output('out.csv')
+    summary = runtmp.output('summary.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--query-groups', groups_csv,
+                    '--query-groups-output', summary)
+    assert os.path.exists(summary)
+
+    df = pandas.read_csv(output)
+    groups = pandas.read_csv(groups_csv)
+    df = df.merge(groups, on='query_name')
+    expected = df.groupby(['group', 'match_name']).agg(
+        n_queries=('query_name', 'count'),
+        max_containment=('containment', 'max'),
+        mean_ani=('query_containment_ani', 'mean')).reset_index()
+
+    sdf = pandas.read_csv(summary)
+    print(sdf)
+    assert list(sdf.columns) == ['group', 'match_name', 'n_queries',
+                                 'max_containment', 'mean_ani']
+    assert set(sdf['group']) == {'akkermansia', 'shewanella'}
+    assert len(sdf) == len(expected)
+
+    merged = sdf.merge(expected, on=['group', 'match_name'],
+                       suffixes=('', '_expected'))
+    assert len(merged) == len(sdf)
+    for col in ['n_queries', 'max_containment', 'mean_ani']:
+        assert list(merged[col].round(6)) == list(merged[col + '_expected'].round(6))
+
+
+def test_query_groups_ungrouped(runtmp, capfd):
+    # queries missing from the groups CSV are left out of the summary
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    groups_csv = runtmp.output('groups.csv')
+    make_query_groups(groups_csv, {sig2: 'akkermansia'})
+
+    output = runtmp.output('out.csv')
+    summary = runtmp.output('summary.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--query-groups', groups_csv,
+                    '--query-groups-output', summary)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "not listed in the query groups CSV" in captured.err
+
+    sdf = pandas.read_csv(summary)
+    assert set(sdf['group']) == {'akkermansia'}
+
+
+def test_query_groups_no_output(runtmp, capfd):
+    # --query-groups requires --query-groups-output
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    groups_csv = runtmp.output('groups.csv')
+    make_query_groups(groups_csv, {sig2: 'akkermansia'})
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--query-groups', groups_csv)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--query-groups requires --query-groups-output" in captured.err
+
+
+def test_query_groups_bad_csv(runtmp, capfd):
+    # the groups CSV must have query_name and group columns
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    groups_csv = runtmp.output('groups.csv')
+    with open(groups_csv, 'wt') as fp:
+        fp.write("name,site\nfoo,bar\n")
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--query-groups', groups_csv,
+                        '--query-groups-output', runtmp.output('summary.csv'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "must have 'query_name' and 'group' columns" in captured.err
+
+
+def test_against_metadata_md5(runtmp):
+    # append against metadata to each row, joined on match_md5
+    import csv
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    metadata_csv = runtmp.output('metadata.csv')
+    with open(metadata_csv, 'w', newline='') as fp:
+        w = csv.writer(fp)
+        w.writerow(['md5', 'genus', 'source'])
+        for path, genus in [(sig2, 'Akkermansia'), (sig47, 'Shewanella')]:
+            ss = sourmash.load_one_signature(path, ksize=31)
+            w.writerow([ss.md5sum(), genus, 'test'])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--against-metadata', metadata_csv)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 5
+    assert list(df.columns[-2:]) == ['genus', 'source']
+
+    for _, row in df.iterrows():
+        m = row['match_name'].split()[0]
+        if m == 'CP001071.1':
+            assert row['genus'] == 'Akkermansia'
+        elif m == 'NC_009661.1':
+            assert row['genus'] == 'Shewanella'
+        else:
+            # no metadata for this match
+            assert pandas.isna(row['genus'])
+
+
+def test_against_metadata_name_columns(runtmp):
+    # join on match_name, appending only selected columns
+    import csv
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    metadata_csv = runtmp.output('metadata.csv')
+    with open(metadata_csv, 'w', newline='') as fp:
+        w = csv.writer(fp)
+        w.writerow(['name', 'genus', 'source'])
+        for path, genus in [(sig2, 'Akkermansia'), (sig47, 'Shewanella'),
+                            (sig63, 'Shewanella')]:
+            ss = sourmash.load_one_signature(path, ksize=31)
+            w.writerow([ss.name, genus, 'test'])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--against-metadata', metadata_csv,
+                    '--against-metadata-columns', 'genus',
+                    '--columns', 'query_name,match_name,containment')
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ['query_name', 'match_name', 'containment', 'genus']
+    assert set(df['genus']) == {'Akkermansia', 'Shewanella'}
+
+
+def test_against_metadata_no_key(runtmp, capfd):
+    # the metadata CSV must have a column to join on
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    metadata_csv = runtmp.output('metadata.csv')
+    with open(metadata_csv, 'wt') as fp:
+        fp.write("accession,genus\nfoo,bar\n")
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--against-metadata', metadata_csv)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "must have an 'md5', 'match_name', or 'name' column" in captured.err
+
+
+def test_against_metadata_conflicting_column(runtmp, capfd):
+    # metadata columns may not shadow result columns
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    metadata_csv = runtmp.output('metadata.csv')
+    with open(metadata_csv, 'wt') as fp:
+        fp.write("md5,containment\nfoo,bar\n")
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--against-metadata', metadata_csv)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "against metadata column 'containment' is already a result column" in captured.err
+
+
+def test_dna_query_protein_against(runtmp, capfd):
+    # searching DNA sketches against a protein database explains which
+    # parameters to use, and that DNA is not translated at search time.
+    sig2 = get_test_data('2.fa.sig.gz')
+    protsigs = get_test_data('protein.zip')
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sig2, protsigs,
+                        '-o', output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "no DNA sketches were found in the search collection, which contains protein (k=19) sketches" in captured.err
+    assert "run with '-m protein -k 19'" in captured.err
+    assert "not translated between DNA and protein at search time" in captured.err
+    assert "manysketch -p protein,k=10" in captured.err
+
+
+def test_protein_moltype_dna_query(runtmp, capfd):
+    # asking for protein sketches from a DNA-only query explains the mismatch.
+    sig2 = get_test_data('2.fa.sig.gz')
+    protsigs = get_test_data('protein.zip')
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sig2, protsigs,
+                        '-k', '19', '-s', '100', '--moltype', 'protein',
+                        '-o', output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "No compatible query signatures: no protein sketches were found in the query collection, which contains DNA (k=31) sketches" in captured.err
+    assert "run with '-m DNA -k 31'" in captured.err
+
+
+def test_ksize_mismatch_explained(runtmp, capfd):
+    # asking for a missing ksize suggests the available ones.
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sig2, sig47,
+                        '-k', '21', '-o', output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "the query collection has no DNA k=21 sketches; available DNA ksizes are 31. Run with '-k 31'." in captured.err
+
+
+def test_output_downsampled(runtmp, capfd):
+    # sketches downsampled to reach the search scaled are summarized and
+    # written to --output-downsampled
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    ds_output = runtmp.output('downsampled.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '-s', '10000',
+                    '--output-downsampled', ds_output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "NOTE: downsampled 3 query sketch(es) from scaled=1000 to scaled=10000" in captured.err
+    assert "NOTE: downsampled 3 search sketch(es) from scaled=1000 to scaled=10000" in captured.err
+
+    df = pandas.read_csv(ds_output)
+    assert list(df.columns) == ['sketch_type', 'name', 'md5', 'original_scaled',
+                                'target_scaled', 'n_hashes_before',
+                                'n_hashes_after']
+    assert len(df) == 6
+    assert sorted(df['sketch_type']) == ['query'] * 3 + ['search'] * 3
+    assert set(df['original_scaled']) == {1000}
+    assert set(df['target_scaled']) == {10000}
+    assert all(df['n_hashes_after'] < df['n_hashes_before'])
+
+
+def test_output_downsampled_none(runtmp, capfd):
+    # nothing is downsampled at the sketches' own scaled
+    query_list = runtmp.output('query.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+    ds_output = runtmp.output('downsampled.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                    '-o', output, '--output-downsampled', ds_output)
+
+    captured = capfd.readouterr()
+    assert "NOTE: downsampled" not in captured.err
+
+    df = pandas.read_csv(ds_output)
+    assert len(df) == 0
+    assert 'original_scaled' in df.columns
+
+
+def test_output_downsampled_indexed(runtmp):
+    # RocksDB searches don't report downsampling
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, db,
+                        '-o', runtmp.output('out.csv'),
+                        '--output-downsampled', runtmp.output('ds.csv'))
+
+    assert '--output-downsampled is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_moltype_search(runtmp):
+    # search DNA and protein queries against their own databases in one run
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    protsigs = get_test_data('protein.zip')
+
+    queries = runtmp.output('mixed.zip')
+    runtmp.sourmash('sig', 'cat', sig2, sig47, protsigs, '-o', queries)
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', queries, sig47, '-o', output,
+                    '--moltype-search', f'protein,k=19,scaled=100:{protsigs}')
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert set(df['moltype']) == {'DNA', 'protein'}
+
+    dna = df[df['moltype'] == 'DNA']
+    assert len(dna) == 1
+    assert dna.iloc[0]['query_name'] == dna.iloc[0]['match_name']
+
+    protein = df[df['moltype'] == 'protein']
+    assert len(protein) == 4
+    assert set(protein['query_name']) == {'GCA_001593925', 'GCA_001593935'}
+
+
+def test_moltype_search_not_tagged_by_default(runtmp):
+    # rows only have a moltype column when searching several moltypes
+    sig47 = get_test_data('47.fa.sig.gz')
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', sig47, sig47, '-o', output)
+
+    df = pandas.read_csv(output)
+    assert 'moltype' not in df.columns
+
+
+def test_moltype_search_bad_spec(runtmp, capfd):
+    sig47 = get_test_data('47.fa.sig.gz')
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sig47, sig47, '-o', output,
+                        '--moltype-search', 'rna,k=10:foo.zip')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "unknown moltype 'rna' in search 'rna,k=10:foo.zip'" in captured.err
+
+
+def test_screen(runtmp):
+    # --screen reports presence/absence and the first match for each query
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--screen', '-c', '1')
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ['query_name', 'query_md5', 'present',
+                                'match_name', 'match_md5', 'containment',
+                                'intersect_hashes', 'query_containment_ani']
+    assert len(df) == 3
+
+    rows = { r['query_name'].split()[0]: r for r in df.to_dict(orient='records') }
+    assert not rows['CP001071.1']['present']
+    assert pandas.isna(rows['CP001071.1']['match_name'])
+
+    md5_47 = sourmash.load_one_signature(sig47, ksize=31).md5sum()
+    for name in ('NC_009661.1', 'NC_011665.1'):
+        row = rows[name]
+        assert row['present']
+        # with one thread, the first search sketch is found first
+        assert row['match_md5'] == md5_47
+        assert row['containment'] > 0.01
+
+
+def test_screen_stops_early(runtmp, capfd):
+    # once every query has matched, no more search sketches are loaded
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63, sig2])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--screen', '-c', '1')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert 'Processed 1 of 3 search sigs; 1 of 1 queries matched.' in captured.err
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    assert df.iloc[0]['present']
+
+
+def test_screen_indexed(runtmp):
+    # --screen is not supported when searching a rocksdb
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, db,
+                        '-o', output, '--screen')
+
+    assert '--screen is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_screen_with_moltype_search(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--screen',
+                        '--moltype-search', f'protein,k=19,scaled=100:{against_list}')
+
+    assert '--screen cannot be combined with --moltype-search' in runtmp.last_result.err
+
+
+def test_negative_controls(runtmp):
+    # matches also found by a negative control query are flagged
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    controls = runtmp.output('controls.txt')
+    md5_47 = sourmash.load_one_signature(sig47, ksize=31).md5sum()
+    with open(controls, 'wt') as fp:
+        fp.write(f"{md5_47}\n")
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--negative-controls', controls)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert 'in_negative_control' in df.columns
+    assert len(df) == 5
+
+    for row in df.to_dict(orient='records'):
+        query = row['query_name'].split()[0]
+        if query == 'CP001071.1':
+            # 2.fa matches only itself, which no control matched
+            assert not row['in_negative_control']
+        else:
+            # 47 is the control; it matches both 47 and 63
+            assert row['in_negative_control']
+
+    assert 'Negative controls: 1 control queries matched 2 search sketches; flagged 2 sample matches.' in runtmp.last_result.err
+
+
+def test_negative_controls_subtract(runtmp):
+    # with --subtract-negative-controls, control matches are removed
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    controls = runtmp.output('controls.txt')
+    md5_47 = sourmash.load_one_signature(sig47, ksize=31).md5sum()
+    with open(controls, 'wt') as fp:
+        fp.write(f"{md5_47}\n")
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--negative-controls', controls,
+                    '--subtract-negative-controls')
+
+    df = pandas.read_csv(output)
+    print(df)
+    queries = sorted(df['query_name'].str.split().str[0])
+    # the control's own rows are kept; 63's matches are removed
+    assert queries == ['CP001071.1', 'NC_009661.1', 'NC_009661.1']
+
+
+def test_negative_controls_indexed(runtmp):
+    # --negative-controls is not supported when searching a rocksdb
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    controls = runtmp.output('controls.txt')
+    with open(controls, 'wt') as fp:
+        fp.write("CP001071.1\n")
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, db,
+                        '-o', output, '--negative-controls', controls)
+
+    assert '--negative-controls is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("compress", [False, True])
+def test_output_checksums(runtmp, compress):
+    # --output-checksums writes an xxhsum-style sidecar for each output
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv.gz' if compress else 'out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--output-checksums')
+
+    sidecar = output + '.xxh64'
+    assert os.path.exists(sidecar)
+    with open(sidecar) as fp:
+        checksum, filename = fp.read().rstrip('\n').split('  ')
+    assert len(checksum) == 16
+    int(checksum, 16)
+    assert filename == os.path.basename(output)
+
+    size = os.path.getsize(output)
+    assert f"Wrote '{output}': {size} bytes, xxh64 {checksum}" in runtmp.last_result.err
+
+    # without the option, no sidecar is written
+    os.unlink(sidecar)
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output)
+    assert not os.path.exists(sidecar)
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_dry_run(runtmp, indexed):
+    # --dry-run reports what would be searched, and writes no output
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--dry-run')
+
+    assert not os.path.exists(output)
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "Dry run of 'manysearch'; nothing will be computed." in err
+    assert f"query '{query_list}': 3 sketches" in err
+    assert f"against '{against_list}': 2 sketches" in err
+    assert "comparisons: 6" in err
+    if indexed:
+        assert "(searched in a RocksDB index)" in err
+    else:
+        assert "(loaded one at a time)" in err
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_error_code_no_exist(runtmp, indexed):
+    # a missing input exits with the InputNotFound code
+    from sourmash_plugin_branchwater import ERROR_CODES
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(against_list, [sig2])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output)
+
+    assert runtmp.last_result.status == ERROR_CODES['InputNotFound']
+
+
+def test_error_code_no_compatible(runtmp):
+    # no sketches at the selected ksize exits with NoCompatibleSketches
+    from sourmash_plugin_branchwater import ERROR_CODES
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '-k', '19')
+
+    assert runtmp.last_result.status == ERROR_CODES['NoCompatibleSketches']
+
+
+def test_error_code_exception(runtmp):
+    # Python API errors are raised as BranchwaterError, with the code
+    from sourmash_plugin_branchwater import (sourmash_plugin_branchwater,
+                                             BranchwaterError, ERROR_CODES)
+
+    with pytest.raises(BranchwaterError) as exc:
+        sourmash_plugin_branchwater.reload_index(runtmp.output('no-exist'))
+
+    assert exc.value.name == 'InputNotFound'
+    assert exc.value.code == ERROR_CODES['InputNotFound']
+    # BranchwaterError is a RuntimeError, as errors were before.
+    assert isinstance(exc.value, RuntimeError)
+
+
+def make_index_set(runtmp):
+    # build a directory with one index each for k=21 and k=31
+    sigs = runtmp.output('sigs.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=21,k=31,scaled=1',
+                    get_test_data('short.fa'), get_test_data('short2.fa'),
+                    get_test_data('short3.fa'), '-o', sigs)
+
+    index_set = runtmp.output('dbs')
+    os.mkdir(index_set)
+    for ksize in (21, 31):
+        index_siglist(runtmp, sigs, os.path.join(index_set, f'k{ksize}.rocksdb'),
+                      ksize=ksize, scaled=1)
+    return sigs, index_set
+
+
+def test_index_set_picks_ksize(runtmp):
+    # a directory of indexes is searched with the index for -k
+    sigs, index_set = make_index_set(runtmp)
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', sigs, index_set,
+                    '-o', output, '-k', '21', '--scaled', '1')
+
+    df = pandas.read_csv(output)
+    assert len(df) >= 3
+    assert not os.path.exists(runtmp.output('out.k21.csv'))
+
+
+def test_index_set_all_ksizes(runtmp):
+    # --all-ksizes searches each index, writing outputs tagged by ksize
+    sigs, index_set = make_index_set(runtmp)
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', sigs, index_set,
+                    '-o', output, '--scaled', '1', '--all-ksizes')
+
+    assert not os.path.exists(output)
+    for ksize in (21, 31):
+        df = pandas.read_csv(runtmp.output(f'out.k{ksize}.csv'))
+        assert len(df) >= 3
+
+
+def test_index_set_missing_ksize(runtmp):
+    # a ksize with no index in the set is reported, with the available ones
+    from sourmash_plugin_branchwater import ERROR_CODES
+
+    sigs, index_set = make_index_set(runtmp)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sigs, index_set,
+                        '-o', output, '-k', '51', '--scaled', '1')
+
+    assert 'has no index for k=51 DNA; available: k=21 DNA, k=31 DNA' in runtmp.last_result.err
+    assert runtmp.last_result.status == ERROR_CODES['NoCompatibleSketches']
+
+
+def test_all_ksizes_requires_index_set(runtmp):
+    query_list = runtmp.output('query.txt')
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                        '-o', runtmp.output('out.csv'), '--all-ksizes')
+
+    assert '--all-ksizes requires a directory of RocksDB indexes' in runtmp.last_result.err
+
+
+def test_hash_seed_mismatch(runtmp):
+    # sketches built with another seed are an error, unless it is requested
+    from sourmash_plugin_branchwater import ERROR_CODES
+
+    sigs = runtmp.output('seed43.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,scaled=1,seed=43',
+                    get_test_data('short.fa'), get_test_data('short2.fa'),
+                    '-o', sigs)
+    output = runtmp.output('out.csv')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', sigs, sigs,
+                        '-o', output, '--scaled', '1')
+
+    assert 'was built with hash seed 43, but seed 42 was requested' in runtmp.last_result.err
+    assert runtmp.last_result.status == ERROR_CODES['SeedMismatch']
+
+    runtmp.sourmash('scripts', 'manysearch', sigs, sigs,
+                    '-o', output, '--scaled', '1', '--hash-seed', '43')
+    df = pandas.read_csv(output)
+    assert len(df) >= 2
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_hash_seed_query_against_differ(runtmp, indexed):
+    # query and search sketches must share the requested seed
+    query = runtmp.output('seed43.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,scaled=1,seed=43',
+                    get_test_data('short.fa'), '-o', query)
+    against = runtmp.output('seed42.zip')
+    runtmp.sourmash('sketch', 'dna', '-p', 'k=31,scaled=1',
+                    get_test_data('short.fa'), '-o', against)
+
+    if indexed:
+        against = index_siglist(runtmp, against, runtmp.output('db'), scaled=1)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query, against,
+                        '-o', runtmp.output('out.csv'), '--scaled', '1',
+                        '--hash-seed', '43')
+
+    assert "search sketch" in runtmp.last_result.err
+    assert 'was built with hash seed 42, but seed 43 was requested' in runtmp.last_result.err
+
+
+def test_unique_est_kmers(runtmp):
+    # unique k-mer estimates use n_hashes at the comparison scaled.
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--scaled', '2000')
+
+    expected = {}
+    for filename in (sig2, sig47, sig63):
+        ss = sourmash.load_one_signature(filename, ksize=31)
+        mh = ss.minhash.downsample(scaled=2000)
+        expected[ss.name] = len(mh) * 2000
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    for idx, row in df.iterrows():
+        assert row['query_unique_est_kmers'] == expected[row['query_name']]
+        assert row['match_unique_est_kmers'] == expected[row['match_name']]
+
+
+@pytest.mark.parametrize("variant", ["trailing_slash", "current_file", "parent_dir"])
+def test_indexed_path_variants(runtmp, capfd, variant):
+    # a RocksDB index is found with a trailing separator, from a file inside
+    # it, or from a directory holding just that index.
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    os.mkdir(runtmp.output('parent'))
+    db = index_siglist(runtmp, against_list, runtmp.output('parent/db'))
+    against = {
+        'trailing_slash': db + os.sep,
+        'current_file': os.path.join(db, 'CURRENT'),
+        'parent_dir': runtmp.output('parent'),
+    }[variant]
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against,
+                    '-o', output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    if variant == 'trailing_slash':
+        assert 'Using RocksDB index' not in captured.err
+    else:
+        assert f"Using RocksDB index '{db}' found at '{against}'." in captured.err
+
+
+def test_indexed_incomplete(runtmp, capfd):
+    # a copied index missing its CURRENT file is reported as such, rather
+    # than loaded as signatures.
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2, sig47])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+    os.unlink(os.path.join(db, 'CURRENT'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, db,
+                        '-o', runtmp.output('out.csv'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "holds RocksDB files but no CURRENT file" in captured.err
+
+
+def test_collapse_identical(runtmp):
+    # sketches with identical content are reported once, with match_aliases
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    copy_a = runtmp.output('copy_a.sig')
+    copy_b = runtmp.output('copy_b.sig')
+    runtmp.sourmash('sig', 'rename', sig47, 'copy a', '-o', copy_a)
+    runtmp.sourmash('sig', 'rename', sig47, 'copy b', '-o', copy_b)
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig2, sig47, copy_a, sig63, copy_b])
+
+    output = runtmp.output('out.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output)
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+    assert 'match_aliases' not in df.columns
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--collapse-identical')
+    assert "Collapsed 2 search sketches with identical content into 3 sketches." in runtmp.last_result.err
+
+    df = pandas.read_csv(output, keep_default_na=False)
+    assert len(df) == 2
+
+    rows = { row['match_md5']: row for row in df.to_dict(orient='records') }
+    ss47 = sourmash.load_one_signature(sig47, ksize=31)
+    row = rows.pop(ss47.md5sum())
+    names = [row['match_name']] + row['match_aliases'].split(';')
+    assert sorted(names) == sorted([ss47.name, 'copy a', 'copy b'])
+
+    # the match without copies has no aliases
+    (row,) = rows.values()
+    assert row['match_name'].startswith('NC_011665.1')
+    assert row['match_aliases'] == ''
+
+
+def test_collapse_identical_indexed(runtmp):
+    # --collapse-identical is not supported for RocksDB searches
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    make_file_list(query_list, [sig2])
+    make_file_list(against_list, [sig2])
+
+    db = index_siglist(runtmp, against_list, runtmp.output('db'))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, db,
+                        '-o', runtmp.output('out.csv'),
+                        '--collapse-identical')
+
+    assert '--collapse-identical is not supported when searching a rocksdb' in runtmp.last_result.err
+
+
+def test_anonymize_queries(runtmp):
+    # query names are replaced with salted hashes, with a private mapping
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output('out.csv')
+    plain = runtmp.output('plain.csv')
+    mapping = runtmp.output('mapping.csv')
+
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', plain)
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--anonymize-queries', mapping,
+                    '--anonymize-salt', 'pepper')
+
+    df = pandas.read_csv(output)
+    plain_df = pandas.read_csv(plain)
+    assert len(df) == len(plain_df)
+    assert df['query_name'].str.startswith('anon_').all()
+    assert not set(df['query_name']) & set(plain_df['query_name'])
+    # match names are left alone
+    assert set(df['match_name']) == set(plain_df['match_name'])
+
+    assert os.stat(mapping).st_mode & 0o777 == 0o600
+    mapping_df = pandas.read_csv(mapping)
+    assert list(mapping_df.columns) == ['original', 'anonymized']
+    to_original = dict(zip(mapping_df['anonymized'], mapping_df['original']))
+    assert sorted(df['query_name'].map(to_original)) == sorted(plain_df['query_name'])
+
+    # the same salt gives the same names
+    output2 = runtmp.output('out2.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output2, '--anonymize-queries', runtmp.output('mapping2.csv'),
+                    '--anonymize-salt', 'pepper')
+    assert set(pandas.read_csv(output2)['query_name']) == set(df['query_name'])
+
+    # and a different salt gives different names
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output2, '--anonymize-queries', runtmp.output('mapping2.csv'))
+    assert not set(pandas.read_csv(output2)['query_name']) & set(df['query_name'])
+
+
+def test_anonymize_queries_not_recorded(runtmp):
+    # the salt would undo anonymization, so isn't in the run summary
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('2.fa.sig.gz')])
+
+    summary = runtmp.output('run.json')
+    runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                    '-o', runtmp.output('out.csv'),
+                    '--anonymize-queries', runtmp.output('mapping.csv'),
+                    '--anonymize-salt', 'pepper', '--run-summary', summary)
+
+    with open(summary) as fp:
+        text = fp.read()
+    assert 'pepper' not in text
+    assert 'anonymize_salt' not in text
+
+
+def make_mixed_scaled_list(runtmp):
+    # 2 and 47 at scaled=1000, and 63 at scaled=2000
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    ss = sourmash.load_one_signature(sig63, ksize=31)
+    mh = ss.minhash.downsample(scaled=2000)
+    sig63_2000 = runtmp.output('63.scaled2000.sig')
+    with open(sig63_2000, 'wt') as fp:
+        sourmash.save_signatures([sourmash.SourmashSignature(mh, name=ss.name)], fp)
+
+    against_list = runtmp.output('against.txt')
+    make_file_list(against_list, [sig2, sig47, sig63_2000])
+    return against_list
+
+
+def test_mixed_scaled_harmonize(runtmp, capfd):
+    # by default, a collection with mixed scaled values is downsampled to
+    # the largest scaled, with a warning.
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz')])
+    against_list = make_mixed_scaled_list(runtmp)
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--scaled', '2000')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: search sketches have mixed scaled values (2 at scaled=1000, 1 at scaled=2000); downsampling all to scaled=2000." in captured.err
+    # the query collection has a single scaled
+    assert "query sketches have mixed scaled values" not in captured.err
+
+
+def test_mixed_scaled_error(runtmp, capfd):
+    # with --mixed-scaled error, the sketches with a smaller scaled are listed
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz')])
+    against_list = make_mixed_scaled_list(runtmp)
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                        '-o', output, '--scaled', '2000',
+                        '--mixed-scaled', 'error')
+
+    from sourmash_plugin_branchwater import ERROR_CODES
+    assert runtmp.last_result.status == ERROR_CODES['ScaledMismatch']
+    assert not os.path.exists(output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "These 2 sketches have a scaled below 2000:" in captured.err
+    assert "'CP001071.1 Akkermansia muciniphila ATCC BAA-835, complete genome' (scaled=1000)" in captured.err
+    assert "'NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence' (scaled=1000)" in captured.err
+    assert "'NC_011665.1" not in captured.err
+
+
+def test_mixed_scaled_error_single_scaled(runtmp):
+    # a collection downsampled as a whole isn't mixed
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz')])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz')])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--scaled', '2000',
+                    '--mixed-scaled', 'error')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+
+
+def test_output_format_parquet(runtmp):
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', csv_output)
+    pq_output = runtmp.output('out.parquet')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', pq_output, '--output-format', 'parquet')
+
+    key = ['query_name', 'match_name']
+    csv_df = pandas.read_csv(csv_output).sort_values(key).reset_index(drop=True)
+    pq_df = pandas.read_parquet(pq_output).sort_values(key).reset_index(drop=True)
+    print(pq_df.dtypes)
+    assert len(pq_df) == 5
+    assert pandas.api.types.is_unsigned_integer_dtype(pq_df['intersect_hashes'])
+    assert pandas.api.types.is_float_dtype(pq_df['containment'])
+    # columns that weren't calculated are in the schema, as nulls
+    assert 'moltype' in pq_df.columns
+    assert pq_df['moltype'].isna().all()
+    pandas.testing.assert_frame_equal(pq_df[csv_df.columns], csv_df,
+                                      check_dtype=False)
+
+
+def test_output_format_parquet_query_buckets(runtmp, capfd):
+    query_list = runtmp.output('query.txt')
+    sig47 = get_test_data('47.fa.sig.gz')
+    make_file_list(query_list, [sig47])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                        '-o', runtmp.output('out'), '--query-buckets', '2',
+                        '--output-format', 'parquet')
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert '--output-format parquet cannot be used with --query-buckets.' in captured.err
+
+
+def test_output_format_jsonl(runtmp):
+    # names with commas are written without any quoting
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+    renamed = runtmp.output('renamed.sig.gz')
+    runtmp.sourmash('sig', 'rename', sig47, 'sample, with "commas"',
+                    '-o', renamed)
+
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [renamed])
+    make_file_list(against_list, [sig47, sig63])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', csv_output)
+    output = runtmp.output('out.jsonl')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output, '--output-format', 'jsonl')
+
+    with open(output) as fp:
+        rows = [json.loads(line) for line in fp]
+    print(rows)
+    assert len(rows) == 2
+    csv_df = pandas.read_csv(csv_output)
+    for row in rows:
+        # fields are in CSV column order, with JSON types
+        assert list(row.keys()) == list(csv_df.columns)
+        assert row['query_name'] == 'sample, with "commas"'
+        assert isinstance(row['intersect_hashes'], int)
+        assert isinstance(row['containment'], float)
+
+    df = pandas.read_json(output, lines=True)
+    key = ['match_name']
+    pandas.testing.assert_frame_equal(
+        df.sort_values(key).reset_index(drop=True),
+        csv_df.sort_values(key).reset_index(drop=True),
+        check_dtype=False)
+
+
+def test_output_format_sqlite(runtmp, capfd):
+    # SQLite output has the same results as CSV, in a table named for the
+    # command, indexed on query_name and match_name
+    query_list = runtmp.output('query.txt')
+    against_list = runtmp.output('against.txt')
+    make_file_list(query_list, [get_test_data('47.fa.sig.gz')])
+    make_file_list(against_list, [get_test_data('2.fa.sig.gz'),
+                                  get_test_data('47.fa.sig.gz'),
+                                  get_test_data('63.fa.sig.gz')])
+
+    csv_output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', csv_output)
+    output = runtmp.output('results.db')
+    utils.run_sqlite_output(runtmp, capfd, 'scripts', 'manysearch',
+                            query_list, against_list, '-o', output)
+    # running the command again replaces its table
+    utils.run_sqlite_output(runtmp, capfd, 'scripts', 'manysearch',
+                            query_list, against_list, '-o', output)
+
+    with sqlite3.connect(output) as conn:
+        tables = conn.execute("SELECT name FROM sqlite_master WHERE type = 'table'").fetchall()
+        indexes = conn.execute("SELECT name FROM sqlite_master WHERE type = 'index'").fetchall()
+        df = pandas.read_sql('SELECT * FROM manysearch', conn)
+        types = dict(conn.execute("SELECT name, type FROM pragma_table_info('manysearch')").fetchall())
+    print(df)
+    assert tables == [('manysearch',)]
+    assert sorted(indexes) == [('manysearch_match_name',), ('manysearch_query_name',)]
+    assert types['query_name'] == 'TEXT'
+    assert types['intersect_hashes'] == 'INTEGER'
+    assert types['containment'] == 'REAL'
+
+    csv_df = pandas.read_csv(csv_output)
+    key = ['match_name']
+    pandas.testing.assert_frame_equal(
+        df[csv_df.columns].sort_values(key).reset_index(drop=True),
+        csv_df.sort_values(key).reset_index(drop=True),
+        check_dtype=False)
+
+
+def test_output_format_sqlite_gz(runtmp, capfd):
+    sig47 = get_test_data('47.fa.sig.gz')
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig47])
+
+    with pytest.raises(utils.SourmashCommandFailed) as exc:
+        utils.run_sqlite_output(runtmp, capfd, 'scripts', 'manysearch',
+                                query_list, query_list,
+                                '-o', runtmp.output('results.db.gz'))
+
+    print(exc.value.message)
+    assert "SQLite databases can't be compressed; remove '.gz'" in exc.value.message
+
+
+def test_output_format_jsonl_columns_gz(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    query_list = runtmp.output('query.txt')
+    make_file_list(query_list, [sig47])
+
+    output = runtmp.output('out.jsonl.gz')
+    runtmp.sourmash('scripts', 'manysearch', query_list, query_list,
+                    '-o', output, '--output-format', 'jsonl',
+                    '--columns', 'match_name,containment')
+
+    df = pandas.read_json(output, lines=True)
+    assert list(df.columns) == ['match_name', 'containment']
+    assert list(df['containment']) == [1.0]
@@ -0,0 +1,133 @@
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def load_hashes(filename):
+    mh = sourmash.load_one_signature(filename, ksize=31).minhash
+    return set(mh.downsample(scaled=1000).hashes)
+
+
+def load_output(filename):
+    sigs = sourmash.load_file_as_signatures(filename)
+    return { ss.name: set(ss.minhash.hashes) for ss in sigs }
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'setops')
+
+    assert 'usage:  setops' in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("op", ["union", "intersect", "subtract"])
+def test_setops_each_sketch(runtmp, op):
+    # each sketch in A is combined with the union of all sketches in B
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    a_list = runtmp.output('a.txt')
+    make_file_list(a_list, [sig2, sig47])
+    b_list = runtmp.output('b.txt')
+    make_file_list(b_list, [sig63])
+
+    output = runtmp.output('out.zip')
+
+    runtmp.sourmash('scripts', 'setops', a_list, b_list,
+                    '--op', op, '-o', output)
+    assert os.path.exists(output)
+
+    h2, h47, h63 = load_hashes(sig2), load_hashes(sig47), load_hashes(sig63)
+    if op == "union":
+        expected = {'2': h2 | h63, '47': h47 | h63}
+    elif op == "intersect":
+        expected = {'2': h2 & h63, '47': h47 & h63}
+    else:
+        expected = {'2': h2 - h63, '47': h47 - h63}
+
+    results = load_output(output)
+    assert len(results) == 2
+    for name, hashes in results.items():
+        key = '2' if name.startswith('CP001071.1') else '47'
+        assert hashes == expected[key]
+
+    # 47 and 63 overlap; 2 does not.
+    if op == "intersect":
+        assert 'WARNING: 1 output sketches have no hashes.' in runtmp.last_result.err
+
+
+def test_setops_merge(runtmp):
+    # with --merge, A is merged into a single sketch first
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    a_list = runtmp.output('a.txt')
+    make_file_list(a_list, [sig2, sig47])
+
+    output = runtmp.output('out.sig.gz')
+
+    runtmp.sourmash('scripts', 'setops', a_list, sig63,
+                    '--op', 'subtract', '--merge', '--name', 'leftovers',
+                    '-o', output)
+    assert os.path.exists(output)
+
+    h2, h47, h63 = load_hashes(sig2), load_hashes(sig47), load_hashes(sig63)
+
+    results = load_output(output)
+    assert list(results) == ['leftovers']
+    assert results['leftovers'] == (h2 | h47) - h63
+
+
+def test_setops_merge_default_name(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    output = runtmp.output('out.sig')
+
+    runtmp.sourmash('scripts', 'setops', sig47, sig63,
+                    '--op', 'intersect', '--merge', '-o', output)
+
+    results = load_output(output)
+    assert list(results) == [f'intersect of {sig47} and {sig63}']
+    assert results[f'intersect of {sig47} and {sig63}'] == load_hashes(sig47) & load_hashes(sig63)
+
+
+def test_setops_bad_op(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    output = runtmp.output('out.zip')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'setops', sig47, sig63,
+                        '--op', 'xor', '-o', output)
+
+    assert "invalid choice: 'xor'" in runtmp.last_result.err
+
+
+def test_setops_no_compatible_sketches(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    output = runtmp.output('out.zip')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'setops', sig47, sig63,
+                        '--op', 'union', '-k', '21', '-o', output)
+
+    assert not os.path.exists(output)
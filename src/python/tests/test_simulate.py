@@ -0,0 +1,161 @@
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+
+
+def write_spec(filename, rows):
+    with open(filename, 'wt') as fp:
+        fp.write("name,n_hashes,parent,containment,ani\n")
+        for row in rows:
+            fp.write(",".join(str(x) for x in row))
+            fp.write("\n")
+    return filename
+
+
+def load_by_name(filename):
+    sigs = sourmash.load_file_as_signatures(filename)
+    return { ss.name: ss for ss in sigs }
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'simulate')
+
+    assert 'usage:  simulate' in runtmp.last_result.err
+
+
+def test_simple(runtmp):
+    spec = write_spec(runtmp.output('spec.csv'),
+                      [('a', 1000, '', '', ''),
+                       ('b', 800, 'a', 0.5, ''),
+                       ('c', 500, '', '', '')])
+    output = runtmp.output('sims.zip')
+
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', output)
+    assert os.path.exists(output)
+
+    sigs = load_by_name(output)
+    assert set(sigs) == {'a', 'b', 'c'}
+
+    a, b, c = sigs['a'].minhash, sigs['b'].minhash, sigs['c'].minhash
+    assert len(a) == 1000
+    assert len(b) == 800
+    assert len(c) == 500
+    assert a.ksize == 31
+    assert a.scaled == 1000
+
+    # b takes half of its hashes from a; c shares nothing
+    assert b.count_common(a) == 400
+    assert b.contained_by(a) == pytest.approx(0.5)
+    assert c.count_common(a) == 0
+    assert c.count_common(b) == 0
+
+
+def test_ani_and_truth(runtmp):
+    spec = write_spec(runtmp.output('spec.csv'),
+                      [('a', 5000, '', '', ''),
+                       ('b', 5000, 'a', '', 0.99)])
+    output = runtmp.output('sims.sig.gz')
+    truth = runtmp.output('truth.csv')
+
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', output,
+                    '--output-truth', truth)
+
+    sigs = load_by_name(output)
+    df = pandas.read_csv(truth).set_index('name')
+    print(df)
+
+    expected_shared = round(0.99 ** 31 * 5000)
+    assert df.loc['b', 'n_shared'] == expected_shared
+    assert df.loc['b', 'parent'] == 'a'
+    assert df.loc['b', 'ani'] == pytest.approx(0.99, abs=0.001)
+    assert pandas.isna(df.loc['a', 'containment'])
+
+    assert df.loc['a', 'md5'] == sigs['a'].md5sum()
+    assert df.loc['b', 'md5'] == sigs['b'].md5sum()
+    assert sigs['b'].minhash.count_common(sigs['a'].minhash) == expected_shared
+
+
+def test_search_finds_simulated_overlap(runtmp):
+    # manysearch reports the containment the sketches were built with
+    spec = write_spec(runtmp.output('spec.csv'),
+                      [('genome', 2000, '', '', ''),
+                       ('half', 1000, 'genome', 0.5, ''),
+                       ('tenth', 1000, 'genome', 0.1, '')])
+    sims = runtmp.output('sims.zip')
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', sims)
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'manysearch', sims, sims, '-o', output,
+                    '-t', '0.05')
+
+    df = pandas.read_csv(output)
+    print(df)
+    rows = { (r['query_name'], r['match_name']): r
+             for r in df.to_dict(orient='records') }
+
+    assert rows[('half', 'genome')]['containment'] == pytest.approx(0.5)
+    assert rows[('tenth', 'genome')]['containment'] == pytest.approx(0.1)
+    assert ('half', 'tenth') not in rows or \
+        rows[('half', 'tenth')]['containment'] < 0.1
+
+
+def test_seed(runtmp):
+    spec = write_spec(runtmp.output('spec.csv'),
+                      [('a', 100, '', '', ''),
+                       ('b', 100, 'a', 0.3, '')])
+    out1 = runtmp.output('out1.zip')
+    out2 = runtmp.output('out2.zip')
+    out3 = runtmp.output('out3.zip')
+
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', out1, '--seed', '1')
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', out2, '--seed', '1')
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', out3, '--seed', '2')
+
+    s1, s2, s3 = load_by_name(out1), load_by_name(out2), load_by_name(out3)
+    assert s1['a'].md5sum() == s2['a'].md5sum()
+    assert s1['b'].md5sum() == s2['b'].md5sum()
+    assert s1['a'].md5sum() != s3['a'].md5sum()
+
+
+def test_protein(runtmp):
+    spec = write_spec(runtmp.output('spec.csv'),
+                      [('a', 200, '', '', ''),
+                       ('b', 200, 'a', 0.25, '')])
+    output = runtmp.output('sims.zip')
+
+    runtmp.sourmash('scripts', 'simulate', spec, '-o', output,
+                    '-m', 'protein', '-k', '10', '-s', '100')
+
+    sigs = load_by_name(output)
+    mh = sigs['a'].minhash
+    assert mh.moltype == 'protein'
+    assert mh.ksize == 10
+    assert mh.scaled == 100
+    assert sigs['b'].minhash.count_common(mh) == 50
+
+
+@pytest.mark.parametrize("rows,msg", [
+    ([('b', 100, 'a', 0.5, '')], "which is not defined above it"),
+    ([('a', 100, '', '', ''), ('b', 100, 'a', '', '')],
+     "needs exactly one of containment or ani"),
+    ([('a', 100, '', 0.5, '')], "has a containment or ani, but no parent"),
+    ([('a', 100, '', '', ''), ('b', 300, 'a', 0.5, '')],
+     "needs 150 hashes from parent 'a', which only has 100"),
+    ([('a', 100, '', '', ''), ('a', 100, '', '', '')],
+     "sketch name 'a' appears more than once"),
+    ([('a', 100, '', '', ''), ('b', 100, 'a', 1.5, '')],
+     "that is not between 0 and 1"),
+])
+def test_bad_spec(runtmp, rows, msg):
+    spec = write_spec(runtmp.output('spec.csv'), rows)
+    output = runtmp.output('sims.zip')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'simulate', spec, '-o', output)
+
+    print(runtmp.last_result.err)
+    assert msg in runtmp.last_result.err
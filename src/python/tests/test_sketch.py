@@ -800,3 +800,380 @@ def test_manysketch_prefix_duplicated_force(runtmp, capfd):
     print(sigs)
 
     assert len(sigs) == 3
+
+
+def test_manysketch_sanitize_names(runtmp):
+    # long names and names with control characters are sanitized
+    fa1 = get_test_data('short.fa')
+    fa3 = get_test_data('short3.fa')
+
+    fa_csv = runtmp.output('db-fa.csv')
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        fp.write('"a very long name",{},\n'.format(fa1))
+        fp.write('"two\tpart",{},\n'.format(fa3))
+
+    output = runtmp.output('db.zip')
+    modified = runtmp.output('modified.csv')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1",
+                    '--sanitize-names', '--max-name-length', '10',
+                    '--output-modified-names', modified)
+
+    idx = sourmash.load_file_as_index(output)
+    names = set(ss.name for ss in idx.signatures())
+    assert names == {'a very lon', 'two part'}
+
+    assert 'WARNING: sanitized 2 sketch names.' in runtmp.last_result.err
+
+    df = pandas.read_csv(modified)
+    print(df)
+    assert len(df) == 2
+    assert set(df['original_name']) == {'a very long name', 'two\tpart'}
+    assert set(df['sanitized_name']) == {'a very lon', 'two part'}
+
+
+def test_manysketch_sanitize_names_singleton(runtmp):
+    # record names are sanitized in singleton mode
+    fa_csv = runtmp.output('db-fa.csv')
+    fa3 = get_test_data('short3.fa')
+    make_assembly_csv(fa_csv, [fa3])
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1", '--singleton',
+                    '--sanitize-names', '--max-name-length', '5')
+
+    idx = sourmash.load_file_as_index(output)
+    names = set(ss.name for ss in idx.signatures())
+    assert names == {'first', 'other'}
+
+
+def test_manysketch_no_sanitize_names(runtmp):
+    # names are left alone by default
+    fa_csv = runtmp.output('db-fa.csv')
+    fa1 = get_test_data('short.fa')
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        fp.write('"a very long name",{},\n'.format(fa1))
+
+    output = runtmp.output('db.zip')
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1",
+                    '--max-name-length', '5')
+
+    idx = sourmash.load_file_as_index(output)
+    names = set(ss.name for ss in idx.signatures())
+    assert names == {'a very long name'}
+
+
+def test_manysketch_singleton_invalid_utf8(runtmp):
+    # record names with invalid UTF-8 are converted lossily, not a panic
+    fa = runtmp.output('bad-utf8.fa')
+    with open(fa, 'wb') as fp:
+        fp.write(b">bad\xff\xfename\nACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT\n")
+        fp.write(b">goodname\nTTTTACGTACGTACGTACGTACGTACGTACGTACGTACGT\n")
+
+    fa_csv = runtmp.output('db-fa.csv')
+    make_assembly_csv(fa_csv, [fa])
+
+    output = runtmp.output('db.zip')
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1", '--singleton')
+
+    idx = sourmash.load_file_as_index(output)
+    names = set(ss.name for ss in idx.signatures())
+    assert names == {'bad\ufffd\ufffdname', 'goodname'}
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "is not valid UTF-8; invalid bytes replaced" in err
+    assert "WARNING: 1 FASTA record names were not valid UTF-8." in err
+
+
+def test_manysketch_channel_capacity(runtmp):
+    # a small writer channel still writes all sketches
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    make_assembly_csv(fa_csv, [fa1, fa2, fa3])
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1", '--singleton',
+                    '--channel-capacity', '1')
+
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx) == 4
+
+
+
+def test_manysketch_output_template(runtmp):
+    # route each parameter set to its own zip file
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+    protfa1 = get_test_data('short-protein.fa')
+
+    make_assembly_csv(fa_csv, [fa1, fa2, fa3], [protfa1])
+
+    output = runtmp.output('db.{moltype}.k{ksize}.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=21,k=31,scaled=1",
+                    '--param-str', "protein,k=10,scaled=1")
+
+    assert not os.path.exists(output)
+
+    expected = {'db.dna.k21.zip': (3, 21, True),
+                'db.dna.k31.zip': (3, 31, True),
+                'db.protein.k10.zip': (1, 10, False)}
+    for filename, (n_sigs, ksize, is_dna) in expected.items():
+        path = runtmp.output(filename)
+        assert os.path.exists(path)
+        assert f"Wrote {n_sigs} sketches to '{path}'" in runtmp.last_result.err
+
+        idx = sourmash.load_file_as_index(path)
+        sigs = list(idx.signatures())
+        assert len(sigs) == n_sigs
+        for sig in sigs:
+            assert sig.minhash.ksize == ksize
+            assert sig.minhash.is_dna == is_dna
+
+        # each zip has its own manifest
+        mf = idx.manifest
+        assert len(mf) == n_sigs
+
+
+def test_manysketch_output_template_scaled(runtmp):
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+
+    make_assembly_csv(fa_csv, [fa1, fa2])
+
+    output = runtmp.output('db.s{scaled}.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1",
+                    '--param-str', "dna,k=31,scaled=10")
+
+    for scaled in [1, 10]:
+        idx = sourmash.load_file_as_index(runtmp.output(f'db.s{scaled}.zip'))
+        sigs = list(idx.signatures())
+        assert len(sigs) == 2
+        assert {sig.minhash.scaled for sig in sigs} == {scaled}
+
+
+def test_manysketch_output_template_bad_placeholder(runtmp):
+    fa_csv = runtmp.output('db-fa.csv')
+    make_assembly_csv(fa_csv, [get_test_data('short.fa')])
+
+    output = runtmp.output('db.{name}.zip')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                        '--param-str', "dna,k=31,scaled=1")
+
+    assert "unknown placeholder '{name}' in output template" in runtmp.last_result.err
+
+
+def test_sketch_records(runtmp):
+    # sketching in-memory sequences matches singleton sketches of a FASTA
+    import screed
+    from sourmash_plugin_branchwater import sketch_records
+
+    fa3 = get_test_data('short3.fa')
+    records = [ (r.name, r.sequence, 'dna') for r in screed.open(fa3) ]
+
+    sigs = sketch_records(records, "dna,k=31,scaled=1")
+    assert [ sig.name for sig in sigs ] == ['firstname', 'other']
+
+    singleton_sketch = runtmp.output('short3.sig')
+    runtmp.sourmash('sketch', 'dna', fa3, '-o', singleton_sketch,
+                    '--param-str', "dna,k=31,scaled=1", "--singleton")
+    expected = list(sourmash.load_signatures(singleton_sketch))
+
+    for sig, ss in zip(sigs, expected):
+        assert sig.minhash == ss.minhash
+
+
+def test_sketch_records_mult_moltype(runtmp):
+    # each record is sketched with the parameters for its moltype
+    import screed
+    from sourmash_plugin_branchwater import sketch_records
+
+    dna = [ (r.name, r.sequence, 'dna')
+            for r in screed.open(get_test_data('short.fa')) ]
+    prot = [ (r.name, r.sequence.encode('utf-8'), 'protein')
+             for r in screed.open(get_test_data('short-protein.fa')) ]
+
+    sigs = sketch_records(dna + prot,
+                          "dna,k=21,k=31,scaled=1_protein,k=10,scaled=1")
+    assert len(sigs) == 2 * len(dna) + len(prot)
+
+    for sig in sigs:
+        if sig.minhash.moltype == 'protein':
+            assert sig.minhash.ksize == 10
+        else:
+            assert sig.minhash.ksize in (21, 31)
+
+
+def test_sketch_records_no_compatible(runtmp):
+    from sourmash_plugin_branchwater import sketch_records
+
+    with pytest.raises(Exception):
+        sketch_records([('seq1', 'ACGTACGT', 'dna')], "protein,k=10,scaled=1")
+
+
+def test_sketch_records_long_sequence(runtmp):
+    # long sequences are added to several sketches in parallel; the
+    # sketches are the same as when built one parameter set at a time
+    import random
+    from sourmash_plugin_branchwater import sketch_records
+
+    rng = random.Random(42)
+    seq = ''.join(rng.choice('ACGT') for _ in range(200_000))
+    records = [('long', seq, 'dna')]
+
+    sigs = sketch_records(records, "dna,k=21,k=31,k=51,scaled=10")
+    assert len(sigs) == 3
+
+    for sig in sigs:
+        ksize = sig.minhash.ksize
+        expected = sketch_records(records, f"dna,k={ksize},scaled=10")
+        assert len(expected) == 1
+        assert sig.minhash == expected[0].minhash
+
+
+def test_manysketch_dry_run(runtmp):
+    # --dry-run reports what would be sketched, and writes no output
+    fa_csv = runtmp.output('db-fa.txt')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    make_assembly_csv(fa_csv, [fa1, fa2, fa3])
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=21,k=31,scaled=1", '--dry-run')
+
+    assert not os.path.exists(output)
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "Dry run of 'manysketch'; nothing will be computed." in err
+    assert "parameter sets: 2" in err
+    assert "sketches to build: 6" in err
+
+
+def test_manysketch_progress_not_a_terminal(runtmp, capfd):
+    # --progress falls back to the usual progress lines when stderr is not a terminal
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    make_reads_csv(fa_csv, [("short", fa1, fa2), ('short3', fa3, '')])
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1", '--progress')
+
+    assert os.path.exists(output)
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Starting file 3/3 (100%)" in captured.err
+    assert "DONE. Processed 3 fasta files" in captured.err
+
+
+def test_manysketch_row_param_string(runtmp):
+    # a 'param_string' column overrides --param-str for that row
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+    fa3 = get_test_data('short3.fa')
+
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename,param_string\n")
+        fp.write(f"short,{fa1},,\n")
+        fp.write(f"short2,{fa2},,\"dna,k=21,scaled=100\"\n")
+        fp.write(f"short3,{fa3},,\n")
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1")
+
+    assert os.path.exists(output)
+
+    idx = sourmash.load_file_as_index(output)
+    sigs = { sig.name: sig.minhash for sig in idx.signatures() }
+    assert len(sigs) == 3
+
+    assert (sigs['short'].ksize, sigs['short'].scaled) == (31, 1)
+    assert (sigs['short2'].ksize, sigs['short2'].scaled) == (21, 100)
+    assert (sigs['short3'].ksize, sigs['short3'].scaled) == (31, 1)
+
+
+def test_manysketch_row_param_string_dry_run(runtmp):
+    # --dry-run counts the sketches from each row's own parameters
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,read1,read2,param_string\n")
+        fp.write(f"short,{fa1},,\n")
+        fp.write(f"short2,{fa2},,\"k=21,k=31,k=51,scaled=100\"\n")
+
+    output = runtmp.output('db.zip')
+
+    runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                    '--param-str', "dna,k=31,scaled=1", '--dry-run')
+
+    assert not os.path.exists(output)
+
+    err = runtmp.last_result.err
+    print(err)
+    assert "1 inputs override the parameters with 'param_string'" in err
+    assert "sketches to build: 4" in err
+
+
+def test_manysketch_row_param_string_bad(runtmp, capfd):
+    # an invalid 'param_string' fails before anything is sketched
+    fa_csv = runtmp.output('db-fa.csv')
+
+    fa1 = get_test_data('short.fa')
+    fa2 = get_test_data('short2.fa')
+
+    with open(fa_csv, 'wt') as fp:
+        fp.write("name,genome_filename,protein_filename,param_string\n")
+        fp.write(f"short,{fa1},,\n")
+        fp.write(f"short2,{fa2},,\"k=31,scaled=100,foo\"\n")
+
+    output = runtmp.output('db.zip')
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'manysketch', fa_csv, '-o', output,
+                        '--param-str', "dna,k=31,scaled=1")
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Error parsing param_string 'k=31,scaled=100,foo' for 'short2': unknown component 'foo' in params string" in captured.err
@@ -0,0 +1,156 @@
+import os
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def make_timepoints(filename, timepoints):
+    with open(filename, 'wt') as fp:
+        fp.write("timepoint,path\n")
+        for timepoint, path in timepoints:
+            fp.write(f"{timepoint},{path}\n")
+
+
+def run_manysearch(runtmp, name, queries, against):
+    query_list = runtmp.output(f'{name}.query.txt')
+    against_list = runtmp.output(f'{name}.against.txt')
+    make_file_list(query_list, queries)
+    make_file_list(against_list, against)
+
+    output = runtmp.output(f'{name}.csv')
+    runtmp.sourmash('scripts', 'manysearch', query_list, against_list,
+                    '-o', output)
+    return output
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'timeseries')
+
+    assert 'usage:  timeseries' in runtmp.last_result.err
+
+
+def test_simple(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    # 63 is only found at the first timepoint
+    day1 = run_manysearch(runtmp, 'day1', [sig47], [sig47, sig63])
+    day2 = run_manysearch(runtmp, 'day2', [sig47], [sig47])
+
+    timepoints = runtmp.output('timepoints.csv')
+    make_timepoints(timepoints, [('day1', day1), ('day2', day2)])
+
+    output = runtmp.output('timeseries.csv')
+    runtmp.sourmash('scripts', 'timeseries', timepoints, '-o', output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ['query_name', 'match_name', 'timepoint',
+                                'timepoint_index', 'present', 'containment',
+                                'intersect_hashes', 'query_containment_ani']
+    assert len(df) == 4
+
+    day1_df = pandas.read_csv(day1)
+    for _, row in df.iterrows():
+        assert row['query_name'].startswith('NC_009661.1')
+        if row['match_name'].startswith('NC_009661.1'):
+            # self-match, found at both timepoints
+            assert row['present']
+            assert row['containment'] == 1.0
+            assert row['query_containment_ani'] == 1.0
+        elif row['timepoint'] == 'day1':
+            assert row['present']
+            expected = day1_df[day1_df['match_name'] == row['match_name']]
+            assert row['containment'] == pytest.approx(expected['containment'].iloc[0])
+            assert row['intersect_hashes'] == expected['intersect_hashes'].iloc[0]
+        else:
+            # not found at day2
+            assert row['timepoint'] == 'day2'
+            assert not row['present']
+            assert row['containment'] == 0.0
+            assert row['intersect_hashes'] == 0
+            assert pandas.isna(row['query_containment_ani'])
+
+    # each series is in timepoint order
+    for _, series in df.groupby(['query_name', 'match_name'], sort=False):
+        assert list(series['timepoint']) == ['day1', 'day2']
+        assert list(series['timepoint_index']) == [0, 1]
+
+
+def test_present_only(runtmp):
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig63 = get_test_data('63.fa.sig.gz')
+
+    day1 = run_manysearch(runtmp, 'day1', [sig47], [sig47, sig63])
+    day2 = run_manysearch(runtmp, 'day2', [sig47], [sig47])
+
+    timepoints = runtmp.output('timepoints.csv')
+    make_timepoints(timepoints, [('day1', day1), ('day2', day2)])
+
+    output = runtmp.output('timeseries.csv.gz')
+    runtmp.sourmash('scripts', 'timeseries', timepoints, '-o', output,
+                    '--present-only')
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+    assert df['present'].all()
+
+
+def test_duplicate_timepoint(runtmp, capfd):
+    sig47 = get_test_data('47.fa.sig.gz')
+    day1 = run_manysearch(runtmp, 'day1', [sig47], [sig47])
+
+    timepoints = runtmp.output('timepoints.csv')
+    make_timepoints(timepoints, [('day1', day1), ('day1', day1)])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'timeseries', timepoints,
+                        '-o', runtmp.output('timeseries.csv'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "timepoint 'day1' is listed more than once" in captured.err
+
+
+def test_missing_column(runtmp, capfd):
+    not_search = runtmp.output('not_search.csv')
+    with open(not_search, 'wt') as fp:
+        fp.write("query_name,match_name\nA,B\n")
+
+    timepoints = runtmp.output('timepoints.csv')
+    make_timepoints(timepoints, [('day1', not_search)])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'timeseries', timepoints,
+                        '-o', runtmp.output('timeseries.csv'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert f"search output '{not_search}' has no 'containment' column" in captured.err
+
+
+def test_bad_timepoints_csv(runtmp, capfd):
+    timepoints = runtmp.output('timepoints.csv')
+    with open(timepoints, 'wt') as fp:
+        fp.write("time,file\nday1,day1.csv\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'timeseries', timepoints,
+                        '-o', runtmp.output('timeseries.csv'))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "has no 'timepoint' column" in captured.err
@@ -0,0 +1,136 @@
+import os
+import csv
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+
+
+def get_test_data(filename):
+    thisdir = os.path.dirname(__file__)
+    return os.path.join(thisdir, 'test-data', filename)
+
+
+def make_file_list(filename, paths):
+    with open(filename, 'wt') as fp:
+        fp.write("\n".join(paths))
+        fp.write("\n")
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'weighted_containment')
+
+    assert 'usage:  weighted_containment' in runtmp.last_result.err
+
+
+def test_weighted_containment_values(runtmp):
+    # check weighted containment in both directions against values
+    # calculated here
+    hashes = utils.get_hashes()[:30]
+    a = { h: i % 5 + 1 for i, h in enumerate(hashes[:20]) }
+    b = { h: i % 3 + 1 for i, h in enumerate(hashes[10:]) }
+
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a', a)
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b', b)
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'weighted_containment', sig_list, '-o', output)
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 1
+    assert list(df.columns) == ['query_name', 'query_md5', 'match_name',
+                                'match_md5', 'query_weighted_containment',
+                                'match_weighted_containment',
+                                'max_weighted_containment',
+                                'intersect_hashes', 'query_sum_abunds',
+                                'match_sum_abunds']
+
+    row = df.iloc[0]
+    shared = set(a) & set(b)
+    in_other = {
+        'a': sum(a[h] for h in shared) / sum(a.values()),
+        'b': sum(b[h] for h in shared) / sum(b.values()),
+    }
+
+    assert row['intersect_hashes'] == 10
+    assert row['query_weighted_containment'] == pytest.approx(in_other[row['query_name']])
+    assert row['match_weighted_containment'] == pytest.approx(in_other[row['match_name']])
+    assert row['max_weighted_containment'] == pytest.approx(max(in_other.values()))
+
+
+def test_weighted_containment_abundance_matters(runtmp):
+    # 'b' shares half of its hashes with 'a', but they hold most of its
+    # abundance; flat containment would be 0.5.
+    hashes = utils.get_hashes()[:20]
+    a = { h: 1 for h in hashes[:10] }
+    b = { h: 9 for h in hashes[5:10] }
+    b.update({ h: 1 for h in hashes[10:15] })
+
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a', a)
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b', b)
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'weighted_containment', sig_list, '-o', output)
+
+    df = pandas.read_csv(output)
+    row = df.iloc[0]
+    in_other = {
+        row['query_name']: row['query_weighted_containment'],
+        row['match_name']: row['match_weighted_containment'],
+    }
+    assert in_other['a'] == pytest.approx(0.5)
+    assert in_other['b'] == pytest.approx(0.9)
+
+
+def test_weighted_containment_threshold(runtmp):
+    # pairs with no shared hashes, or below the threshold, are not written
+    hashes = utils.get_hashes()[:30]
+    a = { h: 2 for h in hashes[:10] }
+    a2 = { h: 2 for h in hashes[:10] }
+    a2.update({ h: 1 for h in hashes[20:30] })
+    b = { h: 3 for h in hashes[10:20] }
+
+    sig_a = utils.make_abund_sig(runtmp.output('a.sig'), 'a', a)
+    sig_a2 = utils.make_abund_sig(runtmp.output('a2.sig'), 'a2', a2)
+    sig_b = utils.make_abund_sig(runtmp.output('b.sig'), 'b', b)
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig_a, sig_a2, sig_b])
+
+    output = runtmp.output('out.csv')
+    runtmp.sourmash('scripts', 'weighted_containment', sig_list, '-o', output)
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 1
+    assert {df['query_name'][0], df['match_name'][0]} == {'a', 'a2'}
+    assert df['max_weighted_containment'][0] == pytest.approx(1.0)
+
+    # 'a' is entirely in 'a2', so a threshold of 1.0 excludes the pair
+    runtmp.sourmash('scripts', 'weighted_containment', sig_list, '-o', output,
+                    '-t', '1.0')
+    with open(output, newline='') as fp:
+        rows = list(csv.DictReader(fp))
+    assert len(rows) == 0
+
+
+def test_weighted_containment_flat_sketches(runtmp):
+    # sketches without abundances are an error
+    sig2 = get_test_data('2.fa.sig.gz')
+    sig47 = get_test_data('47.fa.sig.gz')
+    sig_list = runtmp.output('sigs.txt')
+    make_file_list(sig_list, [sig2, sig47])
+
+    output = runtmp.output('out.csv')
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash('scripts', 'weighted_containment', sig_list, '-o', output)
+
+    print(runtmp.last_result.err)
+    assert 'requires abundance-tracking sketches' in runtmp.last_result.err
+    assert not os.path.exists(output)
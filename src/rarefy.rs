@@ -0,0 +1,198 @@
+/// rarefy: how do search matches change as queries are subsampled?
+///
+/// Note: this function loads all _queries_ into memory, and iterates over
+/// database once.
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use crate::errors::BranchwaterError;
+use crate::utils::{
+    csvwriter_thread_with_extras, load_collection, load_sketches, splitmix64, OutputOptions,
+    Policies, RarefactionResult, ReportType, WriterExtras,
+};
+use sourmash::selection::Selection;
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+/// A subsample of one query sketch at one fraction and replicate.
+struct Subsample {
+    query_name: String,
+    query_md5: String,
+    fraction: f64,
+    replicate: usize,
+    minhash: KmerMinHash,
+    n_matches: AtomicUsize,
+}
+
+/// Subsample 'mh' to 'fraction' of its underlying reads: a hash seen
+/// 'abund' times is kept with probability 1 - (1 - fraction)^abund, and
+/// flat sketches are treated as abundance 1. Within a replicate, each hash
+/// gets the same draw at every fraction, so subsamples are nested.
+fn subsample(mh: &KmerMinHash, fraction: f64, seed: u64, replicate: usize) -> KmerMinHash {
+    let mut sub = mh.clone();
+    sub.clear();
+
+    let replicate_seed = splitmix64(seed ^ splitmix64(replicate as u64));
+    for (hash, abund) in mh.to_vec_abunds() {
+        let draw = (splitmix64(replicate_seed ^ hash) >> 11) as f64 / (1u64 << 53) as f64;
+        let keep_prob = 1.0 - (1.0 - fraction).powi(abund.min(i32::MAX as u64) as i32);
+        if draw < keep_prob {
+            if sub.track_abundance() {
+                sub.add_hash_with_abundance(hash, abund);
+            } else {
+                sub.add_hash(hash);
+            }
+        }
+    }
+    sub
+}
+
+/// Subsample each query in 'query_filepath' at each of 'fractions',
+/// 'replicates' times, and count the sketches in 'against_filepath' that
+/// each subsample matches with containment above 'threshold'.
+#[allow(clippy::too_many_arguments)]
+pub fn rarefy(
+    query_filepath: String,
+    against_filepath: String,
+    selection: &Selection,
+    threshold: f64,
+    fractions: Vec<f64>,
+    replicates: usize,
+    seed: u64,
+    output: Vec<String>,
+    policies: &Policies,
+    columns: Option<Vec<String>>,
+    options: &OutputOptions,
+) -> Result<()> {
+    if fractions.is_empty() {
+        bail!("no subsampling fractions given.");
+    }
+    if let Some(f) = fractions.iter().find(|f| !(**f > 0.0 && **f <= 1.0)) {
+        bail!("subsampling fraction {} is not between 0 and 1.", f);
+    }
+    if replicates == 0 {
+        bail!("the number of replicates must be at least 1.");
+    }
+
+    // Load all queries into memory at once, downsampling on the way.
+    let query_collection =
+        load_collection(&query_filepath, selection, ReportType::Query, policies)?;
+    let queries = load_sketches(query_collection, selection, ReportType::Query)?;
+
+    // a fraction of 1 keeps every hash, so only needs one replicate.
+    let mut subsamples = vec![];
+    for query in queries.iter() {
+        for fraction in fractions.iter() {
+            let n_reps = if *fraction >= 1.0 { 1 } else { replicates };
+            for replicate in 0..n_reps {
+                subsamples.push(Subsample {
+                    query_name: query.name.clone(),
+                    query_md5: query.md5sum.clone(),
+                    fraction: *fraction,
+                    replicate,
+                    minhash: subsample(&query.minhash, *fraction, seed, replicate),
+                    n_matches: AtomicUsize::new(0),
+                });
+            }
+        }
+    }
+    eprintln!(
+        "Built {} subsamples of {} queries.",
+        subsamples.len(),
+        queries.len()
+    );
+
+    // Against: Load all _paths_, not signatures, into memory.
+    let against_collection =
+        load_collection(&against_filepath, selection, ReportType::Against, policies)?;
+
+    let processed_sigs = AtomicUsize::new(0);
+    let skipped_paths = AtomicUsize::new(0);
+
+    against_collection.par_iter().for_each(|(_idx, record)| {
+        let i = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+        if i % 1000 == 0 && i > 0 {
+            eprintln!("Processed {} search sigs", i);
+        }
+
+        // against downsampling happens here
+        match against_collection.sig_from_record(record) {
+            Ok(against_sig) => {
+                if let Some(against_mh) = against_sig.minhash() {
+                    for sub in subsamples.iter() {
+                        let query_size = sub.minhash.size() as f64;
+                        if query_size == 0.0 {
+                            continue;
+                        }
+                        let overlap = sub.minhash.count_common(against_mh, true).unwrap() as f64;
+                        if overlap / query_size > threshold {
+                            sub.n_matches.fetch_add(1, atomic::Ordering::SeqCst);
+                        }
+                    }
+                } else {
+                    eprintln!(
+                        "WARNING: no compatible sketches in path '{}'",
+                        record.internal_location()
+                    );
+                    let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+            }
+            Err(err) => {
+                eprintln!("Sketch loading error: {}", err);
+                eprintln!(
+                    "WARNING: no compatible sketches in path '{}'",
+                    record.internal_location()
+                );
+                let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+        }
+    });
+
+    // write one row per subsample, in query/fraction/replicate order.
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<RarefactionResult>(rayon::current_num_threads());
+    let extras = WriterExtras {
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, 0, extras);
+
+    for sub in subsamples.into_iter() {
+        let result = RarefactionResult {
+            query_name: sub.query_name,
+            query_md5: sub.query_md5,
+            fraction: sub.fraction,
+            replicate: sub.replicate,
+            query_n_hashes: sub.minhash.size(),
+            n_matches: sub.n_matches.into_inner(),
+        };
+        if let Err(e) = send.send(result) {
+            eprintln!("Unable to send internal data: {:?}", e);
+            break;
+        }
+    }
+    drop(send);
+
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+
+    // done!
+    let i: usize = processed_sigs.into_inner();
+    eprintln!("DONE. Processed {} search sigs", i);
+
+    let skipped_paths = skipped_paths.into_inner();
+    if skipped_paths > 0 {
+        eprintln!(
+            "WARNING: skipped {} search paths - no compatible signatures.",
+            skipped_paths
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+/// report: a self-contained HTML summary of a run (`fastmultigather
+/// --report`), with the run parameters and the top matches and
+/// composition of each query, for sharing with collaborators without a
+/// notebook step.
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+use crate::biom::utc_timestamp;
+use crate::schema::{field_value, serialize_fields};
+use crate::utils::OutputOptions;
+
+/// The matches listed, and drawn separately in the composition bar, for
+/// each query; the rest are summed as 'other matches'.
+const TOP_MATCHES: usize = 10;
+
+/// The HTML page; the report data replaces `REPORT_DATA` as JSON, and is
+/// rendered by the page's own script, with no external resources.
+const TEMPLATE: &str = include_str!("report.html");
+
+#[derive(Serialize, Clone)]
+struct ReportMatch {
+    rank: usize,
+    name: String,
+    f_unique_weighted: f64,
+    f_unique_to_query: f64,
+    intersect_bp: u64,
+}
+
+#[derive(Serialize, Default)]
+struct QuerySummary {
+    name: String,
+    md5: String,
+    n_matches: usize,
+    /// the summed f_unique_weighted of all matches.
+    f_weighted_found: f64,
+    /// the first TOP_MATCHES matches, by gather rank.
+    top: Vec<ReportMatch>,
+}
+
+/// A summary of each query's gather results, built as results are
+/// written, from any number of threads.
+pub struct RunReport {
+    output: String,
+    params: Vec<(String, String)>,
+    options: OutputOptions,
+    queries: Mutex<BTreeMap<(String, String), QuerySummary>>,
+}
+
+impl RunReport {
+    /// Start a report to be written to 'output', listing 'params' as the
+    /// run parameters, with results written as given in 'options'.
+    pub fn new(output: String, params: Vec<(String, String)>, options: OutputOptions) -> Self {
+        RunReport {
+            output,
+            params,
+            options,
+            queries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record a query, so that it is listed even if it has no matches.
+    pub fn add_query(&self, name: &str, md5: &str) {
+        let name = self.options.anonymize_query(name);
+        let mut queries = self.queries.lock().unwrap();
+        queries
+            .entry((name.clone(), md5.to_string()))
+            .or_insert_with(|| QuerySummary {
+                name,
+                md5: md5.to_string(),
+                ..QuerySummary::default()
+            });
+    }
+
+    /// Add one gather result.
+    pub fn add<T: Serialize>(&self, item: &T) -> Result<()> {
+        let mut fields = serialize_fields(item, self.options.float_precision)?;
+        self.options.anonymize_fields(&mut fields);
+        let column = |name: &str| {
+            field_value(&fields, name)
+                .ok_or_else(|| anyhow!("results have no {} column for the report", name))
+        };
+
+        let query_name = column("query_name")?;
+        let query_md5 = column("query_md5")?;
+        let match_name = column("match_name")?;
+
+        let mut queries = self.queries.lock().unwrap();
+        let query = queries
+            .entry((query_name.to_string(), query_md5.to_string()))
+            .or_insert_with(|| QuerySummary {
+                name: query_name.to_string(),
+                md5: query_md5.to_string(),
+                ..QuerySummary::default()
+            });
+        // placeholder rows for queries with no matches only add the query
+        if match_name.is_empty() {
+            return Ok(());
+        }
+
+        let f_unique_weighted: f64 = column("f_unique_weighted")?.parse()?;
+        query.n_matches += 1;
+        query.f_weighted_found += f_unique_weighted;
+        query.top.push(ReportMatch {
+            rank: column("gather_result_rank")?.parse()?,
+            name: match_name.to_string(),
+            f_unique_weighted,
+            f_unique_to_query: column("f_unique_to_query")?.parse()?,
+            intersect_bp: column("intersect_bp")?.parse()?,
+        });
+        query.top.sort_by_key(|m| m.rank);
+        query.top.truncate(TOP_MATCHES);
+        Ok(())
+    }
+
+    pub fn write(self) -> Result<()> {
+        let queries: Vec<QuerySummary> = self.queries.into_inner().unwrap().into_values().collect();
+        let n_queries = queries.len();
+        let data = serde_json::json!({
+            "generated_by": format!("sourmash_plugin_branchwater {}", env!("CARGO_PKG_VERSION")),
+            "date": utc_timestamp(),
+            "params": self.params,
+            "top_matches": TOP_MATCHES,
+            "queries": queries,
+        });
+        // the data is embedded in a <script> element, which must not be
+        // closed early by a name containing '</script>'.
+        let data = serde_json::to_string(&data)?.replace("</", "<\\/");
+        let html = TEMPLATE.replace("REPORT_DATA", &data);
+
+        let file = File::create(&self.output)
+            .with_context(|| format!("Failed to create report '{}'", self.output))?;
+        let mut w = BufWriter::new(file);
+        w.write_all(html.as_bytes())?;
+        w.flush()?;
+        eprintln!(
+            "Wrote a report on {} queries to '{}'",
+            n_queries, self.output
+        );
+        Ok(())
+    }
+}
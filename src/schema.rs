@@ -0,0 +1,383 @@
+/// schema: the field names and types of each result struct, defined once
+/// in Rust and exported to Python for typed result dataclasses and `.pyi`
+/// stubs (see `sourmash_plugin_branchwater.results`).
+use serde::ser::{self, Impossible, SerializeStruct};
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+
+use crate::utils::{
+    round_float, BetaDiversityResult, BranchwaterGatherResult, BranchwaterPrefetchResult,
+    MultiSearchResult, NumSearchResult, RarefactionResult, ScreenResult, SearchResult,
+    TimeSeriesResult, WeightedContainmentResult,
+};
+
+/// The columns of a result struct, in output order.
+pub trait ResultSchema {
+    const NAME: &'static str;
+
+    /// (field name, Rust type) for each field.
+    fn fields() -> Vec<(&'static str, &'static str)>;
+}
+
+/// The fields of a serialized result, as (name, value) in output order.
+/// Fields skipped when serializing, i.e. results that weren't calculated,
+/// are left out.
+pub type ResultFields = Vec<(&'static str, String)>;
+
+/// Serialize the fields of the result struct 'item' to strings, formatted
+/// as the csv crate writes them, without writing a CSV row. Floats are
+/// rounded to 'float_precision' decimal places, if given.
+pub fn serialize_fields<T: Serialize>(
+    item: &T,
+    float_precision: Option<usize>,
+) -> anyhow::Result<ResultFields> {
+    Ok(item.serialize(FieldsSerializer { float_precision })?)
+}
+
+/// The value of field 'name' in 'fields', if it was serialized.
+pub fn field_value<'a>(fields: &'a ResultFields, name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, value)| value.as_str())
+}
+
+#[derive(Debug)]
+pub struct FieldError(String);
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to serialize result: {}", self.0)
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl ser::Error for FieldError {
+    fn custom<M: Display>(msg: M) -> Self {
+        FieldError(msg.to_string())
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, FieldError> {
+    Err(FieldError(format!("unsupported {} in a result", what)))
+}
+
+/// Implement the `Serializer` methods that a serializer doesn't support.
+macro_rules! unsupported_methods {
+    ($($method:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<$ret, FieldError> {
+                $(let _ = $arg;)*
+                unsupported(stringify!($method))
+            }
+        )*
+    };
+}
+
+/// Serializes a result struct to its `ResultFields`.
+struct FieldsSerializer {
+    float_precision: Option<usize>,
+}
+
+/// Collects the fields of a result struct.
+struct FieldsCollector {
+    fields: ResultFields,
+    float_precision: Option<usize>,
+}
+
+impl Serializer for FieldsSerializer {
+    type Ok = ResultFields;
+    type Error = FieldError;
+    type SerializeSeq = Impossible<ResultFields, FieldError>;
+    type SerializeTuple = Impossible<ResultFields, FieldError>;
+    type SerializeTupleStruct = Impossible<ResultFields, FieldError>;
+    type SerializeTupleVariant = Impossible<ResultFields, FieldError>;
+    type SerializeMap = Impossible<ResultFields, FieldError>;
+    type SerializeStruct = FieldsCollector;
+    type SerializeStructVariant = Impossible<ResultFields, FieldError>;
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<FieldsCollector, FieldError> {
+        Ok(FieldsCollector {
+            fields: Vec::with_capacity(len),
+            float_precision: self.float_precision,
+        })
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<ResultFields, FieldError> {
+        unsupported("serialize_some")
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<ResultFields, FieldError> {
+        unsupported("serialize_newtype_struct")
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<ResultFields, FieldError> {
+        unsupported("serialize_newtype_variant")
+    }
+
+    unsupported_methods! {
+        serialize_bool(v: bool) -> ResultFields;
+        serialize_i8(v: i8) -> ResultFields;
+        serialize_i16(v: i16) -> ResultFields;
+        serialize_i32(v: i32) -> ResultFields;
+        serialize_i64(v: i64) -> ResultFields;
+        serialize_u8(v: u8) -> ResultFields;
+        serialize_u16(v: u16) -> ResultFields;
+        serialize_u32(v: u32) -> ResultFields;
+        serialize_u64(v: u64) -> ResultFields;
+        serialize_f32(v: f32) -> ResultFields;
+        serialize_f64(v: f64) -> ResultFields;
+        serialize_char(v: char) -> ResultFields;
+        serialize_str(v: &str) -> ResultFields;
+        serialize_bytes(v: &[u8]) -> ResultFields;
+        serialize_none() -> ResultFields;
+        serialize_unit() -> ResultFields;
+        serialize_unit_struct(name: &'static str) -> ResultFields;
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str) -> ResultFields;
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(len: usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(name: &'static str, index: u32, variant: &'static str, len: usize) -> Self::SerializeTupleVariant;
+        serialize_map(len: Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(name: &'static str, index: u32, variant: &'static str, len: usize) -> Self::SerializeStructVariant;
+    }
+}
+
+impl SerializeStruct for FieldsCollector {
+    type Ok = ResultFields;
+    type Error = FieldError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FieldError> {
+        let serializer = ValueSerializer {
+            float_precision: self.float_precision,
+        };
+        self.fields.push((key, value.serialize(serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<ResultFields, FieldError> {
+        Ok(self.fields)
+    }
+}
+
+/// Serializes one result field to a string, as the csv crate writes it,
+/// rounding floats to 'float_precision' decimal places, if given.
+struct ValueSerializer {
+    float_precision: Option<usize>,
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = String;
+    type Error = FieldError;
+    type SerializeSeq = Impossible<String, FieldError>;
+    type SerializeTuple = Impossible<String, FieldError>;
+    type SerializeTupleStruct = Impossible<String, FieldError>;
+    type SerializeTupleVariant = Impossible<String, FieldError>;
+    type SerializeMap = Impossible<String, FieldError>;
+    type SerializeStruct = Impossible<String, FieldError>;
+    type SerializeStructVariant = Impossible<String, FieldError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    // floats are formatted with ryu, as in the csv crate, e.g. '1.0'.
+    fn serialize_f32(self, v: f32) -> Result<String, FieldError> {
+        Ok(ryu::Buffer::new().format(v).to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<String, FieldError> {
+        let v = round_float(v, self.float_precision);
+        Ok(ryu::Buffer::new().format(v).to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, FieldError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_none(self) -> Result<String, FieldError> {
+        Ok(String::new())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<String, FieldError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, FieldError> {
+        Ok(String::new())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, FieldError> {
+        Ok(name.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, FieldError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, FieldError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<String, FieldError> {
+        value.serialize(self)
+    }
+
+    unsupported_methods! {
+        serialize_bytes(v: &[u8]) -> String;
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(len: usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(name: &'static str, index: u32, variant: &'static str, len: usize) -> Self::SerializeTupleVariant;
+        serialize_map(len: Option<usize>) -> Self::SerializeMap;
+        serialize_struct(name: &'static str, len: usize) -> Self::SerializeStruct;
+        serialize_struct_variant(name: &'static str, index: u32, variant: &'static str, len: usize) -> Self::SerializeStructVariant;
+    }
+}
+
+/// Define a result struct and its `ResultSchema`, so that the Python
+/// field definitions can never drift from the Rust ones. Every field must
+/// be `pub`.
+macro_rules! result_struct {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$fmeta:meta])*
+                pub $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            $(
+                $(#[$fmeta])*
+                pub $field: $ty
+            ),*
+        }
+
+        impl $crate::schema::ResultSchema for $name {
+            const NAME: &'static str = stringify!($name);
+
+            fn fields() -> Vec<(&'static str, &'static str)> {
+                vec![$((stringify!($field), stringify!($ty))),*]
+            }
+        }
+    };
+}
+
+/// The Python type annotation for a Rust field type.
+pub fn python_type(rust_type: &str) -> String {
+    let rust_type: String = rust_type.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(inner) = rust_type
+        .strip_prefix("Option<")
+        .and_then(|t| t.strip_suffix('>'))
+    {
+        return format!("Optional[{}]", python_type(inner));
+    }
+    match rust_type.as_str() {
+        "String" => "str",
+        "f64" | "f32" => "float",
+        "usize" | "u64" | "u32" | "u8" | "i64" | "i32" => "int",
+        "bool" => "bool",
+        other => panic!("no Python type for result field type '{}'", other),
+    }
+    .to_string()
+}
+
+/// The schema of 'T', as (struct name, [(field name, Python type)]).
+fn schema_of<T: ResultSchema>() -> (String, Vec<(String, String)>) {
+    (
+        T::NAME.to_string(),
+        T::fields()
+            .into_iter()
+            .map(|(name, ty)| (name.to_string(), python_type(ty)))
+            .collect(),
+    )
+}
+
+/// The schemas of all result structs written by branchwater commands.
+pub fn result_schemas() -> Vec<(String, Vec<(String, String)>)> {
+    vec![
+        schema_of::<SearchResult>(),
+        schema_of::<ScreenResult>(),
+        schema_of::<NumSearchResult>(),
+        schema_of::<MultiSearchResult>(),
+        schema_of::<BranchwaterPrefetchResult>(),
+        schema_of::<BranchwaterGatherResult>(),
+        schema_of::<BetaDiversityResult>(),
+        schema_of::<WeightedContainmentResult>(),
+        schema_of::<RarefactionResult>(),
+        schema_of::<TimeSeriesResult>(),
+    ]
+}
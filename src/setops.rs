@@ -0,0 +1,154 @@
+/// setops: union, intersection, and subtraction of sketches between two collections.
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use std::collections::HashSet;
+
+use sourmash::selection::Selection;
+use sourmash::signature::Signature;
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+
+use crate::utils::{
+    load_collection, load_sketches, write_signatures, Policies, ReportType, SmallSignature,
+};
+
+#[derive(Clone, Copy)]
+enum SetOp {
+    Union,
+    Intersect,
+    Subtract,
+}
+
+impl std::str::FromStr for SetOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "union" => Ok(SetOp::Union),
+            "intersect" => Ok(SetOp::Intersect),
+            "subtract" => Ok(SetOp::Subtract),
+            _ => Err(anyhow!(
+                "unknown set operation '{}'; must be one of union, intersect, subtract",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SetOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            SetOp::Union => "union",
+            SetOp::Intersect => "intersect",
+            SetOp::Subtract => "subtract",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// Merge all sketches into a single flat (no abundance) sketch.
+fn flatten_sketches(sketches: &[SmallSignature]) -> Result<KmerMinHash> {
+    let mut merged = sketches[0].minhash.clone();
+    merged.disable_abundance();
+    for sketch in sketches.iter().skip(1) {
+        let mut mh = sketch.minhash.clone();
+        mh.disable_abundance();
+        merged.merge(&mh)?;
+    }
+    Ok(merged)
+}
+
+/// Apply 'op' to 'query' and 'other', returning a new flat sketch.
+fn apply_setop(op: SetOp, query: &KmerMinHash, other: &KmerMinHash) -> Result<KmerMinHash> {
+    let mut result = query.clone();
+    result.disable_abundance();
+    match op {
+        SetOp::Union => result.merge(other)?,
+        SetOp::Intersect => {
+            let (hashes, _) = query.intersection(other)?;
+            result.clear();
+            result.add_many(&hashes)?;
+        }
+        SetOp::Subtract => {
+            let remove: HashSet<u64> = other.iter_mins().copied().collect();
+            let keep: Vec<u64> = query
+                .iter_mins()
+                .filter(|hash| !remove.contains(hash))
+                .copied()
+                .collect();
+            result.clear();
+            result.add_many(&keep)?;
+        }
+    }
+    Ok(result)
+}
+
+fn build_signature(name: &str, filename: &str, mh: KmerMinHash) -> Signature {
+    let mut sig = Signature::default();
+    sig.set_name(name);
+    sig.set_filename(filename);
+    sig.push(Sketch::MinHash(mh));
+    sig
+}
+
+/// Apply a set operation between the sketches in 'collection_a' and the
+/// union of all sketches in 'collection_b', at the common scaled in
+/// 'selection'. Each sketch in A gives one output sketch, unless 'merge'
+/// is set, in which case A is first merged into a single sketch.
+#[allow(clippy::too_many_arguments)]
+pub fn setops(
+    collection_a: String,
+    collection_b: String,
+    operation: String,
+    selection: &Selection,
+    policies: &Policies,
+    merge: bool,
+    name: Option<String>,
+    output: String,
+) -> Result<()> {
+    let op: SetOp = operation.parse()?;
+
+    let a_collection = load_collection(&collection_a, selection, ReportType::Query, policies)?;
+    let a_sketches = load_sketches(a_collection, selection, ReportType::Query)?;
+    let b_collection = load_collection(&collection_b, selection, ReportType::Against, policies)?;
+    let b_sketches = load_sketches(b_collection, selection, ReportType::Against)?;
+
+    if a_sketches.is_empty() || b_sketches.is_empty() {
+        bail!("No compatible sketches to compare; check ksize, scaled, and moltype.");
+    }
+
+    let b_merged = flatten_sketches(&b_sketches)?;
+
+    let sigs: Vec<Signature> = if merge {
+        let a_merged = flatten_sketches(&a_sketches)?;
+        let mh = apply_setop(op, &a_merged, &b_merged)?;
+        let name =
+            name.unwrap_or_else(|| format!("{} of {} and {}", op, collection_a, collection_b));
+        vec![build_signature(&name, &collection_a, mh)]
+    } else {
+        if name.is_some() {
+            eprintln!("WARNING: --name only applies with --merge; keeping original sketch names.");
+        }
+        a_sketches
+            .par_iter()
+            .map(|sketch| {
+                let mh = apply_setop(op, &sketch.minhash, &b_merged)?;
+                Ok(build_signature(&sketch.name, &sketch.location, mh))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let n_empty = sigs
+        .iter()
+        .filter(|sig| sig.minhash().map(|mh| mh.is_empty()).unwrap_or(true))
+        .count();
+    if n_empty > 0 {
+        eprintln!("WARNING: {} output sketches have no hashes.", n_empty);
+    }
+
+    let n_sigs = sigs.len();
+    write_signatures(sigs, output.clone())?;
+    eprintln!("Wrote {} sketches ({}) to '{}'", n_sigs, op, output);
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+/// simulate: build synthetic sketch collections with known overlaps.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use sourmash::ani_utils::ani_from_containment;
+use sourmash::encodings::HashFunctions;
+use sourmash::signature::Signature;
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+use sourmash::sketch::Sketch;
+
+use crate::utils::{hash_function_from_moltype, splitmix64, write_signatures};
+
+/// One row of a simulation spec CSV. A sketch with a 'parent' draws
+/// 'containment' of its hashes from that parent (or, given 'ani', the
+/// containment that corresponds to that ANI), and the rest at random.
+#[derive(Deserialize)]
+struct SketchSpec {
+    name: String,
+    n_hashes: usize,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    containment: Option<f64>,
+    #[serde(default)]
+    ani: Option<f64>,
+}
+
+/// What was actually built for each simulated sketch.
+#[derive(Serialize)]
+struct SimulatedSketch {
+    name: String,
+    md5: String,
+    n_hashes: usize,
+    parent: Option<String>,
+    n_shared: usize,
+    containment: Option<f64>,
+    ani: Option<f64>,
+}
+
+/// Draws hashes below 'max_hash' that have not been used before, so
+/// that sketches share only the hashes they are meant to.
+struct HashSource {
+    state: u64,
+    max_hash: u64,
+    used: HashSet<u64>,
+}
+
+impl HashSource {
+    fn new(seed: u64, max_hash: u64) -> Self {
+        HashSource {
+            state: splitmix64(seed),
+            max_hash,
+            used: HashSet::new(),
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        loop {
+            self.state = splitmix64(self.state);
+            let hash = self.state % self.max_hash;
+            if self.used.insert(hash) {
+                return hash;
+            }
+        }
+    }
+}
+
+fn read_specs(spec_path: &str) -> Result<Vec<SketchSpec>> {
+    let mut rdr = csv::Reader::from_path(spec_path)
+        .with_context(|| format!("Failed to open simulation spec CSV '{}'", spec_path))?;
+    let mut specs = vec![];
+    for result in rdr.deserialize::<SketchSpec>() {
+        let spec = result.map_err(|e| anyhow::anyhow!("Error reading '{}': {}", spec_path, e))?;
+        specs.push(spec);
+    }
+    Ok(specs)
+}
+
+/// Build the sketches described in 'spec_path', writing them to 'output'
+/// and, optionally, what was built (including the containment and ANI of
+/// each sketch in its parent) to 'truth_output'. Sketches are built in
+/// order, so a parent must come before the sketches drawn from it.
+pub fn simulate(
+    spec_path: String,
+    ksize: u8,
+    scaled: usize,
+    moltype: String,
+    seed: u64,
+    output: String,
+    truth_output: Option<String>,
+) -> Result<()> {
+    let specs = read_specs(&spec_path)?;
+    if specs.is_empty() {
+        bail!("no sketches described in '{}'", spec_path);
+    }
+    if scaled == 0 {
+        bail!("simulated sketches must have a scaled value greater than 0.");
+    }
+
+    let hash_function = hash_function_from_moltype(&moltype);
+    // protein-type sketches store ksize in nucleotides.
+    let sketch_ksize = match hash_function {
+        HashFunctions::Murmur64Dna => ksize as u32,
+        _ => ksize as u32 * 3,
+    };
+
+    let mut source = HashSource::new(seed, max_hash_for_scaled(scaled as u64));
+    let mut built: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut sigs = vec![];
+    let mut truth = vec![];
+
+    for (idx, spec) in specs.into_iter().enumerate() {
+        if built.contains_key(&spec.name) {
+            bail!("sketch name '{}' appears more than once", spec.name);
+        }
+
+        let parent = spec.parent.filter(|p| !p.is_empty());
+        let mut hashes = vec![];
+        if let Some(parent) = parent.as_ref() {
+            let fraction = match (spec.containment, spec.ani) {
+                (Some(c), None) => c,
+                (None, Some(ani)) => ani.powi(ksize as i32),
+                _ => bail!(
+                    "sketch '{}' has a parent, so needs exactly one of containment or ani",
+                    spec.name
+                ),
+            };
+            if !(0.0..=1.0).contains(&fraction) {
+                bail!(
+                    "sketch '{}' has containment/ani {} that is not between 0 and 1",
+                    spec.name,
+                    fraction
+                );
+            }
+            let parent_hashes = match built.get(parent) {
+                Some(parent_hashes) => parent_hashes,
+                None => bail!(
+                    "sketch '{}' has parent '{}', which is not defined above it",
+                    spec.name,
+                    parent
+                ),
+            };
+
+            let n_shared = (fraction * spec.n_hashes as f64).round() as usize;
+            if n_shared > parent_hashes.len() {
+                bail!(
+                    "sketch '{}' needs {} hashes from parent '{}', which only has {}",
+                    spec.name,
+                    n_shared,
+                    parent,
+                    parent_hashes.len()
+                );
+            }
+
+            // pick a reproducible random subset of the parent's hashes.
+            let sketch_seed = splitmix64(seed ^ splitmix64(idx as u64));
+            let mut shuffled = parent_hashes.clone();
+            shuffled.sort_by_key(|hash| splitmix64(sketch_seed ^ hash));
+            hashes.extend(shuffled.into_iter().take(n_shared));
+        } else if spec.containment.is_some() || spec.ani.is_some() {
+            bail!(
+                "sketch '{}' has a containment or ani, but no parent",
+                spec.name
+            );
+        }
+
+        let n_shared = hashes.len();
+        let containment = match (&parent, spec.n_hashes) {
+            (None, _) => None,
+            (Some(_), 0) => Some(0.0),
+            (Some(_), n) => Some(n_shared as f64 / n as f64),
+        };
+        while hashes.len() < spec.n_hashes {
+            hashes.push(source.next());
+        }
+
+        let mut mh = KmerMinHash::new(
+            scaled as u64,
+            sketch_ksize,
+            hash_function.clone(),
+            42,
+            false,
+            0,
+        );
+        mh.add_many(&hashes)?;
+
+        let mut sig = Signature::default();
+        sig.set_name(&spec.name);
+        sig.set_filename(&spec_path);
+        sig.push(Sketch::MinHash(mh));
+
+        truth.push(SimulatedSketch {
+            name: spec.name.clone(),
+            md5: sig.md5sum(),
+            n_hashes: hashes.len(),
+            parent,
+            n_shared,
+            containment,
+            ani: containment.map(|c| ani_from_containment(c, ksize as f64)),
+        });
+        sigs.push(sig);
+        built.insert(spec.name, hashes);
+    }
+
+    let n_sigs = sigs.len();
+    write_signatures(sigs, output.clone())?;
+    eprintln!("Wrote {} simulated sketches to '{}'", n_sigs, output);
+
+    if let Some(truth_output) = truth_output {
+        let mut writer = csv::Writer::from_path(&truth_output)?;
+        for row in truth.iter() {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        eprintln!("Wrote simulated overlaps to '{}'", truth_output);
+    }
+
+    Ok(())
+}
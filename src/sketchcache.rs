@@ -0,0 +1,273 @@
+/// sketchcache: an on-disk cache of selected and downsampled sketches, so
+/// that repeated runs against the same database skip parsing its JSON
+/// signatures (`--cache-dir`).
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use rayon::prelude::*;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::UNIX_EPOCH;
+
+use sourmash::collection::Collection;
+use sourmash::encodings::HashFunctions;
+use sourmash::selection::{Select, Selection};
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+use crate::utils::{
+    load_sketches, record_downsample, record_downsample_sizes, ReportType, SmallSignature,
+};
+
+/// Identifies the cache file format; bump when it changes.
+const MAGIC: &[u8; 8] = b"BWCACHE1";
+
+/// A cached sketch, with the scaled and size it had before downsampling.
+struct CachedSketch {
+    sketch: SmallSignature,
+    original_scaled: u64,
+    n_hashes_before: usize,
+}
+
+/// Load the selected sketches of 'collection', loaded from 'path', as
+/// `load_sketches` does, but through a cache in 'cache_dir' if given.
+///
+/// Cache entries are keyed by the path, size and modification time of
+/// 'path', the selection, and the selected manifest records, so a changed
+/// database or selection never reuses a stale entry.
+pub fn load_sketches_cached(
+    path: &str,
+    collection: Collection,
+    selection: &Selection,
+    report_type: ReportType,
+    cache_dir: Option<&String>,
+) -> Result<Vec<SmallSignature>> {
+    let cache_dir = match cache_dir {
+        Some(dir) => dir,
+        None => return load_sketches(collection, selection, report_type),
+    };
+    let key = match cache_key(path, &collection, selection) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("WARNING: cannot cache sketches from '{}': {}", path, e);
+            return load_sketches(collection, selection, report_type);
+        }
+    };
+    let cache_path = PathBuf::from(cache_dir).join(format!("{:016x}.bwcache", key));
+
+    if cache_path.exists() {
+        match read_cache(&cache_path) {
+            Ok(cached) => {
+                eprintln!(
+                    "Loaded {} sketches for '{}' from cache '{}'",
+                    cached.len(),
+                    path,
+                    cache_path
+                );
+                return Ok(cached
+                    .into_iter()
+                    .map(|c| {
+                        record_downsample_sizes(
+                            &report_type,
+                            &c.sketch.name,
+                            &c.sketch.md5sum,
+                            c.original_scaled,
+                            c.n_hashes_before,
+                            &c.sketch.minhash,
+                        );
+                        c.sketch
+                    })
+                    .collect());
+            }
+            Err(e) => eprintln!(
+                "WARNING: ignoring unreadable cache file '{}': {}",
+                cache_path, e
+            ),
+        }
+    }
+
+    let cached = load_for_cache(collection, selection, &report_type);
+    match write_cache(cache_dir, &cache_path, &cached) {
+        Ok(()) => eprintln!(
+            "Saved {} sketches for '{}' to cache '{}'",
+            cached.len(),
+            path,
+            cache_path
+        ),
+        Err(e) => eprintln!(
+            "WARNING: could not write cache file '{}': {}",
+            cache_path, e
+        ),
+    }
+    Ok(cached.into_iter().map(|c| c.sketch).collect())
+}
+
+fn cache_key(path: &str, collection: &Collection, selection: &Selection) -> Result<u64> {
+    let canonical = std::fs::canonicalize(path)?;
+    let metadata = std::fs::metadata(&canonical)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let mut hasher = twox_hash::XxHash64::with_seed(0);
+    hasher.write(canonical.to_string_lossy().as_bytes());
+    hasher.write_u128(mtime);
+    hasher.write_u64(metadata.len());
+    hasher.write_u32(selection.ksize().unwrap_or(0));
+    hasher.write_u32(selection.scaled().unwrap_or(0));
+    hasher.write_u32(selection.num().unwrap_or(0));
+    hasher.write(
+        selection
+            .moltype()
+            .map(|m| m.to_string())
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    for (_idx, record) in collection.iter() {
+        hasher.write(record.md5().as_bytes());
+        hasher.write(record.internal_location().as_str().as_bytes());
+    }
+    Ok(hasher.finish())
+}
+
+fn load_for_cache(
+    collection: Collection,
+    selection: &Selection,
+    report_type: &ReportType,
+) -> Vec<CachedSketch> {
+    collection
+        .par_iter()
+        .filter_map(|(_idx, record)| {
+            let sig = collection.sig_from_record(record).ok()?;
+            let selected_sig = sig.clone().select(selection).ok()?;
+            let minhash = selected_sig.minhash()?.clone();
+            let orig_mh = sig.minhash()?;
+            record_downsample(report_type, &sig.name(), &sig.md5sum(), orig_mh, &minhash);
+
+            Some(CachedSketch {
+                original_scaled: orig_mh.scaled(),
+                n_hashes_before: orig_mh.size(),
+                sketch: SmallSignature {
+                    location: record.internal_location().to_string(),
+                    name: sig.name(),
+                    md5sum: sig.md5sum(),
+                    minhash,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Write the cache to a temporary file first, so that concurrent or
+/// interrupted runs never leave a partial cache file behind.
+fn write_cache(cache_dir: &str, cache_path: &PathBuf, cached: &[CachedSketch]) -> Result<()> {
+    std::fs::create_dir_all(cache_dir)?;
+    let tmp = tempfile::NamedTempFile::new_in(cache_dir)?;
+    {
+        let mut w = BufWriter::new(tmp.as_file());
+        w.write_all(MAGIC)?;
+        write_u64(&mut w, cached.len() as u64)?;
+        for c in cached {
+            let mh = &c.sketch.minhash;
+            write_str(&mut w, &c.sketch.location)?;
+            write_str(&mut w, &c.sketch.name)?;
+            write_str(&mut w, &c.sketch.md5sum)?;
+            write_u64(&mut w, mh.ksize() as u64)?;
+            write_str(&mut w, &mh.hash_function().to_string())?;
+            write_u64(&mut w, mh.seed())?;
+            write_u64(&mut w, mh.scaled())?;
+            write_u64(&mut w, c.original_scaled)?;
+            write_u64(&mut w, c.n_hashes_before as u64)?;
+            let abunds = mh.abunds();
+            w.write_all(&[abunds.is_some() as u8])?;
+            write_u64(&mut w, mh.size() as u64)?;
+            for hash in mh.iter_mins() {
+                write_u64(&mut w, *hash)?;
+            }
+            for abund in abunds.unwrap_or_default() {
+                write_u64(&mut w, abund)?;
+            }
+        }
+        w.flush()?;
+    }
+    tmp.persist(cache_path)?;
+    Ok(())
+}
+
+fn read_cache(cache_path: &PathBuf) -> Result<Vec<CachedSketch>> {
+    let mut r = BufReader::new(File::open(cache_path)?);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("not a branchwater sketch cache, or an unsupported version");
+    }
+    let n_sketches = read_u64(&mut r)?;
+    let mut cached = vec![];
+    for _ in 0..n_sketches {
+        let location = read_str(&mut r)?;
+        let name = read_str(&mut r)?;
+        let md5sum = read_str(&mut r)?;
+        let ksize = read_u64(&mut r)? as u32;
+        let moltype = read_str(&mut r)?;
+        let hash_function = match moltype.as_str() {
+            "DNA" | "protein" | "dayhoff" | "hp" => HashFunctions::try_from(moltype.as_str())?,
+            _ => bail!("unknown moltype '{}'", moltype),
+        };
+        let seed = read_u64(&mut r)?;
+        let scaled = read_u64(&mut r)?;
+        let original_scaled = read_u64(&mut r)?;
+        let n_hashes_before = read_u64(&mut r)? as usize;
+        let mut with_abundance = [0u8; 1];
+        r.read_exact(&mut with_abundance)?;
+        let with_abundance = with_abundance[0] != 0;
+        let n_hashes = read_u64(&mut r)? as usize;
+        let hashes = (0..n_hashes)
+            .map(|_| read_u64(&mut r))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut minhash = KmerMinHash::new(scaled, ksize, hash_function, seed, with_abundance, 0);
+        if with_abundance {
+            let abunds = (0..n_hashes)
+                .map(|_| read_u64(&mut r))
+                .collect::<Result<Vec<_>>>()?;
+            let pairs: Vec<(u64, u64)> = hashes.into_iter().zip(abunds).collect();
+            minhash.add_many_with_abund(&pairs)?;
+        } else {
+            minhash.add_many(&hashes)?;
+        }
+
+        cached.push(CachedSketch {
+            sketch: SmallSignature {
+                location,
+                name,
+                md5sum,
+                minhash,
+            },
+            original_scaled,
+            n_hashes_before,
+        });
+    }
+    Ok(cached)
+}
+
+pub(crate) fn write_u64(w: &mut impl Write, value: u64) -> Result<()> {
+    w.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn write_str(w: &mut impl Write, value: &str) -> Result<()> {
+    write_u64(w, value.len() as u64)?;
+    w.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_str(r: &mut impl Read) -> Result<String> {
+    let len = read_u64(r)? as usize;
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).context("invalid string in cache file")
+}
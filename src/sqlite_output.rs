@@ -0,0 +1,224 @@
+/// sqlite_output: write results to a SQLite database (`--output-format
+/// sqlite`), in one table per command, with a column type for each result
+/// field taken from its `ResultSchema`.
+///
+/// Note: as for Parquet, fields are serialized to strings exactly as they
+/// are for CSV, and then parsed into typed values, so that
+/// `--float-precision`, `--columns` and `--anonymize-queries` apply in the
+/// same way to every format.
+use anyhow::{anyhow, Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::schema::{serialize_fields, ResultSchema};
+use crate::utils::{align_fields, select_fields, AgainstMetadata, OutputOptions};
+
+/// Columns that results are looked up by, indexed when present.
+const INDEXED_COLUMNS: [&str; 2] = ["query_name", "match_name"];
+
+/// The SQLite type of a result column.
+#[derive(Debug, Clone, Copy)]
+enum ColumnType {
+    Text,
+    Real,
+    Integer,
+    /// stored as INTEGER 0 or 1, as SQLite has no boolean type.
+    Boolean,
+}
+
+impl ColumnType {
+    /// The column type of a Rust result field type; SQLite columns are
+    /// all nullable, so `Option<T>` is the type of 'T'.
+    fn of(rust_type: &str) -> Self {
+        let rust_type: String = rust_type.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some(inner) = rust_type
+            .strip_prefix("Option<")
+            .and_then(|t| t.strip_suffix('>'))
+        {
+            return ColumnType::of(inner);
+        }
+        match rust_type.as_str() {
+            "String" => ColumnType::Text,
+            "f64" | "f32" => ColumnType::Real,
+            "usize" | "u64" | "u32" | "u8" | "i64" | "i32" => ColumnType::Integer,
+            "bool" => ColumnType::Boolean,
+            other => panic!("no SQLite type for result field type '{}'", other),
+        }
+    }
+
+    fn sql(&self) -> &'static str {
+        match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Real => "REAL",
+            ColumnType::Integer | ColumnType::Boolean => "INTEGER",
+        }
+    }
+
+    /// The value of a serialized field; empty and missing values are NULL.
+    fn value(&self, column: &str, value: Option<&str>) -> Result<Value> {
+        let value = match value.filter(|v| !v.is_empty()) {
+            Some(value) => value,
+            None => return Ok(Value::Null),
+        };
+        let invalid = || anyhow!("Invalid value '{}' in column '{}'", value, column);
+        Ok(match self {
+            ColumnType::Text => Value::Text(value.to_string()),
+            ColumnType::Real => Value::Real(value.parse().map_err(|_| invalid())?),
+            ColumnType::Integer => Value::Integer(value.parse().map_err(|_| invalid())?),
+            ColumnType::Boolean => {
+                Value::Integer(value.parse::<bool>().map_err(|_| invalid())? as i64)
+            }
+        })
+    }
+}
+
+/// Quote a table or column name for SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Writes results of type 'T' to a table of a SQLite database, replacing
+/// any table of the same name. Every column of 'T' (or of `--columns`) is
+/// in the table, whether or not it was calculated; columns that weren't
+/// are NULL. Rows are written in a single transaction, and `query_name`
+/// and `match_name` are indexed once they are all written.
+pub struct SqliteResultWriter<T> {
+    conn: Connection,
+    table: String,
+    /// the output column names, in order.
+    column_names: Vec<String>,
+    /// the type of each output column; metadata columns are text.
+    column_types: Vec<ColumnType>,
+    /// the field names of 'T', in output order.
+    schema_fields: Vec<&'static str>,
+    /// the position in 'schema_fields' of each output column, or None
+    /// for metadata.
+    columns: Vec<Option<usize>>,
+    /// the position in 'schema_fields' of the metadata key column.
+    key_index: usize,
+    metadata: Option<Arc<AgainstMetadata>>,
+    options: OutputOptions,
+    insert: String,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Serialize + ResultSchema> SqliteResultWriter<T> {
+    /// Open (or create) the database at 'path', and create 'table' for
+    /// the results; floats are rounded as given in 'options'.
+    pub fn new(
+        path: &str,
+        table: &str,
+        columns: Option<Vec<String>>,
+        metadata: Option<Arc<AgainstMetadata>>,
+        options: OutputOptions,
+    ) -> Result<Self> {
+        let selected = select_fields::<T>(columns.as_ref())?;
+        let all_fields: Vec<&'static str> = T::fields().into_iter().map(|(name, _)| name).collect();
+        let position = |name: &str| all_fields.iter().position(|field| *field == name);
+
+        let mut column_names = vec![];
+        let mut column_types = vec![];
+        let mut output_columns = vec![];
+        for (name, rust_type) in selected.iter() {
+            column_names.push(name.to_string());
+            column_types.push(ColumnType::of(rust_type));
+            output_columns.push(position(name));
+        }
+        let mut key_index = 0;
+        if let Some(metadata) = &metadata {
+            let selected: Vec<&str> = selected.iter().map(|(name, _)| *name).collect();
+            metadata.check_fields(&all_fields, &selected)?;
+            key_index = position(metadata.key_column).unwrap();
+            for col in metadata.columns.iter() {
+                column_names.push(col.clone());
+                column_types.push(ColumnType::Text);
+                output_columns.push(None);
+            }
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open SQLite database '{}'", path))?;
+        let definitions: Vec<String> = column_names
+            .iter()
+            .zip(column_types.iter())
+            .map(|(name, column_type)| format!("{} {}", quote(name), column_type.sql()))
+            .collect();
+        // results replace those of an earlier run of the same command, as
+        // they would overwrite a CSV output.
+        conn.execute_batch(&format!(
+            "BEGIN; DROP TABLE IF EXISTS {table}; CREATE TABLE {table} ({});",
+            definitions.join(", "),
+            table = quote(table)
+        ))
+        .with_context(|| format!("Failed to create table '{}' in '{}'", table, path))?;
+        let insert = format!(
+            "INSERT INTO {} VALUES ({})",
+            quote(table),
+            vec!["?"; column_names.len()].join(", ")
+        );
+
+        Ok(SqliteResultWriter {
+            conn,
+            table: table.to_string(),
+            column_names,
+            column_types,
+            schema_fields: all_fields,
+            columns: output_columns,
+            key_index,
+            metadata,
+            options,
+            insert,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Add one result.
+    pub fn write(&mut self, item: &T) -> Result<()> {
+        let mut fields = serialize_fields(item, self.options.float_precision)?;
+        self.options.anonymize_fields(&mut fields);
+        let values = align_fields(&self.schema_fields, fields);
+        let value = |i: usize| values[i].as_deref();
+
+        let metadata_row = self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| value(self.key_index).and_then(|key| metadata.rows.get(key)));
+        let mut metadata_values = metadata_row.into_iter().flatten();
+
+        let mut row = Vec::with_capacity(self.columns.len());
+        for ((column, column_type), name) in self
+            .columns
+            .iter()
+            .zip(self.column_types.iter())
+            .zip(self.column_names.iter())
+        {
+            row.push(match column {
+                Some(i) => column_type.value(name, value(*i))?,
+                None => column_type.value(name, metadata_values.next().map(|v| v.as_str()))?,
+            });
+        }
+        self.conn
+            .prepare_cached(&self.insert)?
+            .execute(params_from_iter(row))?;
+        Ok(())
+    }
+
+    /// Index the table and commit the results.
+    pub fn finish(self) -> Result<()> {
+        for column in INDEXED_COLUMNS {
+            if self.column_names.iter().any(|name| name == column) {
+                self.conn.execute_batch(&format!(
+                    "CREATE INDEX {} ON {} ({});",
+                    quote(&format!("{}_{}", self.table, column)),
+                    quote(&self.table),
+                    quote(column)
+                ))?;
+            }
+        }
+        self.conn.execute_batch("COMMIT;")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+/// taxonomy: lineages for gather matches, and Krona output summarizing the
+/// taxonomic composition of each query (`fastmultigather --lineages`).
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::utils::{round_float, OutputOptions};
+
+/// The ranks recognized in a lineages CSV, from most to least general.
+const RANKS: [&str; 8] = [
+    "superkingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+    "strain",
+];
+
+/// Column names accepted for the identifier of each lineage.
+const IDENT_COLUMNS: [&str; 3] = ["ident", "identifiers", "accession"];
+
+/// Lineages keyed by identifier, as in a `sourmash tax` lineages CSV.
+pub struct Lineages {
+    ranks: Vec<String>,
+    lineages: HashMap<String, Vec<String>>,
+}
+
+impl Lineages {
+    /// Load a CSV with an 'ident' (or 'identifiers' or 'accession') column
+    /// and one column per rank, e.g. 'superkingdom' through 'species'.
+    pub fn from_csv(path: &str) -> Result<Self> {
+        let mut rdr = csv::Reader::from_path(path)
+            .with_context(|| format!("Failed to open lineages CSV '{}'", path))?;
+        let headers = rdr.headers()?.clone();
+
+        let ident_idx = match headers
+            .iter()
+            .position(|h| IDENT_COLUMNS.contains(&h.trim()))
+        {
+            Some(idx) => idx,
+            None => bail!(
+                "lineages CSV '{}' has no identifier column; expected one of: {}",
+                path,
+                IDENT_COLUMNS.join(", ")
+            ),
+        };
+        let rank_indices: Vec<(String, usize)> = RANKS
+            .iter()
+            .filter_map(|rank| {
+                headers
+                    .iter()
+                    .position(|h| h.trim() == *rank)
+                    .map(|idx| (rank.to_string(), idx))
+            })
+            .collect();
+        if rank_indices.is_empty() {
+            bail!(
+                "lineages CSV '{}' has no rank columns; expected some of: {}",
+                path,
+                RANKS.join(", ")
+            );
+        }
+
+        let mut lineages = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let ident = record.get(ident_idx).unwrap_or_default().trim().to_string();
+            let lineage = rank_indices
+                .iter()
+                .map(|(_, idx)| record.get(*idx).unwrap_or_default().trim().to_string())
+                .collect();
+            lineages.insert(ident, lineage);
+        }
+        eprintln!("Loaded {} lineages from '{}'", lineages.len(), path);
+
+        Ok(Lineages {
+            ranks: rank_indices.into_iter().map(|(rank, _)| rank).collect(),
+            lineages,
+        })
+    }
+
+    /// The lineage of a match, found by the first word of its name (usually
+    /// the accession), with or without its version, e.g. 'GCA_000123.1'.
+    pub fn lineage(&self, match_name: &str) -> Option<&Vec<String>> {
+        let ident = match_name.split(' ').next().unwrap_or_default();
+        self.lineages.get(ident).or_else(|| {
+            ident
+                .rsplit_once('.')
+                .and_then(|(unversioned, _)| self.lineages.get(unversioned))
+        })
+    }
+
+    /// Write a Krona text file for one query: one row per lineage, with the
+    /// fraction of the query assigned to it, from 'found', a list of
+    /// (match name, fraction) pairs. Matches with no lineage, and the rest
+    /// of the query, are reported as 'unclassified'. Fractions are rounded
+    /// as given in 'options'.
+    pub fn write_krona(
+        &self,
+        path: &str,
+        found: &[(String, f64)],
+        options: &OutputOptions,
+    ) -> Result<()> {
+        let unclassified = vec!["unclassified".to_string(); self.ranks.len()];
+
+        // sum fractions by lineage, keeping lineages in the order found
+        let mut order: Vec<&Vec<String>> = vec![];
+        let mut fractions: HashMap<&Vec<String>, f64> = HashMap::new();
+        let mut classified = 0.0;
+        for (match_name, fraction) in found {
+            let lineage = match self.lineage(match_name) {
+                Some(lineage) => {
+                    classified += fraction;
+                    lineage
+                }
+                None => &unclassified,
+            };
+            if !fractions.contains_key(lineage) {
+                order.push(lineage);
+            }
+            *fractions.entry(lineage).or_insert(0.0) += fraction;
+        }
+        let remaining = 1.0 - found.iter().map(|(_, f)| f).sum::<f64>();
+        if remaining > 0.0 {
+            if !fractions.contains_key(&unclassified) {
+                order.push(&unclassified);
+            }
+            *fractions.entry(&unclassified).or_insert(0.0) += remaining;
+        }
+        if !found.is_empty() && classified == 0.0 {
+            eprintln!(
+                "WARNING: no lineages found for any of the matches in '{}'",
+                path
+            );
+        }
+
+        let mut w = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create '{}'", path))?,
+        );
+        writeln!(w, "fraction\t{}", self.ranks.join("\t"))?;
+        for lineage in order {
+            writeln!(
+                w,
+                "{}\t{}",
+                round_float(fractions[lineage], options.float_precision),
+                lineage.join("\t")
+            )?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
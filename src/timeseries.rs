@@ -0,0 +1,223 @@
+/// timeseries: per (query, match) time series of containment, from the
+/// `manysearch` output of each of many timepoints.
+///
+/// Note: the search outputs are streamed one row at a time; only one value
+/// per (query, match, timepoint) is held in memory.
+use anyhow::{anyhow, Context, Result};
+use std::collections::{HashMap, HashSet};
+
+use crate::errors::BranchwaterError;
+use crate::utils::{csvwriter_thread_with_extras, OutputOptions, TimeSeriesResult, WriterExtras};
+
+/// One row of search output, at one timepoint.
+#[derive(Clone, Copy)]
+struct Point {
+    containment: f64,
+    intersect_hashes: usize,
+    query_containment_ani: Option<f64>,
+}
+
+/// Names interned as indices, so that each (query, match) pair is stored
+/// as two integers however many timepoints it appears in.
+#[derive(Default)]
+struct Names {
+    index: HashMap<String, u32>,
+    names: Vec<String>,
+}
+
+impl Names {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(idx) = self.index.get(name) {
+            return *idx;
+        }
+        let idx = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.index.insert(name.to_string(), idx);
+        idx
+    }
+}
+
+/// Load the (timepoint, path) pairs from a CSV with `timepoint` and `path`
+/// columns, in time order.
+fn load_timepoints(timepoints_path: &str) -> Result<Vec<(String, String)>> {
+    let mut rdr = csv::Reader::from_path(timepoints_path)
+        .with_context(|| format!("Failed to open timepoints CSV '{}'", timepoints_path))?;
+    let headers = rdr.headers()?.clone();
+    let find_column = |name: &str| {
+        headers.iter().position(|h| h == name).ok_or_else(|| {
+            anyhow!(
+                "timepoints CSV '{}' has no '{}' column",
+                timepoints_path,
+                name
+            )
+        })
+    };
+    let timepoint_idx = find_column("timepoint")?;
+    let path_idx = find_column("path")?;
+
+    let mut seen = HashSet::new();
+    let mut timepoints = vec![];
+    for result in rdr.records() {
+        let record = result?;
+        let timepoint = record[timepoint_idx].to_string();
+        if !seen.insert(timepoint.clone()) {
+            bail!(
+                "timepoint '{}' is listed more than once in '{}'",
+                timepoint,
+                timepoints_path
+            );
+        }
+        timepoints.push((timepoint, record[path_idx].to_string()));
+    }
+    if timepoints.is_empty() {
+        bail!("no timepoints in '{}'", timepoints_path);
+    }
+    Ok(timepoints)
+}
+
+/// Parse an optional numeric column value; branchwater writes empty
+/// values for columns that weren't calculated.
+fn parse_value<T: std::str::FromStr>(value: &str, column: &str, path: &str) -> Result<Option<T>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|_| {
+        anyhow!(
+            "Invalid value '{}' in column '{}' of '{}'",
+            value,
+            column,
+            path
+        )
+    })
+}
+
+/// Build a long-format time series of containment for every (query, match)
+/// pair in the `manysearch` outputs listed in 'timepoints_path'. With
+/// 'fill_missing', each pair has a row for every timepoint, with `present`
+/// false where it wasn't found; otherwise only timepoints where it was
+/// found are written. Results are written as given in 'options'.
+pub fn timeseries(
+    timepoints_path: String,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    fill_missing: bool,
+    options: &OutputOptions,
+) -> Result<()> {
+    let timepoints = load_timepoints(&timepoints_path)?;
+    let n_timepoints = timepoints.len();
+
+    let mut queries = Names::default();
+    let mut matches = Names::default();
+    let mut series: HashMap<(u32, u32), Vec<Option<Point>>> = HashMap::new();
+    let mut n_rows = 0;
+
+    for (t, (timepoint, path)) in timepoints.iter().enumerate() {
+        let (reader, _format) = niffler::from_path(path)
+            .with_context(|| format!("Failed to open search output '{}'", path))?;
+        let mut rdr = csv::Reader::from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        let find_column = |name: &str| headers.iter().position(|h| h == name);
+        let required = |name: &str| {
+            find_column(name)
+                .ok_or_else(|| anyhow!("search output '{}' has no '{}' column", path, name))
+        };
+        let query_idx = required("query_name")?;
+        let match_idx = required("match_name")?;
+        let containment_idx = required("containment")?;
+        let intersect_idx = find_column("intersect_hashes");
+        let ani_idx = find_column("query_containment_ani");
+
+        for result in rdr.records() {
+            let record = result?;
+            let containment = parse_value(&record[containment_idx], "containment", path)?
+                .ok_or_else(|| anyhow!("empty containment in '{}'", path))?;
+            let intersect_hashes = match intersect_idx {
+                Some(idx) => parse_value(&record[idx], "intersect_hashes", path)?.unwrap_or(0),
+                None => 0,
+            };
+            let query_containment_ani = match ani_idx {
+                Some(idx) => parse_value(&record[idx], "query_containment_ani", path)?,
+                None => None,
+            };
+
+            let key = (
+                queries.intern(&record[query_idx]),
+                matches.intern(&record[match_idx]),
+            );
+            let points = series
+                .entry(key)
+                .or_insert_with(|| vec![None; n_timepoints]);
+            if points[t].is_some() {
+                bail!(
+                    "search output '{}' for timepoint '{}' has more than one row for query '{}' and match '{}'",
+                    path,
+                    timepoint,
+                    &record[query_idx],
+                    &record[match_idx]
+                );
+            }
+            points[t] = Some(Point {
+                containment,
+                intersect_hashes,
+                query_containment_ani,
+            });
+            n_rows += 1;
+        }
+        eprintln!("Loaded timepoint '{}' from '{}'", timepoint, path);
+    }
+
+    // write each series in turn, ordered by query and then match name.
+    let mut keys: Vec<(u32, u32)> = series.keys().copied().collect();
+    keys.sort_by(|a, b| {
+        let name = |k: &(u32, u32)| (&queries.names[k.0 as usize], &matches.names[k.1 as usize]);
+        name(a).cmp(&name(b))
+    });
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<TimeSeriesResult>(rayon::current_num_threads());
+    let extras = WriterExtras {
+        options: options.clone(),
+        ..WriterExtras::default()
+    };
+    let thrd = csvwriter_thread_with_extras(recv, output, columns, 0, extras);
+
+    'series: for key in keys.iter() {
+        let points = &series[key];
+        for (t, point) in points.iter().enumerate() {
+            if point.is_none() && !fill_missing {
+                continue;
+            }
+            let result = TimeSeriesResult {
+                query_name: queries.names[key.0 as usize].clone(),
+                match_name: matches.names[key.1 as usize].clone(),
+                timepoint: timepoints[t].0.clone(),
+                timepoint_index: t,
+                present: point.is_some(),
+                containment: point.map_or(0.0, |p| p.containment),
+                intersect_hashes: point.map_or(0, |p| p.intersect_hashes),
+                query_containment_ani: point.and_then(|p| p.query_containment_ani),
+            };
+            if let Err(e) = send.send(result) {
+                eprintln!("Unable to send internal data: {:?}", e);
+                break 'series;
+            }
+        }
+    }
+    drop(send);
+
+    if let Err(e) = thrd
+        .join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    {
+        bail!(BranchwaterError::context("Error writing results", &e));
+    }
+
+    eprintln!(
+        "DONE. Read {} rows from {} timepoints; wrote time series for {} (query, match) pairs.",
+        n_rows,
+        n_timepoints,
+        keys.len()
+    );
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 /// Utility functions for sourmash_plugin_branchwater.
+use rayon::iter::Either;
 use rayon::prelude::*;
 use sourmash::encodings::HashFunctions;
 use sourmash::selection::Select;
@@ -13,19 +14,35 @@ use std::cmp::{Ordering, PartialOrd};
 use std::collections::BinaryHeap;
 use std::fs::{create_dir_all, File};
 use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::marker::PhantomData;
 use std::panic;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
-
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::biom::BiomTable;
+use crate::checkpoint::{CheckpointMatch, Checkpointing, GatherCheckpoint};
+use crate::dryrun::effective_sizes;
+use crate::errors::{BranchwaterError, ErrorCode};
+use crate::matchlist::{MatchKey, MatchList};
+use crate::parquet_output::ParquetResultWriter;
+use crate::report::RunReport;
+use crate::schema::{field_value, serialize_fields, ResultFields, ResultSchema};
+#[cfg(feature = "sqlite")]
+use crate::sqlite_output::SqliteResultWriter;
+use crate::taxonomy::Lineages;
 use sourmash::ani_utils::{ani_ci_from_containment, ani_from_containment};
 use sourmash::collection::Collection;
+use sourmash::encodings::Idx;
+use sourmash::index::revindex::RevIndex;
 use sourmash::manifest::{Manifest, Record};
 use sourmash::selection::Selection;
 use sourmash::signature::{Signature, SigsTrait};
 use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
 use sourmash::storage::{FSStorage, InnerStorage, SigStore};
 use stats::{median, stddev};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 /// Track a name/minhash.
 
 pub struct SmallSignature {
@@ -44,9 +61,17 @@ pub struct PrefetchResult {
     pub overlap: u64,
 }
 
+/// Order by overlap; matches with equal overlap are ordered by md5sum,
+/// name, and then location, with the smallest first in a (max-)heap, so
+/// gather results don't depend on the order in which matches were found,
+/// and so on the number of threads.
 impl Ord for PrefetchResult {
     fn cmp(&self, other: &PrefetchResult) -> Ordering {
-        self.overlap.cmp(&other.overlap)
+        self.overlap
+            .cmp(&other.overlap)
+            .then_with(|| other.md5sum.cmp(&self.md5sum))
+            .then_with(|| other.name.cmp(&self.name))
+            .then_with(|| other.location.cmp(&self.location))
     }
 }
 
@@ -58,7 +83,7 @@ impl PartialOrd for PrefetchResult {
 
 impl PartialEq for PrefetchResult {
     fn eq(&self, other: &Self) -> bool {
-        self.overlap == other.overlap
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -67,6 +92,12 @@ impl Eq for PrefetchResult {}
 /// Find sketches in 'sketchlist' that overlap with 'query' above
 /// specified threshold.
 
+/// The overlap of two sketches can't exceed the size of the smaller one,
+/// so pairs where that is below 'threshold_hashes' can skip intersecting.
+fn may_reach_threshold(a: &KmerMinHash, b: &KmerMinHash, threshold_hashes: u64) -> bool {
+    a.size().min(b.size()) as u64 >= threshold_hashes
+}
+
 pub fn prefetch(
     query_mh: &KmerMinHash,
     sketchlist: BinaryHeap<PrefetchResult>,
@@ -74,27 +105,35 @@ pub fn prefetch(
 ) -> BinaryHeap<PrefetchResult> {
     sketchlist
         .into_par_iter()
-        .filter_map(|result| {
-            let mut mm = None;
-            let searchsig = &result.minhash;
-            // downsample within count_common
-            let overlap = searchsig.count_common(query_mh, true);
-            if let Ok(overlap) = overlap {
-                if overlap >= threshold_hashes {
-                    let result = PrefetchResult { overlap, ..result };
-                    mm = Some(result);
-                }
-            }
-            mm
-        })
+        .filter_map(|result| update_overlap(query_mh, result, threshold_hashes))
         .collect()
 }
 
+/// Recalculate the overlap of one match with 'query_mh', or None if it
+/// is now below 'threshold_hashes'.
+pub(crate) fn update_overlap(
+    query_mh: &KmerMinHash,
+    result: PrefetchResult,
+    threshold_hashes: u64,
+) -> Option<PrefetchResult> {
+    let searchsig = &result.minhash;
+    if !may_reach_threshold(searchsig, query_mh, threshold_hashes) {
+        return None;
+    }
+    // downsample within count_common
+    match searchsig.count_common(query_mh, true) {
+        Ok(overlap) if overlap >= threshold_hashes => Some(PrefetchResult { overlap, ..result }),
+        _ => None,
+    }
+}
+
 /// Write list of prefetch matches.
 pub fn write_prefetch(
     query: &SigStore,
+    query_mh: &KmerMinHash,
     prefetch_output: Option<String>,
-    matchlist: &BinaryHeap<PrefetchResult>,
+    matchlist: &MatchList,
+    options: &OutputOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Define the writer to stdout by default
     let mut writer: Box<dyn Write> = Box::new(std::io::stdout());
@@ -112,25 +151,78 @@ pub fn write_prefetch(
         writer = Box::new(BufWriter::new(file));
     }
 
-    writeln!(
-        &mut writer,
-        "query_filename,query_name,query_md5,match_name,match_md5,intersect_bp"
-    )
-    .ok();
+    let mut writer = Writer::from_writer(writer);
+    let mut column_selection = ColumnSelection::from_options(None, None, options);
+
+    let ksize = query_mh.ksize();
+    let moltype = query_mh.hash_function().to_string();
+
+    // write matches in gather order, largest overlap first; spilled
+    // matches are read back one at a time, so sort the rows, not matches.
+    let mut rows: Vec<(MatchKey, BranchwaterPrefetchResult)> = vec![];
+    // matches are downsampled to the search scaled; do the same for the
+    // query, once per distinct scaled.
+    let mut query_sizes: HashMap<u64, u64> = HashMap::new();
+    matchlist.for_each(|m| {
+        let scaled = m.minhash.scaled();
+        let query_size = match query_sizes.get(&scaled) {
+            Some(size) => *size,
+            None => {
+                let size = if query_mh.scaled() < scaled {
+                    query_mh.downsample_scaled(scaled)?.size()
+                } else {
+                    query_mh.size()
+                } as u64;
+                query_sizes.insert(scaled, size);
+                size
+            }
+        };
+        let match_size = m.minhash.size() as u64;
+        let overlap = m.overlap;
+
+        let union_size = query_size + match_size - overlap;
+        let f_query_match = overlap as f64 / query_size as f64;
+        let f_match_query = overlap as f64 / match_size as f64;
+
+        let query_containment_ani = ani_from_containment(f_query_match, ksize as f64);
+        let match_containment_ani = ani_from_containment(f_match_query, ksize as f64);
+
+        let row = BranchwaterPrefetchResult {
+            intersect_bp: overlap * scaled,
+            jaccard: overlap as f64 / union_size as f64,
+            max_containment: f64::max(f_query_match, f_match_query),
+            f_query_match,
+            f_match_query,
+            f_match: f_match_query,
+            match_filename: m.location.clone(),
+            match_name: m.name.clone(),
+            match_md5: m.md5sum.clone(),
+            match_bp: match_size * scaled,
+            query_filename: query.filename(),
+            query_name: query.name(),
+            query_md5: query.md5sum(),
+            query_bp: query_size * scaled,
+            ksize,
+            moltype: moltype.clone(),
+            scaled,
+            query_n_hashes: query_size,
+            query_containment_ani,
+            match_containment_ani,
+            average_containment_ani: (query_containment_ani + match_containment_ani) / 2.0,
+            max_containment_ani: f64::max(query_containment_ani, match_containment_ani),
+        };
+        rows.push((MatchKey::of(m), row));
+        Ok(())
+    })?;
+    rows.sort_by(|a, b| b.0.cmp(&a.0));
 
-    for m in matchlist.iter() {
-        writeln!(
-            &mut writer,
-            "{},\"{}\",{},\"{}\",{},{}",
-            query.filename(),
-            query.name(),
-            query.md5sum(),
-            m.name,
-            m.md5sum,
-            m.overlap
-        )
-        .ok();
+    for (_, row) in rows {
+        match column_selection.as_mut() {
+            Some(selection) => selection.write_record(&mut writer, &row)?,
+            None => writer.serialize(row)?,
+        }
     }
+    writer.flush()?;
 
     Ok(())
 }
@@ -139,8 +231,14 @@ pub struct FastaData {
     pub name: String,
     pub paths: Vec<PathBuf>,
     pub input_type: String, // to do - this could use moltype enum?
+    /// Parameters from the optional `param_string` column, overriding the
+    /// global ones for this row.
+    pub param_string: Option<String>,
 }
 
+/// Optional last column of a manysketch CSV, with per-row parameters.
+const PARAM_STRING_COLUMN: &str = "param_string";
+
 enum CSVType {
     Assembly,
     Reads,
@@ -149,19 +247,24 @@ enum CSVType {
 }
 
 fn detect_csv_type(headers: &csv::StringRecord) -> CSVType {
-    if headers.len() == 3
+    // ignore a trailing 'param_string' column
+    let n_columns = match param_string_column(headers) {
+        Some(_) => headers.len() - 1,
+        None => headers.len(),
+    };
+    if n_columns == 3
         && headers.get(0).unwrap() == "name"
         && headers.get(1).unwrap() == "genome_filename"
         && headers.get(2).unwrap() == "protein_filename"
     {
         CSVType::Assembly
-    } else if headers.len() == 3
+    } else if n_columns == 3
         && headers.get(0).unwrap() == "name"
         && headers.get(1).unwrap() == "read1"
         && headers.get(2).unwrap() == "read2"
     {
         CSVType::Reads
-    } else if headers.len() == 4
+    } else if n_columns == 4
         && headers.get(0).unwrap() == "name"
         && headers.get(1).unwrap() == "input_moltype"
         && headers.get(2).unwrap() == "prefix"
@@ -173,6 +276,22 @@ fn detect_csv_type(headers: &csv::StringRecord) -> CSVType {
     }
 }
 
+/// The index of the `param_string` column, if it is the last column.
+fn param_string_column(headers: &csv::StringRecord) -> Option<usize> {
+    match headers.iter().last() {
+        Some(PARAM_STRING_COLUMN) => Some(headers.len() - 1),
+        _ => None,
+    }
+}
+
+/// The `param_string` of a row, if given and not empty.
+fn row_param_string(record: &csv::StringRecord, param_col: Option<usize>) -> Option<String> {
+    param_col
+        .and_then(|col| record.get(col))
+        .map(|p| p.trim().to_lowercase())
+        .filter(|p| !p.is_empty())
+}
+
 pub fn load_fasta_fromfile(
     sketchlist_filename: String,
     force: bool,
@@ -181,19 +300,23 @@ pub fn load_fasta_fromfile(
 
     // Check for right header
     let headers = rdr.headers()?;
+    let param_col = param_string_column(headers);
 
     match detect_csv_type(headers) {
-        CSVType::Assembly => process_assembly_csv(rdr),
-        CSVType::Reads => process_reads_csv(rdr),
-        CSVType::Prefix => process_prefix_csv(rdr, force),
+        CSVType::Assembly => process_assembly_csv(rdr, param_col),
+        CSVType::Reads => process_reads_csv(rdr, param_col),
+        CSVType::Prefix => process_prefix_csv(rdr, param_col, force),
         CSVType::Unknown => Err(anyhow!(
-            "Invalid header. Expected 'name,genome_filename,protein_filename', 'name,read1,read2', or 'name,input_moltype,prefix,exclude', but got '{}'",
+            "Invalid header. Expected 'name,genome_filename,protein_filename', 'name,read1,read2', or 'name,input_moltype,prefix,exclude', optionally followed by 'param_string', but got '{}'",
             headers.iter().collect::<Vec<_>>().join(",")
         )),
     }
 }
 
-fn process_assembly_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<FastaData>, usize)> {
+fn process_assembly_csv(
+    mut rdr: csv::Reader<std::fs::File>,
+    param_col: Option<usize>,
+) -> Result<(Vec<FastaData>, usize)> {
     let mut results = Vec::new();
 
     let mut row_count = 0;
@@ -218,6 +341,7 @@ fn process_assembly_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<Fast
             .get(0)
             .ok_or_else(|| anyhow!("Missing 'name' field"))?
             .to_string();
+        let param_string = row_param_string(&record, param_col);
 
         // Handle optional genome_filename
         if let Some(genome_filename) = record.get(1) {
@@ -226,6 +350,7 @@ fn process_assembly_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<Fast
                     name: name.clone(),
                     paths: vec![PathBuf::from(genome_filename)],
                     input_type: "dna".to_string(),
+                    param_string: param_string.clone(),
                 });
                 genome_count += 1;
             }
@@ -237,6 +362,7 @@ fn process_assembly_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<Fast
                     name: name.clone(),
                     paths: vec![PathBuf::from(protein_filename)],
                     input_type: "protein".to_string(),
+                    param_string: param_string.clone(),
                 });
                 protein_count += 1;
             }
@@ -254,7 +380,10 @@ fn process_assembly_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<Fast
     Ok((results, n_fastas))
 }
 
-fn process_reads_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<FastaData>, usize)> {
+fn process_reads_csv(
+    mut rdr: csv::Reader<std::fs::File>,
+    param_col: Option<usize>,
+) -> Result<(Vec<FastaData>, usize)> {
     let mut results = Vec::new();
     let mut processed_rows = std::collections::HashSet::new();
     let mut read1_count = 0;
@@ -291,6 +420,7 @@ fn process_reads_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<FastaDa
             name: name.clone(),
             paths,
             input_type: "dna".to_string(),
+            param_string: row_param_string(&record, param_col),
         });
     }
 
@@ -310,6 +440,7 @@ fn process_reads_csv(mut rdr: csv::Reader<std::fs::File>) -> Result<(Vec<FastaDa
 
 fn process_prefix_csv(
     mut rdr: csv::Reader<std::fs::File>,
+    param_col: Option<usize>,
     force: bool,
 ) -> Result<(Vec<FastaData>, usize)> {
     let mut results = Vec::new();
@@ -396,6 +527,7 @@ fn process_prefix_csv(
                 name: name.clone(),
                 paths: filtered_paths.to_vec(),
                 input_type: moltype.clone(),
+                param_string: row_param_string(&record, param_col),
             });
         }
     }
@@ -435,7 +567,7 @@ fn process_prefix_csv(
 pub fn load_sketches(
     collection: Collection,
     selection: &Selection,
-    _report_type: ReportType,
+    report_type: ReportType,
 ) -> Result<Vec<SmallSignature>> {
     let sketchinfo: Vec<SmallSignature> = collection
         .par_iter()
@@ -443,6 +575,9 @@ pub fn load_sketches(
             let sig = collection.sig_from_record(record).ok()?;
             let selected_sig = sig.clone().select(selection).ok()?;
             let minhash = selected_sig.minhash()?.clone();
+            if let Some(orig_mh) = sig.minhash() {
+                record_downsample(&report_type, &sig.name(), &sig.md5sum(), orig_mh, &minhash);
+            }
 
             Some(SmallSignature {
                 location: record.internal_location().to_string(),
@@ -457,26 +592,38 @@ pub fn load_sketches(
 }
 
 /// Load a collection of sketches from a file, filtering to keep only
-/// those with a minimum overlap.
+/// those with a minimum overlap. With 'max_matchlist_mem', matches over
+/// that many bytes are spilled to disk; see `MatchList`.
 
 pub fn load_sketches_above_threshold(
     against_collection: Collection,
     query: &KmerMinHash,
     threshold_hashes: u64,
-) -> Result<(BinaryHeap<PrefetchResult>, usize, usize)> {
+    max_matchlist_mem: Option<usize>,
+) -> Result<(MatchList, usize, usize)> {
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
 
-    let matchlist: BinaryHeap<PrefetchResult> = against_collection
+    // matches are added as they are found, so the cap holds while loading.
+    let matchlist = Mutex::new(MatchList::new(max_matchlist_mem));
+    against_collection
         .par_iter()
-        .filter_map(|(_idx, against_record)| {
-            let mut results = Vec::new();
+        .try_for_each(|(_idx, against_record)| -> Result<()> {
             // Load against into memory
             if let Ok(against_sig) = against_collection.sig_from_record(against_record) {
                 if let Some(against_mh) = against_sig.minhash() {
                     // downsample against_mh, but keep original md5sum
                     let against_mh_ds = against_mh.downsample_scaled(query.scaled()).unwrap();
-                    if let Ok(overlap) = against_mh_ds.count_common(query, false) {
+                    record_downsample(
+                        &ReportType::Against,
+                        against_record.name(),
+                        &against_mh.md5sum(),
+                        against_mh,
+                        &against_mh_ds,
+                    );
+                    if !may_reach_threshold(&against_mh_ds, query, threshold_hashes) {
+                        // too small to reach the threshold; skip the intersection.
+                    } else if let Ok(overlap) = against_mh_ds.count_common(query, false) {
                         if overlap >= threshold_hashes {
                             let result = PrefetchResult {
                                 name: against_record.name().to_string(),
@@ -485,7 +632,7 @@ pub fn load_sketches_above_threshold(
                                 location: against_record.internal_location().to_string(),
                                 overlap,
                             };
-                            results.push(result);
+                            matchlist.lock().unwrap().push(result)?;
                         }
                     }
                 } else {
@@ -503,19 +650,213 @@ pub fn load_sketches_above_threshold(
                 );
                 let _i = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
             }
-            if results.is_empty() {
-                None
-            } else {
-                Some(results)
-            }
-        })
-        .flatten()
-        .collect();
+            Ok(())
+        })?;
 
     let skipped_paths = skipped_paths.load(atomic::Ordering::SeqCst);
     let failed_paths = failed_paths.load(atomic::Ordering::SeqCst);
 
-    Ok((matchlist, skipped_paths, failed_paths))
+    Ok((matchlist.into_inner().unwrap(), skipped_paths, failed_paths))
+}
+
+/// The hash seed used by sourmash, and by branchwater unless told otherwise.
+pub const DEFAULT_SEED: u64 = 42;
+
+/// Policies controlling whether problems encountered while loading
+/// collections produce a warning (and continue) or an error.
+#[derive(Debug, Clone, Copy)]
+pub struct Policies {
+    /// Warn rather than fail when some signature paths fail to load.
+    pub allow_failed_paths: bool,
+    /// Warn rather than fail when no signatures are loaded.
+    pub allow_empty_collection: bool,
+    /// Keep only the first sketch for each md5sum.
+    pub dedupe_md5: bool,
+    /// Fail when sketches would need to be downsampled to the selected scaled.
+    pub strict_scaled: bool,
+    /// Warn about sketch names with control characters or excessive length.
+    pub validate_names: bool,
+    /// The hash seed that sketches must have been built with.
+    pub hash_seed: u64,
+    /// What to do when the sketches in a collection have different scaled
+    /// values.
+    pub mixed_scaled: MixedScaled,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Policies {
+            allow_failed_paths: false,
+            allow_empty_collection: false,
+            dedupe_md5: false,
+            strict_scaled: false,
+            validate_names: false,
+            hash_seed: DEFAULT_SEED,
+            mixed_scaled: MixedScaled::default(),
+        }
+    }
+}
+
+/// How to handle a collection whose sketches have different scaled values.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MixedScaled {
+    /// downsample every sketch to the largest scaled, with a warning.
+    #[default]
+    Harmonize,
+    /// fail, listing the sketches that would be downsampled.
+    Error,
+}
+
+impl std::str::FromStr for MixedScaled {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "harmonize" => Ok(MixedScaled::Harmonize),
+            "error" => Ok(MixedScaled::Error),
+            _ => Err(anyhow!(
+                "unknown mixed scaled policy '{}'; must be one of harmonize, error",
+                s
+            )),
+        }
+    }
+}
+
+/// A sketch that was downsampled to reach a common scaled.
+#[derive(Serialize, Debug, Clone)]
+pub struct DownsampleEvent {
+    pub sketch_type: String,
+    pub name: String,
+    pub md5: String,
+    pub original_scaled: u64,
+    pub target_scaled: u64,
+    pub n_hashes_before: usize,
+    pub n_hashes_after: usize,
+}
+
+/// Downsampling events recorded during the current command; None when
+/// no command is tracking them.
+static DOWNSAMPLE_LOG: Mutex<Option<Vec<DownsampleEvent>>> = Mutex::new(None);
+
+/// Start recording downsampling events for a command.
+pub fn start_downsample_log() {
+    *DOWNSAMPLE_LOG.lock().unwrap() = Some(vec![]);
+}
+
+/// Record that sketch 'before' was downsampled to 'after', if the scaled
+/// changed and a command is tracking downsampling.
+pub fn record_downsample(
+    report_type: &ReportType,
+    name: &str,
+    md5: &str,
+    before: &KmerMinHash,
+    after: &KmerMinHash,
+) {
+    record_downsample_sizes(
+        report_type,
+        name,
+        md5,
+        before.scaled(),
+        before.size(),
+        after,
+    );
+}
+
+/// Like `record_downsample`, for a sketch whose original scaled and size
+/// are known but which is no longer loaded, e.g. from a sketch cache.
+pub fn record_downsample_sizes(
+    report_type: &ReportType,
+    name: &str,
+    md5: &str,
+    original_scaled: u64,
+    n_hashes_before: usize,
+    after: &KmerMinHash,
+) {
+    if after.scaled() <= original_scaled {
+        return;
+    }
+    if let Some(events) = DOWNSAMPLE_LOG.lock().unwrap().as_mut() {
+        events.push(DownsampleEvent {
+            sketch_type: report_type.to_string(),
+            name: name.to_string(),
+            md5: md5.to_string(),
+            original_scaled,
+            target_scaled: after.scaled(),
+            n_hashes_before,
+            n_hashes_after: after.size(),
+        });
+    }
+}
+
+/// Stop recording downsampling events, summarize them, and optionally
+/// write one row per downsampled sketch to the CSV 'output', with query
+/// names written as given in 'options'.
+pub fn report_downsampling(output: Option<String>, options: &OutputOptions) -> Result<()> {
+    let mut events = DOWNSAMPLE_LOG.lock().unwrap().take().unwrap_or_default();
+    events.sort_by(|a, b| {
+        (&a.sketch_type, &a.name, &a.md5, a.target_scaled).cmp(&(
+            &b.sketch_type,
+            &b.name,
+            &b.md5,
+            b.target_scaled,
+        ))
+    });
+
+    // summarize by sketch type and scaled values.
+    let mut summary: BTreeMap<(String, u64, u64), (usize, usize, usize)> = BTreeMap::new();
+    for event in events.iter() {
+        let entry = summary
+            .entry((
+                event.sketch_type.clone(),
+                event.original_scaled,
+                event.target_scaled,
+            ))
+            .or_default();
+        entry.0 += 1;
+        entry.1 += event.n_hashes_before;
+        entry.2 += event.n_hashes_after;
+    }
+    for ((sketch_type, original_scaled, target_scaled), (n, before, after)) in summary {
+        eprintln!(
+            "NOTE: downsampled {} {} sketch(es) from scaled={} to scaled={} ({} -> {} hashes).",
+            n, sketch_type, original_scaled, target_scaled, before, after
+        );
+    }
+
+    if let Some(output) = output {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(&output)?;
+        writer.write_record([
+            "sketch_type",
+            "name",
+            "md5",
+            "original_scaled",
+            "target_scaled",
+            "n_hashes_before",
+            "n_hashes_after",
+        ])?;
+        let query_type = ReportType::Query.to_string();
+        for event in events.iter() {
+            if event.sketch_type == query_type {
+                writer.serialize(DownsampleEvent {
+                    name: options.anonymize_query(&event.name),
+                    sketch_type: event.sketch_type.clone(),
+                    md5: event.md5.clone(),
+                    ..*event
+                })?;
+            } else {
+                writer.serialize(event)?;
+            }
+        }
+        writer.flush()?;
+        eprintln!(
+            "Wrote {} downsampling event(s) to '{}'",
+            events.len(),
+            output
+        );
+    }
+    Ok(())
 }
 
 pub enum ReportType {
@@ -655,22 +996,47 @@ fn collection_from_signature(sigpath: &Path, report_type: &ReportType) -> Result
     })
 }
 
+/// Does this path look like a signature file (.sig, .sig.gz, .json, .json.gz)?
+fn is_signature_file(path: &Path) -> bool {
+    let name = path.as_str();
+    [".sig", ".sig.gz", ".json", ".json.gz"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
 pub fn load_collection(
     siglist: &String,
     selection: &Selection,
     report_type: ReportType,
-    allow_failed: bool,
+    policies: &Policies,
 ) -> Result<Collection> {
     let sigpath = PathBuf::from(siglist);
 
     if !sigpath.exists() {
-        bail!("No such file or directory: '{}'", &sigpath);
+        bail!(BranchwaterError::new(
+            ErrorCode::InputNotFound,
+            format!("No such file or directory: '{}'", &sigpath)
+        ));
     }
 
-    // disallow rocksdb input here
-    if is_revindex_database(&sigpath) {
+    // disallow rocksdb input here, including paths that only refer to one,
+    // rather than failing to load them as something else.
+    if let Some(index) = find_revindex_database(&sigpath) {
+        if index != sigpath.components().collect::<PathBuf>() {
+            eprintln!("'{}' refers to the RocksDB index '{}'.", sigpath, index);
+        }
         bail!("Cannot load {} signatures from a 'rocksdb' database. Please use sig, zip, or pathlist.", report_type);
     }
+    if sigpath.is_dir()
+        && (looks_like_partial_revindex(&sigpath) || !revindexes_in(&sigpath).is_empty())
+    {
+        bail!(
+            "Cannot load {} signatures from '{}': {}.",
+            report_type,
+            sigpath,
+            describe_non_revindex(&sigpath)
+        );
+    }
 
     eprintln!("Reading {}(s) from: '{}'", report_type, &siglist);
     let mut last_error = None;
@@ -687,14 +1053,23 @@ pub fn load_collection(
         None
     };
 
-    let collection =
-        collection.or_else(|| match collection_from_manifest(&sigpath, &report_type) {
+    // a literal signature file is only ever loaded as signatures; don't
+    // fall back to manifest or pathlist loading, which would report
+    // misleading errors about the lines in the file.
+    let is_sigfile = is_signature_file(&sigpath);
+
+    let collection = collection.or_else(|| {
+        if is_sigfile {
+            return None;
+        }
+        match collection_from_manifest(&sigpath, &report_type) {
             Ok(coll) => Some((coll, 0)),
             Err(e) => {
                 last_error = Some(e);
                 None
             }
-        });
+        }
+    });
 
     let collection =
         collection.or_else(|| match collection_from_signature(&sigpath, &report_type) {
@@ -705,27 +1080,37 @@ pub fn load_collection(
             }
         });
 
-    let collection =
-        collection.or_else(|| match collection_from_pathlist(&sigpath, &report_type) {
+    let collection = collection.or_else(|| {
+        if is_sigfile {
+            return None;
+        }
+        match collection_from_pathlist(&sigpath, &report_type) {
             Ok((coll, n_failed)) => Some((coll, n_failed)),
             Err(e) => {
                 last_error = Some(e);
                 None
             }
-        });
+        }
+    });
 
     match collection {
         Some((coll, n_failed)) => {
             let n_total = coll.len();
+            let available = available_sketch_types(&coll);
             let selected = coll.select(selection)?;
+            if selected.is_empty() && n_total > 0 {
+                let reason = explain_empty_selection(&coll, &available, selection, &report_type);
+                if !policies.allow_empty_collection {
+                    bail!(BranchwaterError::new(
+                        ErrorCode::NoCompatibleSketches,
+                        format!("No compatible {} signatures: {}", report_type, reason)
+                    ));
+                }
+                eprintln!("WARNING: {}", reason);
+            }
             let n_skipped = n_total - selected.len();
-            report_on_collection_loading(
-                &selected,
-                n_skipped,
-                n_failed,
-                report_type,
-                allow_failed,
-            )?;
+            let selected = apply_collection_policies(selected, selection, &report_type, policies)?;
+            report_on_collection_loading(&selected, n_skipped, n_failed, report_type, policies)?;
             Ok(selected)
         }
         None => {
@@ -741,10 +1126,362 @@ pub fn load_collection(
     }
 }
 
+/// The number of offending sketches listed by `audit_collection_scaled`.
+const MAX_LISTED_SCALED: usize = 10;
+
+/// Read the numeric manifest fields named in 'columns' (e.g. "scaled",
+/// "n_hashes") for every record in 'manifest', in order. These have no
+/// getters on Record, so the manifest is written out as CSV once and the
+/// columns are read back; missing values are read as 0.
+pub(crate) fn manifest_columns(manifest: &Manifest, columns: &[&str]) -> Result<Vec<Vec<u64>>> {
+    if manifest.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut buf = Vec::new();
+    manifest.to_writer(&mut buf)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(buf.as_slice());
+    let headers = rdr.headers()?.clone();
+    let indices = columns
+        .iter()
+        .map(|column| {
+            headers
+                .iter()
+                .position(|header| header == *column)
+                .ok_or_else(|| anyhow!("manifest has no '{}' column", column))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    rdr.records()
+        .map(|row| {
+            let row = row?;
+            Ok(indices
+                .iter()
+                .map(|i| row.get(*i).and_then(|v| v.parse().ok()).unwrap_or(0))
+                .collect())
+        })
+        .collect()
+}
+
+/// Check that the sketches in a selected collection share a single scaled.
+/// Sketches with a smaller scaled than the largest are downsampled when
+/// loaded; with `MixedScaled::Error` they're listed in an error instead.
+fn audit_collection_scaled(
+    collection: &Collection,
+    selection: &Selection,
+    report_type: &ReportType,
+    policy: MixedScaled,
+) -> Result<()> {
+    // 0 for num sketches.
+    let scaleds: Vec<u64> = manifest_columns(collection.manifest(), &["scaled"])?
+        .into_iter()
+        .map(|row| row[0])
+        .collect();
+    let mut by_scaled: BTreeMap<u64, usize> = BTreeMap::new();
+    for scaled in scaleds.iter().filter(|s| **s > 0) {
+        *by_scaled.entry(*scaled).or_default() += 1;
+    }
+    if by_scaled.len() < 2 {
+        return Ok(());
+    }
+    let max_scaled = *by_scaled.keys().next_back().unwrap();
+    let counts = by_scaled
+        .iter()
+        .map(|(scaled, n)| format!("{} at scaled={}", n, scaled))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match policy {
+        MixedScaled::Harmonize => {
+            // sketches are downsampled to the selected scaled when loaded,
+            // which is never below the largest scaled left in the selection.
+            let target = selection
+                .scaled()
+                .map_or(max_scaled, |s| (s as u64).max(max_scaled));
+            eprintln!(
+                "WARNING: {} sketches have mixed scaled values ({}); downsampling all to scaled={}.",
+                report_type, counts, target
+            );
+            Ok(())
+        }
+        MixedScaled::Error => {
+            let offending: Vec<String> = collection
+                .iter()
+                .zip(scaleds.iter())
+                .filter(|(_, scaled)| **scaled > 0 && **scaled < max_scaled)
+                .map(|((_idx, record), scaled)| {
+                    format!(
+                        "  '{}' (scaled={}) in '{}'",
+                        record.name(),
+                        scaled,
+                        record.internal_location()
+                    )
+                })
+                .collect();
+            let mut listing = offending
+                .iter()
+                .take(MAX_LISTED_SCALED)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n");
+            if offending.len() > MAX_LISTED_SCALED {
+                listing.push_str(&format!(
+                    "\n  ... and {} more",
+                    offending.len() - MAX_LISTED_SCALED
+                ));
+            }
+            bail!(BranchwaterError::new(
+                ErrorCode::ScaledMismatch,
+                format!(
+                    "{} sketches have mixed scaled values ({}). These {} sketches have a scaled below {}:\n{}\nRe-sketch them at a single scaled, or use '--mixed-scaled harmonize' to downsample them to scaled={}.",
+                    report_type,
+                    counts,
+                    offending.len(),
+                    max_scaled,
+                    listing,
+                    max_scaled
+                )
+            ));
+        }
+    }
+}
+
+/// Apply the md5 deduplication, strict scaled, and mixed scaled policies
+/// to a selected collection.
+fn apply_collection_policies(
+    collection: Collection,
+    selection: &Selection,
+    report_type: &ReportType,
+    policies: &Policies,
+) -> Result<Collection> {
+    if policies.strict_scaled {
+        if let Some(scaled) = selection.scaled() {
+            // selection already excludes sketches with a larger scaled, so
+            // anything left with a smaller scaled would be downsampled.
+            let mut finer = Selection::default();
+            finer.set_scaled(scaled - 1);
+            let n_mismatched = collection.manifest().clone().select(&finer)?.len();
+            if n_mismatched > 0 {
+                bail!(BranchwaterError::new(
+                    ErrorCode::ScaledMismatch,
+                    format!(
+                        "{} {} sketches have a scaled other than {}; refusing to downsample.",
+                        n_mismatched, report_type, scaled
+                    )
+                ));
+            }
+        }
+    }
+
+    audit_collection_scaled(&collection, selection, report_type, policies.mixed_scaled)?;
+
+    check_collection_seed(&collection, policies.hash_seed, report_type)?;
+
+    if policies.validate_names {
+        let bad_names: Vec<String> = collection
+            .iter()
+            .filter(|(_idx, record)| sanitize_name(record.name(), MAX_NAME_LENGTH).is_some())
+            .map(|(_idx, record)| record.name().clone())
+            .collect();
+        if !bad_names.is_empty() {
+            eprintln!(
+                "WARNING: {} {} sketches have names with control characters or more than {} characters:",
+                bad_names.len(),
+                report_type,
+                MAX_NAME_LENGTH
+            );
+            for name in bad_names.iter().take(5) {
+                eprintln!("  {:?}", name);
+            }
+        }
+    }
+
+    if !policies.dedupe_md5 {
+        return Ok(collection);
+    }
+
+    let mut seen = HashSet::new();
+    let records: Vec<Record> = collection
+        .iter()
+        .filter(|(_idx, record)| seen.insert(record.md5().clone()))
+        .map(|(_idx, record)| record.clone())
+        .collect();
+
+    let n_duplicates = collection.len() - records.len();
+    if n_duplicates == 0 {
+        return Ok(collection);
+    }
+    eprintln!(
+        "WARNING: skipped {} {} sketches with duplicate md5sums.",
+        n_duplicates, report_type
+    );
+
+    Ok(Collection::new(
+        Manifest::from(records),
+        collection.storage().clone(),
+    ))
+}
+
+/// Check that the sketches in 'collection' were built with hash seed 'seed'.
+/// Manifests don't record seeds, so this loads the first sketch, and
+/// assumes that a collection uses a single seed; sketches that fail to load
+/// are left for the command to report.
+pub fn check_collection_seed(
+    collection: &Collection,
+    seed: u64,
+    report_type: &ReportType,
+) -> Result<()> {
+    let Some((_idx, record)) = collection.iter().next() else {
+        return Ok(());
+    };
+    let Ok(sig) = collection.sig_from_record(record) else {
+        return Ok(());
+    };
+    if let Some(mh) = sig.minhash() {
+        if mh.seed() != seed {
+            bail!(BranchwaterError::new(
+                ErrorCode::SeedMismatch,
+                format!(
+                    "{} sketch '{}' was built with hash seed {}, but seed {} was requested; sketches can only be compared with the same seed. Use '--hash-seed {}' if that seed is intended.",
+                    report_type,
+                    record.name(),
+                    mh.seed(),
+                    seed,
+                    mh.seed()
+                )
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the sketches indexed by the RocksDB index at 'index' were
+/// built with hash seed 'seed'.
+pub fn check_index_seed(index: &camino::Utf8PathBuf, seed: u64) -> Result<()> {
+    let collection = crate::check::collection_from_revindex(index)?;
+    check_collection_seed(&collection, seed, &ReportType::Against)
+}
+
+/// Collect the moltypes in a collection, with the ksizes available for each.
+fn available_sketch_types(collection: &Collection) -> BTreeMap<String, BTreeSet<u32>> {
+    let mut available: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
+    for (_idx, record) in collection.iter() {
+        available
+            .entry(record.moltype().to_string())
+            .or_default()
+            .insert(record.ksize());
+    }
+    available
+}
+
+/// Explain why none of the sketches in 'collection' matched 'selection',
+/// given the moltypes and ksizes that are available, and suggest the
+/// parameters that would select them.
+fn explain_empty_selection(
+    collection: &Collection,
+    available: &BTreeMap<String, BTreeSet<u32>>,
+    selection: &Selection,
+    report_type: &ReportType,
+) -> String {
+    let describe = |ksizes: &BTreeSet<u32>| {
+        ksizes
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let wanted_moltype = selection
+        .moltype()
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| "DNA".to_string());
+    let wanted_ksize = selection
+        .ksize()
+        .map(|k| k.to_string())
+        .unwrap_or_else(|| "any".to_string());
+
+    match available.get(&wanted_moltype) {
+        None => {
+            let present = available
+                .iter()
+                .map(|(moltype, ksizes)| format!("{} (k={})", moltype, describe(ksizes)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // suggest the first available moltype; BTreeMap order is stable.
+            let (moltype, ksizes) = available.iter().next().expect("non-empty collection");
+            let ksize = ksizes.iter().next().expect("non-empty ksizes");
+            let mut msg = format!(
+                "no {} sketches were found in the {} collection, which contains {} sketches. To use these sketches, run with '-m {} -k {}'.",
+                wanted_moltype, report_type, present, moltype, ksize
+            );
+            if wanted_moltype == "DNA" || moltype == "DNA" {
+                let target = if moltype == "DNA" { &wanted_moltype } else { moltype };
+                msg.push_str(&format!(
+                    " Sketches are not translated between DNA and {} at search time; sketch all inputs as {} (e.g. 'sourmash scripts manysketch -p {},k=10' on protein FASTA, or 'sourmash sketch translate' on DNA sequences) before comparing them.",
+                    target, target, target
+                ));
+            }
+            msg
+        }
+        Some(ksizes) if selection.ksize().into_iter().all(|k| ksizes.contains(&k)) => {
+            match selection.num().filter(|n| *n != 0) {
+                Some(num) => format!(
+                    "the {} collection has {} k={} sketches, but none are num={} sketches.",
+                    report_type, wanted_moltype, wanted_ksize, num
+                ),
+                None => {
+                    // selecting a scaled always drops num sketches, so a
+                    // larger scaled can't help if that's all there is.
+                    let rows =
+                        manifest_columns(collection.manifest(), &["num"]).unwrap_or_default();
+                    let nums: BTreeSet<u64> = collection
+                        .iter()
+                        .zip(rows)
+                        .filter(|((_idx, record), _)| {
+                            record.moltype().to_string() == wanted_moltype
+                                && selection.ksize().map_or(true, |k| record.ksize() == k)
+                        })
+                        .map(|(_, row)| row[0])
+                        .collect();
+                    if !nums.is_empty() && !nums.contains(&0) {
+                        let nums: Vec<String> = nums.iter().map(|n| n.to_string()).collect();
+                        return format!(
+                            "the {} collection has {} k={} sketches, but these are num sketches (num={}), which can't be compared at any scaled; use '--num {}' where supported (e.g. multisearch), or sketch with scaled instead.",
+                            report_type,
+                            wanted_moltype,
+                            wanted_ksize,
+                            nums.join(", "),
+                            nums[0]
+                        );
+                    }
+                    format!(
+                    "the {} collection has {} k={} sketches, but none are compatible with scaled={}; run with a larger '-s/--scaled'.",
+                    report_type,
+                    wanted_moltype,
+                    wanted_ksize,
+                    selection
+                        .scaled()
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|| "unset".to_string())
+                    )
+                }
+            }
+        }
+        Some(ksizes) => format!(
+            "the {} collection has no {} k={} sketches; available {} ksizes are {}. Run with '-k {}'.",
+            report_type,
+            wanted_moltype,
+            wanted_ksize,
+            wanted_moltype,
+            describe(ksizes),
+            ksizes.iter().next().expect("non-empty ksizes")
+        ),
+    }
+}
+
 /// Uses the output of collection loading function to report the
 /// total number of sketches loaded, as well as the number of files,
 /// if any, that failed to load or contained no compatible sketches.
-/// If no sketches were loaded, bail.
+/// If no sketches were loaded, bail unless `allow_empty_collection` is set.
 ///
 /// # Arguments
 ///
@@ -753,6 +1490,7 @@ pub fn load_collection(
 /// * `failed_paths` - # paths that failed to load.
 /// * `report_type` - ReportType Enum (Query or Against). Used to specify
 ///                   which sketch input this information pertains to.
+/// * `policies` - Whether failed paths or an empty collection are errors.
 ///
 /// # Returns
 ///
@@ -768,15 +1506,18 @@ pub fn report_on_collection_loading(
     skipped_paths: usize,
     failed_paths: usize,
     report_type: ReportType,
-    allow_failed: bool,
+    policies: &Policies,
 ) -> Result<()> {
     if failed_paths > 0 {
         eprintln!(
             "WARNING: {} {} paths failed to load. See error messages above.",
             failed_paths, report_type
         );
-        if !allow_failed {
-            bail! {"Signatures failed to load. Exiting."}
+        if !policies.allow_failed_paths {
+            bail!(BranchwaterError::new(
+                ErrorCode::InputLoadFailed,
+                "Signatures failed to load. Exiting."
+            ));
         }
     }
     if skipped_paths > 0 {
@@ -788,7 +1529,13 @@ pub fn report_on_collection_loading(
 
     // Validate sketches
     if collection.is_empty() {
-        bail!("No {} signatures loaded, exiting.", report_type);
+        if !policies.allow_empty_collection {
+            bail!(BranchwaterError::new(
+                ErrorCode::NoCompatibleSketches,
+                format!("No {} signatures loaded, exiting.", report_type)
+            ));
+        }
+        eprintln!("WARNING: no {} signatures loaded.", report_type);
     }
     eprintln!("Loaded {} {} signature(s)", collection.len(), report_type);
     Ok(())
@@ -916,22 +1663,313 @@ pub fn branchwater_calculate_gather_stats(
         max_containment_ani,
         sum_weighted_found: sum_total_weighted_found,
         total_weighted_hashes,
+        match_n_hashes: match_mh.size(),
+        match_intersect_hashes: intersect_orig as usize,
+        match_intersect_hashes_at_rank: match_size,
     };
     Ok(result)
 }
 
-/// Execute the gather algorithm, greedy min-set-cov, by iteratively
-/// removing matches in 'matchlist' from 'query'.
+/// A spike-in reference of known abundance, used to normalize the weighted
+/// abundance of gather matches. 'name' matches a match name, the first word
+/// of a match name (usually the accession), or a match md5.
+#[derive(Debug, Clone)]
+pub struct SpikeIn {
+    pub name: String,
+    pub amount: f64,
+}
+
+impl SpikeIn {
+    /// Build a spike-in from '--spike-in' and '--spike-in-amount', if given.
+    pub fn from_options(name: Option<String>, amount: f64) -> Result<Option<Self>> {
+        match name {
+            Some(name) if amount.is_nan() || amount <= 0.0 => bail!(
+                "the spike-in amount for '{}' must be greater than 0, not {}",
+                name,
+                amount
+            ),
+            Some(name) => Ok(Some(SpikeIn { name, amount })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn matches(&self, match_name: &str, match_md5: &str) -> bool {
+        match_name == self.name
+            || match_md5 == self.name
+            || match_name.split_whitespace().next() == Some(self.name.as_str())
+    }
+}
+
+/// The criterion used to choose the next-best match in gather.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RankBy {
+    /// the most hashes in common with the remaining query.
+    #[default]
+    Overlap,
+    /// the most query abundance in common with the remaining query.
+    FUniqueWeighted,
+    /// the highest containment ANI of the match in the remaining query.
+    Ani,
+}
+
+impl std::str::FromStr for RankBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "overlap" => Ok(RankBy::Overlap),
+            "f_unique_weighted" => Ok(RankBy::FUniqueWeighted),
+            "ani" => Ok(RankBy::Ani),
+            _ => Err(anyhow!(
+                "unknown gather ranking '{}'; must be one of overlap, f_unique_weighted, ani",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for RankBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rank_by = match self {
+            RankBy::Overlap => "overlap",
+            RankBy::FUniqueWeighted => "f_unique_weighted",
+            RankBy::Ani => "ani",
+        };
+        write!(f, "{}", rank_by)
+    }
+}
+
+/// The order in which queries are handed out to threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Schedule {
+    /// the largest queries first, so that a few large queries started
+    /// last don't leave the other threads idle at the end of a run.
+    #[default]
+    Size,
+    /// the order of the query collection.
+    InputOrder,
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "size" => Ok(Schedule::Size),
+            "input-order" => Ok(Schedule::InputOrder),
+            _ => Err(anyhow!(
+                "unknown query schedule '{}'; must be one of size, input-order",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let schedule = match self {
+            Schedule::Size => "size",
+            Schedule::InputOrder => "input-order",
+        };
+        write!(f, "{}", schedule)
+    }
+}
 
+/// Iterate over the records of 'collection' in parallel, in the order
+/// given by 'schedule'. Sizes are estimated from the manifest, at the
+/// scaled of 'selection'; records of the same size keep their input order.
+pub fn par_iter_scheduled<'a>(
+    collection: &'a Collection,
+    selection: &'a Selection,
+    schedule: Schedule,
+) -> impl ParallelIterator<Item = (Idx, &'a Record)> + 'a {
+    match schedule {
+        Schedule::InputOrder => Either::Left(collection.par_iter()),
+        Schedule::Size => {
+            let mut records: Vec<_> = collection
+                .iter()
+                .zip(effective_sizes(collection, selection))
+                .collect();
+            records.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+            // par_bridge hands out records one at a time, in order, as
+            // threads become free; par_iter would split them into blocks.
+            Either::Right(records.into_iter().map(|(record, _)| record).par_bridge())
+        }
+    }
+}
+
+/// Choose the next gather match from 'matches' according to 'rank_by'.
+/// Overlaps in 'matches' are against the remaining 'query_mh'; ties are
+/// broken as in the 'PrefetchResult' ordering, so results are reproducible.
+fn next_best_match<'a>(
+    matches: &'a BinaryHeap<PrefetchResult>,
+    query_mh: &KmerMinHash,
+    ksize: usize,
+    rank_by: RankBy,
+) -> Option<&'a PrefetchResult> {
+    // abundance-weighted overlap is the same as overlap for flat queries.
+    let abunds: HashMap<u64, u64> = match rank_by {
+        RankBy::FUniqueWeighted => query_mh.to_vec_abunds().into_iter().collect(),
+        _ => HashMap::new(),
+    };
+    let score = |m: &PrefetchResult| -> f64 {
+        match rank_by {
+            RankBy::Overlap => m.overlap as f64,
+            RankBy::FUniqueWeighted => m
+                .minhash
+                .iter_mins()
+                .filter_map(|hash| abunds.get(hash))
+                .sum::<u64>() as f64,
+            RankBy::Ani => {
+                ani_from_containment(m.overlap as f64 / m.minhash.size() as f64, ksize as f64)
+            }
+        }
+    };
+
+    match rank_by {
+        RankBy::Overlap => matches.peek(),
+        _ => matches
+            .iter()
+            .map(|m| (score(m), m))
+            .max_by(|(a_score, a), (b_score, b)| {
+                a_score
+                    .partial_cmp(b_score)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| a.cmp(b))
+            })
+            .map(|(_, m)| m),
+    }
+}
+
+/// How gather chooses matches and calculates their statistics.
+#[derive(Debug, Clone, Default)]
+pub struct GatherOptions {
+    /// The criterion used to pick the next-best match at each rank.
+    pub rank_by: RankBy,
+    /// Hold results until gather finishes, and give each a
+    /// 'normalized_abundance' relative to the spike-in's weighted hashes.
+    pub spike_in: Option<SpikeIn>,
+    /// Treat the query as flat, calculating no abundance-weighted
+    /// statistics.
+    pub ignore_abundance: bool,
+}
+
+/// Where gather writes its results for one query.
+#[derive(Default)]
+pub struct GatherOutputs<'a> {
+    /// The gather results, written to stdout if None.
+    pub gather: Option<String>,
+    /// A zipfile of the remaining (subtracted) query sketch after each rank.
+    pub remaining: Option<String>,
+    /// A CSV of the gather rank that claimed each query hash.
+    pub coverage: Option<String>,
+    /// A Krona text file of the fraction of the query assigned to each
+    /// lineage.
+    pub krona: Option<(String, &'a Lineages)>,
+    /// Only these columns of the gather results are written.
+    pub columns: Option<Vec<String>>,
+    /// CSV, Parquet, JSON Lines, or a SQLite table named for 'command'.
+    pub format: OutputFormat,
+    pub command: &'static str,
+    /// Send results here as they are found, rather than writing them.
+    pub results_channel: Option<std::sync::mpsc::SyncSender<BranchwaterGatherResult>>,
+    /// Also add each result to this report.
+    pub report: Option<&'a RunReport>,
+    /// Checkpoint the gather output, to resume or skip it later.
+    pub checkpoint: Option<Checkpointing>,
+}
+
+/// Execute the gather algorithm, greedy min-set-cov, by iteratively
+/// removing matches in 'matchlist' from 'query', choosing matches as given
+/// in 'gather' and writing them to 'outputs' with 'options'.
+///
+/// Matches spilled from 'matchlist' to disk are reloaded when they may be
+/// the next-best match, which requires ranking by overlap. With a
+/// checkpoint, the gather output is flushed and a `GatherCheckpoint` is
+/// written every so many results, and gather can resume from the
+/// checkpoint of an interrupted run. With a results channel, gather stops
+/// early if the receiver is dropped.
 pub fn consume_query_by_gather(
     query: SigStore,
     scaled: u64,
-    matchlist: BinaryHeap<PrefetchResult>,
+    matchlist: MatchList,
     threshold_hashes: u64,
-    gather_output: Option<String>,
+    gather: &GatherOptions,
+    outputs: GatherOutputs,
+    options: &OutputOptions,
 ) -> Result<()> {
+    let GatherOptions {
+        rank_by,
+        spike_in,
+        ignore_abundance,
+    } = gather.clone();
+    let GatherOutputs {
+        gather: gather_output,
+        remaining: remaining_output,
+        coverage: coverage_output,
+        krona: krona_output,
+        columns,
+        format: output_format,
+        command,
+        results_channel,
+        report,
+        checkpoint,
+    } = outputs;
+    check_columns::<BranchwaterGatherResult>(columns.as_ref(), None)?;
+    if matches!(output_format, OutputFormat::Parquet | OutputFormat::Sqlite) {
+        if gather_output.is_none() {
+            bail!(
+                "--output-format {} requires a gather output file.",
+                output_format.extension()
+            );
+        }
+        if checkpoint.is_some() {
+            // a Parquet file or SQLite table can't be truncated back to a
+            // checkpoint.
+            bail!(
+                "--checkpoint-every can't be used with --output-format {}.",
+                output_format.extension()
+            );
+        }
+    }
+    // checkpoints record the rows written to a file, and can't restore
+    // held results or the remaining sketches of earlier ranks.
+    let checkpoint_output = match (&checkpoint, &gather_output) {
+        (None, _) => None,
+        (Some(_), None) => bail!("--checkpoint-every requires a gather output file."),
+        (Some(_), Some(_)) if spike_in.is_some() => {
+            bail!("--checkpoint-every can't be used with --spike-in.")
+        }
+        (Some(_), Some(_)) if remaining_output.is_some() => {
+            bail!("--checkpoint-every can't be used with saving the remaining query sketches.")
+        }
+        (Some(_), Some(output)) => Some(output.clone()),
+    };
+    let resumed = match (&checkpoint, &checkpoint_output) {
+        (Some(c), Some(output)) if c.resume => GatherCheckpoint::load(output)?,
+        _ => None,
+    };
+    if let (Some(resumed), Some(output)) = (&resumed, &checkpoint_output) {
+        resumed.check(
+            output,
+            &query.minhash().unwrap().md5sum(),
+            scaled,
+            threshold_hashes,
+            rank_by,
+        )?;
+        if resumed.complete {
+            eprintln!(
+                "{}: gather already completed in '{}'; skipping.",
+                query.filename(),
+                output
+            );
+            return Ok(());
+        }
+    }
+
     // Define the writer to stdout by default
     let mut writer: Box<dyn Write> = Box::new(std::io::stdout());
+    let mut parquet_writer = None;
 
     if let Some(output_path) = &gather_output {
         // Account for potential missing dir in output path
@@ -941,13 +1979,102 @@ pub fn consume_query_by_gather(
         if let Some(dir) = directory_path {
             create_dir_all(dir)?;
         }
-
-        let file = File::create(output_path)?;
-        writer = Box::new(BufWriter::new(file));
     }
+    // a SQLite database is opened by its writer thread, below, without
+    // truncating the tables of other commands.
+    if let Some(output_path) = gather_output
+        .as_ref()
+        .filter(|_| output_format != OutputFormat::Sqlite)
+    {
+        let file = match &resumed {
+            // keep the rows up to the checkpoint, and append to them.
+            Some(resumed) => {
+                let file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(output_path)
+                    .with_context(|| {
+                        format!("Failed to open '{}' to resume gather", output_path)
+                    })?;
+                if file.metadata()?.len() < resumed.output_bytes {
+                    bail!(
+                        "'{}' is shorter than its checkpoint records; remove '{}' to start over.",
+                        output_path,
+                        GatherCheckpoint::path(output_path)
+                    );
+                }
+                file.set_len(resumed.output_bytes)?;
+                file
+            }
+            None => File::create(output_path)?,
+        };
+        if output_format == OutputFormat::Parquet {
+            parquet_writer = Some(ParquetResultWriter::<BranchwaterGatherResult>::new(
+                Box::new(BufWriter::new(file)),
+                columns.clone(),
+                None,
+                options.clone(),
+            )?);
+        } else {
+            writer = Box::new(BufWriter::new(file));
+        }
+    }
+    let mut jsonl_writer = None;
+    if output_format == OutputFormat::Jsonl {
+        jsonl_writer = Some(JsonlWriter::<_, BranchwaterGatherResult>::new(
+            writer,
+            columns.clone(),
+            None,
+            options.clone(),
+        )?);
+        writer = Box::new(std::io::sink());
+    }
+    // SQLite results are sent to a writer thread, as for 'results_channel'.
+    let mut sqlite_thread = None;
+    let results_channel = match results_channel {
+        None if output_format == OutputFormat::Sqlite => {
+            let (send, recv) = std::sync::mpsc::sync_channel(rayon::current_num_threads());
+            let extras = WriterExtras {
+                format: output_format,
+                command,
+                options: options.clone(),
+                ..WriterExtras::default()
+            };
+            sqlite_thread = Some(csvwriter_thread_with_extras(
+                recv,
+                gather_output.iter().cloned().collect(),
+                columns.clone(),
+                0,
+                extras,
+            ));
+            Some(send)
+        }
+        results_channel => results_channel,
+    };
     // create csv writer
     let mut csv_writer = Writer::from_writer(writer);
+    let mut column_selection = ColumnSelection::from_options(columns, None, options);
+    if resumed.is_some() {
+        // the header was written before the checkpoint.
+        column_selection = Some(
+            column_selection
+                .unwrap_or_else(|| ColumnSelection::from_columns(None))
+                .without_header(),
+        );
+    }
 
+    // optionally, set up a zip writer for the remaining query sketches
+    let remaining_writer = remaining_output.map(|output| {
+        let (send, recv) = std::sync::mpsc::channel::<ZipMessage>();
+        let thrd = sigwriter(recv, output);
+        (send, thrd)
+    });
+
+    if matchlist.n_spilled() > 0 && rank_by != RankBy::Overlap {
+        bail!(
+            "spilling matches to disk requires --rank-by overlap, not --rank-by {}.",
+            rank_by
+        );
+    }
     let mut matching_sketches = matchlist;
     let mut rank = 0;
 
@@ -955,7 +2082,10 @@ pub fn consume_query_by_gather(
 
     let location = query.filename();
 
-    let orig_query_mh = query.minhash().unwrap();
+    let mut orig_query_mh = query.minhash().unwrap().clone();
+    if ignore_abundance {
+        orig_query_mh.disable_abundance();
+    }
     let query_bp = orig_query_mh.n_unique_kmers() as usize;
     let query_n_hashes = orig_query_mh.size();
     let mut query_moltype = orig_query_mh.hash_function().to_string();
@@ -973,6 +2103,10 @@ pub fn consume_query_by_gather(
 
     let mut last_hashes = orig_query_mh.size();
 
+    // optionally, track which gather rank claimed each query hash
+    let mut claimed_by_rank: Option<HashMap<u64, usize>> =
+        coverage_output.as_ref().map(|_| HashMap::new());
+
     // some items for full gather results
 
     let mut sum_weighted_found = 0;
@@ -983,6 +2117,17 @@ pub fn consume_query_by_gather(
     let calc_ani_ci = false;
     let ani_confidence_interval_fraction = None;
 
+    if spike_in.is_some() && !calc_abund_stats {
+        bail!(
+            "spike-in normalization requires a query sketch with abundances, but '{}' has none.",
+            query_name
+        );
+    }
+    // with a spike-in, hold results until its weighted hashes are known
+    let mut held_results: Vec<BranchwaterGatherResult> = vec![];
+    // the weighted fraction of the query found by each match, for Krona
+    let mut krona_found: Vec<(String, f64)> = vec![];
+
     eprintln!(
         "{} iter {}: start: query hashes={} matches={}",
         location,
@@ -990,12 +2135,95 @@ pub fn consume_query_by_gather(
         orig_query_mh.size(),
         matching_sketches.len()
     );
+    if matching_sketches.n_spilled() > 0 {
+        eprintln!(
+            "{} iter {}: spilled {} of {} matches to disk",
+            location,
+            rank,
+            matching_sketches.n_spilled(),
+            matching_sketches.len()
+        );
+    }
+
+    let mut checkpoint_state = checkpoint_output.as_ref().map(|_| {
+        resumed.clone().unwrap_or_else(|| {
+            GatherCheckpoint::new(&query_md5sum, scaled, threshold_hashes, rank_by)
+        })
+    });
+
+    // replay the ranks completed before the checkpoint: remove each match's
+    // hashes from the query, and then drop the matches that no longer
+    // reach the threshold, as gather would have.
+    if let (Some(resumed), Some(output)) = (&resumed, &checkpoint_output) {
+        let keys: HashMap<(&str, &str), usize> = resumed
+            .matches
+            .iter()
+            .enumerate()
+            .map(|(i, m)| ((m.match_md5.as_str(), m.match_location.as_str()), i))
+            .collect();
+        let mut sketches: Vec<Option<KmerMinHash>> = vec![None; resumed.matches.len()];
+        matching_sketches.for_each(|m| {
+            if let Some(i) = keys.get(&(m.md5sum.as_str(), m.location.as_str())) {
+                sketches[*i] = Some(m.minhash.clone());
+            }
+            Ok(())
+        })?;
+
+        for (m, match_mh) in resumed.matches.iter().zip(sketches) {
+            let Some(match_mh) = match_mh else {
+                bail!(
+                    "match '{}' in gather checkpoint '{}' was not found again; remove the checkpoint to start over.",
+                    m.match_name,
+                    GatherCheckpoint::path(output)
+                );
+            };
+            query_mh = query_mh.downsample_scaled(match_mh.scaled())?;
+            orig_query_ds = orig_query_ds.downsample_scaled(match_mh.scaled())?;
+            if let Some(claimed) = claimed_by_rank.as_mut() {
+                let (hashes, _) = query_mh.intersection(&match_mh)?;
+                for hash in hashes {
+                    claimed.entry(hash).or_insert(rank);
+                }
+            }
+            query_mh.remove_from(&match_mh)?;
+            if krona_output.is_some() {
+                krona_found.push((m.match_name.clone(), m.f_unique_weighted));
+            }
+            sum_weighted_found = m.sum_weighted_found;
+            rank += 1;
+        }
+        matching_sketches.update_overlaps(&query_mh, threshold_hashes);
+
+        eprintln!(
+            "{} iter {}: resumed from checkpoint: query hashes={} matches={}",
+            location,
+            rank,
+            query_mh.size(),
+            matching_sketches.len()
+        );
+        last_hashes = query_mh.size();
+        last_matches = matching_sketches.len();
+    }
 
     while !matching_sketches.is_empty() {
-        let best_element = matching_sketches.peek().unwrap();
+        // the next-best match may have been spilled to disk
+        matching_sketches.reload_if_needed(&query_mh, threshold_hashes)?;
+        let best_element =
+            match next_best_match(matching_sketches.in_memory(), &query_mh, ksize, rank_by) {
+                Some(best_element) => best_element,
+                None => break,
+            };
 
         query_mh = query_mh.downsample_scaled(best_element.minhash.scaled())?;
-        orig_query_ds = orig_query_ds.downsample_scaled(best_element.minhash.scaled())?;
+        let query_ds = orig_query_ds.downsample_scaled(best_element.minhash.scaled())?;
+        record_downsample(
+            &ReportType::Query,
+            &query_name,
+            &query_md5sum,
+            &orig_query_ds,
+            &query_ds,
+        );
+        orig_query_ds = query_ds;
 
         //calculate full gather stats
         let match_ = branchwater_calculate_gather_stats(
@@ -1048,24 +2276,93 @@ pub fn consume_query_by_gather(
             n_unique_weighted_found: match_.n_unique_weighted_found,
             sum_weighted_found: match_.sum_weighted_found,
             total_weighted_hashes: match_.total_weighted_hashes,
+            match_n_hashes: match_.match_n_hashes,
+            match_intersect_hashes: match_.match_intersect_hashes,
+            match_intersect_hashes_at_rank: match_.match_intersect_hashes_at_rank,
 
             query_containment_ani_ci_low: match_.query_containment_ani_ci_low,
             query_containment_ani_ci_high: match_.query_containment_ani_ci_high,
             match_containment_ani_ci_low: match_.match_containment_ani_ci_low,
             match_containment_ani_ci_high: match_.match_containment_ani_ci_high,
+            normalized_abundance: None,
         };
         sum_weighted_found = gather_result.sum_weighted_found;
+        if krona_output.is_some() {
+            krona_found.push((
+                gather_result.match_name.clone(),
+                gather_result.f_unique_weighted,
+            ));
+        }
+        // held results are added to the report once they're normalized.
+        if let (Some(report), None) = (report, &spike_in) {
+            report.add(&gather_result)?;
+        }
         // serialize result to file.
-        csv_writer.serialize(gather_result)?;
+        if spike_in.is_some() {
+            held_results.push(gather_result);
+        } else if let Some(send) = &results_channel {
+            if send.send(gather_result).is_err() {
+                // nobody is reading the results any more.
+                break;
+            }
+        } else if let Some(parquet_writer) = parquet_writer.as_mut() {
+            parquet_writer.write(&gather_result)?;
+        } else if let Some(jsonl_writer) = jsonl_writer.as_mut() {
+            jsonl_writer.write(&gather_result)?;
+        } else if let Some(selection) = column_selection.as_mut() {
+            selection.write_record(&mut csv_writer, &gather_result)?;
+        } else {
+            csv_writer.serialize(gather_result)?;
+        }
+
+        if let (Some(state), Some(c), Some(output)) =
+            (checkpoint_state.as_mut(), &checkpoint, &checkpoint_output)
+        {
+            state.matches.push(CheckpointMatch {
+                match_name: match_.match_name.clone(),
+                match_md5: match_.match_md5.clone(),
+                match_location: best_element.location.clone(),
+                f_unique_weighted: match_.f_unique_weighted,
+                sum_weighted_found,
+            });
+            if state.matches.len() % c.every == 0 {
+                csv_writer.flush()?;
+                if let Some(jsonl_writer) = jsonl_writer.as_mut() {
+                    jsonl_writer.flush()?;
+                }
+                state.output_bytes = std::fs::metadata(output)?.len();
+                state.save(output)?;
+            }
+        }
+
+        if let Some(claimed) = claimed_by_rank.as_mut() {
+            let (hashes, _) = query_mh.intersection(&best_element.minhash)?;
+            for hash in hashes {
+                claimed.entry(hash).or_insert(rank);
+            }
+        }
 
         // remove!
         query_mh.remove_from(&best_element.minhash)?;
         // to do -- switch to KmerMinHashTree, for faster removal.
         //query.remove_many(best_element.iter_mins().copied())?; // from sourmash core
 
+        // save the remaining query sketch for this rank, if requested
+        if let Some((send, _)) = &remaining_writer {
+            let mut remaining_sig = Signature::default();
+            remaining_sig.set_name(&format!(
+                "{} remaining after rank {}",
+                options.anonymize_query(&query_name),
+                rank
+            ));
+            remaining_sig.set_filename(&options.anonymize_query(&location));
+            remaining_sig.push(Sketch::MinHash(query_mh.clone()));
+            send.send(ZipMessage::SignatureData(vec![remaining_sig]))?;
+        }
+
         // recalculate remaining overlaps between query and all sketches.
         // note: this is parallelized.
-        matching_sketches = prefetch(&query_mh, matching_sketches, threshold_hashes);
+        matching_sketches.update_overlaps(&query_mh, threshold_hashes);
         rank += 1;
 
         let sub_hashes = last_hashes - query_mh.size();
@@ -1084,28 +2381,175 @@ pub fn consume_query_by_gather(
         last_hashes = query_mh.size();
         last_matches = matching_sketches.len();
     }
+
+    // normalize to the spike-in, and write the held results
+    if let Some(spike_in) = &spike_in {
+        let spike_weighted = held_results
+            .iter()
+            .find(|r| spike_in.matches(&r.match_name, &r.match_md5))
+            .map(|r| r.n_unique_weighted_found)
+            .filter(|n| *n > 0);
+        match spike_weighted {
+            Some(spike_weighted) => {
+                for result in held_results.iter_mut() {
+                    result.normalized_abundance = Some(
+                        result.n_unique_weighted_found as f64 / spike_weighted as f64
+                            * spike_in.amount,
+                    );
+                }
+            }
+            None => eprintln!(
+                "WARNING: spike-in '{}' was not found in the gather results for '{}'; not reporting normalized_abundance.",
+                spike_in.name, query_name
+            ),
+        }
+        for result in held_results {
+            if let Some(report) = report {
+                report.add(&result)?;
+            }
+            if let Some(send) = &results_channel {
+                if send.send(result).is_err() {
+                    break;
+                }
+            } else if let Some(parquet_writer) = parquet_writer.as_mut() {
+                parquet_writer.write(&result)?;
+            } else if let Some(jsonl_writer) = jsonl_writer.as_mut() {
+                jsonl_writer.write(&result)?;
+            } else if let Some(selection) = column_selection.as_mut() {
+                selection.write_record(&mut csv_writer, &result)?;
+            } else {
+                csv_writer.serialize(result)?;
+            }
+        }
+    }
+
+    if let Some(parquet_writer) = parquet_writer {
+        parquet_writer.finish()?;
+    }
+    if let Some(jsonl_writer) = jsonl_writer.as_mut() {
+        jsonl_writer.flush()?;
+    }
+    if let Some(thrd) = sqlite_thread {
+        drop(results_channel);
+        thrd.join()
+            .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    }
+
+    // finish writing the remaining query sketches
+    if let Some((send, thrd)) = remaining_writer {
+        send.send(ZipMessage::WriteManifest)?;
+        drop(send);
+        if let Err(e) = thrd
+            .join()
+            .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+        {
+            eprintln!("Error in sigwriter thread: {:?}", e);
+        }
+    }
+
+    // write out which rank claimed each of the query hashes
+    if let (Some(output), Some(claimed)) = (coverage_output, claimed_by_rank) {
+        let query_ds = orig_query_mh.downsample_scaled(scaled)?;
+        let mut coverage_writer = Writer::from_path(&output)?;
+        for (hash_index, hash) in query_ds.mins().into_iter().enumerate() {
+            coverage_writer.serialize(QueryHashCoverage {
+                hash_index,
+                hash,
+                gather_result_rank: claimed.get(&hash).copied(),
+            })?;
+        }
+        coverage_writer.flush()?;
+    }
+
+    if let Some((output, lineages)) = krona_output {
+        lineages.write_krona(&output, &krona_found, options)?;
+    }
+
+    // record that gather finished, with all of its outputs.
+    if let (Some(mut state), Some(output)) = (checkpoint_state, &checkpoint_output) {
+        csv_writer.flush()?;
+        if let Some(jsonl_writer) = jsonl_writer.as_mut() {
+            jsonl_writer.flush()?;
+        }
+        state.output_bytes = std::fs::metadata(output)?.len();
+        state.complete = true;
+        state.save(output)?;
+    }
     Ok(())
 }
 
-pub fn build_selection(ksize: u8, scaled: usize, moltype: &str) -> Selection {
-    let hash_function = match moltype {
+pub fn hash_function_from_moltype(moltype: &str) -> HashFunctions {
+    match moltype {
         "dna" => HashFunctions::Murmur64Dna,
         "protein" => HashFunctions::Murmur64Protein,
         "dayhoff" => HashFunctions::Murmur64Dayhoff,
         "hp" => HashFunctions::Murmur64Hp,
         _ => panic!("Unknown molecule type: {}", moltype),
-    };
+    }
     // let hash_function = HashFunctions::try_from(moltype)
     //     .map_err(|_| panic!("Unknown molecule type: {}", moltype))
     //     .unwrap();
+}
 
+pub fn build_selection(ksize: u8, scaled: usize, moltype: &str) -> Selection {
     Selection::builder()
         .ksize(ksize.into())
         .scaled(scaled as u32)
-        .moltype(hash_function)
+        .moltype(hash_function_from_moltype(moltype))
+        .build()
+}
+
+/// Build a selection for `num` sketches, which have no scaled value.
+pub fn build_num_selection(ksize: u8, num: u32, moltype: &str) -> Selection {
+    Selection::builder()
+        .ksize(ksize.into())
+        .num(num)
+        .moltype(hash_function_from_moltype(moltype))
         .build()
 }
 
+/// Parse an additional search, given as 'PARAMS:PATH', where PARAMS is a
+/// moltype, optionally followed by 'k=' and 'scaled=' (e.g.
+/// 'protein,k=10,scaled=200:protein.zip'). The ksize and scaled default to
+/// 'ksize' and 'scaled'.
+pub fn parse_moltype_search(spec: &str, ksize: u8, scaled: usize) -> Result<(Selection, String)> {
+    let (params, path) = spec.split_once(':').ok_or_else(|| {
+        anyhow!(
+            "search '{}' must be given as 'moltype[,k=K][,scaled=S]:path'",
+            spec
+        )
+    })?;
+
+    let mut params = params.split(',').map(str::trim);
+    let moltype = params.next().unwrap_or_default().to_lowercase();
+    if !["dna", "protein", "dayhoff", "hp"].contains(&moltype.as_str()) {
+        bail!(
+            "unknown moltype '{}' in search '{}'; must be one of DNA, protein, dayhoff, hp",
+            moltype,
+            spec
+        );
+    }
+
+    let (mut ksize, mut scaled) = (ksize, scaled);
+    for param in params {
+        match param.split_once('=') {
+            Some(("k", value)) => {
+                ksize = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid ksize '{}' in search '{}'", value, spec))?
+            }
+            Some(("scaled", value)) => {
+                scaled = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid scaled '{}' in search '{}'", value, spec))?
+            }
+            _ => bail!("unknown parameter '{}' in search '{}'", param, spec),
+        }
+    }
+
+    Ok((build_selection(ksize, scaled, &moltype), path.to_string()))
+}
+
 pub fn is_revindex_database(path: &camino::Utf8PathBuf) -> bool {
     // quick file check for Revindex database:
     // is path a directory that contains a file named 'CURRENT'?
@@ -1117,238 +2561,2832 @@ pub fn is_revindex_database(path: &camino::Utf8PathBuf) -> bool {
     }
 }
 
-#[derive(Serialize)]
-pub struct SearchResult {
-    pub query_name: String,
-    pub query_md5: String,
-    pub match_name: String,
-    pub containment: f64,
-    pub intersect_hashes: usize,
-    pub match_md5: Option<String>,
-    pub jaccard: Option<f64>,
-    pub max_containment: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub query_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub match_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub average_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub max_containment_ani: Option<f64>,
+/// The RocksDB indexes directly inside the directory 'path', sorted.
+pub(crate) fn revindexes_in(path: &camino::Utf8PathBuf) -> Vec<camino::Utf8PathBuf> {
+    let mut indexes: Vec<camino::Utf8PathBuf> = path
+        .read_dir_utf8()
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path().to_path_buf())
+                .filter(is_revindex_database)
+                .collect()
+        })
+        .unwrap_or_default();
+    indexes.sort();
+    indexes
 }
 
-pub struct InterimGatherResult {
-    intersect_bp: usize,
-    f_orig_query: f64,
-    f_match: f64,
-    f_unique_to_query: f64,
-    f_unique_weighted: f64,
-    average_abund: f64,
-    median_abund: f64,
-    std_abund: f64,
-    match_filename: String,
-    match_name: String,
-    match_md5: String,
-    f_match_orig: f64,
-    unique_intersect_bp: usize,
-    gather_result_rank: usize,
-    remaining_bp: usize,
-    n_unique_weighted_found: usize,
-    total_weighted_hashes: usize,
-    sum_weighted_found: usize,
-    query_containment_ani: f64,
-    query_containment_ani_ci_low: Option<f64>,
-    query_containment_ani_ci_high: Option<f64>,
-    match_containment_ani: f64,
-    match_containment_ani_ci_low: Option<f64>,
-    match_containment_ani_ci_high: Option<f64>,
-    average_containment_ani: f64,
-    max_containment_ani: f64,
+/// Find the RocksDB index that 'path' refers to: 'path' itself, whatever
+/// its trailing separators; the index containing 'path', if it is a file
+/// inside one (e.g. 'db/CURRENT'); or the only index directly inside the
+/// directory 'path'.
+pub fn find_revindex_database(path: &camino::Utf8PathBuf) -> Option<camino::Utf8PathBuf> {
+    let path: camino::Utf8PathBuf = path.components().collect();
+    if is_revindex_database(&path) {
+        return Some(path);
+    }
+    if path.is_file() {
+        return path
+            .parent()
+            .map(|parent| parent.to_path_buf())
+            .filter(is_revindex_database);
+    }
+    match revindexes_in(&path).as_slice() {
+        [index] => Some(index.clone()),
+        _ => None,
+    }
 }
 
-#[derive(Serialize)]
-pub struct BranchwaterGatherResult {
-    pub intersect_bp: usize,
-    pub f_orig_query: f64,
-    pub f_match: f64,
-    pub f_unique_to_query: f64,
-    pub f_unique_weighted: f64,
-    pub average_abund: f64,
-    pub median_abund: f64,
-    pub std_abund: f64,
-    pub match_filename: String,
-    pub match_name: String,
-    pub match_md5: String,
-    pub f_match_orig: f64,
-    pub unique_intersect_bp: usize,
-    pub gather_result_rank: usize,
-    pub remaining_bp: usize,
-    pub query_filename: String,
-    pub query_name: String,
-    pub query_md5: String,
-    pub query_bp: usize,
-    pub ksize: usize,
-    pub moltype: String,
-    pub scaled: usize,
-    pub query_n_hashes: usize,
-    pub query_abundance: bool,
-    pub query_containment_ani: f64,
-    pub match_containment_ani: f64,
-    pub average_containment_ani: f64,
-    pub max_containment_ani: f64,
-    pub n_unique_weighted_found: usize,
-    pub sum_weighted_found: usize,
-    pub total_weighted_hashes: usize,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub query_containment_ani_ci_low: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub query_containment_ani_ci_high: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub match_containment_ani_ci_low: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub match_containment_ani_ci_high: Option<f64>,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct MultiSearchResult {
-    pub query_name: String,
-    pub query_md5: String,
-    pub match_name: String,
-    pub match_md5: String,
-    pub containment: f64,
-    pub max_containment: f64,
-    pub jaccard: f64,
-    pub intersect_hashes: f64,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub query_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub match_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub average_containment_ani: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub max_containment_ani: Option<f64>,
-}
-
-pub fn open_stdout_or_file(output: Option<String>) -> Box<dyn Write + Send + 'static> {
-    // if output is a file, use open_output_file
-    if let Some(path) = output {
-        let outpath: PathBuf = path.into();
-        Box::new(open_output_file(&outpath))
-    } else {
-        Box::new(std::io::stdout())
+/// The RocksDB index that 'path' refers to (see `find_revindex_database`),
+/// reporting when it is not 'path' itself, or an error that says what
+/// 'path' is instead.
+pub fn resolve_revindex_database(path: &camino::Utf8PathBuf) -> Result<camino::Utf8PathBuf> {
+    match find_revindex_database(path) {
+        Some(index) => {
+            if index != path.components().collect::<camino::Utf8PathBuf>() {
+                eprintln!("Using RocksDB index '{}' found at '{}'.", index, path);
+            }
+            Ok(index)
+        }
+        None if !path.exists() => bail!(BranchwaterError::new(
+            ErrorCode::InputNotFound,
+            format!("No such file or directory: '{}'", path)
+        )),
+        None => bail!(
+            "'{}' is not a valid RevIndex database: {}.",
+            path,
+            describe_non_revindex(path)
+        ),
     }
 }
 
-pub fn open_output_file(output: &PathBuf) -> BufWriter<File> {
-    let file = File::create(output).unwrap_or_else(|e| {
-        eprintln!("Error creating output file: {:?}", e);
-        std::process::exit(1);
-    });
-    BufWriter::new(file)
+/// Does the directory 'path' hold RocksDB files, but no 'CURRENT' file,
+/// e.g. an index that was partly copied or is still being built?
+fn looks_like_partial_revindex(path: &camino::Utf8PathBuf) -> bool {
+    path.read_dir_utf8()
+        .map(|entries| {
+            entries.flatten().any(|entry| {
+                let name = entry.file_name();
+                name.ends_with(".sst") || name.starts_with("MANIFEST-") || name == "LOCK"
+            })
+        })
+        .unwrap_or(false)
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Params {
-    pub ksize: u32,
-    pub track_abundance: bool,
-    pub num: u32,
-    pub scaled: u64,
-    pub seed: u32,
-    pub is_protein: bool,
-    pub is_dna: bool,
+/// Say what an existing 'path' is, when it was expected to be a RocksDB
+/// index but isn't one.
+fn describe_non_revindex(path: &camino::Utf8PathBuf) -> String {
+    if path.is_file() {
+        return "it is a file, not a RocksDB index directory".to_string();
+    }
+    let nested = revindexes_in(path);
+    if nested.len() > 1 {
+        let names: Vec<&str> = nested.iter().filter_map(|p| p.file_name()).collect();
+        format!(
+            "it is a directory containing {} RocksDB indexes ({}); please give one of them",
+            nested.len(),
+            names.join(", ")
+        )
+    } else if looks_like_partial_revindex(path) {
+        "it holds RocksDB files but no CURRENT file, so the index may be incomplete or still being built".to_string()
+    } else {
+        "it is a directory with no CURRENT file".to_string()
+    }
 }
-use std::hash::Hash;
-use std::hash::Hasher;
 
-impl Hash for Params {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.ksize.hash(state);
-        self.track_abundance.hash(state);
-        self.num.hash(state);
-        self.scaled.hash(state);
-        self.seed.hash(state);
-        self.is_protein.hash(state);
-        self.is_dna.hash(state);
-    }
+/// On-disk layout version of the RocksDB indexes built by `index`. Bump this
+/// whenever a sourmash core upgrade changes the layout, and teach
+/// `index::index_migrate` about any upgrade that doesn't need a rebuild.
+pub const INDEX_SCHEMA_VERSION: u32 = 1;
+
+/// Name of the schema stamp file written inside each RocksDB index.
+const INDEX_STAMP_FILE: &str = "BRANCHWATER_INDEX";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexStamp {
+    pub schema_version: u32,
+    pub plugin_version: String,
 }
 
-pub enum ZipMessage {
-    SignatureData(Vec<Signature>),
-    WriteManifest,
+impl IndexStamp {
+    pub fn current() -> Self {
+        IndexStamp {
+            schema_version: INDEX_SCHEMA_VERSION,
+            plugin_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
 }
 
-pub fn sigwriter(
-    recv: std::sync::mpsc::Receiver<ZipMessage>,
-    output: String,
-) -> std::thread::JoinHandle<Result<()>> {
-    std::thread::spawn(move || -> Result<()> {
-        // cast output as pathbuf
-        let outpath: PathBuf = output.into();
+/// Write the current schema stamp into the index at `path`.
+pub fn write_index_stamp<P: AsRef<std::path::Path>>(path: P) -> Result<()> {
+    let stamp_path = path.as_ref().join(INDEX_STAMP_FILE);
+    let file = File::create(&stamp_path)
+        .with_context(|| format!("Failed to write index stamp '{}'", stamp_path.display()))?;
+    serde_json::to_writer(file, &IndexStamp::current())?;
+    Ok(())
+}
 
-        let file_writer = open_output_file(&outpath);
+/// Read the schema stamp from the index at `path`; indexes built before
+/// stamps were introduced have none.
+pub fn read_index_stamp<P: AsRef<std::path::Path>>(path: P) -> Result<Option<IndexStamp>> {
+    let stamp_path = path.as_ref().join(INDEX_STAMP_FILE);
+    if !stamp_path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(&stamp_path)?;
+    let stamp = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+        BranchwaterError::new(
+            ErrorCode::IndexCorrupt,
+            format!(
+                "index stamp '{}' is unreadable ({}); please rebuild the index with 'sourmash scripts index'",
+                stamp_path.display(),
+                e
+            ),
+        )
+    })?;
+    Ok(Some(stamp))
+}
 
-        let options = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Stored)
-            .large_file(true);
-        let mut zip = zip::ZipWriter::new(file_writer);
-        let mut manifest_rows: Vec<Record> = Vec::new();
-        // keep track of md5sum occurrences to prevent overwriting duplicates
-        let mut md5sum_occurrences: HashMap<String, usize> = HashMap::new();
+/// Check that the index at `path` was built with a compatible schema.
+pub fn check_index_schema(path: &camino::Utf8PathBuf) -> Result<()> {
+    match read_index_stamp(path)? {
+        Some(stamp) if stamp.schema_version > INDEX_SCHEMA_VERSION => bail!(BranchwaterError::new(
+            ErrorCode::IndexVersionMismatch,
+            format!(
+                "index '{}' uses schema version {} (built by branchwater {}), but this version only supports schema version {}. Please upgrade the branchwater plugin, or rebuild the index with 'sourmash scripts index'.",
+                path,
+                stamp.schema_version,
+                stamp.plugin_version,
+                INDEX_SCHEMA_VERSION
+            )
+        )),
+        // no migration exists between stamped schema versions yet;
+        // index_migrate only stamps unstamped indexes.
+        Some(stamp) if stamp.schema_version < INDEX_SCHEMA_VERSION => bail!(BranchwaterError::new(
+            ErrorCode::IndexVersionMismatch,
+            format!(
+                "index '{}' uses schema version {} (built by branchwater {}), but this version requires schema version {}. Please rebuild the index with 'sourmash scripts index'.",
+                path,
+                stamp.schema_version,
+                stamp.plugin_version,
+                INDEX_SCHEMA_VERSION
+            )
+        )),
+        Some(_) => {}
+        None => eprintln!(
+            "WARNING: index '{}' has no schema stamp and was built by an older version of branchwater. If it fails to open, rebuild it with 'sourmash scripts index'; otherwise 'sourmash scripts index_migrate' will stamp it.",
+            path
+        ),
+    }
+    Ok(())
+}
 
-        while let Ok(message) = recv.recv() {
-            match message {
-                ZipMessage::SignatureData(sigs) => {
-                    for sig in sigs.iter() {
-                        let md5sum_str = sig.md5sum();
-                        let count = md5sum_occurrences.entry(md5sum_str.clone()).or_insert(0);
-                        *count += 1;
-                        let sig_filename = if *count > 1 {
-                            format!("signatures/{}_{}.sig.gz", md5sum_str, count)
-                        } else {
-                            format!("signatures/{}.sig.gz", md5sum_str)
-                        };
-                        write_signature(sig, &mut zip, options, &sig_filename);
-                        let records: Vec<Record> = Record::from_sig(sig, sig_filename.as_str());
-                        manifest_rows.extend(records);
-                    }
-                }
-                ZipMessage::WriteManifest => {
-                    println!("Writing manifest");
-                    // Start the CSV file inside the zip
-                    zip.start_file("SOURMASH-MANIFEST.csv", options).unwrap();
-                    let manifest: Manifest = manifest_rows.clone().into();
-                    manifest.to_writer(&mut zip)?;
-
-                    // Properly finish writing to the ZIP file
-                    if let Err(e) = zip.finish() {
-                        eprintln!("Error finalizing ZIP file: {:?}", e);
-                    }
-                }
-            }
-        }
-        Ok(())
+/// Open a RevIndex read-only, after checking its schema stamp. Open errors
+/// usually mean the index was built by an incompatible version.
+pub fn open_revindex(path: &camino::Utf8PathBuf) -> Result<RevIndex> {
+    if !path.exists() {
+        bail!(BranchwaterError::new(
+            ErrorCode::InputNotFound,
+            format!("No such file or directory: '{}'", path)
+        ));
+    }
+    check_index_schema(path)?;
+    RevIndex::open(path, true, None).map_err(|e| {
+        BranchwaterError::new(
+            ErrorCode::IndexCorrupt,
+            format!(
+                "could not open index '{}' ({}); it may have been built by an incompatible version of sourmash. Please rebuild the index with 'sourmash scripts index'.",
+                path,
+                e
+            ),
+        )
+        .into()
     })
 }
 
-pub fn csvwriter_thread<T: Serialize + Send + 'static>(
-    recv: std::sync::mpsc::Receiver<T>,
-    output: Option<String>,
-) -> std::thread::JoinHandle<()> {
-    // create output file
-    let out = open_stdout_or_file(output);
-    // spawn a thread that is dedicated to printing to a buffered output
-    std::thread::spawn(move || {
-        let mut writer = Writer::from_writer(out);
+/// Read-only RevIndex handles, keyed by canonical path. Sharing one handle
+/// per database lets repeated searches in the same process reuse its
+/// block cache instead of reopening it on every call.
+static REVINDEX_CACHE: OnceLock<Mutex<HashMap<std::path::PathBuf, Arc<RevIndex>>>> =
+    OnceLock::new();
 
-        for res in recv.iter() {
-            if let Err(e) = writer.serialize(res) {
-                eprintln!("Error writing item: {:?}", e);
-            }
-        }
-        writer.flush().expect("Failed to flush writer.");
-    })
+fn revindex_cache_key<P: AsRef<std::path::Path>>(path: P) -> std::path::PathBuf {
+    std::fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_path_buf())
+}
+
+/// Open a RevIndex read-only, reusing an already-open handle if there is one.
+pub fn open_revindex_cached(path: &camino::Utf8PathBuf) -> Result<Arc<RevIndex>> {
+    let key = revindex_cache_key(path);
+    let cache = REVINDEX_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut cache = cache.lock().unwrap();
+
+    if let Some(db) = cache.get(&key) {
+        return Ok(db.clone());
+    }
+    let db = Arc::new(open_revindex(path)?);
+    cache.insert(key, db.clone());
+    Ok(db)
+}
+
+/// Reopen the RevIndex at `path` and swap the new handle into the cache,
+/// picking up changes made by another process. Searches already running
+/// keep their own `Arc` to the old handle, which is closed when the last
+/// of them finishes. If the index can't be opened, the cached handle is
+/// left in place.
+pub fn reload_revindex(path: &camino::Utf8PathBuf) -> Result<()> {
+    let db = Arc::new(open_revindex(path)?);
+    let cache = REVINDEX_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    cache.lock().unwrap().insert(revindex_cache_key(path), db);
+    Ok(())
+}
+
+/// Drop cached RevIndex handles: the one for `path`, or all of them.
+/// Used when a database is (re)written in this process.
+pub fn clear_revindex_cache<P: AsRef<std::path::Path>>(path: Option<P>) {
+    if let Some(cache) = REVINDEX_CACHE.get() {
+        let mut cache = cache.lock().unwrap();
+        match path {
+            Some(path) => {
+                cache.remove(&revindex_cache_key(path));
+            }
+            None => cache.clear(),
+        }
+    }
+}
+
+result_struct! {
+    #[derive(Serialize)]
+    pub struct SearchResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub match_name: String,
+        pub containment: f64,
+        pub intersect_hashes: usize,
+        pub match_md5: Option<String>,
+        pub jaccard: Option<f64>,
+        pub max_containment: Option<f64>,
+        // estimated unique k-mers, n_hashes * scaled at the comparison scaled.
+        pub query_unique_est_kmers: Option<u64>,
+        pub match_unique_est_kmers: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub match_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub average_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max_containment_ani: Option<f64>,
+        // the moltype of the search, when searching several moltypes at once.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub moltype: Option<String>,
+        // whether a negative control query also matched this match.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub in_negative_control: Option<bool>,
+        // with --collapse-identical, the ';'-separated names of other
+        // sketches with the same content as the match.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub match_aliases: Option<String>,
+    }
+}
+
+result_struct! {
+    /// Result of screening one query with `manysearch --screen`: whether any
+    /// match was found, and the first match found, if any.
+    #[derive(Serialize)]
+    pub struct ScreenResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub present: bool,
+        pub match_name: Option<String>,
+        pub match_md5: Option<String>,
+        pub containment: Option<f64>,
+        pub intersect_hashes: Option<usize>,
+        pub query_containment_ani: Option<f64>,
+    }
+}
+
+result_struct! {
+    /// Result of comparing two `num` sketches, for which only jaccard is
+    /// meaningful.
+    #[derive(Serialize)]
+    pub struct NumSearchResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub jaccard: f64,
+        pub ksize: u32,
+        pub num: u32,
+        pub moltype: String,
+    }
+}
+
+/// Why a query was skipped rather than searched.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Fewer than `--min-query-hashes` hashes.
+    TooFewHashes,
+    /// No sketch matching the selected ksize and moltype.
+    NoCompatibleSketch,
+    /// No hashes left at the selected scaled.
+    NoHashesAfterDownsampling,
+}
+
+/// A query skipped because it has fewer than `--min-query-hashes` hashes,
+/// or, in `fastmultigather`, because it has no usable sketch.
+#[derive(Serialize)]
+pub struct SkippedQuery {
+    pub query_name: String,
+    pub query_md5: String,
+    pub query_n_hashes: usize,
+    pub min_query_hashes: usize,
+    pub reason: SkipReason,
+}
+
+/// Load the `query_md5` values from previous output CSVs (optionally
+/// gzipped), so that queries that have already been searched can be skipped.
+pub fn load_done_query_md5s(paths: &[String]) -> Result<HashSet<String>> {
+    let mut done = HashSet::new();
+    for path in paths {
+        let (reader, _format) = niffler::from_path(path)
+            .with_context(|| format!("Failed to open previous output '{}'", path))?;
+        // the last row of an interrupted run's output index may be cut short.
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let md5_idx = rdr
+            .headers()?
+            .iter()
+            .position(|h| h == "query_md5")
+            .ok_or_else(|| anyhow!("previous output '{}' has no 'query_md5' column", path))?;
+        for result in rdr.records() {
+            let record = result?;
+            if let Some(md5) = record.get(md5_idx) {
+                done.insert(md5.to_string());
+            }
+        }
+    }
+    Ok(done)
+}
+
+/// Remove queries whose md5sum is in `done` from a query collection.
+pub fn skip_done_queries(collection: Collection, done: &HashSet<String>) -> Collection {
+    if done.is_empty() {
+        return collection;
+    }
+
+    let records: Vec<Record> = collection
+        .iter()
+        .filter(|(_idx, record)| !done.contains(record.md5()))
+        .map(|(_idx, record)| record.clone())
+        .collect();
+
+    let n_done = collection.len() - records.len();
+    eprintln!(
+        "Skipping {} query sketches already present in previous output.",
+        n_done
+    );
+
+    Collection::new(Manifest::from(records), collection.storage().clone())
+}
+
+/// Collapse sketches with identical content (the same md5sum) to the
+/// first of them. Returns the remaining collection and, for every md5sum,
+/// the ';'-separated names of the sketches that were collapsed into it.
+pub fn collapse_identical(collection: Collection) -> (Collection, HashMap<String, String>) {
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    let mut records: Vec<Record> = vec![];
+    for (_idx, record) in collection.iter() {
+        match aliases.get_mut(record.md5()) {
+            Some(names) => names.push(record.name().clone()),
+            None => {
+                aliases.insert(record.md5().clone(), vec![]);
+                records.push(record.clone());
+            }
+        }
+    }
+
+    let n_collapsed = collection.len() - records.len();
+    if n_collapsed > 0 {
+        eprintln!(
+            "Collapsed {} search sketches with identical content into {} sketches.",
+            n_collapsed,
+            records.len()
+        );
+    }
+
+    let aliases = aliases
+        .into_iter()
+        .map(|(md5, names)| (md5, names.join(";")))
+        .collect();
+    let collection = Collection::new(Manifest::from(records), collection.storage().clone());
+    (collection, aliases)
+}
+
+/// Skip queries with too few hashes to give meaningful containment values,
+/// keeping track of them so they can be reported rather than searched.
+pub struct QuerySizeFilter {
+    min_query_hashes: usize,
+    skipped: Mutex<Vec<SkippedQuery>>,
+}
+
+impl QuerySizeFilter {
+    pub fn new(min_query_hashes: usize) -> Self {
+        QuerySizeFilter {
+            min_query_hashes,
+            skipped: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns true if the query should be searched; otherwise, records
+    /// it as skipped.
+    pub fn check(&self, query_name: &str, query_md5: &str, query_n_hashes: usize) -> bool {
+        if query_n_hashes >= self.min_query_hashes {
+            return true;
+        }
+        self.skip(
+            query_name,
+            query_md5,
+            query_n_hashes,
+            SkipReason::TooFewHashes,
+        );
+        false
+    }
+
+    /// Record a query as skipped for 'reason'.
+    pub fn skip(
+        &self,
+        query_name: &str,
+        query_md5: &str,
+        query_n_hashes: usize,
+        reason: SkipReason,
+    ) {
+        self.skipped.lock().unwrap().push(SkippedQuery {
+            query_name: query_name.to_string(),
+            query_md5: query_md5.to_string(),
+            query_n_hashes,
+            min_query_hashes: self.min_query_hashes,
+            reason,
+        });
+    }
+
+    /// The number of queries skipped so far.
+    pub fn n_skipped(&self) -> usize {
+        self.skipped.lock().unwrap().len()
+    }
+
+    /// Report on skipped queries, writing them to `output` as CSV if given,
+    /// with query names written as given in 'options'.
+    pub fn report(self, output: Option<String>, options: &OutputOptions) -> Result<()> {
+        // queries are skipped in whatever order threads reach them.
+        let mut skipped = self.skipped.into_inner().unwrap();
+        skipped.sort_by(|a, b| (&a.query_name, &a.query_md5).cmp(&(&b.query_name, &b.query_md5)));
+        let n_skipped = |reason: SkipReason| skipped.iter().filter(|q| q.reason == reason).count();
+        let too_few = n_skipped(SkipReason::TooFewHashes);
+        if too_few > 0 {
+            eprintln!(
+                "WARNING: skipped {} queries with fewer than {} hashes.",
+                too_few, self.min_query_hashes
+            );
+        }
+        let incompatible = n_skipped(SkipReason::NoCompatibleSketch);
+        if incompatible > 0 {
+            eprintln!(
+                "WARNING: skipped {} queries with no compatible sketch.",
+                incompatible
+            );
+        }
+        let empty = n_skipped(SkipReason::NoHashesAfterDownsampling);
+        if empty > 0 {
+            eprintln!(
+                "WARNING: skipped {} queries with no hashes after downsampling.",
+                empty
+            );
+        }
+
+        if let Some(output_path) = output {
+            if let Some(dir) = Path::new(&output_path).parent() {
+                create_dir_all(dir)?;
+            }
+            let mut writer = Writer::from_path(&output_path)?;
+            if skipped.is_empty() {
+                writer.write_record([
+                    "query_name",
+                    "query_md5",
+                    "query_n_hashes",
+                    "min_query_hashes",
+                    "reason",
+                ])?;
+            }
+            for query in skipped.iter() {
+                writer.serialize(SkippedQuery {
+                    query_name: options.anonymize_query(&query.query_name),
+                    query_md5: query.query_md5.clone(),
+                    ..*query
+                })?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A file written for a query, as listed in an `OutputIndex`.
+#[derive(Serialize)]
+struct ProducedFile {
+    query_name: String,
+    query_md5: String,
+    output_type: String,
+    path: String,
+}
+
+/// The index CSV of the files written for each query by `fastmultigather`.
+///
+/// A query's files are appended, and flushed, only once they are all
+/// complete, so the index of an interrupted run still lists every query
+/// that it completed. Its `query_md5` column makes it a previous output
+/// for `--skip-done`, and `--resume` skips the queries it lists.
+pub struct OutputIndex {
+    path: String,
+    writer: Mutex<Writer<File>>,
+    n_files: AtomicUsize,
+}
+
+impl OutputIndex {
+    /// Open the index at 'path'; with 'append', add to it if it exists,
+    /// and otherwise start it over.
+    pub fn open(path: &str, append: bool) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(append)
+            .write(true)
+            .truncate(!append)
+            .open(path)
+            .map_err(|e| {
+                BranchwaterError::new(
+                    ErrorCode::OutputWriteFailed,
+                    format!("could not create output '{}': {}", path, e),
+                )
+            })?;
+        Self::from_file(path, file)
+    }
+
+    /// Start an index in 'file', writing the header unless it already
+    /// has rows.
+    fn from_file(path: &str, file: File) -> Result<Self> {
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+        if is_empty {
+            writer.write_record(["query_name", "query_md5", "output_type", "path"])?;
+            writer.flush()?;
+        }
+        Ok(OutputIndex {
+            path: path.to_string(),
+            writer: Mutex::new(writer),
+            n_files: AtomicUsize::new(0),
+        })
+    }
+
+    /// Record the completed outputs of a query, listed under 'query_name'
+    /// as it is written in the outputs.
+    pub fn record(
+        &self,
+        query_name: &str,
+        query_md5: &str,
+        outputs: Vec<(&str, String)>,
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for (output_type, path) in outputs {
+            writer.serialize(ProducedFile {
+                query_name: query_name.to_string(),
+                query_md5: query_md5.to_string(),
+                output_type: output_type.to_string(),
+                path,
+            })?;
+            self.n_files.fetch_add(1, atomic::Ordering::SeqCst);
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Where the index is written.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Flush the index to disk, returning the number of files it lists.
+    fn sync(self) -> Result<usize> {
+        self.writer
+            .into_inner()
+            .unwrap()
+            .into_inner()
+            .map_err(|e| anyhow!("{}", e))?
+            .sync_all()?;
+        Ok(self.n_files.into_inner())
+    }
+}
+
+/// Per-query outputs written into a directory shared with other jobs.
+///
+/// Every file name starts with the job prefix, and is created exclusively
+/// (O_EXCL), so two jobs (or two queries with the same name) never write
+/// to the same file. Once a query's outputs are complete they are
+/// recorded in '.{prefix}.index.csv.tmp', and `finish` atomically renames
+/// it to '{prefix}.index.csv'; files from interrupted queries are never
+/// listed.
+pub struct OutputDir {
+    dir: PathBuf,
+    prefix: String,
+    index: OutputIndex,
+}
+
+impl OutputDir {
+    pub fn new(dir: &str, prefix: Option<String>) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        create_dir_all(&dir)?;
+
+        let prefix = prefix.unwrap_or_else(|| {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            format!("job-{:x}-{}", nanos, std::process::id())
+        });
+        if prefix.is_empty() || prefix.contains('/') {
+            bail!("invalid job prefix '{}'", prefix);
+        }
+
+        // the index of an interrupted job is left as its temporary file.
+        let index = dir.join(format!("{}.index.csv", prefix));
+        let tmp = dir.join(format!(".{}.index.csv.tmp", prefix));
+        if index.exists() || tmp.exists() {
+            bail!(
+                "'{}' already exists; job prefix '{}' has already been used in '{}'",
+                if index.exists() { &index } else { &tmp },
+                prefix,
+                dir
+            );
+        }
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+            .map_err(|e| {
+                BranchwaterError::new(
+                    ErrorCode::OutputWriteFailed,
+                    format!("could not create output '{}': {}", tmp, e),
+                )
+            })?;
+        let index = OutputIndex::from_file(tmp.as_str(), file)?;
+        eprintln!("Writing outputs to '{}' with job prefix '{}'", dir, prefix);
+
+        Ok(OutputDir { dir, prefix, index })
+    }
+
+    /// Exclusively create the (empty) output file for `location` and
+    /// `suffix`, returning its path.
+    pub fn reserve(&self, location: &str, suffix: &str) -> Result<String> {
+        let path = self
+            .dir
+            .join(format!("{}.{}.{}", self.prefix, location, suffix));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| {
+                BranchwaterError::new(
+                    ErrorCode::OutputWriteFailed,
+                    format!("could not create output '{}': {}", path, e),
+                )
+            })?;
+        Ok(path.into_string())
+    }
+
+    /// The path of a job-wide output called 'name', e.g. the skipped
+    /// queries CSV.
+    pub fn path(&self, name: &str) -> String {
+        self.dir
+            .join(format!("{}.{}", self.prefix, name))
+            .into_string()
+    }
+
+    /// The index of the outputs of this job, written as queries complete.
+    pub fn index(&self) -> &OutputIndex {
+        &self.index
+    }
+
+    /// The indexes of the other jobs in the directory, finished or not,
+    /// for `--resume` to skip the queries they completed.
+    pub fn previous_indexes(&self) -> Result<Vec<String>> {
+        let mut indexes = vec![];
+        for entry in self.dir.read_dir_utf8()? {
+            let path = entry?.into_path();
+            let name = path.file_name().unwrap_or_default();
+            if (name.ends_with(".index.csv") || name.ends_with(".index.csv.tmp"))
+                && path.as_str() != self.index.path()
+            {
+                indexes.push(path.into_string());
+            }
+        }
+        indexes.sort();
+        Ok(indexes)
+    }
+
+    /// Finish the index CSV of produced files, renaming it into place so
+    /// that readers never see a partial index.
+    pub fn finish(self) -> Result<()> {
+        let tmp = self.index.path().to_string();
+        let n_files = self.index.sync()?;
+        let index = self.dir.join(format!("{}.index.csv", self.prefix));
+        std::fs::rename(&tmp, &index)?;
+        eprintln!("Wrote index of {} output files to '{}'", n_files, index);
+        Ok(())
+    }
+}
+
+/// The gather rank, if any, that claimed a single query hash. Hashes are
+/// indexed in sorted order.
+#[derive(Serialize)]
+pub struct QueryHashCoverage {
+    pub hash_index: usize,
+    pub hash: u64,
+    pub gather_result_rank: Option<usize>,
+}
+
+/// Capacity for channels between worker threads and writer threads.
+/// Defaults (0) to the number of threads; larger values let fast producers
+/// run further ahead of slow writers, at the cost of memory.
+pub fn channel_capacity(requested: usize) -> usize {
+    if requested > 0 {
+        requested
+    } else {
+        rayon::current_num_threads()
+    }
+}
+
+/// Names longer than this are flagged by name validation, and truncated
+/// by default when sanitizing.
+pub const MAX_NAME_LENGTH: usize = 1024;
+
+/// Replace control characters (e.g. newlines and tabs) in `name` with
+/// spaces and truncate it to `max_length` characters (0 for no limit).
+/// Returns None if the name needs no changes.
+pub fn sanitize_name(name: &str, max_length: usize) -> Option<String> {
+    let too_long = max_length > 0 && name.chars().count() > max_length;
+    if !too_long && !name.chars().any(char::is_control) {
+        return None;
+    }
+
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if too_long {
+        sanitized = sanitized.chars().take(max_length).collect();
+    }
+    Some(sanitized)
+}
+
+/// A sketch name changed by `NameSanitizer`.
+#[derive(Serialize)]
+pub struct ModifiedName {
+    pub original_name: String,
+    pub sanitized_name: String,
+}
+
+/// Sanitize sketch names, keeping track of modified names so they can
+/// be reported.
+pub struct NameSanitizer {
+    max_length: usize,
+    modified: Mutex<Vec<ModifiedName>>,
+}
+
+impl NameSanitizer {
+    pub fn new(max_length: usize) -> Self {
+        NameSanitizer {
+            max_length,
+            modified: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the sanitized name, recording it if it was modified.
+    pub fn sanitize(&self, name: &str) -> String {
+        match sanitize_name(name, self.max_length) {
+            Some(sanitized) => {
+                self.modified.lock().unwrap().push(ModifiedName {
+                    original_name: name.to_string(),
+                    sanitized_name: sanitized.clone(),
+                });
+                sanitized
+            }
+            None => name.to_string(),
+        }
+    }
+
+    /// Report on modified names, writing them to `output` as CSV if given.
+    pub fn report(self, output: Option<String>) -> Result<()> {
+        let modified = self.modified.into_inner().unwrap();
+        if !modified.is_empty() {
+            eprintln!("WARNING: sanitized {} sketch names.", modified.len());
+        }
+
+        if let Some(output_path) = output {
+            if let Some(dir) = Path::new(&output_path).parent() {
+                create_dir_all(dir)?;
+            }
+            let mut writer = Writer::from_path(&output_path)?;
+            if modified.is_empty() {
+                writer.write_record(["original_name", "sanitized_name"])?;
+            }
+            for name in modified.iter() {
+                writer.serialize(name)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub struct InterimGatherResult {
+    intersect_bp: usize,
+    f_orig_query: f64,
+    f_match: f64,
+    f_unique_to_query: f64,
+    f_unique_weighted: f64,
+    average_abund: f64,
+    median_abund: f64,
+    std_abund: f64,
+    match_filename: String,
+    match_name: String,
+    match_md5: String,
+    f_match_orig: f64,
+    unique_intersect_bp: usize,
+    gather_result_rank: usize,
+    remaining_bp: usize,
+    n_unique_weighted_found: usize,
+    total_weighted_hashes: usize,
+    sum_weighted_found: usize,
+    query_containment_ani: f64,
+    query_containment_ani_ci_low: Option<f64>,
+    query_containment_ani_ci_high: Option<f64>,
+    match_containment_ani: f64,
+    match_containment_ani_ci_low: Option<f64>,
+    match_containment_ani_ci_high: Option<f64>,
+    average_containment_ani: f64,
+    max_containment_ani: f64,
+    match_n_hashes: usize,
+    match_intersect_hashes: usize,
+    match_intersect_hashes_at_rank: usize,
+}
+
+result_struct! {
+    /// A single row of prefetch output; same columns, in the same order,
+    /// as 'sourmash prefetch'.
+    #[derive(Serialize)]
+    pub struct BranchwaterPrefetchResult {
+        pub intersect_bp: u64,
+        pub jaccard: f64,
+        pub max_containment: f64,
+        pub f_query_match: f64,
+        pub f_match_query: f64,
+        // fraction of the match found in the query, as in gather output.
+        pub f_match: f64,
+        pub match_filename: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub match_bp: u64,
+        pub query_filename: String,
+        pub query_name: String,
+        pub query_md5: String,
+        pub query_bp: u64,
+        pub ksize: usize,
+        pub moltype: String,
+        pub scaled: u64,
+        pub query_n_hashes: u64,
+        pub query_containment_ani: f64,
+        pub match_containment_ani: f64,
+        pub average_containment_ani: f64,
+        pub max_containment_ani: f64,
+    }
+}
+
+result_struct! {
+    #[derive(Serialize)]
+    pub struct BranchwaterGatherResult {
+        pub intersect_bp: usize,
+        pub f_orig_query: f64,
+        pub f_match: f64,
+        pub f_unique_to_query: f64,
+        pub f_unique_weighted: f64,
+        pub average_abund: f64,
+        pub median_abund: f64,
+        pub std_abund: f64,
+        pub match_filename: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub f_match_orig: f64,
+        pub unique_intersect_bp: usize,
+        pub gather_result_rank: usize,
+        pub remaining_bp: usize,
+        pub query_filename: String,
+        pub query_name: String,
+        pub query_md5: String,
+        pub query_bp: usize,
+        pub ksize: usize,
+        pub moltype: String,
+        pub scaled: usize,
+        pub query_n_hashes: usize,
+        pub query_abundance: bool,
+        pub query_containment_ani: f64,
+        pub match_containment_ani: f64,
+        pub average_containment_ani: f64,
+        pub max_containment_ani: f64,
+        pub n_unique_weighted_found: usize,
+        pub sum_weighted_found: usize,
+        pub total_weighted_hashes: usize,
+        // hashes in the match, hashes of the match found in the original query,
+        // and hashes assigned to the match at this rank, all at the common scaled.
+        pub match_n_hashes: usize,
+        pub match_intersect_hashes: usize,
+        pub match_intersect_hashes_at_rank: usize,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_containment_ani_ci_low: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_containment_ani_ci_high: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub match_containment_ani_ci_low: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub match_containment_ani_ci_high: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub normalized_abundance: Option<f64>,
+    }
+}
+
+impl BranchwaterGatherResult {
+    /// A placeholder result for a query with no matches: the query columns
+    /// are filled in, 'match_name' and 'match_md5' are empty, and all match
+    /// statistics are zero.
+    pub fn no_match(
+        query_filename: String,
+        query_name: String,
+        query_md5: String,
+        query_mh: &KmerMinHash,
+    ) -> Self {
+        let scaled = query_mh.scaled() as usize;
+        BranchwaterGatherResult {
+            intersect_bp: 0,
+            f_orig_query: 0.0,
+            f_match: 0.0,
+            f_unique_to_query: 0.0,
+            f_unique_weighted: 0.0,
+            average_abund: 0.0,
+            median_abund: 0.0,
+            std_abund: 0.0,
+            match_filename: String::new(),
+            match_name: String::new(),
+            match_md5: String::new(),
+            f_match_orig: 0.0,
+            unique_intersect_bp: 0,
+            gather_result_rank: 0,
+            remaining_bp: query_mh.size() * scaled,
+            query_filename,
+            query_name,
+            query_md5,
+            query_bp: query_mh.n_unique_kmers() as usize,
+            ksize: query_mh.ksize(),
+            moltype: query_mh.hash_function().to_string(),
+            scaled,
+            query_n_hashes: query_mh.size(),
+            query_abundance: query_mh.track_abundance(),
+            query_containment_ani: 0.0,
+            match_containment_ani: 0.0,
+            average_containment_ani: 0.0,
+            max_containment_ani: 0.0,
+            n_unique_weighted_found: 0,
+            sum_weighted_found: 0,
+            total_weighted_hashes: query_mh.sum_abunds() as usize,
+            match_n_hashes: 0,
+            match_intersect_hashes: 0,
+            match_intersect_hashes_at_rank: 0,
+            query_containment_ani_ci_low: None,
+            query_containment_ani_ci_high: None,
+            match_containment_ani_ci_low: None,
+            match_containment_ani_ci_high: None,
+            normalized_abundance: None,
+        }
+    }
+}
+
+/// Estimate ANI from containment at a fixed ksize, for use in inner loops.
+/// Computes `1/ksize` once rather than per call, and estimates both
+/// directions of a pair at once. For ANI >= 0.5 (containment >= 0.5^ksize)
+/// the values are bit-identical to `ani_from_containment`; below that they
+/// may differ from it by up to `f64::EPSILON / 2`, as `ani_from_containment`
+/// rounds them to a multiple of that.
+#[derive(Clone, Copy)]
+pub struct AniEstimator {
+    ksize: f64,
+    inv_ksize: f64,
+}
+
+impl AniEstimator {
+    pub fn new(ksize: f64) -> Self {
+        AniEstimator {
+            ksize,
+            inv_ksize: 1.0 / ksize,
+        }
+    }
+
+    #[inline]
+    pub fn ani(&self, containment: f64) -> f64 {
+        if containment == 0.0 {
+            0.0
+        } else if containment == 1.0 {
+            1.0
+        } else {
+            // ani_from_containment computes 1 - (1 - ani), which is exact
+            // for ani >= 0.5, and rounds smaller values to the nearest
+            // multiple of f64::EPSILON / 2.
+            containment.powf(self.inv_ksize)
+        }
+    }
+
+    /// Return (query_containment_ani, match_containment_ani,
+    /// average_containment_ani, max_containment_ani) for a pair.
+    #[inline]
+    pub fn pair(&self, query_containment: f64, match_containment: f64) -> (f64, f64, f64, f64) {
+        let qani = self.ani(query_containment);
+        let mani = self.ani(match_containment);
+        (qani, mani, (qani + mani) / 2., f64::max(qani, mani))
+    }
+
+    /// The smallest containment whose ANI is at least `min_ani`.
+    pub fn min_containment(&self, min_ani: f64) -> f64 {
+        min_ani.powf(self.ksize)
+    }
+}
+
+/// Estimate the number of unique k-mers in a sketch from 'n_hashes', its
+/// number of hashes at 'scaled'. Both must be at the scaled the sketch was
+/// compared at, not at the scaled it was loaded with.
+#[inline]
+pub fn unique_est_kmers(n_hashes: usize, scaled: u64) -> u64 {
+    n_hashes as u64 * scaled
+}
+
+/// The number of hashes in 'mh' at 'scaled', downsampling if 'mh' has a
+/// lower scaled.
+pub fn n_hashes_at_scaled(mh: &KmerMinHash, scaled: u64) -> usize {
+    if mh.scaled() < scaled {
+        mh.downsample_scaled(scaled).map_or(0, |mh| mh.size())
+    } else {
+        mh.size()
+    }
+}
+
+result_struct! {
+    #[derive(Serialize, Deserialize)]
+    pub struct MultiSearchResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub containment: f64,
+        pub max_containment: f64,
+        pub jaccard: f64,
+        pub intersect_hashes: f64,
+        // the parameters of the comparison, at the common scaled; missing from
+        // output written by older versions.
+        #[serde(default)]
+        pub ksize: u32,
+        #[serde(default)]
+        pub scaled: u64,
+        #[serde(default)]
+        pub moltype: String,
+        // estimated unique k-mers, n_hashes * scaled at the common scaled.
+        #[serde(default)]
+        pub query_unique_est_kmers: u64,
+        #[serde(default)]
+        pub match_unique_est_kmers: u64,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub match_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub average_containment_ani: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max_containment_ani: Option<f64>,
+    }
+}
+
+result_struct! {
+    /// Abundance-weighted comparison of two sketches, as written by `betadiversity`.
+    #[derive(Serialize, Deserialize)]
+    pub struct BetaDiversityResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub bray_curtis: f64,
+        pub weighted_jaccard: f64,
+        pub intersect_hashes: usize,
+        pub query_sum_abunds: u64,
+        pub match_sum_abunds: u64,
+    }
+}
+
+result_struct! {
+    /// Abundance-weighted, directional containment of two sketches, as written
+    /// by `weighted_containment`.
+    #[derive(Serialize, Deserialize)]
+    pub struct WeightedContainmentResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub match_name: String,
+        pub match_md5: String,
+        pub query_weighted_containment: f64,
+        pub match_weighted_containment: f64,
+        pub max_weighted_containment: f64,
+        pub intersect_hashes: usize,
+        pub query_sum_abunds: u64,
+        pub match_sum_abunds: u64,
+    }
+}
+
+result_struct! {
+    /// Number of matches found by one subsample of a query, as written by `rarefy`.
+    #[derive(Serialize, Deserialize)]
+    pub struct RarefactionResult {
+        pub query_name: String,
+        pub query_md5: String,
+        pub fraction: f64,
+        pub replicate: usize,
+        pub query_n_hashes: usize,
+        pub n_matches: usize,
+    }
+}
+
+result_struct! {
+    /// The containment of a match in a query at one timepoint, as written
+    /// by `timeseries`. Timepoints where the match was not found have
+    /// `present` false and a containment of 0.
+    #[derive(Serialize, Deserialize)]
+    pub struct TimeSeriesResult {
+        pub query_name: String,
+        pub match_name: String,
+        pub timepoint: String,
+        pub timepoint_index: usize,
+        pub present: bool,
+        pub containment: f64,
+        pub intersect_hashes: usize,
+        pub query_containment_ani: Option<f64>,
+    }
+}
+
+/// Check that output paths are distinct and in a supported format.
+pub fn check_output_paths(outputs: &[String]) -> Result<()> {
+    let mut seen = HashSet::new();
+    for output in outputs {
+        if !seen.insert(output) {
+            bail!("Output '{}' given more than once.", output);
+        }
+    }
+    Ok(())
+}
+
+/// Duplicate everything written to each of several outputs.
+struct FanOutWriter {
+    writers: Vec<Box<dyn Write + Send + 'static>>,
+}
+
+impl Write for FanOutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for writer in self.writers.iter_mut() {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for writer in self.writers.iter_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// How every output of a command is written. Options are given to each
+/// call, rather than set for the process, so that they don't carry over
+/// to later calls, or to calls running at the same time.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Round floats to this many decimal places (`--float-precision`), or
+    /// write them at full precision if None.
+    pub float_precision: Option<usize>,
+    /// Anonymize query names and filenames (`--anonymize-queries`).
+    pub anonymizer: Option<QueryAnonymizer>,
+    /// Checksum each file opened by `open_outputs` (`--output-checksums`).
+    pub checksums: bool,
+}
+
+/// Round 'value' to 'precision' decimal places, if given. The result is
+/// the float closest to the rounded decimal, so it is written with at
+/// most that many decimal places; -0 is written as 0.
+pub fn round_float(value: f64, precision: Option<usize>) -> f64 {
+    let Some(precision) = precision else {
+        return value;
+    };
+    if !value.is_finite() {
+        return value;
+    }
+    // f64 has at most 17 significant decimal digits.
+    let scale = 10f64.powi(precision.min(17) as i32);
+    let rounded = (value * scale).round() / scale;
+    if !rounded.is_finite() {
+        value
+    } else if rounded == 0.0 {
+        0.0
+    } else {
+        rounded
+    }
+}
+
+/// Query names and filenames replaced by salted hashes in every output
+/// (`--anonymize-queries`), with the salt and the names seen so far.
+/// Clones share the names seen, so that all the outputs of a call add to
+/// one mapping.
+#[derive(Debug, Clone)]
+pub struct QueryAnonymizer {
+    salt: String,
+    names: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// The result columns that identify a query, and are anonymized.
+const ANONYMIZED_COLUMNS: [&str; 2] = ["query_name", "query_filename"];
+
+impl QueryAnonymizer {
+    /// Replace query names and filenames with hashes salted with 'salt'.
+    pub fn new(salt: String) -> Self {
+        QueryAnonymizer {
+            salt,
+            names: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The anonymized form of a query name or filename; empty values are
+    /// left empty.
+    fn anonymize(&self, value: &str) -> String {
+        if value.is_empty() {
+            return String::new();
+        }
+        let mut names = self.names.lock().unwrap();
+        if let Some(anon) = names.get(value) {
+            return anon.clone();
+        }
+        let digest = md5::compute(format!("{}\0{}", self.salt, value));
+        let anon = format!("anon_{}", &format!("{:x}", digest)[..16]);
+        names.insert(value.to_string(), anon.clone());
+        anon
+    }
+
+    /// The (original, anonymized) query names and filenames written so
+    /// far, sorted by original name, for the private mapping file.
+    pub fn names(&self) -> Vec<(String, String)> {
+        let mut names: Vec<(String, String)> = self
+            .names
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, anon)| (name.clone(), anon.clone()))
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl OutputOptions {
+    /// The anonymized form of a query name or filename: 'value' unchanged
+    /// unless `--anonymize-queries` is on.
+    pub(crate) fn anonymize_query(&self, value: &str) -> String {
+        match &self.anonymizer {
+            Some(anonymizer) => anonymizer.anonymize(value),
+            None => value.to_string(),
+        }
+    }
+
+    /// Anonymize the query columns of a serialized result.
+    pub(crate) fn anonymize_fields(&self, fields: &mut ResultFields) {
+        let Some(anonymizer) = &self.anonymizer else {
+            return;
+        };
+        for (column, value) in fields.iter_mut() {
+            if ANONYMIZED_COLUMNS.contains(column) {
+                *value = anonymizer.anonymize(value);
+            }
+        }
+    }
+}
+
+/// Computes an XXH64 checksum of everything written to an output file. When
+/// dropped, after any compression has finished, it reports the checksum and
+/// writes it to a `<path>.xxh64` sidecar, in the format used by `xxhsum`.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: twox_hash::XxHash64,
+    n_bytes: u64,
+    path: PathBuf,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W, path: PathBuf) -> Self {
+        ChecksumWriter {
+            inner,
+            hasher: twox_hash::XxHash64::with_seed(0),
+            n_bytes: 0,
+            path,
+        }
+    }
+
+    fn write_sidecar(&self, checksum: &str) -> Result<()> {
+        let sidecar = format!("{}.xxh64", self.path);
+        let mut file = File::create(&sidecar)
+            .with_context(|| format!("Failed to create checksum file '{}'", sidecar))?;
+        writeln!(
+            file,
+            "{}  {}",
+            checksum,
+            self.path.file_name().unwrap_or(self.path.as_str())
+        )?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        self.n_bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for ChecksumWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.inner.flush() {
+            eprintln!("Error flushing output '{}': {}", self.path, e);
+            return;
+        }
+        let checksum = format!("{:016x}", self.hasher.finish());
+        eprintln!(
+            "Wrote '{}': {} bytes, xxh64 {}",
+            self.path, self.n_bytes, checksum
+        );
+        if let Err(e) = self.write_sidecar(&checksum) {
+            eprintln!("Error: {e}");
+        }
+    }
+}
+
+/// Open each of `outputs` for writing, gzip-compressing those ending in
+/// '.gz', and checksumming them if set in 'options'; with no outputs,
+/// write to stdout.
+pub fn open_outputs(
+    outputs: Vec<String>,
+    options: &OutputOptions,
+) -> Box<dyn Write + Send + 'static> {
+    let checksums = options.checksums;
+    let mut writers: Vec<Box<dyn Write + Send + 'static>> = outputs
+        .into_iter()
+        .map(|path| {
+            let outpath: PathBuf = path.into();
+            let file: Box<dyn Write + Send + 'static> = if checksums {
+                Box::new(ChecksumWriter::new(
+                    open_output_file(&outpath),
+                    outpath.clone(),
+                ))
+            } else {
+                Box::new(open_output_file(&outpath))
+            };
+            if outpath.as_str().ends_with(".gz") {
+                niffler::send::get_writer(
+                    Box::new(file),
+                    niffler::send::compression::Format::Gzip,
+                    niffler::Level::Six,
+                )
+                .unwrap_or_else(|e| {
+                    eprintln!("Error creating output file: {:?}", e);
+                    std::process::exit(ErrorCode::OutputWriteFailed.exit_code().into());
+                })
+            } else {
+                file
+            }
+        })
+        .collect();
+
+    match writers.len() {
+        0 => Box::new(std::io::stdout()),
+        1 => writers.pop().unwrap(),
+        _ => Box::new(FanOutWriter { writers }),
+    }
+}
+
+pub fn open_output_file(output: &PathBuf) -> BufWriter<File> {
+    let file = File::create(output).unwrap_or_else(|e| {
+        eprintln!("Error creating output file: {:?}", e);
+        std::process::exit(ErrorCode::OutputWriteFailed.exit_code().into());
+    });
+    BufWriter::new(file)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Params {
+    pub ksize: u32,
+    pub track_abundance: bool,
+    pub num: u32,
+    pub scaled: u64,
+    pub seed: u32,
+    pub is_protein: bool,
+    pub is_dna: bool,
+}
+use std::hash::Hash;
+use std::hash::Hasher;
+
+impl Hash for Params {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ksize.hash(state);
+        self.track_abundance.hash(state);
+        self.num.hash(state);
+        self.scaled.hash(state);
+        self.seed.hash(state);
+        self.is_protein.hash(state);
+        self.is_dna.hash(state);
+    }
+}
+
+pub enum ZipMessage {
+    SignatureData(Vec<Signature>),
+    WriteManifest,
+}
+
+/// Placeholders allowed in a sigwriter output template.
+const OUTPUT_TEMPLATE_FIELDS: [&str; 3] = ["moltype", "ksize", "scaled"];
+
+/// Check that every `{...}` placeholder in an output template is one of
+/// `{moltype}`, `{ksize}`, or `{scaled}`.
+pub fn check_output_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed '{{' in output template '{}'", template))?;
+        let field = &rest[start + 1..start + end];
+        if !OUTPUT_TEMPLATE_FIELDS.contains(&field) {
+            bail!(
+                "unknown placeholder '{{{}}}' in output template '{}'; must be one of {{moltype}}, {{ksize}}, {{scaled}}",
+                field,
+                template
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Fill in an output template with the parameters of the (single) sketch
+/// in 'sig'. Protein-type ksizes are reported in amino acids.
+fn render_output_template(template: &str, sig: &Signature) -> String {
+    let (moltype, ksize, scaled) = match sig.minhash() {
+        Some(mh) => {
+            let hash_function = mh.hash_function();
+            let ksize = match hash_function {
+                HashFunctions::Murmur64Dna => mh.ksize(),
+                _ => mh.ksize() / 3,
+            };
+            (
+                hash_function.to_string().to_lowercase(),
+                ksize.to_string(),
+                mh.scaled().to_string(),
+            )
+        }
+        None => ("unknown".into(), "0".into(), "0".into()),
+    };
+    template
+        .replace("{moltype}", &moltype)
+        .replace("{ksize}", &ksize)
+        .replace("{scaled}", &scaled)
+}
+
+/// A zip file of signatures being written, with its manifest.
+struct ZipSigWriter {
+    zip: zip::ZipWriter<BufWriter<File>>,
+    manifest_rows: Vec<Record>,
+    // keep track of md5sum occurrences to prevent overwriting duplicates
+    md5sum_occurrences: HashMap<String, usize>,
+}
+
+impl ZipSigWriter {
+    fn new(output: &str) -> Self {
+        // cast output as pathbuf
+        let outpath: PathBuf = output.into();
+        let file_writer = open_output_file(&outpath);
+
+        ZipSigWriter {
+            zip: zip::ZipWriter::new(file_writer),
+            manifest_rows: Vec::new(),
+            md5sum_occurrences: HashMap::new(),
+        }
+    }
+
+    fn options() -> zip::write::FileOptions {
+        zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(true)
+    }
+
+    fn write(&mut self, sig: &Signature) {
+        let md5sum_str = sig.md5sum();
+        let count = self
+            .md5sum_occurrences
+            .entry(md5sum_str.clone())
+            .or_insert(0);
+        *count += 1;
+        let sig_filename = if *count > 1 {
+            format!("signatures/{}_{}.sig.gz", md5sum_str, count)
+        } else {
+            format!("signatures/{}.sig.gz", md5sum_str)
+        };
+        write_signature(sig, &mut self.zip, Self::options(), &sig_filename);
+        let records: Vec<Record> = Record::from_sig(sig, sig_filename.as_str());
+        self.manifest_rows.extend(records);
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // Start the CSV file inside the zip
+        self.zip
+            .start_file("SOURMASH-MANIFEST.csv", Self::options())
+            .unwrap();
+        let manifest: Manifest = self.manifest_rows.clone().into();
+        manifest.to_writer(&mut self.zip)?;
+
+        // Properly finish writing to the ZIP file
+        if let Err(e) = self.zip.finish() {
+            eprintln!("Error finalizing ZIP file: {:?}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Write signatures to the zip file 'output'. If 'output' contains
+/// `{moltype}`, `{ksize}`, or `{scaled}` placeholders, each signature is
+/// instead routed to the zip file named by filling them in from its sketch,
+/// e.g. 'out.{moltype}.k{ksize}.zip'.
+pub fn sigwriter(
+    recv: std::sync::mpsc::Receiver<ZipMessage>,
+    output: String,
+) -> std::thread::JoinHandle<Result<()>> {
+    std::thread::spawn(move || -> Result<()> {
+        let is_template = output.contains('{');
+        // zip writers by output path, in order of creation
+        let mut writers: Vec<(String, ZipSigWriter)> = Vec::new();
+        if !is_template {
+            writers.push((output.clone(), ZipSigWriter::new(&output)));
+        }
+
+        while let Ok(message) = recv.recv() {
+            match message {
+                ZipMessage::SignatureData(sigs) => {
+                    for sig in sigs.iter() {
+                        let idx = if is_template {
+                            let path = render_output_template(&output, sig);
+                            match writers.iter().position(|(p, _)| *p == path) {
+                                Some(idx) => idx,
+                                None => {
+                                    let writer = ZipSigWriter::new(&path);
+                                    writers.push((path, writer));
+                                    writers.len() - 1
+                                }
+                            }
+                        } else {
+                            0
+                        };
+                        writers[idx].1.write(sig);
+                    }
+                }
+                ZipMessage::WriteManifest => {
+                    println!("Writing manifest");
+                    for (path, writer) in writers.iter_mut() {
+                        if is_template {
+                            eprintln!(
+                                "Wrote {} sketches to '{}'",
+                                writer.manifest_rows.len(),
+                                path
+                            );
+                        }
+                        writer.finish()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Write signatures to a zip collection, or to a (possibly gzipped)
+/// JSON signature file.
+pub fn write_signatures(sigs: Vec<Signature>, output: String) -> Result<()> {
+    if output.ends_with(".zip") {
+        let (send, recv) = std::sync::mpsc::sync_channel::<ZipMessage>(1);
+        let thrd = sigwriter(recv, output);
+        send.send(ZipMessage::SignatureData(sigs))?;
+        send.send(ZipMessage::WriteManifest)?;
+        drop(send);
+        thrd.join()
+            .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
+    } else {
+        let file = open_output_file(&output.clone().into());
+        let mut writer: Box<dyn Write> = if output.ends_with(".gz") {
+            niffler::get_writer(
+                Box::new(file),
+                niffler::compression::Format::Gzip,
+                niffler::compression::Level::Six,
+            )?
+        } else {
+            Box::new(file)
+        };
+        serde_json::to_writer(&mut writer, &sigs)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// splitmix64, a small mixing function used to make reproducible
+/// pseudo-random draws from a seed without a random number crate.
+pub fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Parse a comma-separated list of output columns.
+pub fn parse_column_list(columns: Option<String>) -> Option<Vec<String>> {
+    columns.map(|cols| {
+        cols.split(',')
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect()
+    })
+}
+
+/// Against-side metadata from `--against-metadata`, appended to each
+/// result row by joining on `match_md5` or `match_name`.
+pub struct AgainstMetadata {
+    pub(crate) key_column: &'static str,
+    pub(crate) columns: Vec<String>,
+    pub(crate) rows: HashMap<String, Vec<String>>,
+}
+
+impl AgainstMetadata {
+    /// Load a metadata CSV keyed by an `md5` column (joined on `match_md5`),
+    /// or else a `match_name` or `name` column (joined on `match_name`).
+    /// `columns` selects the metadata columns to append; by default, all
+    /// columns other than the key are appended.
+    pub fn from_csv(path: &str, columns: Option<Vec<String>>) -> Result<Self> {
+        let mut rdr = csv::Reader::from_path(path)
+            .with_context(|| format!("Failed to open against metadata CSV '{}'", path))?;
+        let headers = rdr.headers()?.clone();
+        let position = |name: &str| headers.iter().position(|h| h == name);
+
+        let (key_idx, key_column) = if let Some(idx) = position("md5") {
+            (idx, "match_md5")
+        } else if let Some(idx) = position("match_name").or_else(|| position("name")) {
+            (idx, "match_name")
+        } else {
+            bail!(
+                "against metadata CSV '{}' must have an 'md5', 'match_name', or 'name' column to join on",
+                path
+            );
+        };
+
+        let columns: Vec<String> = match columns {
+            Some(columns) => columns,
+            None => headers
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx != key_idx)
+                .map(|(_, h)| h.to_string())
+                .collect(),
+        };
+        let mut indices = vec![];
+        for col in columns.iter() {
+            match position(col) {
+                Some(idx) => indices.push(idx),
+                None => bail!(
+                    "against metadata CSV '{}' has no column '{}'. Available columns: {}",
+                    path,
+                    col,
+                    headers.iter().collect::<Vec<_>>().join(",")
+                ),
+            }
+        }
+
+        let mut rows = HashMap::new();
+        for result in rdr.records() {
+            let record = result?;
+            let values = indices.iter().map(|&i| record[i].to_string()).collect();
+            rows.insert(record[key_idx].to_string(), values);
+        }
+
+        Ok(AgainstMetadata {
+            key_column,
+            columns,
+            rows,
+        })
+    }
+
+    /// Check that results with 'fields', of which 'selected' are written,
+    /// can be joined against this metadata.
+    pub(crate) fn check_fields(&self, fields: &[&str], selected: &[&str]) -> Result<()> {
+        if !fields.contains(&self.key_column) {
+            bail!(
+                "results have no '{}' column to join against metadata on",
+                self.key_column
+            );
+        }
+        if let Some(col) = self
+            .columns
+            .iter()
+            .find(|col| selected.contains(&col.as_str()))
+        {
+            bail!(
+                "against metadata column '{}' is already a result column",
+                col
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Check that 'columns', and the columns of 'metadata', can be written
+/// for results of type 'T', so that a bad `--columns` fails when a writer
+/// is set up rather than with its first result, even if there are none.
+pub(crate) fn check_columns<T: ResultSchema>(
+    columns: Option<&Vec<String>>,
+    metadata: Option<&AgainstMetadata>,
+) -> Result<()> {
+    let selected: Vec<&str> = select_fields::<T>(columns)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    if let Some(metadata) = metadata {
+        let all_fields: Vec<&str> = T::fields().into_iter().map(|(name, _)| name).collect();
+        metadata.check_fields(&all_fields, &selected)?;
+    }
+    Ok(())
+}
+
+/// The (name, Rust type) of the fields of 'T' to write, in output order:
+/// 'columns' if given, or else all of them.
+pub(crate) fn select_fields<T: ResultSchema>(
+    columns: Option<&Vec<String>>,
+) -> Result<Vec<(&'static str, &'static str)>> {
+    let fields = T::fields();
+    let Some(columns) = columns else {
+        return Ok(fields);
+    };
+    let mut selected = vec![];
+    let mut missing = vec![];
+    for col in columns.iter() {
+        match fields.iter().find(|(name, _)| name == col) {
+            Some(field) => selected.push(*field),
+            None => missing.push(col.as_str()),
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "unknown output column(s): {}. Available columns: {}",
+            missing.join(","),
+            fields
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    }
+    Ok(selected)
+}
+
+/// Writes results as JSON Lines: one JSON object per result, with its
+/// fields in output order, so that names containing commas or quotes
+/// need no CSV quoting. Like CSV, fields that weren't calculated are left
+/// out; unset optional fields are null.
+pub struct JsonlWriter<W: Write, T> {
+    out: W,
+    fields: Vec<&'static str>,
+    metadata: Option<Arc<AgainstMetadata>>,
+    options: OutputOptions,
+    _result: PhantomData<T>,
+}
+
+impl<W: Write, T: Serialize + ResultSchema> JsonlWriter<W, T> {
+    /// Write to 'out', keeping only 'columns' if given, and appending the
+    /// columns of 'metadata' as strings; floats are rounded as given in
+    /// 'options'.
+    pub fn new(
+        out: W,
+        columns: Option<Vec<String>>,
+        metadata: Option<Arc<AgainstMetadata>>,
+        options: OutputOptions,
+    ) -> Result<Self> {
+        let fields: Vec<&'static str> = select_fields::<T>(columns.as_ref())?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        if let Some(metadata) = &metadata {
+            let all_fields: Vec<&str> = T::fields().into_iter().map(|(name, _)| name).collect();
+            metadata.check_fields(&all_fields, &fields)?;
+        }
+        Ok(JsonlWriter {
+            out,
+            fields,
+            metadata,
+            options,
+            _result: PhantomData,
+        })
+    }
+
+    /// Write one result as a line of JSON.
+    pub fn write(&mut self, item: &T) -> Result<()> {
+        let value = serde_json::to_value(item)?;
+        let row = value
+            .as_object()
+            .ok_or_else(|| anyhow!("failed to serialize result as a JSON object"))?;
+
+        let mut entries: Vec<(&str, serde_json::Value)> = vec![];
+        for &field in self.fields.iter() {
+            let Some(value) = row.get(field) else {
+                continue;
+            };
+            let value = match value.as_str() {
+                Some(name) if ANONYMIZED_COLUMNS.contains(&field) => {
+                    serde_json::Value::String(self.options.anonymize_query(name))
+                }
+                _ => round_json_float(value, self.options.float_precision),
+            };
+            entries.push((field, value));
+        }
+        if let Some(metadata) = &self.metadata {
+            let values = row
+                .get(metadata.key_column)
+                .and_then(|key| key.as_str())
+                .and_then(|key| metadata.rows.get(key));
+            for (idx, col) in metadata.columns.iter().enumerate() {
+                let value = values.map_or(serde_json::Value::Null, |values| {
+                    serde_json::Value::String(values[idx].clone())
+                });
+                entries.push((col, value));
+            }
+        }
+
+        let mut line = String::from("{");
+        for (idx, (name, value)) in entries.iter().enumerate() {
+            if idx > 0 {
+                line.push(',');
+            }
+            line.push_str(&serde_json::to_string(name)?);
+            line.push(':');
+            line.push_str(&serde_json::to_string(value)?);
+        }
+        line.push('}');
+        writeln!(self.out, "{}", line)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// 'value', or if it is a float, 'value' rounded to 'precision' decimal
+/// places, if given.
+fn round_json_float(value: &serde_json::Value, precision: Option<usize>) -> serde_json::Value {
+    match value.as_f64() {
+        Some(float) if value.is_f64() && precision.is_some() => {
+            serde_json::Number::from_f64(round_float(float, precision))
+                .map_or(serde_json::Value::Null, serde_json::Value::Number)
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Restrict CSV output to a user-specified subset of columns, and/or
+/// append against-side metadata columns to each row.
+///
+/// Column names are checked with `check_columns` when a writer is set up;
+/// without `--columns`, the header is that of the first result written.
+pub struct ColumnSelection {
+    columns: Option<Vec<String>>,
+    metadata: Option<Arc<AgainstMetadata>>,
+    options: OutputOptions,
+    /// the field names of the results, in output order.
+    schema: Vec<&'static str>,
+    indices: Option<Vec<usize>>,
+    key_index: usize,
+    write_header: bool,
+}
+
+impl ColumnSelection {
+    pub fn new(columns: Vec<String>) -> Self {
+        Self::from_columns(Some(columns))
+    }
+
+    /// Select 'columns', or all columns if None.
+    pub fn from_columns(columns: Option<Vec<String>>) -> Self {
+        ColumnSelection {
+            columns,
+            metadata: None,
+            options: OutputOptions::default(),
+            schema: vec![],
+            indices: None,
+            key_index: 0,
+            write_header: true,
+        }
+    }
+
+    /// Don't write a header, e.g. when appending to an existing output.
+    pub fn without_header(self) -> Self {
+        ColumnSelection {
+            write_header: false,
+            ..self
+        }
+    }
+
+    /// Build a selection if either columns or metadata are given, or
+    /// 'options' change how fields are written.
+    pub fn from_options(
+        columns: Option<Vec<String>>,
+        metadata: Option<Arc<AgainstMetadata>>,
+        options: &OutputOptions,
+    ) -> Option<Self> {
+        if columns.is_none()
+            && metadata.is_none()
+            && options.float_precision.is_none()
+            && options.anonymizer.is_none()
+        {
+            return None;
+        }
+        Some(ColumnSelection {
+            metadata,
+            options: options.clone(),
+            ..Self::from_columns(columns)
+        })
+    }
+
+    /// Write only the selected columns of 'item', plus any metadata
+    /// columns, writing the header first if needed. Floats are rounded as
+    /// given in the options, and query columns are anonymized with
+    /// `--anonymize-queries`.
+    pub fn write_record<W: Write, T: Serialize + ResultSchema>(
+        &mut self,
+        writer: &mut Writer<W>,
+        item: &T,
+    ) -> Result<()> {
+        let mut fields = serialize_fields(item, self.options.float_precision)?;
+        self.options.anonymize_fields(&mut fields);
+
+        if self.indices.is_none() {
+            let schema: Vec<&'static str> = T::fields().into_iter().map(|(name, _)| name).collect();
+            let position = |name: &str| schema.iter().position(|field| *field == name);
+            let indices: Vec<usize> = match &self.columns {
+                Some(columns) => select_fields::<T>(Some(columns))?
+                    .into_iter()
+                    .filter_map(|(name, _)| position(name))
+                    .collect(),
+                // all of the fields this result has; fields that weren't
+                // calculated are left out, as when serializing to CSV.
+                None => fields
+                    .iter()
+                    .filter_map(|(name, _)| position(name))
+                    .collect(),
+            };
+            let mut out_header: Vec<&str> = indices.iter().map(|&i| schema[i]).collect();
+
+            if let Some(metadata) = &self.metadata {
+                metadata.check_fields(&schema, &out_header)?;
+                self.key_index = position(metadata.key_column).unwrap();
+                out_header.extend(metadata.columns.iter().map(|col| col.as_str()));
+            }
+            if self.write_header {
+                writer.write_record(&out_header)?;
+            }
+            self.schema = schema;
+            self.indices = Some(indices);
+        }
+
+        let values = align_fields(&self.schema, fields);
+        let value = |i: usize| values[i].as_deref().unwrap_or("");
+        let indices = self.indices.as_ref().unwrap();
+        let mut record: Vec<&str> = indices.iter().map(|&i| value(i)).collect();
+        if let Some(metadata) = &self.metadata {
+            match metadata.rows.get(value(self.key_index)) {
+                Some(row) => record.extend(row.iter().map(|v| v.as_str())),
+                None => record.extend(metadata.columns.iter().map(|_| "")),
+            }
+        }
+        writer.write_record(&record)?;
+        Ok(())
+    }
+}
+
+/// Place the serialized 'fields' of a result at their positions in
+/// 'schema', its field names in output order; fields that weren't
+/// serialized are None.
+pub(crate) fn align_fields(schema: &[&'static str], fields: ResultFields) -> Vec<Option<String>> {
+    let mut values: Vec<Option<String>> = vec![None; schema.len()];
+    // fields are serialized in schema order, so each search starts where
+    // the last one matched.
+    let mut start = 0;
+    for (name, value) in fields {
+        if let Some(offset) = schema[start..].iter().position(|field| *field == name) {
+            values[start + offset] = Some(value);
+            start += offset + 1;
+        }
+    }
+    values
+}
+
+/// Stable (FNV-1a) hash of a query name, used to assign it to one of
+/// `n_buckets` output partitions.
+fn query_bucket(query_name: &str, n_buckets: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in query_name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % n_buckets as u64) as usize
+}
+
+/// CSV writer, and column selection if any, for a single output partition.
+type BucketWriter = (Writer<Box<dyn Write + Send>>, Option<ColumnSelection>);
+
+/// Write results into hive-partitioned directories bucketed by a hash of
+/// query_name, i.e. `{output}/query_bucket={bucket}/part-0.csv`, so that
+/// tools like DuckDB can query them lazily.
+struct PartitionedCsvWriter {
+    outputs: Vec<String>,
+    n_buckets: usize,
+    columns: Option<Vec<String>>,
+    metadata: Option<Arc<AgainstMetadata>>,
+    options: OutputOptions,
+    writers: HashMap<usize, BucketWriter>,
+}
+
+impl PartitionedCsvWriter {
+    fn new(
+        outputs: Vec<String>,
+        n_buckets: usize,
+        columns: Option<Vec<String>>,
+        metadata: Option<Arc<AgainstMetadata>>,
+        options: OutputOptions,
+    ) -> Self {
+        PartitionedCsvWriter {
+            outputs,
+            n_buckets,
+            columns,
+            metadata,
+            options,
+            writers: HashMap::new(),
+        }
+    }
+
+    fn write<T: Serialize + ResultSchema>(&mut self, item: &T) -> Result<()> {
+        let fields = serialize_fields(item, None)?;
+        let query_name = field_value(&fields, "query_name")
+            .ok_or_else(|| anyhow!("results have no query_name column to partition on"))?;
+        let bucket = query_bucket(query_name, self.n_buckets);
+
+        if !self.writers.contains_key(&bucket) {
+            let width = (self.n_buckets - 1).to_string().len();
+            let mut paths = vec![];
+            for output in self.outputs.iter() {
+                let dir = PathBuf::from(output).join(format!("query_bucket={:0width$}", bucket));
+                create_dir_all(&dir)?;
+                paths.push(dir.join("part-0.csv").into_string());
+            }
+            let writer = Writer::from_writer(open_outputs(paths, &self.options));
+            let selection = ColumnSelection::from_options(
+                self.columns.clone(),
+                self.metadata.clone(),
+                &self.options,
+            );
+            self.writers.insert(bucket, (writer, selection));
+        }
+
+        let (writer, selection) = self.writers.get_mut(&bucket).unwrap();
+        match selection {
+            Some(selection) => selection.write_record(writer, item)?,
+            None => writer.serialize(item)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (writer, _) in self.writers.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// One row of the per-group summary written with `--query-groups`.
+#[derive(Serialize)]
+struct QueryGroupMatch {
+    group: String,
+    match_name: String,
+    n_queries: usize,
+    max_containment: f64,
+    mean_ani: Option<f64>,
+}
+
+/// Running per (group, match) statistics for a `QueryGroupSummary`.
+#[derive(Default)]
+struct GroupMatchStats {
+    n_queries: usize,
+    max_containment: f64,
+    ani_sum: f64,
+    n_ani: usize,
+}
+
+/// Aggregate search results per user-defined group of queries, as results
+/// stream through the writer thread: for each group and match, the number
+/// of queries matching, the max containment, and the mean ANI.
+pub struct QueryGroupSummary {
+    groups: HashMap<String, String>,
+    output: String,
+    options: OutputOptions,
+    stats: BTreeMap<(String, String), GroupMatchStats>,
+    n_ungrouped: usize,
+}
+
+/// Result columns holding the query containment, in order of preference.
+const GROUP_CONTAINMENT_COLUMNS: [&str; 2] = ["containment", "f_orig_query"];
+
+impl QueryGroupSummary {
+    /// Load a `query_name,group` CSV mapping queries to groups, for a
+    /// summary written to 'output' with 'options'.
+    pub fn from_csv(groups_csv: &str, output: String, options: OutputOptions) -> Result<Self> {
+        let mut rdr = csv::Reader::from_path(groups_csv)
+            .with_context(|| format!("Failed to open query groups CSV '{}'", groups_csv))?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| {
+            headers.iter().position(|h| h == name).ok_or_else(|| {
+                anyhow!(
+                    "query groups CSV '{}' must have '{}' and 'group' columns",
+                    groups_csv,
+                    name
+                )
+            })
+        };
+        let name_idx = column("query_name")?;
+        let group_idx = column("group")?;
+
+        let mut groups = HashMap::new();
+        for result in rdr.records() {
+            let record = result?;
+            groups.insert(record[name_idx].to_string(), record[group_idx].to_string());
+        }
+
+        Ok(QueryGroupSummary {
+            groups,
+            output,
+            options,
+            stats: BTreeMap::new(),
+            n_ungrouped: 0,
+        })
+    }
+
+    fn add<T: Serialize>(&mut self, item: &T) -> Result<()> {
+        // stats are kept at full precision, and rounded when written.
+        let fields = serialize_fields(item, None)?;
+        let column = |name: &str| field_value(&fields, name);
+
+        let query_name = column("query_name")
+            .ok_or_else(|| anyhow!("results have no query_name column to group on"))?;
+        let Some(group) = self.groups.get(query_name) else {
+            self.n_ungrouped += 1;
+            return Ok(());
+        };
+        let match_name = column("match_name").unwrap_or_default().to_string();
+        // placeholder rows for queries with no matches have nothing to summarize
+        if match_name.is_empty() {
+            return Ok(());
+        }
+        let containment: f64 = GROUP_CONTAINMENT_COLUMNS
+            .iter()
+            .find_map(|name| column(name))
+            .ok_or_else(|| anyhow!("results have no containment column to summarize"))?
+            .parse()?;
+        let ani: Option<f64> = column("query_containment_ani")
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse())
+            .transpose()?;
+
+        let stats = self.stats.entry((group.clone(), match_name)).or_default();
+        stats.n_queries += 1;
+        stats.max_containment = stats.max_containment.max(containment);
+        if let Some(ani) = ani {
+            stats.ani_sum += ani;
+            stats.n_ani += 1;
+        }
+        Ok(())
+    }
+
+    fn write(self) -> Result<()> {
+        if self.n_ungrouped > 0 {
+            eprintln!(
+                "WARNING: {} results had queries not listed in the query groups CSV; they are not included in '{}'.",
+                self.n_ungrouped, self.output
+            );
+        }
+        let precision = self.options.float_precision;
+        let mut writer = Writer::from_writer(open_outputs(vec![self.output], &self.options));
+        for ((group, match_name), stats) in self.stats {
+            let mean_ani = (stats.n_ani > 0).then(|| stats.ani_sum / stats.n_ani as f64);
+            writer.serialize(QueryGroupMatch {
+                group,
+                match_name,
+                n_queries: stats.n_queries,
+                max_containment: round_float(stats.max_containment, precision),
+                mean_ani: mean_ani.map(|ani| round_float(ani, precision)),
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Negative control queries for `manysearch` (`--negative-controls`).
+/// Matches that any control query also matched are flagged in the
+/// `in_negative_control` column or, with 'subtract', removed from the
+/// results of the other queries.
+pub struct NegativeControls {
+    controls: HashSet<String>,
+    subtract: bool,
+}
+
+impl NegativeControls {
+    /// Load control query names (or md5s), one per line.
+    pub fn from_file(path: &str, subtract: bool) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open negative controls file '{}'", path))?;
+        let mut controls = HashSet::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() {
+                controls.insert(line.to_string());
+            }
+        }
+        if controls.is_empty() {
+            bail!("no negative control queries listed in '{}'", path);
+        }
+        Ok(NegativeControls { controls, subtract })
+    }
+
+    fn is_control(&self, result: &SearchResult) -> bool {
+        self.controls.contains(&result.query_name) || self.controls.contains(&result.query_md5)
+    }
+
+    /// Start a thread that holds all results sent to the returned sender
+    /// until the search finishes, then flags or subtracts matches found in
+    /// the controls and passes the results on to 'send'.
+    pub fn start(
+        self,
+        send: std::sync::mpsc::SyncSender<SearchResult>,
+    ) -> (
+        std::sync::mpsc::SyncSender<SearchResult>,
+        std::thread::JoinHandle<Result<()>>,
+    ) {
+        let (held_send, held_recv) =
+            std::sync::mpsc::sync_channel::<SearchResult>(rayon::current_num_threads());
+        let thrd = std::thread::spawn(move || -> Result<()> {
+            let match_key = |r: &SearchResult| r.match_md5.clone().unwrap_or(r.match_name.clone());
+
+            let mut held = vec![];
+            let mut control_matches = HashSet::new();
+            let mut n_control_queries = HashSet::new();
+            for result in held_recv.iter() {
+                if self.is_control(&result) {
+                    control_matches.insert(match_key(&result));
+                    n_control_queries.insert(result.query_name.clone());
+                }
+                held.push(result);
+            }
+
+            let mut n_flagged = 0;
+            for mut result in held {
+                let is_control = self.is_control(&result);
+                let in_control = is_control || control_matches.contains(&match_key(&result));
+                if in_control && !is_control {
+                    n_flagged += 1;
+                    if self.subtract {
+                        continue;
+                    }
+                }
+                result.in_negative_control = Some(in_control);
+                send.send(result)?;
+            }
+
+            eprintln!(
+                "Negative controls: {} control queries matched {} search sketches; {} {} sample matches.",
+                n_control_queries.len(),
+                control_matches.len(),
+                if self.subtract { "removed" } else { "flagged" },
+                n_flagged
+            );
+            Ok(())
+        });
+        (held_send, thrd)
+    }
+}
+
+/// The file format results are written in (`--output-format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// Apache Parquet, with typed columns; see `ParquetResultWriter`.
+    Parquet,
+    /// JSON Lines, one object per result; see `JsonlWriter`.
+    Jsonl,
+    /// A table of a SQLite database, named for the command; requires the
+    /// `sqlite` feature. See `SqliteResultWriter`.
+    Sqlite,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "sqlite" if cfg!(feature = "sqlite") => Ok(OutputFormat::Sqlite),
+            "sqlite" => Err(anyhow!(
+                "branchwater was built without SQLite output; rebuild it with the 'sqlite' feature to use --output-format sqlite."
+            )),
+            _ => Err(anyhow!(
+                "unknown output format '{}'; must be one of csv, parquet, jsonl, sqlite",
+                s
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The file extension of outputs named by branchwater, e.g. per-query
+    /// gather outputs.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Sqlite => "sqlite",
+        }
+    }
+
+    /// Check that results can be written to 'output' in this format: only
+    /// Parquet is written to '.parquet' files, only CSV can be partitioned,
+    /// and Parquet and SQLite are written to exactly one uncompressed file.
+    pub fn check_outputs(&self, output: &[String], query_buckets: usize) -> Result<()> {
+        if *self != OutputFormat::Parquet {
+            if let Some(path) = output
+                .iter()
+                .find(|path| path.ends_with(".parquet") || path.ends_with(".pq"))
+            {
+                bail!(
+                    "'{}' is named as a Parquet file, but results are written as {}; use --output-format parquet.",
+                    path,
+                    self.extension()
+                );
+            }
+        }
+        if *self == OutputFormat::Csv {
+            return Ok(());
+        }
+        if query_buckets > 0 {
+            bail!(
+                "--output-format {} cannot be used with --query-buckets.",
+                self.extension()
+            );
+        }
+        if *self == OutputFormat::Jsonl {
+            return Ok(());
+        }
+        match output {
+            [] => bail!(
+                "--output-format {} requires an output file.",
+                self.extension()
+            ),
+            [path] if path.ends_with(".gz") && *self == OutputFormat::Parquet => bail!(
+                "Parquet outputs are compressed already; remove '.gz' from '{}'.",
+                path
+            ),
+            [path] if path.ends_with(".gz") => bail!(
+                "SQLite databases can't be compressed; remove '.gz' from '{}'.",
+                path
+            ),
+            [_] => Ok(()),
+            _ => bail!(
+                "--output-format {} writes to a single output file.",
+                self.extension()
+            ),
+        }
+    }
+}
+
+/// Optional extras for the CSV writer thread.
+#[derive(Default)]
+pub struct WriterExtras {
+    /// Summarize results per query group (`--query-groups`).
+    pub query_groups: Option<QueryGroupSummary>,
+    /// Append against-side metadata to each row (`--against-metadata`).
+    pub against_metadata: Option<AgainstMetadata>,
+    /// Build a matches x queries BIOM table (`--output-biom`).
+    pub biom: Option<BiomTable>,
+    /// Write results as Parquet rather than CSV (`--output-format`).
+    pub format: OutputFormat,
+    /// The command writing the results, which names the table of
+    /// `--output-format sqlite`; the result type names it if this is empty.
+    pub command: &'static str,
+    /// Summarize gather results in an HTML report (`--report`).
+    pub report: Option<RunReport>,
+    /// How results are written, e.g. `--float-precision`.
+    pub options: OutputOptions,
+}
+
+pub fn csvwriter_thread<T: Serialize + ResultSchema + Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+) -> std::thread::JoinHandle<Result<()>> {
+    csvwriter_thread_with_extras(
+        recv,
+        output,
+        columns,
+        query_buckets,
+        WriterExtras::default(),
+    )
+}
+
+/// Like `csvwriter_thread`, but also summarizes results per query group
+/// and/or appends against-side metadata, as given in `extras`, and writes
+/// Parquet or JSON Lines rather than CSV if requested.
+pub fn csvwriter_thread_with_extras<T: Serialize + ResultSchema + Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    extras: WriterExtras,
+) -> std::thread::JoinHandle<Result<()>> {
+    match extras.format {
+        OutputFormat::Csv => {}
+        OutputFormat::Parquet => {
+            return parquetwriter_thread(recv, output, columns, query_buckets, extras)
+        }
+        OutputFormat::Jsonl => {
+            return jsonlwriter_thread(recv, output, columns, query_buckets, extras)
+        }
+        OutputFormat::Sqlite => {
+            return sqlitewriter_thread(recv, output, columns, query_buckets, extras)
+        }
+    }
+
+    let mut query_groups = extras.query_groups;
+    let metadata = extras.against_metadata.map(Arc::new);
+    let mut biom = extras.biom;
+    let report = extras.report;
+    let options = extras.options;
+
+    if query_buckets > 0 {
+        return std::thread::spawn(move || -> Result<()> {
+            if let Err(e) = check_columns::<T>(columns.as_ref(), metadata.as_deref()) {
+                // keep draining the channel so that senders don't fail
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+            if output.is_empty() {
+                // keep draining the channel so that senders don't fail
+                for _ in recv.iter() {}
+                bail!("an output directory is required for partitioned output");
+            }
+            let mut writer =
+                PartitionedCsvWriter::new(output, query_buckets, columns, metadata, options);
+            for res in recv.iter() {
+                let written = match query_groups.as_mut() {
+                    Some(groups) => groups.add(&res).and_then(|_| writer.write(&res)),
+                    None => writer.write(&res),
+                }
+                .and_then(|_| biom.as_mut().map_or(Ok(()), |biom| biom.add(&res)))
+                .and_then(|_| report.as_ref().map_or(Ok(()), |report| report.add(&res)));
+                if let Err(e) = written {
+                    for _ in recv.iter() {}
+                    return Err(e);
+                }
+            }
+            writer.flush()?;
+            if let Some(groups) = query_groups {
+                groups.write()?;
+            }
+            if let Some(biom) = biom {
+                biom.write()?;
+            }
+            if let Some(report) = report {
+                report.write()?;
+            }
+            Ok(())
+        });
+    }
+
+    // create output file(s)
+    let out = open_outputs(output, &options);
+    // spawn a thread that is dedicated to printing to a buffered output
+    std::thread::spawn(move || -> Result<()> {
+        if let Err(e) = check_columns::<T>(columns.as_ref(), metadata.as_deref()) {
+            // keep draining the channel so that senders don't fail
+            for _ in recv.iter() {}
+            return Err(e);
+        }
+        let mut writer = Writer::from_writer(out);
+        let mut column_selection = ColumnSelection::from_options(columns, metadata, &options);
+
+        for res in recv.iter() {
+            if let Some(groups) = query_groups.as_mut() {
+                if let Err(e) = groups.add(&res) {
+                    for _ in recv.iter() {}
+                    return Err(e);
+                }
+            }
+            if let Some(biom) = biom.as_mut() {
+                if let Err(e) = biom.add(&res) {
+                    for _ in recv.iter() {}
+                    return Err(e);
+                }
+            }
+            if let Some(report) = report.as_ref() {
+                if let Err(e) = report.add(&res) {
+                    for _ in recv.iter() {}
+                    return Err(e);
+                }
+            }
+            if let Some(selection) = column_selection.as_mut() {
+                if let Err(e) = selection.write_record(&mut writer, &res) {
+                    // keep draining the channel so that senders don't fail
+                    for _ in recv.iter() {}
+                    return Err(e);
+                }
+            } else if let Err(e) = writer.serialize(res) {
+                eprintln!("Error writing item: {:?}", e);
+            }
+        }
+        writer.flush().map_err(|e| {
+            BranchwaterError::new(
+                ErrorCode::OutputWriteFailed,
+                format!("failed to write output: {}", e),
+            )
+        })?;
+        if let Some(groups) = query_groups {
+            groups.write()?;
+        }
+        if let Some(biom) = biom {
+            biom.write()?;
+        }
+        if let Some(report) = report {
+            report.write()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write each result sent on 'recv' as a line of JSON to one or more
+/// outputs, or stdout, with the same extras as
+/// `csvwriter_thread_with_extras`.
+pub fn jsonlwriter_thread<T: Serialize + ResultSchema + Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    extras: WriterExtras,
+) -> std::thread::JoinHandle<Result<()>> {
+    let mut query_groups = extras.query_groups;
+    let metadata = extras.against_metadata.map(Arc::new);
+    let mut biom = extras.biom;
+    let report = extras.report;
+    let options = extras.options;
+
+    // create output file(s)
+    let checked = OutputFormat::Jsonl.check_outputs(&output, query_buckets);
+    let out = open_outputs(output, &options);
+    std::thread::spawn(move || -> Result<()> {
+        let writer = checked.and_then(|_| {
+            JsonlWriter::<_, T>::new(BufWriter::new(out), columns, metadata, options)
+        });
+        let mut writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                // keep draining the channel so that senders don't fail
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        };
+
+        for res in recv.iter() {
+            let written = match query_groups.as_mut() {
+                Some(groups) => groups.add(&res).and_then(|_| writer.write(&res)),
+                None => writer.write(&res),
+            }
+            .and_then(|_| biom.as_mut().map_or(Ok(()), |biom| biom.add(&res)))
+            .and_then(|_| report.as_ref().map_or(Ok(()), |report| report.add(&res)));
+            if let Err(e) = written {
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        }
+        writer.flush().map_err(|e| {
+            BranchwaterError::new(
+                ErrorCode::OutputWriteFailed,
+                format!("failed to write output: {}", e),
+            )
+        })?;
+        if let Some(groups) = query_groups {
+            groups.write()?;
+        }
+        if let Some(biom) = biom {
+            biom.write()?;
+        }
+        if let Some(report) = report {
+            report.write()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write each result sent on 'recv' to a single Parquet file, with the
+/// same extras as `csvwriter_thread_with_extras`.
+fn parquetwriter_thread<T: Serialize + ResultSchema + Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    extras: WriterExtras,
+) -> std::thread::JoinHandle<Result<()>> {
+    let mut query_groups = extras.query_groups;
+    let metadata = extras.against_metadata.map(Arc::new);
+    let mut biom = extras.biom;
+    let report = extras.report;
+    let options = extras.options;
+
+    std::thread::spawn(move || -> Result<()> {
+        let writer = extras
+            .format
+            .check_outputs(&output, query_buckets)
+            .and_then(|_| {
+                let out = open_outputs(output, &options);
+                ParquetResultWriter::<T>::new(out, columns, metadata, options)
+            });
+        let mut writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                // keep draining the channel so that senders don't fail
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        };
+
+        for res in recv.iter() {
+            let written = match query_groups.as_mut() {
+                Some(groups) => groups.add(&res).and_then(|_| writer.write(&res)),
+                None => writer.write(&res),
+            }
+            .and_then(|_| biom.as_mut().map_or(Ok(()), |biom| biom.add(&res)))
+            .and_then(|_| report.as_ref().map_or(Ok(()), |report| report.add(&res)));
+            if let Err(e) = written {
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        }
+        writer.finish().map_err(|e| {
+            BranchwaterError::new(
+                ErrorCode::OutputWriteFailed,
+                format!("failed to write output: {}", e),
+            )
+        })?;
+        if let Some(groups) = query_groups {
+            groups.write()?;
+        }
+        if let Some(biom) = biom {
+            biom.write()?;
+        }
+        if let Some(report) = report {
+            report.write()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write each result sent on 'recv' to a table of a single SQLite
+/// database, with the same extras as `csvwriter_thread_with_extras`.
+#[cfg(feature = "sqlite")]
+fn sqlitewriter_thread<T: Serialize + ResultSchema + Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    extras: WriterExtras,
+) -> std::thread::JoinHandle<Result<()>> {
+    let mut query_groups = extras.query_groups;
+    let metadata = extras.against_metadata.map(Arc::new);
+    let mut biom = extras.biom;
+    let report = extras.report;
+    let options = extras.options;
+    let table = match extras.command {
+        "" => T::NAME,
+        command => command,
+    };
+
+    std::thread::spawn(move || -> Result<()> {
+        let writer = extras
+            .format
+            .check_outputs(&output, query_buckets)
+            .and_then(|_| {
+                SqliteResultWriter::<T>::new(&output[0], table, columns, metadata, options)
+            });
+        let mut writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                // keep draining the channel so that senders don't fail
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        };
+
+        for res in recv.iter() {
+            let written = match query_groups.as_mut() {
+                Some(groups) => groups.add(&res).and_then(|_| writer.write(&res)),
+                None => writer.write(&res),
+            }
+            .and_then(|_| biom.as_mut().map_or(Ok(()), |biom| biom.add(&res)))
+            .and_then(|_| report.as_ref().map_or(Ok(()), |report| report.add(&res)));
+            if let Err(e) = written {
+                for _ in recv.iter() {}
+                return Err(e);
+            }
+        }
+        writer.finish().map_err(|e| {
+            BranchwaterError::new(
+                ErrorCode::OutputWriteFailed,
+                format!("failed to write output: {}", e),
+            )
+        })?;
+        if let Some(groups) = query_groups {
+            groups.write()?;
+        }
+        if let Some(biom) = biom {
+            biom.write()?;
+        }
+        if let Some(report) = report {
+            report.write()?;
+        }
+        Ok(())
+    })
+}
+
+/// Without the `sqlite` feature, `--output-format sqlite` is rejected
+/// when it is parsed; this only fails results sent by library callers.
+#[cfg(not(feature = "sqlite"))]
+fn sqlitewriter_thread<T: Send + 'static>(
+    recv: std::sync::mpsc::Receiver<T>,
+    _output: Vec<String>,
+    _columns: Option<Vec<String>>,
+    _query_buckets: usize,
+    _extras: WriterExtras,
+) -> std::thread::JoinHandle<Result<()>> {
+    std::thread::spawn(move || -> Result<()> {
+        // keep draining the channel so that senders don't fail
+        for _ in recv.iter() {}
+        bail!("branchwater was built without SQLite output; rebuild it with the 'sqlite' feature.")
+    })
 }
 
 pub fn write_signature(
@@ -1377,3 +5415,94 @@ pub fn write_signature(
     zip.start_file(sig_filename, zip_options).unwrap();
     zip.write_all(&gzipped_buffer).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ani_estimator_matches_ani_from_containment() {
+        for ksize in [10.0, 21.0, 31.0, 51.0] {
+            let estimator = AniEstimator::new(ksize);
+            for i in 0..=1000 {
+                let containment = i as f64 / 1000.0;
+                let ani = ani_from_containment(containment, ksize);
+                if ani < 0.5 {
+                    continue;
+                }
+                assert_eq!(
+                    estimator.ani(containment).to_bits(),
+                    ani.to_bits(),
+                    "containment={} ksize={}",
+                    containment,
+                    ksize
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ani_estimator_is_close_below_half_ani() {
+        // containments down to the smallest positive f64, where ANI < 0.5
+        // for every ksize.
+        for ksize in [10.0, 21.0, 31.0, 51.0] {
+            let estimator = AniEstimator::new(ksize);
+            let mut containment = 0.5f64.powf(ksize);
+            while containment >= f64::MIN_POSITIVE {
+                let diff = estimator.ani(containment) - ani_from_containment(containment, ksize);
+                assert!(
+                    diff.abs() <= f64::EPSILON,
+                    "containment={} ksize={}",
+                    containment,
+                    ksize
+                );
+                containment /= 3.0;
+            }
+        }
+    }
+
+    fn prefetch_result(overlap: u64, md5sum: &str, name: &str, location: &str) -> PrefetchResult {
+        PrefetchResult {
+            name: name.to_string(),
+            md5sum: md5sum.to_string(),
+            location: location.to_string(),
+            minhash: KmerMinHash::new(1, 31, HashFunctions::Murmur64Dna, 42, false, 0),
+            overlap,
+        }
+    }
+
+    #[test]
+    fn prefetch_results_pop_in_tie_break_order() {
+        let expected = [
+            (20, "ccc", "a", "a.sig"),
+            (10, "aaa", "b", "b.sig"),
+            (10, "bbb", "a", "c.sig"),
+            (10, "bbb", "b", "a.sig"),
+            (10, "bbb", "b", "b.sig"),
+        ];
+        // the pop order doesn't depend on the order matches were found in.
+        let mut orders: Vec<Vec<usize>> = vec![(0..expected.len()).collect()];
+        orders.push((0..expected.len()).rev().collect());
+        orders.push(vec![3, 1, 4, 0, 2]);
+        for order in orders {
+            let mut heap: BinaryHeap<PrefetchResult> = order
+                .iter()
+                .map(|&i| {
+                    let (overlap, md5sum, name, location) = expected[i];
+                    prefetch_result(overlap, md5sum, name, location)
+                })
+                .collect();
+            for (overlap, md5sum, name, location) in expected {
+                let result = heap.pop().unwrap();
+                assert_eq!(
+                    (result.overlap, result.md5sum.as_str(), result.name.as_str()),
+                    (overlap, md5sum, name),
+                    "insertion order {:?}",
+                    order
+                );
+                assert_eq!(result.location, location);
+            }
+            assert!(heap.is_empty());
+        }
+    }
+}
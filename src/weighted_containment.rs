@@ -0,0 +1,105 @@
+/// weighted_containment: abundance-weighted, directional containment between
+/// pairs of metagenome sketches, e.g. for contamination or source tracking.
+use anyhow::Result;
+
+use crate::abundance::compare_abundance_pairs;
+use crate::utils::{OutputOptions, Policies, WeightedContainmentResult};
+use sourmash::selection::Selection;
+
+/// Per-pair sums over the shared hashes of two abundance sketches.
+struct SharedAbundances {
+    intersect_hashes: usize,
+    query_shared: u64,
+    against_shared: u64,
+}
+
+/// Walk the (sorted) hashes of two abundance sketches together, summing
+/// the abundance in each sketch of the hashes they share.
+fn shared_abundances(query: &[(u64, u64)], against: &[(u64, u64)]) -> SharedAbundances {
+    let mut sums = SharedAbundances {
+        intersect_hashes: 0,
+        query_shared: 0,
+        against_shared: 0,
+    };
+    let mut query_iter = query.iter().peekable();
+    let mut against_iter = against.iter().peekable();
+
+    while let (Some((q_hash, q_abund)), Some((a_hash, a_abund))) =
+        (query_iter.peek(), against_iter.peek())
+    {
+        match q_hash.cmp(a_hash) {
+            std::cmp::Ordering::Less => {
+                query_iter.next();
+            }
+            std::cmp::Ordering::Greater => {
+                against_iter.next();
+            }
+            std::cmp::Ordering::Equal => {
+                sums.intersect_hashes += 1;
+                sums.query_shared += q_abund;
+                sums.against_shared += a_abund;
+                query_iter.next();
+                against_iter.next();
+            }
+        }
+    }
+    sums
+}
+
+/// For every pair of abundance-tracking sketches in 'siglist', compute the
+/// fraction of each sketch's weighted hashes (the sum of its abundances)
+/// that are found in the other. Pairs where either fraction is above
+/// 'threshold' are written.
+///
+/// Note: this function loads all _signatures_ into memory.
+#[allow(clippy::too_many_arguments)]
+pub fn weighted_containment(
+    siglist: String,
+    threshold: f64,
+    selection: &Selection,
+    policies: &Policies,
+    output: Vec<String>,
+    columns: Option<Vec<String>>,
+    query_buckets: usize,
+    options: &OutputOptions,
+) -> Result<()> {
+    let fraction = |shared: u64, total: u64| {
+        if total > 0 {
+            shared as f64 / total as f64
+        } else {
+            0.0
+        }
+    };
+
+    compare_abundance_pairs(
+        "weighted_containment",
+        siglist,
+        selection,
+        policies,
+        output,
+        columns,
+        query_buckets,
+        options,
+        |pair| {
+            let sums = shared_abundances(pair.query_abunds, pair.against_abunds);
+            let query_containment = fraction(sums.query_shared, pair.query_total);
+            let match_containment = fraction(sums.against_shared, pair.against_total);
+
+            if query_containment <= threshold && match_containment <= threshold {
+                return None;
+            }
+            Some(WeightedContainmentResult {
+                query_name: pair.query.name.clone(),
+                query_md5: pair.query.md5sum.clone(),
+                match_name: pair.against.name.clone(),
+                match_md5: pair.against.md5sum.clone(),
+                query_weighted_containment: query_containment,
+                match_weighted_containment: match_containment,
+                max_weighted_containment: query_containment.max(match_containment),
+                intersect_hashes: sums.intersect_hashes,
+                query_sum_abunds: pair.query_total,
+                match_sum_abunds: pair.against_total,
+            })
+        },
+    )
+}